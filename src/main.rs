@@ -1,27 +1,320 @@
+use clap::Parser;
+use nestacean::nes::audio::DEFAULT_SAMPLE_RATE;
+use nestacean::nes::audio_out::AudioOutput;
+use nestacean::nes::cart::Cart;
+use nestacean::nes::config::Config;
+use nestacean::nes::cpu::CpuStepResult;
+use nestacean::nes::gamepad::Gamepads;
+use nestacean::nes::input::{channel_hotkey, Hotkey};
+use nestacean::nes::movie::Movie;
+use nestacean::nes::nestest;
+use nestacean::nes::nsf::{Nsf, NsfPlayer};
+use nestacean::nes::ppu_viewer::PpuViewer;
+use nestacean::nes::savestate::StateError;
+use nestacean::nes::wav::toggle_recording;
 use nestacean::nes::NES;
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// nestacean <rom> [--debug] [--scale N] [--ppu-viewer] [--from-state] [--record-movie FILE |
+// --play-movie FILE], or one of the modes that don't run a game
+#[derive(Parser)]
+#[command(version, about = "A NES emulator")]
+struct Args {
+    #[arg(
+        help = "iNES file to run, zipped or not; an .ips or .bps patch beside it is applied",
+        required_unless_present_any = ["trace_compare", "nsf"]
+    )]
+    rom: Option<PathBuf>,
+    #[arg(long, help = "Step through the game in the CPU debugger")]
+    debug: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Window size as a multiple of the NES picture",
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u32).range(1..=16)
+    )]
+    scale: u32,
+    #[arg(
+        long,
+        help = "Open windows showing the pattern tables, nametables, palettes and sprites"
+    )]
+    ppu_viewer: bool,
+    #[arg(long, help = "Start from the .state file next to the ROM")]
+    from_state: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Record the controllers to a movie, saved on quitting"
+    )]
+    record_movie: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Play back a movie recorded on this ROM",
+        conflicts_with_all = ["record_movie", "from_state"]
+    )]
+    play_movie: Option<PathBuf>,
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["ROM", "LOG"],
+        help = "Run nestest headless and compare its trace with a known good log",
+        conflicts_with_all = ["rom", "nsf"]
+    )]
+    trace_compare: Option<Vec<String>>,
+    #[arg(
+        long,
+        num_args = 1..=2,
+        value_names = ["FILE", "TRACK"],
+        help = "Play an NSF or NSFe file, from TRACK (numbered from 1) or its default track",
+        conflicts_with = "rom"
+    )]
+    nsf: Option<Vec<String>>,
+}
+
+// nestacean --trace-compare <nestest.nes> <nestest.log>
+fn trace_compare(rom_path: &str, log_path: &str) -> ! {
+    let rom = std::fs::read(rom_path).unwrap_or_else(|err| {
+        eprintln!("Could not read {}: {}", rom_path, err);
+        std::process::exit(2);
+    });
+    let log = std::fs::read_to_string(log_path).unwrap_or_else(|err| {
+        eprintln!("Could not read {}: {}", log_path, err);
+        std::process::exit(2);
+    });
+    let mut cpu = nestest::headless_cpu(&rom).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", rom_path, err);
+        std::process::exit(2);
+    });
+
+    match nestest::compare_trace(&mut cpu, &log) {
+        Ok(lines) => {
+            println!("Trace matches ({} lines)", lines);
+            std::process::exit(0);
+        }
+        Err(mismatch) => {
+            eprintln!("{}", mismatch);
+            std::process::exit(1);
+        }
+    }
+}
+
+// the settings in the config file, or the defaults if it can't be read
+fn load_config() -> Config {
+    let config = Config::default_path().map_or(Ok(Config::default()), |path| Config::load(&path));
+    config.unwrap_or_else(|err| {
+        eprintln!("{}, using the default settings", err);
+        Config::default()
+    })
+}
+
+// nestacean --nsf <file.nsf> [track], plays until the window is closed
+fn play_nsf(path: &str, track: Option<usize>) -> ! {
+    let nsf = Nsf::from_file(path).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", path, err);
+        std::process::exit(2);
+    });
+    let mut player = NsfPlayer::new(nsf);
+    // only the channel and record ones do anything here
+    let hotkeys = load_config().hotkeys;
+    let wav_path = Path::new(path).with_extension("wav");
+    if let Some(track) = track {
+        player.select_track(track);
+    }
+    let sdl_context = sdl2::init().unwrap();
+    // only there to take the hotkeys
+    let _window = sdl_context
+        .video()
+        .unwrap()
+        .window(&format!("nestacean - {}", player.nsf().title), 320, 80)
+        .position_centered()
+        .build()
+        .unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let audio = sdl_context.audio().unwrap();
+    let mut output = AudioOutput::open(&audio, DEFAULT_SAMPLE_RATE, Duration::from_millis(100))
+        .unwrap_or_else(|err| {
+            eprintln!("Could not open audio: {}", err);
+            std::process::exit(1);
+        });
+    player.bus_mut().set_sample_rate(output.sample_rate());
+    println!(
+        "Playing track {}/{} {}",
+        player.track() + 1,
+        player.track_count(),
+        player.track_title().unwrap_or("")
+    );
+
+    // the sound card sets the pace, a frame is run whenever the queue gets short
+    let low_water = output.sample_rate() / 20;
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    // a recording in progress still gets its header finished
+                    if let Err(err) = player.bus_mut().stop_recording() {
+                        eprintln!("Recording failed: {}", err);
+                    }
+                    std::process::exit(0);
+                }
+                Event::KeyDown {
+                    keycode: Some(key),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => {
+                    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    match hotkeys.hotkey(key) {
+                        // R records to a WAV named after the file being played
+                        Some(Hotkey::Record) => {
+                            toggle_recording(player.bus_mut(), &wav_path, shift)
+                        }
+                        Some(hotkey) => channel_hotkey(player.bus_mut().apu_mut(), hotkey, shift),
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        if player.run_frame() == CpuStepResult::Halted {
+            eprintln!("The NSF driver jammed the CPU");
+            std::process::exit(1);
+        }
+        output.feed(player.bus_mut().samples_mut()).unwrap();
+        while output.queued() > low_water {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
 
 fn main() {
+    let args = Args::parse();
+    if let Some(paths) = &args.trace_compare {
+        trace_compare(&paths[0], &paths[1]);
+    }
+    if let Some(nsf) = &args.nsf {
+        // tracks are numbered from 1 on the command line
+        let track = match nsf.get(1).map(|track| track.parse::<usize>()) {
+            Some(Ok(track)) => Some(track.max(1) - 1),
+            Some(Err(_)) => {
+                eprintln!("Track must be a number, not {}", nsf[1]);
+                std::process::exit(2);
+            }
+            None => None,
+        };
+        play_nsf(&nsf[0], track);
+    }
+    // clap asks for one when neither of the other modes is picked
+    let rom_path = args.rom.as_deref().expect("no ROM given");
+    let cart = Cart::from_file(rom_path).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", rom_path.display(), err);
+        std::process::exit(2);
+    });
+
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+    // the game's name from the ROM database, or the file's when the dump isn't in it
+    let stem = rom_path.file_stem().unwrap_or_default().display();
+    let title = match &cart.title {
+        Some(title) => format!("nestacean - {}", title),
+        None => format!("nestacean - {}", stem),
+    };
     let window = video_subsystem
-        .window("Snake game", (32.0 * 10.0) as u32, (32.0 * 10.0) as u32)
+        .window(&title, 256 * args.scale, 240 * args.scale)
         .position_centered()
         .build()
         .unwrap();
 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    canvas.set_scale(10.0, 10.0).unwrap();
-
+    // no vsync, the frame limiter paces the game and presenting mustn't cap fast-forward
+    let canvas = window.into_canvas().build().unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
     let texture_creator = canvas.texture_creator();
-    let rng = rand::rng();
 
-    let mut nes = NES::new(&texture_creator, canvas, rng);
+    let mut nes = NES::new(&texture_creator, canvas, cart);
+    nes.set_state_path(rom_path.with_extension("state"));
+    nes.set_recording_path(rom_path.with_extension("wav"));
+    let config = load_config();
+    *nes.input_map_mut() = config.input;
+    *nes.hotkeys_mut() = config.hotkeys;
+    nes.set_fast_forward(config.fast_forward);
+    nes.set_turbo(config.turbo);
+    nes.set_opposite_directions(config.opposite_directions);
+    // pads already plugged in show up as connection events once the subsystem is up
+    match sdl_context.game_controller() {
+        Ok(subsystem) => {
+            let mut gamepads = Gamepads::new(subsystem);
+            gamepads.set_deadzone(config.gamepad_deadzone);
+            nes.set_gamepads(gamepads);
+        }
+        Err(err) => eprintln!("Gamepads unavailable: {}", err),
+    }
 
-    // nes.enable_cpu_debug();
+    // the game plays on without sound if there's no audio device
+    let audio = sdl_context.audio().and_then(|audio| {
+        AudioOutput::open(&audio, DEFAULT_SAMPLE_RATE, Duration::from_millis(100))
+    });
+    match audio {
+        Ok(output) => nes.set_audio_output(output),
+        Err(err) => eprintln!("Could not open audio: {}", err),
+    }
+    if args.from_state {
+        let state_path = rom_path.with_extension("state");
+        let loaded = std::fs::read(&state_path)
+            .map_err(StateError::from)
+            .and_then(|state| nes.load_state(&state));
+        if let Err(err) = loaded {
+            eprintln!("Could not load {}: {}", state_path.display(), err);
+            std::process::exit(2);
+        }
+    }
+    if let Some(path) = &args.record_movie {
+        nes.record_movie(path.clone());
+    }
+    if let Some(path) = &args.play_movie {
+        let played = Movie::load(path).and_then(|movie| nes.play_movie(movie));
+        if let Err(err) = played {
+            eprintln!("Could not play {}: {}", path.display(), err);
+            std::process::exit(2);
+        }
+    }
+    if args.ppu_viewer {
+        match PpuViewer::open(&video_subsystem) {
+            Ok(viewer) => nes.set_ppu_viewer(viewer),
+            Err(err) => eprintln!("Could not open the PPU viewer: {}", err),
+        }
+    }
+    if args.debug {
+        nes.enable_cpu_debug();
+    }
     loop {
-        //TODO: only interrupted with manual interrupts right now
-        nes.tick(&mut event_pump);
+        match nes.run_frame(&mut event_pump) {
+            CpuStepResult::Running | CpuStepResult::Breakpoint(_) | CpuStepResult::Stuck(_) => {}
+            CpuStepResult::Break => {
+                nes.finish();
+                std::process::exit(0);
+            }
+            CpuStepResult::Halted => {
+                nes.finish();
+                let cpu = nes.cpu();
+                match cpu.get_error() {
+                    Some(err) => eprintln!("CPU stopped: {}", err),
+                    None => eprintln!(
+                        "CPU jammed on opcode {:02X} at {:04X}",
+                        cpu.get_current_opcode(),
+                        cpu.get_pc().wrapping_sub(1)
+                    ),
+                }
+                std::process::exit(1);
+            }
+        }
     }
 }
@@ -0,0 +1,415 @@
+// 2A03 audio unit. Clocked once per CPU cycle. Writes to $4000-$4013, $4015 and $4017 are
+// decoded into the channels' registers and the frame counter's mode; the channels themselves
+// are in apu/channels.rs and apu/dmc.rs. The frame counter clocks their envelopes and linear
+// counters on quarter frames and their length counters and sweeps on half frames, and the
+// five outputs are mixed with the 2A03's nonlinear DAC curves. Debug views are in apu/debug.rs.
+
+mod channels;
+mod debug;
+mod dmc;
+
+use super::cart::Region;
+use super::mem::Peek;
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+pub use channels::{Noise, Pulse, Sweep, Triangle};
+pub use debug::{ChannelInfo, WAVEFORM_INTERVAL, WAVEFORM_LEN};
+use debug::Scope;
+pub use dmc::Dmc;
+
+const PULSE_1: u16 = 0x4000;
+const PULSE_2: u16 = 0x4004;
+const TRIANGLE: u16 = 0x4008;
+const NOISE: u16 = 0x400C;
+const DMC: u16 = 0x4010;
+const STATUS: u16 = 0x4015;
+const FRAME_COUNTER: u16 = 0x4017;
+
+// The frame counter's timing in CPU cycles. The four step sequence raises the IRQ over its last
+// three cycles; the steps are where each lands, the four step sequence ending on the fourth and
+// the five step one on the fifth with nothing clocked on the fourth.
+struct FrameTiming {
+    four_step_cycles: u16,
+    five_step_cycles: u16,
+    irq_cycle: u16,
+    steps: [u16; 5],
+}
+
+const NTSC_FRAME_TIMING: FrameTiming = FrameTiming {
+    four_step_cycles: 29830,
+    five_step_cycles: 37282,
+    irq_cycle: 29828,
+    steps: [7457, 14913, 22371, 29829, 37281],
+};
+// Dendy keeps the NTSC timing
+const PAL_FRAME_TIMING: FrameTiming = FrameTiming {
+    four_step_cycles: 33254,
+    five_step_cycles: 41566,
+    irq_cycle: 33252,
+    steps: [8313, 16627, 24939, 33253, 41565],
+};
+
+// $4017
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameCounter {
+    // five steps a sequence rather than four, which never raises the frame IRQ
+    pub five_step: bool,
+    pub irq_inhibit: bool,
+    // set at the end of a four step sequence, cleared by reading $4015 or inhibiting it
+    pub irq_flag: bool,
+}
+
+// the five channels, in $4015's bit order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] =
+        [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+        }
+    }
+}
+
+// the 2A03's two DACs, levels in and 0.0 to about 1.0 out between them
+fn pulse_dac(pulses: f32) -> f32 {
+    if pulses == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulses + 100.0) }
+}
+
+fn tnd_dac(triangle: f32, noise: f32, dmc: f32) -> f32 {
+    let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+    if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) }
+}
+
+pub struct Apu {
+    cycles: u64,
+    pulse: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    // CPU cycles into the current sequence
+    frame_cycle: u16,
+    // the region's frame counter timing, configuration rather than state
+    frame_timing: &'static FrameTiming,
+    // listening controls, applied before mixing and not part of the emulated state
+    volumes: [f32; 5],
+    muted: [bool; 5],
+    solo: Option<Channel>,
+    // the oscilloscope, while it's on
+    scope: Option<Box<Scope>>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            cycles: 0,
+            pulse: [Pulse::default(); 2],
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            frame_counter: FrameCounter::default(),
+            frame_cycle: 0,
+            frame_timing: &NTSC_FRAME_TIMING,
+            volumes: [1.0; 5],
+            muted: [false; 5],
+            solo: None,
+            scope: None,
+        }
+    }
+
+    // the frame counter, noise and DMC run at different rates on PAL; NTSC until set
+    pub fn set_region(&mut self, region: Region) {
+        self.frame_timing = match region {
+            Region::Pal => &PAL_FRAME_TIMING,
+            Region::Ntsc | Region::Dendy => &NTSC_FRAME_TIMING,
+        };
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+    }
+
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+        // the pulses run at the APU's own rate, half the CPU's
+        if self.cycles.is_multiple_of(2) {
+            for pulse in &mut self.pulse {
+                pulse.clock_timer();
+            }
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.tick();
+        self.tick_frame_counter();
+        if self.scope.is_some() {
+            self.tick_scope();
+        }
+    }
+
+    fn tick_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let five_step = self.frame_counter.five_step;
+        let timing = self.frame_timing;
+        match timing.steps.iter().position(|&step| step == self.frame_cycle) {
+            Some(0 | 2) => self.clock_quarter_frame(),
+            Some(1) => self.clock_half_frame(),
+            Some(3) if !five_step => self.clock_half_frame(),
+            Some(4) if five_step => self.clock_half_frame(),
+            _ => {}
+        }
+        let frame = &mut self.frame_counter;
+        if !five_step && !frame.irq_inhibit && self.frame_cycle >= timing.irq_cycle {
+            frame.irq_flag = true;
+        }
+        let length = if five_step { timing.five_step_cycles } else { timing.four_step_cycles };
+        if self.frame_cycle >= length {
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        for pulse in &mut self.pulse {
+            pulse.clock_quarter_frame();
+        }
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    // half frames are quarter frames too
+    fn clock_half_frame(&mut self) {
+        self.clock_quarter_frame();
+        self.pulse[0].clock_half_frame(true);
+        self.pulse[1].clock_half_frame(false);
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // The mixed output, 0.0 to about 1.0. The pulses share one DAC and the triangle, noise
+    // and DMC another, each with its own curve. Channel volumes scale the levels going in.
+    pub fn output(&self) -> f32 {
+        let level = |channel: Channel, output: u8| output as f32 * self.gain(channel);
+        let pulses = level(Channel::Pulse1, self.pulse[0].output())
+            + level(Channel::Pulse2, self.pulse[1].output());
+        pulse_dac(pulses)
+            + tnd_dac(
+                level(Channel::Triangle, self.triangle.output()),
+                level(Channel::Noise, self.noise.output()),
+                level(Channel::Dmc, self.dmc.output),
+            )
+    }
+
+    // one channel through its DAC as if the others were silent, ignoring the mixing controls
+    pub fn channel_output(&self, channel: Channel) -> f32 {
+        match channel {
+            Channel::Pulse1 => pulse_dac(self.pulse[0].output() as f32),
+            Channel::Pulse2 => pulse_dac(self.pulse[1].output() as f32),
+            Channel::Triangle => tnd_dac(self.triangle.output() as f32, 0.0, 0.0),
+            Channel::Noise => tnd_dac(0.0, self.noise.output() as f32, 0.0),
+            Channel::Dmc => tnd_dac(0.0, 0.0, self.dmc.output as f32),
+        }
+    }
+
+    // 1.0 is as the console plays it, 0.0 silent
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volumes[channel as usize] = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.volumes[channel as usize]
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    // plays only `channel`, at its volume and even if it's muted; None plays them all again
+    pub fn set_solo(&mut self, channel: Option<Channel>) {
+        self.solo = channel;
+    }
+
+    pub fn solo(&self) -> Option<Channel> {
+        self.solo
+    }
+
+    // what the channel's level is scaled by in the mix, after mute and solo
+    pub fn gain(&self, channel: Channel) -> f32 {
+        let audible = match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted[channel as usize],
+        };
+        if audible { self.volumes[channel as usize] } else { 0.0 }
+    }
+
+    // the IRQ line into the CPU
+    pub fn irq(&self) -> bool {
+        self.dmc.irq_flag || self.frame_counter.irq_flag
+    }
+
+    // the address of a sample byte the DMC wants fetched, for the CPU to run as a DMA
+    pub fn take_dmc_request(&mut self) -> Option<u16> {
+        self.dmc.take_request()
+    }
+
+    // the byte the DMA for the last request read
+    pub fn fill_dmc_sample(&mut self, sample: u8) {
+        self.dmc.fill(sample);
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // `channel` 0 or 1, for $4000 and $4004
+    pub fn pulse(&self, channel: usize) -> &Pulse {
+        &self.pulse[channel]
+    }
+
+    pub fn triangle(&self) -> &Triangle {
+        &self.triangle
+    }
+
+    pub fn noise(&self) -> &Noise {
+        &self.noise
+    }
+
+    pub fn dmc(&self) -> &Dmc {
+        &self.dmc
+    }
+
+    pub fn frame_counter(&self) -> &FrameCounter {
+        &self.frame_counter
+    }
+
+    // A CPU write to `addr` in $4000-$4017. $4014 and $4016 belong to OAM DMA and the
+    // controllers and are ignored here, as are the unused $4009 and $400D.
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            PULSE_1..=0x4003 => self.pulse[0].write(addr - PULSE_1, data),
+            PULSE_2..=0x4007 => self.pulse[1].write(addr - PULSE_2, data),
+            TRIANGLE..=0x400B => self.triangle.write(addr - TRIANGLE, data),
+            NOISE..=0x400F => self.noise.write(addr - NOISE, data),
+            DMC..=0x4013 => self.dmc.write(addr - DMC, data),
+            STATUS => self.write_status(data),
+            // Restarts the sequence, the real one waits 3 or 4 cycles to do so. Switching to
+            // five steps clocks a half frame straight away.
+            FRAME_COUNTER => {
+                let irq_inhibit = data & 0x40 != 0;
+                self.frame_counter = FrameCounter {
+                    five_step: data & 0x80 != 0,
+                    irq_inhibit,
+                    irq_flag: self.frame_counter.irq_flag && !irq_inhibit,
+                };
+                self.frame_cycle = 0;
+                if self.frame_counter.five_step {
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // one enable bit per channel, from pulse 1 up to the DMC; disabling one silences it by
+    // clearing its length counter, enabling the DMC starts its sample. Any write acknowledges
+    // the DMC IRQ.
+    fn write_status(&mut self, data: u8) {
+        let [pulse_1, pulse_2] = &mut self.pulse;
+        let lengths = [
+            (&mut pulse_1.enabled, &mut pulse_1.length_counter),
+            (&mut pulse_2.enabled, &mut pulse_2.length_counter),
+            (&mut self.triangle.enabled, &mut self.triangle.length_counter),
+            (&mut self.noise.enabled, &mut self.noise.length_counter),
+        ];
+        for (bit, (enabled, length_counter)) in lengths.into_iter().enumerate() {
+            *enabled = data & (1 << bit) != 0;
+            if !*enabled {
+                *length_counter = 0;
+            }
+        }
+        self.dmc.set_enabled(data & 0x10 != 0);
+        self.dmc.irq_flag = false;
+    }
+
+    // $4015 as the CPU reads it, which acknowledges the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.status();
+        self.frame_counter.irq_flag = false;
+        status
+    }
+
+    // which channels are still sounding, a length counter above 0 or DMC bytes left, and the
+    // two IRQ flags; bit 5 isn't driven
+    fn status(&self) -> u8 {
+        let lengths = [
+            self.pulse[0].length_counter,
+            self.pulse[1].length_counter,
+            self.triangle.length_counter,
+            self.noise.length_counter,
+        ];
+        let mut status = 0;
+        for (bit, length) in lengths.into_iter().enumerate() {
+            status |= ((length > 0) as u8) << bit;
+        }
+        status |= ((self.dmc.bytes_remaining > 0) as u8) << 4;
+        status |= (self.frame_counter.irq_flag as u8) << 6;
+        status | (self.dmc.irq_flag as u8) << 7
+    }
+}
+
+// only $4015 can be read
+impl Peek for Apu {
+    fn peek(&self, _addr: u16) -> u8 {
+        self.status()
+    }
+}
+
+impl Savestate for Apu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u64(self.cycles);
+        for pulse in &self.pulse {
+            pulse.save_state(w);
+        }
+        self.triangle.save_state(w);
+        self.noise.save_state(w);
+        self.dmc.save_state(w);
+        w.write_bool(self.frame_counter.five_step);
+        w.write_bool(self.frame_counter.irq_inhibit);
+        w.write_bool(self.frame_counter.irq_flag);
+        w.write_u16(self.frame_cycle);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cycles = r.read_u64()?;
+        for pulse in &mut self.pulse {
+            pulse.load_state(r)?;
+        }
+        self.triangle.load_state(r)?;
+        self.noise.load_state(r)?;
+        self.dmc.load_state(r)?;
+        self.frame_counter.five_step = r.read_bool()?;
+        self.frame_counter.irq_inhibit = r.read_bool()?;
+        self.frame_counter.irq_flag = r.read_bool()?;
+        self.frame_cycle = r.read_u16()?.min(self.frame_timing.five_step_cycles);
+        Ok(())
+    }
+}
@@ -0,0 +1,470 @@
+// The pulse, triangle and noise channels. Writes to $4000-$400F land in the pub fields as
+// decoded; the timers, sequencers, envelopes and sweeps that turn them into sound are clocked
+// by the Apu, every CPU cycle and on the frame counter's quarter and half frames. The DMC
+// plays samples by itself and lives in apu/dmc.rs.
+
+use crate::nes::cart::Region;
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+// length counter loads, indexed by the top five bits of $4003/$4007/$400B/$400F
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15,
+];
+// noise timer periods in CPU cycles, indexed by $400E; Dendy uses the NTSC ones
+const NTSC_NOISE_PERIODS: [u16; 16] =
+    [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const PAL_NOISE_PERIODS: [u16; 16] =
+    [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+// the volume ramp shared by the pulses and noise, 15 down to 0 and optionally around again
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Envelope {
+    start: bool,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // a quarter frame; `period` is the channel's volume field, `looping` its length halt
+    fn clock(&mut self, period: u8, looping: bool) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = period;
+        } else if self.divider == 0 {
+            self.divider = period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.start);
+        w.write_u8(self.divider);
+        w.write_u8(self.decay);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.start = r.read_bool()?;
+        self.divider = r.read_u8()? & 0x0F;
+        self.decay = r.read_u8()? & 0x0F;
+        Ok(())
+    }
+}
+
+// a half frame, length counters stop at 0 and while halted
+fn clock_length(halt: bool, length_counter: &mut u8) {
+    if !halt && *length_counter > 0 {
+        *length_counter -= 1;
+    }
+}
+
+// $4001/$4005
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sweep {
+    pub enabled: bool,
+    pub period: u8,
+    pub negate: bool,
+    pub shift: u8,
+}
+
+// the two square wave channels at $4000-$4003 and $4004-$4007
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pulse {
+    pub duty: u8,
+    // also the envelope's loop flag
+    pub length_halt: bool,
+    pub constant_volume: bool,
+    // the constant volume, or the envelope's period
+    pub volume: u8,
+    pub sweep: Sweep,
+    pub timer_period: u16,
+    pub length_counter: u8,
+    pub enabled: bool,
+    timer: u16,
+    step: u8,
+    envelope: Envelope,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub(super) fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.length_halt = data & 0x20 != 0;
+                self.constant_volume = data & 0x10 != 0;
+                self.volume = data & 0x0F;
+            }
+            1 => {
+                self.sweep = Sweep {
+                    enabled: data & 0x80 != 0,
+                    period: (data >> 4) & 0b111,
+                    negate: data & 0x08 != 0,
+                    shift: data & 0b111,
+                };
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length_counter = load_length(self.enabled, data);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // every other CPU cycle
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub(super) fn clock_quarter_frame(&mut self) {
+        self.envelope.clock(self.volume, self.length_halt);
+    }
+
+    // Pulse 1 negates in ones' complement, so its sweep down goes one further than pulse 2's.
+    pub(super) fn clock_half_frame(&mut self, ones_complement: bool) {
+        clock_length(self.length_halt, &mut self.length_counter);
+        if self.sweep_divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.muted() {
+            let change = self.timer_period >> self.sweep.shift;
+            self.timer_period = if !self.sweep.negate {
+                self.timer_period + change
+            } else if ones_complement {
+                self.timer_period.saturating_sub(change + 1)
+            } else {
+                self.timer_period - change
+            };
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep.period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    // Periods under 8 are too high to hear and get silenced, and so does a sweep up that would
+    // overflow the timer, whether or not the sweep is enabled.
+    fn muted(&self) -> bool {
+        let target = self.timer_period + (self.timer_period >> self.sweep.shift);
+        self.timer_period < 8 || (!self.sweep.negate && target > 0x7FF)
+    }
+
+    // the constant volume or where the envelope is, 0-15
+    pub fn current_volume(&self) -> u8 {
+        if self.constant_volume { self.volume } else { self.envelope.decay }
+    }
+
+    // 0-15
+    pub fn output(&self) -> u8 {
+        if self.length_counter == 0
+            || DUTY_CYCLES[self.duty as usize][self.step as usize] == 0
+            || self.muted()
+        {
+            0
+        } else {
+            self.current_volume()
+        }
+    }
+}
+
+// $4008-$400B
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Triangle {
+    // also holds the linear counter's reload flag
+    pub length_halt: bool,
+    pub linear_reload: u8,
+    pub timer_period: u16,
+    pub length_counter: u8,
+    pub enabled: bool,
+    timer: u16,
+    step: u8,
+    linear_counter: u8,
+    reload_linear: bool,
+}
+
+impl Triangle {
+    pub(super) fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length_halt = data & 0x80 != 0;
+                self.linear_reload = data & 0x7F;
+            }
+            // $4009 isn't connected
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length_counter = load_length(self.enabled, data);
+                self.reload_linear = true;
+            }
+        }
+    }
+
+    // every CPU cycle, the sequencer only moves while both counters are non-zero
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length_counter > 0 && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub(super) fn clock_quarter_frame(&mut self) {
+        if self.reload_linear {
+            self.linear_counter = self.linear_reload;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.length_halt {
+            self.reload_linear = false;
+        }
+    }
+
+    pub(super) fn clock_half_frame(&mut self) {
+        clock_length(self.length_halt, &mut self.length_counter);
+    }
+
+    pub fn linear_counter(&self) -> u8 {
+        self.linear_counter
+    }
+
+    // 0-15; a stopped triangle holds its level rather than dropping to 0
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.step as usize]
+    }
+}
+
+// $400C-$400F
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Noise {
+    pub length_halt: bool,
+    pub constant_volume: bool,
+    pub volume: u8,
+    // the short 93 step sequence instead of the 32767 step one
+    pub short_mode: bool,
+    // index into the period table
+    pub period: u8,
+    pub length_counter: u8,
+    pub enabled: bool,
+    timer: u16,
+    // 15 bit LFSR, never 0
+    shift_register: u16,
+    envelope: Envelope,
+    // the region's period table, configuration rather than state
+    periods: &'static [u16; 16],
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            length_halt: false,
+            constant_volume: false,
+            volume: 0,
+            short_mode: false,
+            period: 0,
+            length_counter: 0,
+            enabled: false,
+            timer: 0,
+            shift_register: 1,
+            envelope: Envelope::default(),
+            periods: &NTSC_NOISE_PERIODS,
+        }
+    }
+}
+
+impl Noise {
+    pub(super) fn set_region(&mut self, region: Region) {
+        self.periods = match region {
+            Region::Pal => &PAL_NOISE_PERIODS,
+            Region::Ntsc | Region::Dendy => &NTSC_NOISE_PERIODS,
+        };
+    }
+
+    pub(super) fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length_halt = data & 0x20 != 0;
+                self.constant_volume = data & 0x10 != 0;
+                self.volume = data & 0x0F;
+            }
+            1 => {}
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period = data & 0x0F;
+            }
+            _ => {
+                self.length_counter = load_length(self.enabled, data);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // every CPU cycle
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.periods[self.period as usize] - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub(super) fn clock_quarter_frame(&mut self) {
+        self.envelope.clock(self.volume, self.length_halt);
+    }
+
+    pub(super) fn clock_half_frame(&mut self) {
+        clock_length(self.length_halt, &mut self.length_counter);
+    }
+
+    // CPU cycles between shifts of the LFSR
+    pub fn timer_period(&self) -> u16 {
+        self.periods[self.period as usize]
+    }
+
+    // the constant volume or where the envelope is, 0-15
+    pub fn current_volume(&self) -> u8 {
+        if self.constant_volume { self.volume } else { self.envelope.decay }
+    }
+
+    // 0-15
+    pub fn output(&self) -> u8 {
+        if self.length_counter == 0 || self.shift_register & 1 != 0 {
+            0
+        } else {
+            self.current_volume()
+        }
+    }
+}
+
+// a disabled channel ignores length counter loads
+fn load_length(enabled: bool, data: u8) -> u8 {
+    if enabled { LENGTH_TABLE[data as usize >> 3] } else { 0 }
+}
+
+impl Savestate for Pulse {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&[self.duty, self.volume, self.sweep.period, self.sweep.shift]);
+        w.write_bool(self.length_halt);
+        w.write_bool(self.constant_volume);
+        w.write_bool(self.sweep.enabled);
+        w.write_bool(self.sweep.negate);
+        w.write_u16(self.timer_period);
+        w.write_u8(self.length_counter);
+        w.write_bool(self.enabled);
+        w.write_u16(self.timer);
+        w.write_u8(self.step);
+        self.envelope.save_state(w);
+        w.write_u8(self.sweep_divider);
+        w.write_bool(self.sweep_reload);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut fields = [0u8; 4];
+        r.read_into(&mut fields)?;
+        let [duty, volume, period, shift] = fields;
+        self.duty = duty & 0b11;
+        self.volume = volume & 0x0F;
+        self.sweep.period = period & 0b111;
+        self.sweep.shift = shift & 0b111;
+        self.length_halt = r.read_bool()?;
+        self.constant_volume = r.read_bool()?;
+        self.sweep.enabled = r.read_bool()?;
+        self.sweep.negate = r.read_bool()?;
+        self.timer_period = r.read_u16()? & 0x07FF;
+        self.length_counter = r.read_u8()?;
+        self.enabled = r.read_bool()?;
+        self.timer = r.read_u16()? & 0x07FF;
+        self.step = r.read_u8()? % 8;
+        self.envelope.load_state(r)?;
+        self.sweep_divider = r.read_u8()? & 0b111;
+        self.sweep_reload = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Savestate for Triangle {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.length_halt);
+        w.write_u8(self.linear_reload);
+        w.write_u16(self.timer_period);
+        w.write_u8(self.length_counter);
+        w.write_bool(self.enabled);
+        w.write_u16(self.timer);
+        w.write_u8(self.step);
+        w.write_u8(self.linear_counter);
+        w.write_bool(self.reload_linear);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.length_halt = r.read_bool()?;
+        self.linear_reload = r.read_u8()? & 0x7F;
+        self.timer_period = r.read_u16()? & 0x07FF;
+        self.length_counter = r.read_u8()?;
+        self.enabled = r.read_bool()?;
+        self.timer = r.read_u16()? & 0x07FF;
+        self.step = r.read_u8()? % 32;
+        self.linear_counter = r.read_u8()? & 0x7F;
+        self.reload_linear = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Savestate for Noise {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.length_halt);
+        w.write_bool(self.constant_volume);
+        w.write_u8(self.volume);
+        w.write_bool(self.short_mode);
+        w.write_u8(self.period);
+        w.write_u8(self.length_counter);
+        w.write_bool(self.enabled);
+        w.write_u16(self.timer);
+        w.write_u16(self.shift_register);
+        self.envelope.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.length_halt = r.read_bool()?;
+        self.constant_volume = r.read_bool()?;
+        self.volume = r.read_u8()? & 0x0F;
+        self.short_mode = r.read_bool()?;
+        self.period = r.read_u8()? & 0x0F;
+        self.length_counter = r.read_u8()?;
+        self.enabled = r.read_bool()?;
+        self.timer = r.read_u16()?.min(self.periods[15] - 1);
+        self.shift_register = (r.read_u16()? & 0x7FFF).max(1);
+        self.envelope.load_state(r)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+// Views of the APU for audio debugging: what each channel is set to play, decoded into
+// frequencies and levels, and an oscilloscope of each channel's recent output. The scope costs
+// a little every cycle, so it only runs once it's been switched on with set_waveform_capture.
+
+use super::{Apu, Channel};
+
+// samples of history kept per channel
+pub const WAVEFORM_LEN: usize = 1024;
+// CPU cycles between scope samples, a little over 44 kHz on NTSC
+pub const WAVEFORM_INTERVAL: u32 = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelInfo {
+    pub channel: Channel,
+    // $4015's bit for the channel
+    pub enabled: bool,
+    // the length counter hasn't run out, or for the DMC a sample is still being read
+    pub playing: bool,
+    // the timer period in CPU cycles for the noise and DMC, as written for the others
+    pub period: u16,
+    // the note for the pulses and triangle, how often the LFSR shifts for the noise and the
+    // bit rate for the DMC
+    pub frequency: f32,
+    // the constant volume or the envelope, 0-15; the triangle and DMC have neither
+    pub volume: Option<u8>,
+    // the pulses' duty cycle, 0-3 for 12.5%, 25%, 50% and 25% negated
+    pub duty: Option<u8>,
+    // the level going into the DAC right now, 0-15 or 0-127 for the DMC
+    pub output: u8,
+}
+
+pub(super) struct Scope {
+    levels: [[u8; WAVEFORM_LEN]; 5],
+    // where the next sample goes, which is also the oldest
+    next: usize,
+    divider: u32,
+}
+
+impl Scope {
+    pub(super) fn new() -> Box<Self> {
+        Box::new(Scope { levels: [[0; WAVEFORM_LEN]; 5], next: 0, divider: 0 })
+    }
+}
+
+impl Apu {
+    // `clock_hz` is the CPU's, Region::cpu_clock_hz
+    pub fn channel_info(&self, channel: Channel, clock_hz: u32) -> ChannelInfo {
+        let clock_hz = clock_hz as f32;
+        match channel {
+            Channel::Pulse1 | Channel::Pulse2 => {
+                let pulse = &self.pulse[channel as usize];
+                ChannelInfo {
+                    channel,
+                    enabled: pulse.enabled,
+                    playing: pulse.length_counter > 0,
+                    period: pulse.timer_period,
+                    frequency: clock_hz / (16.0 * (pulse.timer_period as f32 + 1.0)),
+                    volume: Some(pulse.current_volume()),
+                    duty: Some(pulse.duty),
+                    output: pulse.output(),
+                }
+            }
+            Channel::Triangle => ChannelInfo {
+                channel,
+                enabled: self.triangle.enabled,
+                playing: self.triangle.length_counter > 0 && self.triangle.linear_counter() > 0,
+                period: self.triangle.timer_period,
+                frequency: clock_hz / (32.0 * (self.triangle.timer_period as f32 + 1.0)),
+                volume: None,
+                duty: None,
+                output: self.triangle.output(),
+            },
+            Channel::Noise => ChannelInfo {
+                channel,
+                enabled: self.noise.enabled,
+                playing: self.noise.length_counter > 0,
+                period: self.noise.timer_period(),
+                frequency: clock_hz / self.noise.timer_period() as f32,
+                volume: Some(self.noise.current_volume()),
+                duty: None,
+                output: self.noise.output(),
+            },
+            Channel::Dmc => ChannelInfo {
+                channel,
+                enabled: self.dmc.enabled,
+                playing: self.dmc.bytes_remaining > 0,
+                period: self.dmc.timer_period(),
+                frequency: clock_hz / self.dmc.timer_period() as f32,
+                volume: None,
+                duty: None,
+                output: self.dmc.output,
+            },
+        }
+    }
+
+    pub fn channels_info(&self, clock_hz: u32) -> [ChannelInfo; 5] {
+        Channel::ALL.map(|channel| self.channel_info(channel, clock_hz))
+    }
+
+    // Starts or stops recording the channels' levels every WAVEFORM_INTERVAL cycles. Starting
+    // clears whatever was recorded before.
+    pub fn set_waveform_capture(&mut self, enabled: bool) {
+        self.scope = enabled.then(Scope::new);
+    }
+
+    pub fn waveform_capture(&self) -> bool {
+        self.scope.is_some()
+    }
+
+    // The last WAVEFORM_LEN levels of `channel`, oldest first, as ChannelInfo::output has
+    // them. Empty when the capture is off.
+    pub fn waveform(&self, channel: Channel) -> Vec<u8> {
+        let Some(scope) = &self.scope else {
+            return Vec::new();
+        };
+        let levels = &scope.levels[channel as usize];
+        levels[scope.next..].iter().chain(&levels[..scope.next]).copied().collect()
+    }
+
+    // every cycle while capturing
+    pub(super) fn tick_scope(&mut self) {
+        let Some(scope) = &mut self.scope else {
+            return;
+        };
+        if scope.divider > 0 {
+            scope.divider -= 1;
+            return;
+        }
+        scope.divider = WAVEFORM_INTERVAL - 1;
+        let levels = [
+            self.pulse[0].output(),
+            self.pulse[1].output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output,
+        ];
+        for (history, level) in scope.levels.iter_mut().zip(levels) {
+            history[scope.next] = level;
+        }
+        scope.next = (scope.next + 1) % WAVEFORM_LEN;
+    }
+}
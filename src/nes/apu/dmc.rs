@@ -0,0 +1,216 @@
+// Delta modulation channel, $4010-$4013. A memory reader fetches sample bytes from $8000-$FFFF
+// one at a time into a one byte buffer; the fetch is a DMA that stalls the CPU, so the APU only
+// asks for it and the bus passes the request to the CPU, which runs it and hands the byte back
+// (Bus::cpu_cycle, Bus::fill_dmc_sample). The output unit shifts each byte out a bit at a time,
+// moving the 7 bit output level up or down by 2 per bit.
+
+use crate::nes::cart::Region;
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+// timer periods in CPU cycles, indexed by $4010's rate; Dendy uses the NTSC ones
+const NTSC_RATE_TABLE: [u16; 16] =
+    [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+const PAL_RATE_TABLE: [u16; 16] =
+    [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dmc {
+    pub irq_enabled: bool,
+    pub loop_flag: bool,
+    // index into the rate table
+    pub rate: u8,
+    // the 7 bit output level, set directly by $4011
+    pub output: u8,
+    pub sample_addr: u16,
+    pub sample_length: u16,
+    pub enabled: bool,
+    // where the memory reader is in the current sample
+    pub current_addr: u16,
+    pub bytes_remaining: u16,
+    // raised when a sample ends without looping and IRQs are enabled
+    pub irq_flag: bool,
+    timer: u16,
+    shift_register: u8,
+    bits_remaining: u8,
+    // the output unit had no byte to play, the level holds
+    silence: bool,
+    sample_buffer: Option<u8>,
+    // a fetch has been handed to the CPU and not filled yet
+    fetching: bool,
+    // the region's rate table, configuration rather than state
+    rate_table: &'static [u16; 16],
+}
+
+// what $4010-$4013 all at 0 decode to
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc {
+            irq_enabled: false,
+            loop_flag: false,
+            rate: 0,
+            output: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            enabled: false,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            irq_flag: false,
+            timer: NTSC_RATE_TABLE[0] - 1,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            sample_buffer: None,
+            fetching: false,
+            rate_table: &NTSC_RATE_TABLE,
+        }
+    }
+}
+
+impl Dmc {
+    pub(super) fn set_region(&mut self, region: Region) {
+        self.rate_table = match region {
+            Region::Pal => &PAL_RATE_TABLE,
+            Region::Ntsc | Region::Dendy => &NTSC_RATE_TABLE,
+        };
+    }
+
+    pub(super) fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.loop_flag = data & 0x40 != 0;
+                self.rate = data & 0x0F;
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+            }
+            1 => self.output = data & 0x7F,
+            // samples start at $C000 + 64 * A and are 16 * L + 1 bytes long
+            2 => self.sample_addr = 0xC000 | (data as u16) << 6,
+            _ => self.sample_length = (data as u16) << 4 | 1,
+        }
+    }
+
+    // $4015 bit 4: clearing it stops the sample after the byte in the buffer, setting it
+    // starts the sample over unless one is still playing
+    pub(super) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    // CPU cycles between output bits
+    pub fn timer_period(&self) -> u16 {
+        self.rate_table[self.rate as usize]
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // one CPU cycle
+    pub(super) fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate_table[self.rate as usize] - 1;
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output <= 125 {
+                    self.output += 2;
+                }
+            } else if self.output >= 2 {
+                self.output -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    // the address to fetch when the buffer is empty and the sample isn't over, once per fetch
+    pub(super) fn take_request(&mut self) -> Option<u16> {
+        if self.sample_buffer.is_some() || self.bytes_remaining == 0 || self.fetching {
+            return None;
+        }
+        self.fetching = true;
+        Some(self.current_addr)
+    }
+
+    // The byte the CPU fetched. The address wraps from $FFFF to $8000; the last byte of a
+    // sample restarts it when looping and raises the IRQ otherwise.
+    pub(super) fn fill(&mut self, sample: u8) {
+        self.fetching = false;
+        self.sample_buffer = Some(sample);
+        self.current_addr = self.current_addr.checked_add(1).unwrap_or(0x8000);
+        if self.bytes_remaining == 0 {
+            return;
+        }
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+}
+
+impl Savestate for Dmc {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.loop_flag);
+        w.write_u8(self.rate);
+        w.write_u8(self.output);
+        w.write_u16(self.sample_addr);
+        w.write_u16(self.sample_length);
+        w.write_bool(self.enabled);
+        w.write_u16(self.current_addr);
+        w.write_u16(self.bytes_remaining);
+        w.write_bool(self.irq_flag);
+        w.write_u16(self.timer);
+        w.write_u8(self.shift_register);
+        w.write_u8(self.bits_remaining);
+        w.write_bool(self.silence);
+        w.write_bool(self.sample_buffer.is_some());
+        w.write_u8(self.sample_buffer.unwrap_or(0));
+        w.write_bool(self.fetching);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = r.read_bool()?;
+        self.loop_flag = r.read_bool()?;
+        self.rate = r.read_u8()? & 0x0F;
+        self.output = r.read_u8()? & 0x7F;
+        self.sample_addr = r.read_u16()? | 0xC000;
+        self.sample_length = r.read_u16()?;
+        self.enabled = r.read_bool()?;
+        self.current_addr = r.read_u16()? | 0x8000;
+        self.bytes_remaining = r.read_u16()?;
+        self.irq_flag = r.read_bool()?;
+        self.timer = r.read_u16()?.min(self.rate_table[0] - 1);
+        self.shift_register = r.read_u8()?;
+        self.bits_remaining = r.read_u8()?.clamp(1, 8);
+        self.silence = r.read_bool()?;
+        let buffered = r.read_bool()?;
+        let sample = r.read_u8()?;
+        self.sample_buffer = buffered.then_some(sample);
+        self.fetching = r.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+// Getting the mixed APU and expansion output, one value per CPU cycle, down to a sound card
+// rate. The Bus resamples it as it ticks and keeps the result in a ring buffer for the frontend
+// to drain, or hands it to an AudioSink in batches; none of this knows about SDL. When the
+// frontend falls behind, the oldest samples in the ring are dropped so latency can't build up.
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+// about 185 ms at 44.1 kHz
+pub const DEFAULT_RING_CAPACITY: usize = 8192;
+// samples an AudioSink gets at a time, short of a flush
+pub const SINK_BATCH: usize = 512;
+
+// Receives mono samples at the Bus's sample rate, see Bus::set_audio_sink. Batches hold
+// SINK_BATCH samples except the one Bus::flush_audio sends.
+pub trait AudioSink {
+    fn samples(&mut self, samples: &[f32]);
+}
+
+impl<F: FnMut(&[f32])> AudioSink for F {
+    fn samples(&mut self, samples: &[f32]) {
+        self(samples)
+    }
+}
+
+pub struct SampleRing {
+    samples: Box<[f32]>,
+    // index of the oldest sample
+    start: usize,
+    len: usize,
+    // samples pushed over a full ring
+    overruns: u64,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> Self {
+        SampleRing {
+            samples: vec![0.0; capacity.max(1)].into_boxed_slice(),
+            start: 0,
+            len: 0,
+            overruns: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    // a full ring overwrites its oldest sample
+    pub fn push(&mut self, sample: f32) {
+        let capacity = self.capacity();
+        self.samples[(self.start + self.len) % capacity] = sample;
+        if self.len == capacity {
+            self.start = (self.start + 1) % capacity;
+            self.overruns += 1;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    // moves the oldest samples into `out`, returning how many there were
+    pub fn pop_into(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.len);
+        for sample in &mut out[..count] {
+            *sample = self.samples[self.start];
+            self.start = (self.start + 1) % self.capacity();
+        }
+        self.len -= count;
+        count
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
+// Band-limited resampling, the way a blip buffer does it. The input only matters where it
+// changes, so each change is spread over the next TAPS output samples as a windowed sinc
+// impulse, offset by where between two output samples it happened, and the output integrates
+// those impulses back into steps. Nothing above the output rate's Nyquist frequency gets
+// through to alias, unlike picking or averaging every Nth input. The output lags the input by
+// TAPS / 2 samples. Rates are kept as integers so the output doesn't drift from the CPU clock.
+const TAPS: usize = 16;
+// sub-sample positions the impulse is precomputed at
+const PHASES: usize = 64;
+// passband edge as a fraction of the output rate, a little under Nyquist
+const CUTOFF: f64 = 0.45;
+
+pub struct Resampler {
+    clock_hz: u64,
+    sample_rate: u64,
+    // input samples seen times the output rate, less what's been output
+    phase: u64,
+    level: f32,
+    // what's been integrated so far, in f64 so rounding doesn't drift over a long session
+    output: f64,
+    // impulses still to be added to the next TAPS output samples
+    pending: [f64; TAPS],
+    // one normalized impulse per phase
+    kernels: Box<[[f64; TAPS]; PHASES]>,
+}
+
+impl Resampler {
+    pub fn new(clock_hz: u32, sample_rate: u32) -> Self {
+        Resampler {
+            clock_hz: clock_hz as u64,
+            sample_rate: sample_rate.max(1) as u64,
+            phase: 0,
+            level: 0.0,
+            output: 0.0,
+            pending: [0.0; TAPS],
+            kernels: Resampler::kernels(),
+        }
+    }
+
+    // Blackman windowed sinc, for a step `phase / PHASES` of a sample before the next output
+    fn kernels() -> Box<[[f64; TAPS]; PHASES]> {
+        use std::f64::consts::PI;
+        let mut kernels = Box::new([[0.0; TAPS]; PHASES]);
+        for (phase, kernel) in kernels.iter_mut().enumerate() {
+            let offset = 1.0 - phase as f64 / PHASES as f64;
+            for (tap, weight) in kernel.iter_mut().enumerate() {
+                let t = tap as f64 - (TAPS / 2) as f64 + offset;
+                let x = 2.0 * CUTOFF * t;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                let w = (t + (TAPS / 2) as f64) / TAPS as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+                *weight = sinc * window.max(0.0);
+            }
+            let sum: f64 = kernel.iter().sum();
+            for weight in kernel.iter_mut() {
+                *weight /= sum;
+            }
+        }
+        kernels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    // one input sample, and the output sample it completes if any
+    pub fn push(&mut self, input: f32) -> Option<f32> {
+        if input != self.level {
+            let delta = (input - self.level) as f64;
+            self.level = input;
+            let phase = (self.phase * PHASES as u64 / self.clock_hz) as usize;
+            for (pending, weight) in self.pending.iter_mut().zip(&self.kernels[phase]) {
+                *pending += delta * weight;
+            }
+        }
+        self.phase += self.sample_rate;
+        if self.phase < self.clock_hz {
+            return None;
+        }
+        self.phase -= self.clock_hz;
+        self.output += self.pending[0];
+        self.pending.copy_within(1.., 0);
+        self.pending[TAPS - 1] = 0.0;
+        Some(self.output as f32)
+    }
+}
+
+// The console's own output stage: two high-passes, at 90 Hz and 440 Hz, that take out the
+// DC the DACs sit on, then a 14 kHz low-pass. First order each, run at the output rate on the
+// mix; without them the output is the raw 0.0-1.0 DAC level.
+pub struct OutputFilter {
+    high_passes: [HighPass; 2],
+    low_pass: LowPass,
+}
+
+impl OutputFilter {
+    pub fn new(sample_rate: u32) -> Self {
+        OutputFilter {
+            high_passes: [HighPass::new(90.0, sample_rate), HighPass::new(440.0, sample_rate)],
+            low_pass: LowPass::new(14_000.0, sample_rate),
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let sample = self.high_passes.iter_mut().fold(sample, |sample, hp| hp.process(sample));
+        self.low_pass.process(sample)
+    }
+}
+
+// 1 / 2 pi f, for the RC filters below
+fn time_constant(cutoff_hz: f32) -> f32 {
+    1.0 / (2.0 * std::f32::consts::PI * cutoff_hz)
+}
+
+struct HighPass {
+    alpha: f32,
+    last_input: f32,
+    last_output: f32,
+}
+
+impl HighPass {
+    fn new(cutoff_hz: f32, sample_rate: u32) -> Self {
+        let rc = time_constant(cutoff_hz);
+        let dt = 1.0 / sample_rate.max(1) as f32;
+        HighPass { alpha: rc / (rc + dt), last_input: 0.0, last_output: 0.0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.last_output = self.alpha * (self.last_output + input - self.last_input);
+        self.last_input = input;
+        self.last_output
+    }
+}
+
+struct LowPass {
+    alpha: f32,
+    last_output: f32,
+}
+
+impl LowPass {
+    fn new(cutoff_hz: f32, sample_rate: u32) -> Self {
+        let rc = time_constant(cutoff_hz);
+        let dt = 1.0 / sample_rate.max(1) as f32;
+        LowPass { alpha: dt / (rc + dt), last_output: 0.0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.last_output += self.alpha * (input - self.last_output);
+        self.last_output
+    }
+}
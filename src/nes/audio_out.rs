@@ -0,0 +1,100 @@
+// SDL audio for the frontend. Samples are moved from the Bus's ring buffer into an SDL
+// AudioQueue, which plays them on SDL's own thread. The queue is kept under a latency cap by
+// dropping samples when the emulator runs ahead of the sound card; when it runs behind and the
+// queue empties, the last level is held for a while to cover the gap without a click.
+
+use super::audio::SampleRing;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+use std::time::Duration;
+
+pub struct AudioOutput {
+    queue: AudioQueue<f32>,
+    sample_rate: u32,
+    // the most samples the queue may hold
+    max_queued: u32,
+    scratch: Vec<f32>,
+    last_sample: f32,
+    started: bool,
+    underruns: u64,
+    overruns: u64,
+}
+
+impl AudioOutput {
+    // mono f32 at `sample_rate`, or whatever rate the device insists on
+    pub fn open(
+        audio: &AudioSubsystem,
+        sample_rate: u32,
+        latency: Duration,
+    ) -> Result<AudioOutput, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(sample_rate as i32),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+        let queue = audio.open_queue::<f32, _>(None, &desired)?;
+        let sample_rate = queue.spec().freq as u32;
+        queue.resume();
+        Ok(AudioOutput {
+            queue,
+            sample_rate,
+            max_queued: (sample_rate as f64 * latency.as_secs_f64()) as u32,
+            scratch: Vec::new(),
+            last_sample: 0.0,
+            started: false,
+            underruns: 0,
+            overruns: 0,
+        })
+    }
+
+    // what the Bus should be set to with Bus::set_sample_rate
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // samples waiting to be played
+    pub fn queued(&self) -> u32 {
+        self.queue.size() / size_of::<f32>() as u32
+    }
+
+    // times the queue ran dry
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    // times samples were dropped to keep the latency down
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    // Queues everything waiting in `ring`, once a frame or so.
+    pub fn feed(&mut self, ring: &mut SampleRing) -> Result<(), String> {
+        let queued = self.queued();
+        if self.started && queued == 0 {
+            self.underruns += 1;
+            let gap = vec![self.last_sample; self.max_queued as usize / 2];
+            self.queue.queue_audio(&gap)?;
+        }
+        self.scratch.resize(ring.len(), 0.0);
+        let count = ring.pop_into(&mut self.scratch);
+        let room = self.max_queued.saturating_sub(self.queued()) as usize;
+        // keep the newest samples, the ones that go with what's on screen
+        let skip = count.saturating_sub(room);
+        if skip > 0 {
+            self.overruns += 1;
+        }
+        let samples = &self.scratch[skip..count];
+        if let Some(&last) = samples.last() {
+            self.queue.queue_audio(samples)?;
+            self.last_sample = last;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    // drops everything queued, for when the emulator pauses or seeks
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.started = false;
+    }
+}
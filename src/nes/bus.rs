@@ -0,0 +1,713 @@
+use super::apu::Apu;
+use super::audio::{
+    AudioSink, OutputFilter, Resampler, SampleRing, DEFAULT_RING_CAPACITY, DEFAULT_SAMPLE_RATE,
+    SINK_BATCH,
+};
+use super::cart::{Cart, Region};
+use super::controller::Controller;
+use super::device::{InputDevice, DEVICE_BITS};
+use super::mapper::{self, Mapper};
+use super::mem::{CpuLines, Memory, Peek, Read, Write};
+use super::ppu::{Ppu, PpuBackend};
+use super::ppu_bus::{A12Filter, PpuBus};
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+use super::wav::Recording;
+use std::io;
+use std::path::Path;
+
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// | Upper Bank    |       |               |
+// |_ _ _ _ _ _ _ _| $C000 | PRG-ROM       |
+// | PRG-ROM       |       |               |
+// | Lower Bank    |       |               |
+// |_______________| $8000 |_______________|
+// | SRAM          |       | SRAM          |
+// |_______________| $6000 |_______________|
+// | Expansion ROM |       | Expansion ROM |
+// |_______________| $4020 |_______________|
+// | I/O Registers |       |               |
+// |_ _ _ _ _ _ _ _| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_ _ _ _ _ _ _ _| $2008 |               |
+// | I/O Registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_ _ _ _ _ _ _ _| $0800 |               |
+// | RAM           |       | RAM           |
+// |_ _ _ _ _ _ _ _| $0200 |               |
+// | Stack         |       |               |
+// |_ _ _ _ _ _ _ _| $0100 |               |
+// | Zero Page     |       |               |
+// |_______________| $0000 |_______________|
+
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_IO_REGISTERS: u16 = 0x4000;
+const APU_IO_REGISTERS_END: u16 = 0x4017;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD1: u16 = 0x4016;
+const JOYPAD2: u16 = 0x4017;
+const EXPANSION: u16 = 0x4020;
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7FFF;
+const PRG_ROM: u16 = 0x8000;
+const PRG_ROM_END: u16 = 0xFFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnmappedAccessPolicy {
+    Ignore,
+    // log::warn! with the address and the PC of the instruction doing it
+    Warn,
+    Panic,
+}
+
+pub struct Bus {
+    cpu_vram: Memory<[u8; 2048]>,
+    // nametable RAM, on the PPU's bus
+    ciram: Memory<[u8; 2048]>,
+    a12: A12Filter,
+    ppu: Ppu,
+    apu: Apu,
+    // the APU and expansion audio brought down to the output rate, waiting for the frontend
+    resampler: Resampler,
+    // None plays the DAC level unfiltered
+    filter: Option<OutputFilter>,
+    samples: SampleRing,
+    // takes the samples instead of the ring when set, with the batch it's due next
+    audio_sink: Option<Box<dyn AudioSink>>,
+    sink_batch: Vec<f32>,
+    recording: Option<Box<Recording>>,
+    // CPU cycles the rest of the system has been clocked for
+    cycles: u64,
+    cart: Option<Cart>,
+    // board registers for the cart, always Some when there's a cart
+    mapper: Option<Box<dyn Mapper>>,
+    prg_ram_enabled: bool,
+    // last values written to $4000-$4017
+    apu_io: [u8; 0x18],
+    controllers: [Controller; 2],
+    // on the expansion port, or in a controller port instead of the standard controller
+    device: Option<Box<dyn InputDevice>>,
+    // The joypad port the last access read and the bits it drove, while its /OE is still low.
+    // Back to back reads of a port are one long pulse to the controller, so they see the same
+    // bit and only clock its shift register once.
+    joypad_oe: Option<(u16, u8)>,
+    // last value driven on the data bus, what unmapped reads see
+    open_bus: u8,
+    unmapped_access_policy: UnmappedAccessPolicy,
+    // opcode address of the instruction being executed
+    pc: u16,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            cpu_vram: Memory::new([0u8; 2048], true),
+            ciram: Memory::new([0u8; 2048], true),
+            a12: A12Filter::default(),
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            resampler: Resampler::new(Region::Ntsc.cpu_clock_hz(), DEFAULT_SAMPLE_RATE),
+            filter: Some(OutputFilter::new(DEFAULT_SAMPLE_RATE)),
+            samples: SampleRing::new(DEFAULT_RING_CAPACITY),
+            audio_sink: None,
+            sink_batch: Vec::with_capacity(SINK_BATCH),
+            recording: None,
+            cycles: 0,
+            cart: None,
+            mapper: None,
+            prg_ram_enabled: true,
+            apu_io: [0u8; 0x18],
+            controllers: [Controller::new(); 2],
+            device: None,
+            joypad_oe: None,
+            open_bus: 0,
+            unmapped_access_policy: UnmappedAccessPolicy::Warn,
+            pc: 0,
+        }
+    }
+
+    pub fn with_cart(cart: Cart) -> Self {
+        let mapper =
+            mapper::new(cart.mapper).expect("Cart::from_bytes only accepts supported mappers");
+        Bus::with_mapper(cart, mapper)
+    }
+
+    // for boards the header can't describe, like an MMC3 with the alternate IRQ behaviour
+    pub fn with_mapper(cart: Cart, mapper: Box<dyn Mapper>) -> Self {
+        let mut ppu = Ppu::new();
+        ppu.set_region(cart.region);
+        let mut apu = Apu::new();
+        apu.set_region(cart.region);
+        Bus {
+            ppu,
+            apu,
+            resampler: Resampler::new(cart.region.cpu_clock_hz(), DEFAULT_SAMPLE_RATE),
+            mapper: Some(mapper),
+            cart: Some(cart),
+            ..Bus::new()
+        }
+    }
+
+    // Mappers like MMC1 and MMC3 can switch the RAM off, it then reads as open bus and
+    // ignores writes
+    pub fn set_prg_ram_enabled(&mut self, enabled: bool) {
+        self.prg_ram_enabled = enabled;
+    }
+
+    pub fn prg_ram(&self) -> &[u8] {
+        self.cart.as_ref().map_or(&[], |cart| cart.prg_ram.data())
+    }
+
+    // restores a battery save, a shorter save only fills the start of the RAM
+    pub fn load_prg_ram(&mut self, data: &[u8]) {
+        let Some(cart) = &mut self.cart else {
+            return;
+        };
+        let len = data.len().min(cart.prg_ram.len());
+        cart.prg_ram.data_mut()[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn apu_io_register(&self, addr: u16) -> u8 {
+        self.apu_io[(addr - APU_IO_REGISTERS) as usize]
+    }
+
+    // port 0 is read at $4016, port 1 at $4017
+    pub fn controller(&self, port: usize) -> &Controller {
+        &self.controllers[port]
+    }
+
+    pub fn controller_mut(&mut self, port: usize) -> &mut Controller {
+        &mut self.controllers[port]
+    }
+
+    // None unplugs the device, and gives a controller port it took over back to the standard
+    // controller
+    pub fn set_device(&mut self, device: Option<Box<dyn InputDevice>>) {
+        self.device = device;
+    }
+
+    pub fn device(&self) -> Option<&dyn InputDevice> {
+        self.device.as_deref()
+    }
+
+    pub fn device_mut(&mut self) -> Option<&mut (dyn InputDevice + 'static)> {
+        self.device.as_deref_mut()
+    }
+
+    fn controller_plugged(&self, port: usize) -> bool {
+        self.device.as_ref().is_none_or(|device| device.port() != Some(port))
+    }
+
+    // A DMC DMA that halts the CPU on a joypad read repeats the read until the sample fetch,
+    // which reads elsewhere and ends the pulse; the CPU's own read then clocks the port a
+    // second time and the bits the halted read saw are lost.
+    fn read_joypad(&mut self, addr: u16, held: Option<(u16, u8)>) -> u8 {
+        let port = (addr - JOYPAD1) as usize;
+        let bits = match held {
+            Some((held_addr, bits)) if held_addr == addr => bits,
+            _ => {
+                let plugged = self.controller_plugged(port);
+                let controller = if plugged { self.controllers[port].read() } else { 0 };
+                let device = self.device.as_mut().map_or(0, |device| device.read(port));
+                controller | (device & DEVICE_BITS)
+            }
+        };
+        self.joypad_oe = Some((addr, bits));
+        bits | (self.open_bus & 0b1110_0000)
+    }
+
+    fn peek_joypad(&self, addr: u16) -> u8 {
+        let port = (addr - JOYPAD1) as usize;
+        let plugged = self.controller_plugged(port);
+        let controller = if plugged { self.controllers[port].peek() } else { 0 };
+        let device = self.device.as_ref().map_or(0, |device| device.peek(port));
+        controller | (device & DEVICE_BITS) | (self.open_bus & 0b1110_0000)
+    }
+
+    // Most of the block is write only. $4015 reads back channel status with bit 5 left
+    // floating, and the joypad ports only drive their low bits.
+    fn read_apu_io(&self, addr: u16) -> u8 {
+        match addr {
+            APU_STATUS => self.apu.peek(addr) | (self.open_bus & 0b0010_0000),
+            JOYPAD1 | JOYPAD2 => self.peek_joypad(addr),
+            _ => self.open_bus,
+        }
+    }
+
+    fn prg_ram_mapped(&self) -> bool {
+        let board_enabled = self.mapper.as_ref().is_none_or(|mapper| mapper.prg_ram_enabled());
+        self.prg_ram_enabled && board_enabled && !self.prg_ram().is_empty()
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        match &self.cart {
+            Some(cart) if self.prg_ram_mapped() => cart.prg_ram.peek(addr - PRG_RAM),
+            _ => self.open_bus,
+        }
+    }
+
+    // Clocks the PPU and APU, three dots (3.2 on PAL) and one APU clock per CPU cycle. A CPU on
+    // the bus does this itself at the start of each of its cycles, through cpu_cycle; this is
+    // for running the rest of the system while the CPU isn't.
+    pub fn tick(&mut self, cpu_cycles: u64) {
+        for _ in 0..cpu_cycles {
+            self.cycles += 1;
+            for _ in 0..self.ppu_dots_this_cycle() {
+                self.a12.tick();
+                let (ppu, mut ppu_bus) = self.ppu_and_bus();
+                ppu.tick(&mut ppu_bus);
+            }
+            self.apu.tick();
+            if let Some(mapper) = &mut self.mapper {
+                mapper.cpu_clock();
+            }
+            let output = self.apu.output() + self.expansion_audio();
+            if let Some(sample) = self.resampler.push(output) {
+                self.output_sample(sample);
+            }
+            if let Some(recording) = &mut self.recording {
+                recording.tick_stems(&self.apu);
+            }
+        }
+    }
+
+    fn output_sample(&mut self, sample: f32) {
+        let sample = match &mut self.filter {
+            Some(filter) => filter.process(sample),
+            None => sample,
+        };
+        if let Some(recording) = &mut self.recording {
+            recording.write_mix(sample);
+        }
+        let Some(sink) = &mut self.audio_sink else {
+            self.samples.push(sample);
+            return;
+        };
+        self.sink_batch.push(sample);
+        if self.sink_batch.len() == SINK_BATCH {
+            sink.samples(&self.sink_batch);
+            self.sink_batch.clear();
+        }
+    }
+
+    // Sends audio to `sink` rather than the ring, for embedders with their own output.
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio_sink = Some(sink);
+        self.sink_batch.clear();
+    }
+
+    // goes back to the ring, dropping a partial batch
+    pub fn clear_audio_sink(&mut self) {
+        self.audio_sink = None;
+        self.sink_batch.clear();
+    }
+
+    // Starts writing what's played to a WAV file at `path`, and with `stems` a file for each
+    // APU channel next to it. A recording already going is stopped first.
+    pub fn start_recording(&mut self, path: impl AsRef<Path>, stems: bool) -> io::Result<()> {
+        self.stop_recording()?;
+        let clock_hz = self.region().cpu_clock_hz();
+        let recording = Recording::start(path.as_ref(), self.sample_rate(), clock_hz, stems)?;
+        self.recording = Some(Box::new(recording));
+        Ok(())
+    }
+
+    // finishes the files, with the first error hit while writing them if there was one
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recording.take() {
+            Some(recording) => recording.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // hands the sink what's built up of the next batch, e.g. at the end of a frame
+    pub fn flush_audio(&mut self) {
+        let Some(sink) = &mut self.audio_sink else {
+            return;
+        };
+        if !self.sink_batch.is_empty() {
+            sink.samples(&self.sink_batch);
+            self.sink_batch.clear();
+        }
+    }
+
+    // the rate the frontend's audio device plays at; drops whatever was waiting
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler = Resampler::new(self.region().cpu_clock_hz(), sample_rate);
+        if self.filter.is_some() {
+            self.filter = Some(OutputFilter::new(sample_rate));
+        }
+        self.samples.clear();
+    }
+
+    // the console's high-pass and low-pass output filters, on by default
+    pub fn set_output_filter(&mut self, enabled: bool) {
+        self.filter = enabled.then(|| OutputFilter::new(self.sample_rate()));
+    }
+
+    pub fn output_filter_enabled(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    // audio at the output rate, oldest first, for the frontend to drain
+    pub fn samples_mut(&mut self) -> &mut SampleRing {
+        &mut self.samples
+    }
+
+    // PAL runs 16 dots to every 5 CPU cycles, one cycle in five gets the extra dot
+    fn ppu_dots_this_cycle(&self) -> u64 {
+        match self.ppu.region() {
+            Region::Pal => self.cycles * 16 / 5 - (self.cycles - 1) * 16 / 5,
+            Region::Ntsc | Region::Dendy => 3,
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    // for the channel volume, mute and solo controls
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn cart(&self) -> Option<&Cart> {
+        self.cart.as_ref()
+    }
+
+    // the PPU's side of the system: cart CHR through the mapper and the nametables
+    pub fn ppu_bus(&mut self) -> PpuBus<'_> {
+        self.ppu_and_bus().1
+    }
+
+    // the PPU next to its bus, for the debug views that peek at CHR and the nametables
+    pub fn ppu_view(&mut self) -> (&Ppu, PpuBus<'_>) {
+        let (ppu, ppu_bus) = self.ppu_and_bus();
+        (ppu, ppu_bus)
+    }
+
+    // the PPU along with its bus, which borrows the rest of the system
+    fn ppu_and_bus(&mut self) -> (&mut Ppu, PpuBus<'_>) {
+        let mapper = self.mapper.as_deref_mut().map(|mapper| mapper as &mut dyn Mapper);
+        let ppu_bus = PpuBus::new(&mut self.ciram, self.cart.as_mut(), mapper, &mut self.a12);
+        (&mut self.ppu, ppu_bus)
+    }
+
+    // the cart's region, for picking CPU, PPU and APU timing; NTSC without a cart
+    pub fn region(&self) -> Region {
+        self.cart.as_ref().map_or(Region::Ntsc, |cart| cart.region)
+    }
+
+    // an NMI from the PPU, for the driver to pass on with Cpu::trigger_nmi
+    pub fn take_nmi(&mut self) -> bool {
+        self.ppu.take_nmi()
+    }
+
+    pub fn take_frame_ready(&mut self) -> bool {
+        self.ppu.take_frame_ready()
+    }
+
+    // the cart's and the APU's IRQ outputs, for the driver to pass on with Cpu::set_irq
+    pub fn irq(&self) -> bool {
+        self.mapper.as_ref().is_some_and(|mapper| mapper.irq()) || self.apu.irq()
+    }
+
+    // a DMC sample fetch, for the driver to pass on with Cpu::start_dmc_dma
+    pub fn take_dmc_request(&mut self) -> Option<u16> {
+        self.apu.take_dmc_request()
+    }
+
+    // the byte from Cpu::take_dmc_sample
+    pub fn fill_dmc_sample(&mut self, sample: u8) {
+        self.apu.fill_dmc_sample(sample);
+    }
+
+    // expansion audio from the cart, to be mixed with the APU
+    pub fn expansion_audio(&self) -> f32 {
+        self.mapper.as_ref().map_or(0.0, |mapper| mapper.audio_output())
+    }
+
+    fn mapper_read(&mut self, addr: u16) -> Option<u8> {
+        let (Some(cart), Some(mapper)) = (&self.cart, &mut self.mapper) else {
+            return None;
+        };
+        mapper.cpu_read(cart, addr)
+    }
+
+    fn mapper_peek(&self, addr: u16) -> Option<u8> {
+        let (Some(cart), Some(mapper)) = (&self.cart, &self.mapper) else {
+            return None;
+        };
+        mapper.cpu_peek(cart, addr)
+    }
+
+    fn mapper_write(&mut self, addr: u16, data: u8) -> bool {
+        let (Some(cart), Some(mapper)) = (&mut self.cart, &mut self.mapper) else {
+            return false;
+        };
+        mapper.cpu_write(cart, addr, data)
+    }
+
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus
+    }
+
+    pub fn set_unmapped_access_policy(&mut self, policy: UnmappedAccessPolicy) {
+        self.unmapped_access_policy = policy;
+    }
+
+    pub fn set_ppu_backend(&mut self, backend: PpuBackend) {
+        self.ppu.set_backend(backend);
+    }
+
+    // Everything the CPU can't see directly: RAM, the cart, PPU and APU state and the IO
+    // latches. The unmapped access policy and PPU backend are configuration and are
+    // left alone.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        Savestate::save_state(self, &mut w);
+        w.finish()
+    }
+
+    // on error the bus may be partially restored and should be reset or reloaded
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data)?;
+        Savestate::load_state(self, &mut r)?;
+        if !r.is_at_end() {
+            return Err(StateError::TrailingData);
+        }
+        Ok(())
+    }
+
+    fn unmapped_access(&self, access: &str, addr: u16) {
+        match self.unmapped_access_policy {
+            UnmappedAccessPolicy::Ignore => {}
+            UnmappedAccessPolicy::Warn => {
+                log::warn!("unmapped {} at {:04X} (PC {:04X})", access, addr, self.pc)
+            }
+            UnmappedAccessPolicy::Panic => {
+                panic!("unmapped {} at {:04X} (PC {:04X})", access, addr, self.pc)
+            }
+        }
+    }
+}
+
+impl Savestate for Bus {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.cpu_vram.save_state(w);
+        self.ppu.save_state(w);
+        self.apu.save_state(w);
+        w.write_u64(self.cycles);
+        w.write_bool(self.cart.is_some());
+        if let (Some(cart), Some(mapper)) = (&self.cart, &self.mapper) {
+            cart.save_state(w);
+            mapper.save_state(w);
+        }
+        match &self.cart {
+            Some(cart) => cart.prg_ram.save_state(w),
+            None => w.write_block(&[]),
+        }
+        w.write_bool(self.prg_ram_enabled);
+        w.write_bytes(&self.apu_io);
+        w.write_u8(self.open_bus);
+        self.ciram.save_state(w);
+        self.a12.save_state(w);
+        for controller in &self.controllers {
+            controller.save_state(w);
+        }
+        w.write_bool(self.device.is_some());
+        if let Some(device) = &self.device {
+            device.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cpu_vram.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.cycles = r.read_u64()?;
+        let has_cart = r.read_bool()?;
+        match (&mut self.cart, has_cart) {
+            (Some(cart), true) => {
+                cart.load_state(r)?;
+                if let Some(mapper) = &mut self.mapper {
+                    mapper.load_state(r)?;
+                }
+            }
+            (None, false) => {}
+            _ => return Err(StateError::Mismatch("inserted cart")),
+        }
+        match &mut self.cart {
+            Some(cart) => cart.prg_ram.load_state(r)?,
+            None => r.read_block_into(&mut [])?,
+        }
+        self.prg_ram_enabled = r.read_bool()?;
+        r.read_into(&mut self.apu_io)?;
+        self.open_bus = r.read_u8()?;
+        self.ciram.load_state(r)?;
+        self.a12.load_state(r)?;
+        for controller in &mut self.controllers {
+            controller.load_state(r)?;
+        }
+        match (&mut self.device, r.read_bool()?) {
+            (Some(device), true) => device.load_state(r)?,
+            (None, false) => {}
+            _ => return Err(StateError::Mismatch("plugged in device")),
+        }
+        Ok(())
+    }
+}
+
+impl Read for Bus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let held = self.joypad_oe.take();
+        let data = match addr {
+            // 2 KiB mirrored four times, the Memory wraps the address for us
+            RAM..=RAM_MIRRORS_END => self.cpu_vram.read(addr),
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let (ppu, mut ppu_bus) = self.ppu_and_bus();
+                let data = ppu.read_register(addr, &mut ppu_bus);
+                if !ppu.is_rendering() {
+                    ppu_bus.watch_a12(ppu.vram_addr());
+                }
+                data
+            }
+            // reading the status acknowledges the frame IRQ, peeking doesn't
+            APU_STATUS => self.apu.read_status() | (self.open_bus & 0b0010_0000),
+            JOYPAD1 | JOYPAD2 => self.read_joypad(addr, held),
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => self.read_apu_io(addr),
+            EXPANSION..=PRG_ROM_END => match self.mapper_read(addr) {
+                Some(data) => data,
+                None if (PRG_RAM..=PRG_RAM_END).contains(&addr) => self.read_prg_ram(addr),
+                None => {
+                    self.unmapped_access("read", addr);
+                    self.open_bus
+                }
+            },
+            _ => {
+                self.unmapped_access("read", addr);
+                self.open_bus
+            }
+        };
+        self.open_bus = data;
+        data
+    }
+
+    fn begin_instruction(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    fn cpu_cycle(&mut self) -> Option<CpuLines> {
+        self.tick(1);
+        Some(CpuLines {
+            nmi: self.take_nmi(),
+            irq: self.irq(),
+            dmc_request: self.take_dmc_request(),
+        })
+    }
+
+    fn dmc_sample(&mut self, sample: u8) -> bool {
+        self.fill_dmc_sample(sample);
+        true
+    }
+}
+
+impl Peek for Bus {
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram.peek(addr),
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => self.ppu.peek(addr),
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => self.read_apu_io(addr),
+            EXPANSION..=PRG_ROM_END => match self.mapper_peek(addr) {
+                Some(data) => data,
+                None if (PRG_RAM..=PRG_RAM_END).contains(&addr) => self.read_prg_ram(addr),
+                None => self.open_bus,
+            },
+            _ => self.open_bus,
+        }
+    }
+}
+
+impl Write for Bus {
+    fn write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        self.joypad_oe = None;
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram.write(addr, data),
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                if let Some(mapper) = &mut self.mapper {
+                    mapper.ppu_register_write(addr & 0b0010_0000_0000_0111, data);
+                }
+                let (ppu, mut ppu_bus) = self.ppu_and_bus();
+                ppu.write_register(addr, data, &mut ppu_bus);
+                // Outside rendering the PPU leaves v on its address bus, so pointing it at
+                // $1000-$1FFF with PPUADDR or stepping there with PPUDATA raises A12 too.
+                if !ppu.is_rendering() {
+                    ppu_bus.watch_a12(ppu.vram_addr());
+                }
+            }
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
+                // the CPU sees $4014 writes itself and runs the DMA, which lands back here as
+                // 256 OAMDATA writes starting at the current OAMADDR
+                self.apu_io[(addr - APU_IO_REGISTERS) as usize] = data;
+                if addr == JOYPAD1 {
+                    for controller in &mut self.controllers {
+                        controller.write(data);
+                    }
+                    if let Some(device) = &mut self.device {
+                        device.write(data);
+                    }
+                }
+                self.apu.write_register(addr, data);
+            }
+            EXPANSION..=PRG_ROM_END => {
+                if self.mapper_write(addr, data) {
+                    return;
+                }
+                match addr {
+                    PRG_RAM..=PRG_RAM_END => {
+                        let writable = self.prg_ram_mapped()
+                            && self.mapper.as_ref().is_none_or(|mapper| mapper.prg_ram_writable());
+                        if let (true, Some(cart)) = (writable, &mut self.cart) {
+                            cart.prg_ram.write(addr - PRG_RAM, data);
+                        }
+                    }
+                    // ROM, nothing on the board listens
+                    PRG_ROM..=PRG_ROM_END => {}
+                    _ => self.unmapped_access("write", addr),
+                }
+            }
+            _ => {
+                self.unmapped_access("write", addr);
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use thiserror::Error;
+
+use super::mapper;
+use super::mem::Memory;
+use super::patch::{self, PatchError};
+use super::romdb::{self, RomDb};
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const ZIP_TAG: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const GZIP_TAG: [u8; 2] = [0x1F, 0x8B];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+const PRG_RAM_PAGE_SIZE: usize = 8192;
+const FOUR_SCREEN_VRAM_SIZE: usize = 2048;
+
+// The console a game was made for, which picks the CPU clock and the PPU's frame timing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    // PAL famiclones, PAL frame length with NTSC-like CPU timing
+    Dendy,
+}
+
+impl Region {
+    pub fn cpu_clock_hz(self) -> u32 {
+        match self {
+            Region::Ntsc => 1_789_773,
+            Region::Pal => 1_662_607,
+            Region::Dendy => 1_773_448,
+        }
+    }
+
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    pub fn frame_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+}
+
+// The TV system fields of the header. NES 2.0 has a proper timing field in byte 12, iNES only
+// the rarely set byte 9 and the unofficial byte 10, which are only trusted when the rest of
+// the header is clean: old rippers left their names in bytes 7-15.
+fn header_region(header: &[u8]) -> Region {
+    if (header[7] >> 2) & 0b11 == 0b10 {
+        return match header[12] & 0b11 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            // multi-region carts run as NTSC
+            _ => Region::Ntsc,
+        };
+    }
+    if header[11..16].iter().any(|&byte| byte != 0) {
+        return Region::Ntsc;
+    }
+    if header[9] & 1 != 0 || header[10] & 0b11 == 0b10 {
+        Region::Pal
+    } else {
+        Region::Ntsc
+    }
+}
+
+// How the four nametables map onto the console's two 1 KiB CIRAM pages. Boards with mirroring
+// control change the cart's screen_mirroring at runtime, the PPU bus looks it up per access.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    // the first two nametables in CIRAM, the other two in RAM on the cart
+    FourScreen,
+    // every nametable on the first or the second page
+    SingleScreenA,
+    SingleScreenB,
+}
+
+impl Mirroring {
+    // CIRAM page for a nametable address
+    pub fn ciram_page(self, addr: u16) -> u8 {
+        let quadrant = ((addr >> 10) & 0b11) as u8;
+        match self {
+            Mirroring::Vertical | Mirroring::FourScreen => quadrant & 1,
+            Mirroring::Horizontal => quadrant >> 1,
+            Mirroring::SingleScreenA => 0,
+            Mirroring::SingleScreenB => 1,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CartError {
+    #[error("Could not read ROM file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not read zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Archive has no .nes file")]
+    NoRomInArchive,
+    #[error("Could not apply patch: {0}")]
+    Patch(#[from] PatchError),
+    #[error("File is {0} bytes, too short for an iNES header")]
+    TooShort(usize),
+    #[error("File is not in iNES file format")]
+    BadMagic,
+    #[error("Mapper {0} is not supported")]
+    UnsupportedMapper(u16),
+    // the header promises more data than the file holds
+    #[error("{section} is truncated: expected {expected} bytes, found {found}")]
+    Truncated {
+        section: &'static str,
+        expected: usize,
+        found: usize,
+    },
+}
+
+// The image inside a zip or gzip file, anything else is passed through as is. Archives are
+// recognized by their magic rather than the file extension.
+fn unpack(data: Vec<u8>) -> Result<Vec<u8>, CartError> {
+    if data.starts_with(&ZIP_TAG) {
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            if entry.is_file() && entry.name().to_ascii_lowercase().ends_with(".nes") {
+                let mut rom = Vec::new();
+                entry.read_to_end(&mut rom)?;
+                return Ok(rom);
+            }
+        }
+        Err(CartError::NoRomInArchive)
+    } else if data.starts_with(&GZIP_TAG) {
+        let mut rom = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut rom)?;
+        Ok(rom)
+    } else {
+        Ok(data)
+    }
+}
+
+// A NES 2.0 ROM size: the LSB from byte 4 or 5 and the MSB nibble from byte 9 count `unit`
+// sized banks, unless the MSB is $F and the LSB holds an exponent and a multiplier instead.
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        // 2^E * (MM * 2 + 1) bytes, one too big to count fails as truncated
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        return 1usize
+            .checked_shl((lsb >> 2) as u32)
+            .and_then(|size| size.checked_mul(multiplier))
+            .unwrap_or(usize::MAX);
+    }
+    ((msb as usize) << 8 | lsb as usize) * unit
+}
+
+// NES 2.0 RAM sizes are shift counts, 64 << n bytes, with 0 for none
+fn nes2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+// `len` bytes of `raw` from `start`, or the error for a file that ends early
+fn section<'a>(
+    raw: &'a [u8],
+    name: &'static str,
+    start: usize,
+    len: usize,
+) -> Result<&'a [u8], CartError> {
+    let end = start.checked_add(len);
+    end.and_then(|end| raw.get(start..end)).ok_or(CartError::Truncated {
+        section: name,
+        expected: len,
+        found: raw.len().saturating_sub(start),
+    })
+}
+
+pub struct Cart {
+    pub prg_rom: Memory<Vec<u8>>,
+    // CHR RAM when the header has no CHR ROM banks
+    pub chr_rom: Memory<Vec<u8>>,
+    pub mapper: u8,
+    // the board variant within the mapper, NES 2.0 only, 0 for iNES
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub prg_ram_size: usize,
+    // shows up at $6000-$7FFF, or wherever the mapper banks it
+    pub prg_ram: Memory<Vec<u8>>,
+    // PRG RAM is battery backed and should be saved between runs
+    pub battery: bool,
+    // nametable RAM for the last two nametables of four-screen boards, empty on the others
+    pub vram: Memory<Vec<u8>>,
+    // hashes of PRG and CHR ROM, what the ROM database knows dumps by
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+    // from the ROM database, None for unknown dumps
+    pub title: Option<String>,
+    pub region: Region,
+}
+
+impl Cart {
+    // An iNES or NES 2.0 file, or a .zip or .gz holding one. A patch next to it with the same
+    // name and an .ips or .bps extension, `game.ips` or `game.nes.ips` for `game.nes`, is
+    // applied.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Cart, CartError> {
+        let path = path.as_ref();
+        let patch = ["ips", "bps"].iter().find_map(|extension| {
+            let appended = format!("{}.{}", path.display(), extension);
+            [path.with_extension(extension), appended.into()]
+                .into_iter()
+                .find(|candidate| candidate.is_file())
+        });
+        Cart::from_file_with_patch(path, patch.as_deref())
+    }
+
+    // from_file with an explicit patch, or none at all
+    pub fn from_file_with_patch(
+        path: impl AsRef<Path>,
+        patch: Option<&Path>,
+    ) -> Result<Cart, CartError> {
+        let mut rom = unpack(std::fs::read(path)?)?;
+        if let Some(patch) = patch {
+            rom = patch::apply(&rom, &std::fs::read(patch)?)?;
+        }
+        Cart::from_bytes(&rom)
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Cart, CartError> {
+        Cart::from_bytes_with_db(raw, romdb::embedded())
+    }
+
+    // like from_bytes, with the header corrected from `db` when the dump is in it
+    pub fn from_bytes_with_db(raw: &[u8], db: &RomDb) -> Result<Cart, CartError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(CartError::TooShort(raw.len()));
+        }
+        if raw[0..4] != NES_TAG {
+            return Err(CartError::BadMagic);
+        }
+
+        let mut mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+        let nes2 = (raw[7] >> 2) & 0b11 == 0b10;
+        // NES 2.0 has mapper bits 8-11 and the submapper in byte 8
+        let submapper = if nes2 { raw[8] >> 4 } else { 0 };
+        if nes2 && raw[8] & 0x0F != 0 {
+            return Err(CartError::UnsupportedMapper((raw[8] as u16 & 0x0F) << 8 | mapper as u16));
+        }
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let mut region = header_region(&raw[..HEADER_SIZE]);
+        let mut screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let (prg_rom_size, chr_rom_size, prg_ram_size, chr_ram_size) = if nes2 {
+            (
+                nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE),
+                nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE),
+                // volatile and battery backed RAM
+                nes2_ram_size(raw[10] & 0x0F) + nes2_ram_size(raw[10] >> 4),
+                nes2_ram_size(raw[11] & 0x0F),
+            )
+        } else {
+            (
+                raw[4] as usize * PRG_ROM_PAGE_SIZE,
+                raw[5] as usize * CHR_ROM_PAGE_SIZE,
+                // a 0 here means 8 KiB, for the many dumps that predate the field
+                raw[8].max(1) as usize * PRG_RAM_PAGE_SIZE,
+                0,
+            )
+        };
+        let battery = raw[6] & 0b10 != 0;
+
+        let trainer_size = if raw[6] & 0b100 != 0 { TRAINER_SIZE } else { 0 };
+        section(raw, "Trainer", HEADER_SIZE, trainer_size)?;
+
+        let prg_rom_start = HEADER_SIZE + trainer_size;
+        let prg_rom = section(raw, "PRG ROM", prg_rom_start, prg_rom_size)?;
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let chr_rom_data = section(raw, "CHR ROM", chr_rom_start, chr_rom_size)?;
+
+        // PRG and CHR are contiguous after the trainer
+        let rom = &raw[prg_rom_start..chr_rom_start + chr_rom_size];
+        let crc32 = crc32fast::hash(rom);
+        let sha1 = sha1_smol::Sha1::from(rom).digest().bytes();
+        let info = db.lookup(crc32, &sha1);
+        if let Some(info) = info {
+            mapper = info.mapper.unwrap_or(mapper);
+            screen_mirroring = info.mirroring.unwrap_or(screen_mirroring);
+            region = info.region.unwrap_or(region);
+        }
+
+        if !mapper::is_supported(mapper) {
+            return Err(CartError::UnsupportedMapper(mapper as u16));
+        }
+
+        let vram_size = match screen_mirroring {
+            Mirroring::FourScreen => FOUR_SCREEN_VRAM_SIZE,
+            _ => 0,
+        };
+
+        // 8 KiB of CHR RAM unless a NES 2.0 header gives its size
+        let chr_rom = if chr_rom_size == 0 {
+            let chr_ram_size = if chr_ram_size == 0 { CHR_ROM_PAGE_SIZE } else { chr_ram_size };
+            Memory::new(vec![0u8; chr_ram_size], true)
+        } else {
+            Memory::new(chr_rom_data.to_vec(), false)
+        };
+
+        Ok(Cart {
+            prg_rom: Memory::new(prg_rom.to_vec(), false),
+            chr_rom,
+            mapper,
+            submapper,
+            screen_mirroring,
+            prg_ram_size,
+            prg_ram: Memory::new(vec![0u8; prg_ram_size], true),
+            battery,
+            vram: Memory::new(vec![0u8; vram_size], true),
+            crc32,
+            sha1,
+            title: info.map(|info| info.title.clone()),
+            region,
+        })
+    }
+}
+
+// The mapper number is stored so a state can't be loaded into a different kind of cart, the
+// board's registers are saved by its Mapper.
+impl Savestate for Cart {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.mapper);
+        w.write_u8(self.screen_mirroring as u8);
+        self.chr_rom.save_state(w);
+        self.vram.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mapper = r.read_u8()?;
+        if mapper != self.mapper {
+            return Err(StateError::WrongMapper { state: mapper, cart: self.mapper });
+        }
+        // boards with mirroring control change it at runtime
+        self.screen_mirroring = match r.read_u8()? {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenA,
+            4 => Mirroring::SingleScreenB,
+            _ => return Err(StateError::Corrupt("invalid mirroring")),
+        };
+        self.chr_rom.load_state(r)?;
+        self.vram.load_state(r)?;
+        Ok(())
+    }
+}
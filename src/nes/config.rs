@@ -0,0 +1,190 @@
+// Frontend settings, read at startup from a text file of `name = value` lines. The key
+// bindings are one line per button with SDL's name for the key, turbo A and B included; the
+// turbo period is in frames and the gamepad deadzone a fraction of the stick's travel. What
+// holding opposite directions does is allow, neutralize or last. Hotkeys take a key the same
+// way, and fast-forward has a speed cap (0 for none) and a number of frames to skip showing:
+//   input.a = K
+//   input.start = Return
+//   input.select = Right Shift
+//   input.turbo_a = I
+//   input.opposite_directions = neutralize
+//   turbo.period = 3
+//   gamepad.deadzone = 0.3
+//   hotkey.fast_forward = Tab
+//   hotkey.pause = Pause
+//   hotkey.frame_advance = F6
+//   hotkey.mute_dmc = F9
+//   fast_forward.speed = 4
+//   fast_forward.frame_skip = 3
+// Anything not in the file keeps its default, and a missing file is all defaults.
+// Blank lines and lines starting with # are skipped.
+
+use super::controller::Button;
+use super::gamepad::DEFAULT_DEADZONE;
+use super::input::{Hotkey, Hotkeys, InputMap, OppositeDirections, Turbo, TURBO_BUTTONS};
+use super::speed::FastForward;
+use sdl2::keyboard::Keycode;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub input: InputMap,
+    pub opposite_directions: OppositeDirections,
+    pub turbo: Turbo,
+    pub gamepad_deadzone: f32,
+    pub hotkeys: Hotkeys,
+    pub fast_forward: FastForward,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            input: InputMap::default(),
+            opposite_directions: OppositeDirections::default(),
+            turbo: Turbo::default(),
+            gamepad_deadzone: DEFAULT_DEADZONE,
+            hotkeys: Hotkeys::default(),
+            fast_forward: FastForward::default(),
+        }
+    }
+}
+
+impl Config {
+    // $XDG_CONFIG_HOME/nestacean/nestacean.cfg, or under ~/.config without it
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("nestacean").join("nestacean.cfg"))
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(format!("Could not read {}: {}", path.display(), err)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = |what: &str| format!("Config line {}: {}", number + 1, what);
+
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(bad_line("expected name = value"));
+            };
+            let (name, value) = (name.trim(), value.trim());
+            if name == "gamepad.deadzone" {
+                let deadzone = value.parse::<f32>().ok().filter(|d| (0.0..=1.0).contains(d));
+                config.gamepad_deadzone =
+                    deadzone.ok_or_else(|| bad_line("deadzone must be from 0.0 to 1.0"))?;
+                continue;
+            }
+            if name == "input.opposite_directions" {
+                let policy = OppositeDirections::ALL.into_iter().find(|p| p.name() == value);
+                let policy = policy.ok_or_else(|| bad_line("expected allow, neutralize or last"))?;
+                config.opposite_directions = policy;
+                continue;
+            }
+            if name == "fast_forward.speed" {
+                let speed = value.parse::<f32>().ok().filter(|speed| *speed >= 0.0);
+                config.fast_forward.speed =
+                    speed.ok_or_else(|| bad_line("fast-forward speed must be 0 or more"))?;
+                continue;
+            }
+            if name == "fast_forward.frame_skip" {
+                let frame_skip = value.parse::<u8>().ok();
+                config.fast_forward.frame_skip =
+                    frame_skip.ok_or_else(|| bad_line("frame skip must be from 0 to 255"))?;
+                continue;
+            }
+            if let Some(hotkey) = name.strip_prefix("hotkey.") {
+                let Some(&hotkey) = Hotkey::ALL.iter().find(|h| h.name() == hotkey) else {
+                    return Err(bad_line(&format!("unknown hotkey {}", hotkey)));
+                };
+                match parse_key(value).map_err(|err| bad_line(&err))? {
+                    Some(key) => config.hotkeys.set_binding(hotkey, key),
+                    None => config.hotkeys.clear_binding(hotkey),
+                }
+                continue;
+            }
+            if name == "turbo.period" {
+                let period = value.parse::<u8>().ok().filter(|&period| period > 0);
+                let period = period.ok_or_else(|| bad_line("turbo period must be 1 or more"))?;
+                config.turbo = Turbo::new(period);
+                continue;
+            }
+            let Some(button) = name.strip_prefix("input.") else {
+                return Err(bad_line(&format!("unknown setting {}", name)));
+            };
+            let (button, turbo) = match button.strip_prefix("turbo_") {
+                Some(button) => (button, true),
+                None => (button, false),
+            };
+            let Some(&button) = Button::ALL.iter().find(|b| b.name() == Some(button)) else {
+                return Err(bad_line(&format!("unknown button {}", button)));
+            };
+            if turbo && !TURBO_BUTTONS.contains(&button) {
+                return Err(bad_line("only a and b have turbo"));
+            }
+            let key = parse_key(value).map_err(|err| bad_line(&err))?;
+            match (key, turbo) {
+                (Some(key), false) => config.input.set_binding(button, key),
+                (Some(key), true) => config.input.set_turbo_binding(button, key),
+                (None, false) => config.input.clear_binding(button),
+                (None, true) => config.input.clear_turbo_binding(button),
+            }
+        }
+        Ok(config)
+    }
+
+    // writes the whole config, creating the directory it goes in
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_string())
+    }
+}
+
+// SDL's name for a key, none or nothing for no key
+fn parse_key(value: &str) -> Result<Option<Keycode>, String> {
+    match value {
+        "" | "none" => Ok(None),
+        key => match Keycode::from_name(key) {
+            Some(key) => Ok(Some(key)),
+            None => Err(format!("unknown key {}", key)),
+        },
+    }
+}
+
+// the file format, for Config::parse
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let key_name = |key: Option<Keycode>| key.map_or("none".to_string(), |key| key.name());
+        for button in Button::ALL {
+            let key = key_name(self.input.binding(button));
+            writeln!(f, "input.{} = {}", button.name().unwrap_or_default(), key)?;
+        }
+        for button in TURBO_BUTTONS {
+            let key = key_name(self.input.turbo_binding(button));
+            writeln!(f, "input.turbo_{} = {}", button.name().unwrap_or_default(), key)?;
+        }
+        writeln!(f, "input.opposite_directions = {}", self.opposite_directions.name())?;
+        writeln!(f, "turbo.period = {}", self.turbo.period())?;
+        writeln!(f, "gamepad.deadzone = {}", self.gamepad_deadzone)?;
+        for hotkey in Hotkey::ALL {
+            writeln!(f, "hotkey.{} = {}", hotkey.name(), key_name(self.hotkeys.binding(hotkey)))?;
+        }
+        writeln!(f, "fast_forward.speed = {}", self.fast_forward.speed)?;
+        writeln!(f, "fast_forward.frame_skip = {}", self.fast_forward.frame_skip)
+    }
+}
@@ -0,0 +1,166 @@
+// The standard controller: eight buttons latched into a shift register. Writing 1 to bit 0 of
+// $4016 holds the strobe, which keeps reloading the register from the buttons; writing 0 lets
+// it go, and each read of $4016 (or $4017 for the second port) then returns the next button in
+// bit 0, A first and Right last. After all eight an official controller reads 1.
+
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+
+// a set of buttons, in the order they shift out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Button(u8);
+
+impl Button {
+    pub const NONE: Button = Button(0);
+    pub const A: Button = Button(0x01);
+    pub const B: Button = Button(0x02);
+    pub const SELECT: Button = Button(0x04);
+    pub const START: Button = Button(0x08);
+    pub const UP: Button = Button(0x10);
+    pub const DOWN: Button = Button(0x20);
+    pub const LEFT: Button = Button(0x40);
+    pub const RIGHT: Button = Button(0x80);
+    // each button on its own
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::SELECT,
+        Button::START,
+        Button::UP,
+        Button::DOWN,
+        Button::LEFT,
+        Button::RIGHT,
+    ];
+
+    // None for no buttons or more than one
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Button::A => Some("a"),
+            Button::B => Some("b"),
+            Button::SELECT => Some("select"),
+            Button::START => Some("start"),
+            Button::UP => Some("up"),
+            Button::DOWN => Some("down"),
+            Button::LEFT => Some("left"),
+            Button::RIGHT => Some("right"),
+            _ => None,
+        }
+    }
+
+    pub fn from_bits(bits: u8) -> Button {
+        Button(bits)
+    }
+
+    // bit 0 is A, bit 7 Right
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, buttons: Button) -> bool {
+        self.0 & buttons.0 == buttons.0
+    }
+
+    pub fn insert(&mut self, buttons: Button) {
+        self.0 |= buttons.0;
+    }
+
+    pub fn remove(&mut self, buttons: Button) {
+        self.0 &= !buttons.0;
+    }
+
+    pub fn set(&mut self, buttons: Button, pressed: bool) {
+        if pressed {
+            self.insert(buttons);
+        } else {
+            self.remove(buttons);
+        }
+    }
+}
+
+impl BitOr for Button {
+    type Output = Button;
+
+    fn bitor(self, rhs: Button) -> Button {
+        Button(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Button {
+    fn bitor_assign(&mut self, rhs: Button) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for Button {
+    type Output = Button;
+
+    fn bitand(self, rhs: Button) -> Button {
+        Button(self.0 & rhs.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Controller {
+    // what the player is holding, set by the frontend
+    buttons: Button,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Controller::default()
+    }
+
+    // takes effect at the next strobe, or right away while the strobe is held
+    pub fn set_buttons(&mut self, buttons: Button) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons.bits();
+        }
+    }
+
+    pub fn buttons(&self) -> Button {
+        self.buttons
+    }
+
+    // a write to $4016, only the strobe bit matters
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    // The bit a read drives, and the clock that moves on to the next button. While the strobe
+    // is held the register keeps reloading, so every read sees A.
+    pub fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe {
+            self.shift = (self.shift >> 1) | 0x80;
+        }
+        bit
+    }
+
+    pub fn peek(&self) -> u8 {
+        if self.strobe { self.buttons.bits() & 1 } else { self.shift & 1 }
+    }
+}
+
+// the buttons are the player's, not the machine's, and aren't saved
+impl Savestate for Controller {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.strobe);
+        w.write_u8(self.shift);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.strobe = r.read_bool()?;
+        self.shift = r.read_u8()?;
+        Ok(())
+    }
+}
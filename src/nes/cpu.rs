@@ -1,4 +1,13 @@
-use std::io::{self, Write};
+use super::dma::{DmaCycle, DmcDma, OamDma, OAM_DMA_REGISTER};
+use super::mem::{Memory, Peek, Read, Write};
+use super::opcodes::{opcode_info, AddressingMode, Mnemonic};
+use super::profile::{ProfileReport, Profiler};
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+use super::trace::{self, TraceSink};
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write as IoWrite};
+use std::ops::RangeInclusive;
 
 const CLS: &str = "\x1B[2J\x1B[1;1H";
 
@@ -9,28 +18,146 @@ const FLAG_OVERFLOW: u8 = 0b0100_0000;
 const FLAG_DECIMAL: u8 = 0b0000_1000;
 const FLAG_INTERRUPT: u8 = 0b0000_0100;
 const FLAG_BREAK: u8 = 0b0001_0000;
+const FLAG_UNUSED: u8 = 0b0010_0000;
 const BIT_7: u8 = 0b1000_0000;
 const STACK_PTR_TOP: u8 = 0xFF;
 const STACK_BOTTOM: u16 = 0x0100;
-const PROGRAM_START: u16 = 0x8000;
 const PC_INIT_LOCATION: u16 = 0xFFFC;
 const INTERRUPT_VEC_LOW: u16 = 0xFFFE;
-const INTERRUPT_VEC_HIGH: u16 = 0xFFFF;
-
-enum AddressingMode {
-    ZeroPage,
-    ZeroPageX,
-    ZeroPageY,
-    Absolute,
-    AbsoluteX,
-    AbsoluteY,
-    IndexedIndirect,
-    IndirectIndexed,
+const NMI_VEC_LOW: u16 = 0xFFFA;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IllegalOpcodePolicy {
+    Panic,
+    TreatAsNop,
+    ReturnError,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuError {
+    IllegalOpcode { opcode: u8, pc: u16 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemAccess {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: MemAccess) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, MemAccess::Read)
+                | (WatchKind::Write, MemAccess::Write)
+        )
+    }
+}
+
+// reported to the watch callback for every matching access done by an instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub value: u8,
+    pub access: MemAccess,
+    pub pc: u16,
+    pub cycle: u64,
+}
+
+pub type WatchCallback = Box<dyn FnMut(&WatchHit)>;
+
+// reported to the trap hook once a BRK or interrupt sequence has fetched its vector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrapEvent {
+    pub interrupt: Interrupt,
+    // the vector actually used, an NMI can hijack BRK and IRQ
+    pub vector: u16,
+    // what the sequence pushed, for BRK this is the address of the BRK plus 2
+    pub return_addr: u16,
+    pub target: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrapAction {
+    // jump to the vector target as usual
+    Continue,
+    // jump here instead, the pushed return address and status are left alone
+    Redirect(u16),
+    // stop the CPU, it reports Halted until reset
+    Halt,
+}
+
+pub type TrapHook = Box<dyn FnMut(&TrapEvent) -> TrapAction>;
+
+// CPU state around a single micro-op, cycle is the one the op executes on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MicroOpEvent {
+    pub op: MicroOp,
+    pub opcode: u8,
+    pub cycle: u64,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+}
+
+// Called around every micro-op taken from the instruction queue. Fetch/decode and DMA
+// cycles aren't micro-ops and don't show up here.
+pub trait MicroOpHook {
+    fn before(&mut self, _event: &MicroOpEvent) {}
+    fn after(&mut self, _event: &MicroOpEvent) {}
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { opcode, pc } => {
+                write!(f, "illegal opcode {:02X} at {:04X}", opcode, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuStepResult {
+    Running,
+    // the CPU jammed, stopped on an illegal opcode error or was halted by the trap hook.
+    // Only a reset gets it going again
+    Halted,
+    // a BRK sequence finished without a trap hook, run_with_callback won't advance until reset
+    Break,
+    // run_until_break stopped before fetching the instruction at this breakpoint
+    Breakpoint(u16),
+    // loop detection saw the instruction at this address jump back to itself for longer
+    // than the limit, with no interrupt in between
+    Stuck(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interrupt {
+    Brk,
+    Irq,
+    Nmi,
 }
 
 enum InstType {
     Read,
-    RMW,
+    Rmw,
     Write,
 }
 
@@ -65,6 +192,22 @@ pub enum MicroOp {
     RotateLeftAddress,
     RotateRight,
     RotateRightAddress,
+    ShiftLeftInclusiveOrAddress,
+    RotateLeftLogicalAndAddress,
+    ShiftRightExclusiveOrAddress,
+    RotateRightAddWithCarryAddress,
+    DecrementCompareAddress,
+    IncrementSubWithCarryAddress,
+    AndWithCarry,
+    AndShiftRight,
+    AndRotateRight,
+    AndXSubtract,
+    StoreAccumulatorXHigh,
+    StoreXHigh,
+    StoreYHigh,
+    StoreStackPointerHigh,
+    LoadAccumulatorXStackPointerFromAddress,
+    Jam,
     LoadAccPlaceholder,
     Break,
     ReadAccumulator,
@@ -96,8 +239,10 @@ pub enum MicroOp {
     LoadAccumulatorY,
     PushAccumulator,
     PushStatusBrkPhp,
+    PushStatusInterrupt,
     PullAccumulator,
     PullStatus,
+    PullStatusIncrementSP,
     PushPCH,
     PushPCL,
     PullPCL,
@@ -110,6 +255,7 @@ pub enum MicroOp {
     DecrementX,
     DecrementY,
     DummyCycle,
+    ReadUnfixedAddress,
     AddXtoPointer,
     FetchPointerLowByte,
     FetchPointerHighByte,
@@ -117,6 +263,9 @@ pub enum MicroOp {
     ReadHighFromIndirectLatch,
     ReadLowFromIndirect,
     ReadAddress,
+    ReadImmediate,
+    LoadAccumulatorXFromAddress,
+    StoreAccumulatorAndX,
     WriteBackAndIncrement,
     WriteBackAndDecrement,
     WriteToAddress,
@@ -175,7 +324,7 @@ impl InstructionQueue {
     }
 }
 
-pub struct Cpu {
+pub struct Cpu<B> {
     accumulator: u8,
     index_x: u8,
     index_y: u8,
@@ -183,19 +332,72 @@ pub struct Cpu {
     sp: u8,
     status_p: u8,
     current_inst: InstructionQueue,
-    memory: Box<[u8; 0x10000]>,
+    bus: B,
     temp_addr: u16,
     temp_val: u8,
     temp_ptr: u16,
+    // indexed address before the high byte carry is fixed up
+    unfixed_addr: u16,
     page_crossed: bool,
     debug_active: bool,
+    debug_step: bool,
     debug_mem_page: u8,
+    breakpoints: HashSet<u16>,
+    // JSR/interrupt nesting, RTS/RTI unwind it. Goes negative on RTS tricks, which is fine
+    // since stepping only compares depths
+    call_depth: i64,
+    watchpoints: Vec<(RangeInclusive<u16>, WatchKind)>,
+    watch_callback: Option<WatchCallback>,
+    trap_hook: Option<TrapHook>,
+    trap_halted: bool,
+    micro_op_hook: Option<Box<dyn MicroOpHook>>,
+    loop_limit: Option<u64>,
+    loop_pc: u16,
+    loop_since: u64,
+    stuck: bool,
     current_opcode: u8,
     running: bool,
+    jammed: bool,
+    unstable_store_corruption: bool,
+    illegal_opcode_policy: IllegalOpcodePolicy,
+    error: Option<CpuError>,
+    cycles: u64,
+    nmi_pending: bool,
+    irq_line: bool,
+    // I flag as seen by IRQ polling
+    irq_inhibit: bool,
+    // what the last poll saw, used instead of the live lines when the poll before the
+    // fetch was skipped
+    polled_irq: bool,
+    polled_nmi: bool,
+    poll_skipped: bool,
+    interrupt: Interrupt,
+    interrupt_vector: u16,
+    trace_sink: Option<Box<dyn TraceSink>>,
+    profiler: Option<Box<Profiler>>,
+    oam_dma: Option<OamDma>,
+    dmc_dma: Option<DmcDma>,
+    dmc_sample: Option<u8>,
+}
+
+impl Default for Cpu<Memory<Box<[u8; 0x10000]>>> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl Cpu {
+impl Cpu<Memory<Box<[u8; 0x10000]>>> {
     pub fn new() -> Self {
+        Cpu::with_bus(Memory::new(Box::new([0u8; 0x10000]), true))
+    }
+
+    pub fn get_memory(&self) -> &[u8; 0x10000] {
+        self.bus.data()
+    }
+}
+
+impl<B: Read + Write + Peek> Cpu<B> {
+    pub fn with_bus(bus: B) -> Self {
         Self {
             accumulator: 0u8,
             index_x: 0u8,
@@ -204,23 +406,66 @@ impl Cpu {
             sp: 0u8,
             status_p: 0u8,
             current_inst: InstructionQueue::new(),
-            memory: Box::new([0u8; 0x10000]),
+            bus,
             temp_addr: 0u16,
             temp_val: 0u8,
             temp_ptr: 0u16,
+            unfixed_addr: 0u16,
             page_crossed: false,
             running: true,
+            jammed: false,
             debug_active: false,
+            debug_step: false,
             debug_mem_page: 0u8,
+            breakpoints: HashSet::new(),
+            call_depth: 0,
+            watchpoints: Vec::new(),
+            watch_callback: None,
+            trap_hook: None,
+            trap_halted: false,
+            micro_op_hook: None,
+            loop_limit: None,
+            loop_pc: 0,
+            loop_since: 0,
+            stuck: false,
             current_opcode: 0u8, // doesn't really conflict with BRK, because current_inst is empty so the first opcode will be fetched
+            unstable_store_corruption: true,
+            illegal_opcode_policy: IllegalOpcodePolicy::Panic,
+            error: None,
+            cycles: 0,
+            nmi_pending: false,
+            irq_line: false,
+            irq_inhibit: false,
+            polled_irq: false,
+            polled_nmi: false,
+            poll_skipped: false,
+            interrupt: Interrupt::Brk,
+            interrupt_vector: INTERRUPT_VEC_LOW,
+            trace_sink: None,
+            profiler: None,
+            oam_dma: None,
+            dmc_dma: None,
+            dmc_sample: None,
         }
     }
 
-    pub fn mem_read(&self, pos: u16) -> u8 {
-        self.memory[pos as usize]
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
     }
 
-    pub fn mem_read_u16(&self, pos: u16) -> u16 {
+    pub fn mem_read(&mut self, pos: u16) -> u8 {
+        self.bus.read(pos)
+    }
+
+    pub fn mem_peek(&self, pos: u16) -> u8 {
+        self.bus.peek(pos)
+    }
+
+    pub fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let low_byte = self.mem_read(pos) as u16;
         let high_byte = self.mem_read(pos + 1) as u16;
         (high_byte << 8) | low_byte
@@ -228,10 +473,45 @@ impl Cpu {
 
     pub fn enable_debug(&mut self) {
         self.debug_active = true;
+        self.debug_step = true;
+    }
+
+    pub fn set_trace_sink(&mut self, sink: Box<dyn TraceSink>) {
+        self.trace_sink = Some(sink);
+    }
+
+    pub fn clear_trace_sink(&mut self) {
+        self.trace_sink = None;
+    }
+
+    // starts counting executions and cycles per opcode, from zero
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Box::default());
+    }
+
+    pub fn disable_profiling(&mut self) {
+        self.profiler = None;
+    }
+
+    // empty when profiling is off
+    pub fn profile_report(&self) -> ProfileReport {
+        self.profiler
+            .as_ref()
+            .map(|profiler| profiler.report())
+            .unwrap_or_default()
+    }
+
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+
+    // SHA/SHX/SHY/TAS page-cross high byte corruption, on by default like most real 6502s
+    pub fn set_unstable_store_corruption(&mut self, enabled: bool) {
+        self.unstable_store_corruption = enabled;
     }
 
     pub fn mem_write(&mut self, pos: u16, byte: u8) {
-        self.memory[pos as usize] = byte;
+        self.bus.write(pos, byte);
     }
 
     pub fn mem_write_u16(&mut self, pos: u16, bytes: u16) {
@@ -241,12 +521,113 @@ impl Cpu {
         self.mem_write(pos + 1, high_byte);
     }
 
+    // every access made while executing instructions goes through these two, mem_read and
+    // mem_write are left for tooling so debugger reads don't set off watchpoints
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        let value = self.bus.read(addr);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, value, MemAccess::Read);
+        }
+        value
+    }
+
+    fn bus_write(&mut self, addr: u16, value: u8) {
+        self.bus.write(addr, value);
+        // the DMA unit is part of the 2A03, so it reacts no matter what the bus maps there
+        if addr == OAM_DMA_REGISTER {
+            self.oam_dma = Some(OamDma::new(value));
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, value, MemAccess::Write);
+        }
+    }
+
+    fn check_watchpoints(&mut self, addr: u16, value: u8, access: MemAccess) {
+        let hit = self
+            .watchpoints
+            .iter()
+            .any(|(range, kind)| range.contains(&addr) && kind.matches(access));
+        if let (true, Some(callback)) = (hit, self.watch_callback.as_mut()) {
+            callback(&WatchHit {
+                addr,
+                value,
+                access,
+                pc: self.pc,
+                cycle: self.cycles,
+            });
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        self.watchpoints.push((range, kind));
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn set_watch_callback(&mut self, callback: WatchCallback) {
+        self.watch_callback = Some(callback);
+    }
+
+    pub fn set_trap_hook(&mut self, hook: TrapHook) {
+        self.trap_hook = Some(hook);
+    }
+
+    pub fn clear_trap_hook(&mut self) {
+        self.trap_hook = None;
+    }
+
+    pub fn set_micro_op_hook(&mut self, hook: Box<dyn MicroOpHook>) {
+        self.micro_op_hook = Some(hook);
+    }
+
+    pub fn clear_micro_op_hook(&mut self) {
+        self.micro_op_hook = None;
+    }
+
+    fn micro_op_event(&self, op: MicroOp) -> MicroOpEvent {
+        MicroOpEvent {
+            op,
+            opcode: self.current_opcode,
+            cycle: self.cycles,
+            a: self.accumulator,
+            x: self.index_x,
+            y: self.index_y,
+            p: self.status_p,
+            sp: self.sp,
+            pc: self.pc,
+        }
+    }
+
+    fn run_trap_hook(&mut self, return_addr: u16) {
+        let Some(hook) = self.trap_hook.as_mut() else {
+            return;
+        };
+        let event = TrapEvent {
+            interrupt: self.interrupt,
+            vector: self.interrupt_vector,
+            return_addr,
+            target: self.pc,
+            a: self.accumulator,
+            x: self.index_x,
+            y: self.index_y,
+        };
+        match hook(&event) {
+            TrapAction::Continue => {}
+            TrapAction::Redirect(addr) => self.pc = addr,
+            TrapAction::Halt => self.trap_halted = true,
+        }
+    }
+
     fn add_page_cross_penalty(&mut self) {
         self.page_crossed = false;
-        if self.current_inst.ops[self.current_inst.front] == MicroOp::DummyCycle {
+        if !self.current_inst.is_empty()
+            && self.current_inst.ops[self.current_inst.front] == MicroOp::ReadUnfixedAddress
+        {
             return;
         }
-        self.current_inst.push_front(MicroOp::DummyCycle);
+        self.current_inst.push_front(MicroOp::ReadUnfixedAddress);
     }
 
     fn compare(&mut self, a: u8, b: u8) {
@@ -277,7 +658,7 @@ impl Cpu {
 
         self.set_flags_zero_neg(result);
 
-        if ((self.accumulator ^ result) & (value ^ result) & 0x80) != 0 {
+        if ((self.accumulator ^ value) & (self.accumulator ^ result) & 0x80) != 0 {
             self.status_p |= FLAG_OVERFLOW;
         } else {
             self.status_p &= !FLAG_OVERFLOW;
@@ -364,6 +745,11 @@ impl Cpu {
         }
     }
 
+    // B only exists on the stack copy of P and bit 5 always reads back as set
+    fn pull_status(&mut self, value: u8) {
+        self.status_p = (value & !FLAG_BREAK) | FLAG_UNUSED;
+    }
+
     fn set_flags_zero_neg(&mut self, value: u8) {
         // set zero flag
         if value == 0x00 {
@@ -380,6 +766,20 @@ impl Cpu {
         }
     }
 
+    // SHA/SHX/SHY/TAS store value & (H + 1), H being the high byte of the un-indexed address.
+    // When the index crosses a page, the same value replaces the high byte of the target.
+    fn store_high_and(&mut self, value: u8, index: u8) {
+        let base = self.temp_addr.wrapping_sub(index as u16);
+        let result = value & ((base >> 8) as u8).wrapping_add(1);
+        let crossed = (base & 0xFF00) != (self.temp_addr & 0xFF00);
+        let address = if crossed && self.unstable_store_corruption {
+            ((result as u16) << 8) | (self.temp_addr & 0x00FF)
+        } else {
+            self.temp_addr
+        };
+        self.bus_write(address, result);
+    }
+
     fn dispatch_generic_instruction(
         address_mode: AddressingMode,
         inst: MicroOp,
@@ -392,7 +792,7 @@ impl Cpu {
                     queue.push_back(MicroOp::FetchZeroPage);
                     queue.push_back(inst);
                 }
-                InstType::RMW => {
+                InstType::Rmw => {
                     queue.push_back(MicroOp::FetchZeroPage);
                     queue.push_back(MicroOp::ReadAddress);
                     queue.push_back(inst);
@@ -409,7 +809,7 @@ impl Cpu {
                     queue.push_back(MicroOp::AddXtoZeroPageAddress);
                     queue.push_back(inst);
                 }
-                InstType::RMW => {
+                InstType::Rmw => {
                     queue.push_back(MicroOp::FetchZeroPage);
                     queue.push_back(MicroOp::AddXtoZeroPageAddress);
                     queue.push_back(MicroOp::ReadAddress);
@@ -428,7 +828,7 @@ impl Cpu {
                     queue.push_back(MicroOp::AddYtoZeroPageAddress);
                     queue.push_back(inst);
                 }
-                InstType::RMW => {
+                InstType::Rmw => {
                     queue.push_back(MicroOp::FetchZeroPage);
                     queue.push_back(MicroOp::AddYtoZeroPageAddress);
                     queue.push_back(MicroOp::ReadAddress);
@@ -447,7 +847,7 @@ impl Cpu {
                     queue.push_back(MicroOp::FetchHighAddrByte);
                     queue.push_back(inst);
                 }
-                InstType::RMW => {
+                InstType::Rmw => {
                     queue.push_back(MicroOp::FetchLowAddrByte);
                     queue.push_back(MicroOp::FetchHighAddrByte);
                     queue.push_back(MicroOp::ReadAddress);
@@ -466,10 +866,10 @@ impl Cpu {
                     queue.push_back(MicroOp::FetchHighAddrByteWithX);
                     queue.push_back(inst);
                 }
-                InstType::RMW => {
+                InstType::Rmw => {
                     queue.push_back(MicroOp::FetchLowAddrByte);
                     queue.push_back(MicroOp::FetchHighAddrByteWithX);
-                    queue.push_back(MicroOp::DummyCycle);
+                    queue.push_back(MicroOp::ReadUnfixedAddress);
                     queue.push_back(MicroOp::ReadAddress);
                     queue.push_back(inst);
                     queue.push_back(MicroOp::WriteToAddress);
@@ -477,7 +877,7 @@ impl Cpu {
                 InstType::Write => {
                     queue.push_back(MicroOp::FetchLowAddrByte);
                     queue.push_back(MicroOp::FetchHighAddrByteWithX);
-                    queue.push_back(MicroOp::DummyCycle);
+                    queue.push_back(MicroOp::ReadUnfixedAddress);
                     queue.push_back(inst);
                 }
             },
@@ -487,10 +887,10 @@ impl Cpu {
                     queue.push_back(MicroOp::FetchHighAddrByteWithY);
                     queue.push_back(inst);
                 }
-                InstType::RMW => {
+                InstType::Rmw => {
                     queue.push_back(MicroOp::FetchLowAddrByte);
                     queue.push_back(MicroOp::FetchHighAddrByteWithY);
-                    queue.push_back(MicroOp::DummyCycle);
+                    queue.push_back(MicroOp::ReadUnfixedAddress);
                     queue.push_back(MicroOp::ReadAddress);
                     queue.push_back(inst);
                     queue.push_back(MicroOp::WriteToAddress);
@@ -498,7 +898,7 @@ impl Cpu {
                 InstType::Write => {
                     queue.push_back(MicroOp::FetchLowAddrByte);
                     queue.push_back(MicroOp::FetchHighAddrByteWithY);
-                    queue.push_back(MicroOp::DummyCycle);
+                    queue.push_back(MicroOp::ReadUnfixedAddress);
                     queue.push_back(inst);
                 }
             },
@@ -510,7 +910,7 @@ impl Cpu {
                     queue.push_back(MicroOp::FetchPointerHighByte);
                     queue.push_back(inst);
                 }
-                InstType::RMW => {
+                InstType::Rmw => {
                     queue.push_back(MicroOp::FetchZeroPage);
                     queue.push_back(MicroOp::AddXtoPointer);
                     queue.push_back(MicroOp::FetchPointerLowByte);
@@ -534,11 +934,11 @@ impl Cpu {
                     queue.push_back(MicroOp::FetchPointerHighByteWithY);
                     queue.push_back(inst);
                 }
-                InstType::RMW => {
+                InstType::Rmw => {
                     queue.push_back(MicroOp::FetchZeroPage);
                     queue.push_back(MicroOp::FetchPointerLowByte);
                     queue.push_back(MicroOp::FetchPointerHighByteWithY);
-                    queue.push_back(MicroOp::DummyCycle);
+                    queue.push_back(MicroOp::ReadUnfixedAddress);
                     queue.push_back(MicroOp::ReadAddress);
                     queue.push_back(inst);
                     queue.push_back(MicroOp::WriteToAddress);
@@ -547,115 +947,514 @@ impl Cpu {
                     queue.push_back(MicroOp::FetchZeroPage);
                     queue.push_back(MicroOp::FetchPointerLowByte);
                     queue.push_back(MicroOp::FetchPointerHighByteWithY);
-                    queue.push_back(MicroOp::DummyCycle);
+                    queue.push_back(MicroOp::ReadUnfixedAddress);
                     queue.push_back(inst);
                 }
             },
+            // decoded by hand in decode_opcode
+            AddressingMode::Implied
+            | AddressingMode::Accumulator
+            | AddressingMode::Immediate
+            | AddressingMode::Indirect
+            | AddressingMode::Relative => unreachable!("{:?} has no generic sequence", address_mode),
         }
         queue
     }
 
-    //TODO: might be redundant to have this and the self initializer. see load_program
+    //TODO: might be redundant to have this and the self initializer. see load_program_at
     pub fn reset(&mut self) {
         self.accumulator = 0;
         self.index_x = 0;
         self.index_y = 0;
         self.sp = STACK_PTR_TOP;
-        self.status_p = 0;
+        self.status_p = FLAG_UNUSED;
         self.temp_addr = 0;
         self.page_crossed = false;
-        self.current_inst = InstructionQueue::new();
+        self.current_inst.clear();
         self.pc = self.mem_read_u16(PC_INIT_LOCATION);
         self.running = true;
+        self.jammed = false;
+        self.trap_halted = false;
+        self.error = None;
+        self.cycles = 7; // the reset sequence takes 7 cycles
+        self.oam_dma = None;
+        self.dmc_dma = None;
+        self.dmc_sample = None;
+        self.call_depth = 0;
+        self.nmi_pending = false;
+        self.irq_inhibit = self.status_p & FLAG_INTERRUPT != 0;
+        self.poll_skipped = false;
+        self.loop_since = self.cycles;
+        self.stuck = false;
+    }
+
+    // copies the program to origin and points the reset vector at it
+    pub fn load_program_at(&mut self, origin: u16, program: &[u8]) {
+        for (i, byte) in program.iter().enumerate() {
+            self.mem_write(origin.wrapping_add(i as u16), *byte);
+        }
+        self.set_reset_vector(origin);
+    }
+
+    pub fn set_reset_vector(&mut self, addr: u16) {
+        self.mem_write_u16(PC_INIT_LOCATION, addr);
+    }
+
+    pub fn set_nmi_vector(&mut self, addr: u16) {
+        self.mem_write_u16(NMI_VEC_LOW, addr);
+    }
+
+    pub fn set_irq_vector(&mut self, addr: u16) {
+        self.mem_write_u16(INTERRUPT_VEC_LOW, addr);
+    }
+
+    pub fn tick(&mut self) -> CpuStepResult {
+        if self.debug_active && (self.debug_step || self.breakpoint_hit().is_some()) {
+            self.debug_prompt();
+        }
+        self.execute_current_cycle();
+        self.step_result()
+    }
+
+    // runs at most budget cycles, stopping early on a halt or BRK. Returns the cycles used
+    pub fn run_cycles(&mut self, budget: u64) -> u64 {
+        let start = self.cycles;
+        for _ in 0..budget {
+            self.execute_current_cycle();
+            if self.step_result() != CpuStepResult::Running {
+                break;
+            }
+        }
+        self.cycles - start
+    }
+
+    // finishes the instruction in flight, or runs the next one when called between instructions
+    pub fn run_instruction(&mut self) -> CpuStepResult {
+        // OAM DMA is the tail of the write that started it, a DMC stall belongs to the
+        // instruction it interrupts
+        let mut in_flight = !self.current_inst.is_empty() || self.oam_dma.is_some();
+        loop {
+            self.execute_current_cycle();
+            in_flight |= !self.current_inst.is_empty();
+            let result = self.step_result();
+            if result != CpuStepResult::Running || (in_flight && self.at_instruction_boundary()) {
+                return result;
+            }
+        }
+    }
+
+    // runs until the CPU is about to fetch an instruction at a breakpoint, always executing
+    // at least one cycle so it can be called again to get past the breakpoint it stopped on
+    pub fn run_until_break(&mut self) -> CpuStepResult {
+        loop {
+            self.execute_current_cycle();
+            let result = self.step_result();
+            if result != CpuStepResult::Running {
+                return result;
+            }
+            if let Some(addr) = self.breakpoint_hit() {
+                return CpuStepResult::Breakpoint(addr);
+            }
+        }
+    }
+
+    // runs one instruction, a JSR (or an interrupt) runs until its RTS (RTI) has completed
+    pub fn step_over(&mut self) -> CpuStepResult {
+        self.run_to_depth(self.call_depth)
+    }
+
+    // runs until the current subroutine or interrupt handler has returned
+    pub fn step_out(&mut self) -> CpuStepResult {
+        self.run_to_depth(self.call_depth - 1)
+    }
+
+    fn run_to_depth(&mut self, depth: i64) -> CpuStepResult {
+        loop {
+            self.execute_current_cycle();
+            let result = self.step_result();
+            if result != CpuStepResult::Running {
+                return result;
+            }
+            if self.at_instruction_boundary() && self.call_depth <= depth {
+                return CpuStepResult::Running;
+            }
+            if let Some(addr) = self.breakpoint_hit() {
+                return CpuStepResult::Breakpoint(addr);
+            }
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &HashSet<u16> {
+        &self.breakpoints
+    }
+
+    fn breakpoint_hit(&self) -> Option<u16> {
+        if self.at_instruction_boundary() && self.breakpoints.contains(&self.pc) {
+            Some(self.pc)
+        } else {
+            None
+        }
     }
 
-    pub fn load_test_game(&mut self) {
-        let game_code = vec![
-            0x20, 0x06, 0x06, 0x20, 0x38, 0x06, 0x20, 0x0d, 0x06, 0x20, 0x2a, 0x06, 0x60, 0xa9,
-            0x02, 0x85, 0x02, 0xa9, 0x04, 0x85, 0x03, 0xa9, 0x11, 0x85, 0x10, 0xa9, 0x10, 0x85,
-            0x12, 0xa9, 0x0f, 0x85, 0x14, 0xa9, 0x04, 0x85, 0x11, 0x85, 0x13, 0x85, 0x15, 0x60,
-            0xa5, 0xfe, 0x85, 0x00, 0xa5, 0xfe, 0x29, 0x03, 0x18, 0x69, 0x02, 0x85, 0x01, 0x60,
-            0x20, 0x4d, 0x06, 0x20, 0x8d, 0x06, 0x20, 0xc3, 0x06, 0x20, 0x19, 0x07, 0x20, 0x20,
-            0x07, 0x20, 0x2d, 0x07, 0x4c, 0x38, 0x06, 0xa5, 0xff, 0xc9, 0x77, 0xf0, 0x0d, 0xc9,
-            0x64, 0xf0, 0x14, 0xc9, 0x73, 0xf0, 0x1b, 0xc9, 0x61, 0xf0, 0x22, 0x60, 0xa9, 0x04,
-            0x24, 0x02, 0xd0, 0x26, 0xa9, 0x01, 0x85, 0x02, 0x60, 0xa9, 0x08, 0x24, 0x02, 0xd0,
-            0x1b, 0xa9, 0x02, 0x85, 0x02, 0x60, 0xa9, 0x01, 0x24, 0x02, 0xd0, 0x10, 0xa9, 0x04,
-            0x85, 0x02, 0x60, 0xa9, 0x02, 0x24, 0x02, 0xd0, 0x05, 0xa9, 0x08, 0x85, 0x02, 0x60,
-            0x60, 0x20, 0x94, 0x06, 0x20, 0xa8, 0x06, 0x60, 0xa5, 0x00, 0xc5, 0x10, 0xd0, 0x0d,
-            0xa5, 0x01, 0xc5, 0x11, 0xd0, 0x07, 0xe6, 0x03, 0xe6, 0x03, 0x20, 0x2a, 0x06, 0x60,
-            0xa2, 0x02, 0xb5, 0x10, 0xc5, 0x10, 0xd0, 0x06, 0xb5, 0x11, 0xc5, 0x11, 0xf0, 0x09,
-            0xe8, 0xe8, 0xe4, 0x03, 0xf0, 0x06, 0x4c, 0xaa, 0x06, 0x4c, 0x35, 0x07, 0x60, 0xa6,
-            0x03, 0xca, 0x8a, 0xb5, 0x10, 0x95, 0x12, 0xca, 0x10, 0xf9, 0xa5, 0x02, 0x4a, 0xb0,
-            0x09, 0x4a, 0xb0, 0x19, 0x4a, 0xb0, 0x1f, 0x4a, 0xb0, 0x2f, 0xa5, 0x10, 0x38, 0xe9,
-            0x20, 0x85, 0x10, 0x90, 0x01, 0x60, 0xc6, 0x11, 0xa9, 0x01, 0xc5, 0x11, 0xf0, 0x28,
-            0x60, 0xe6, 0x10, 0xa9, 0x1f, 0x24, 0x10, 0xf0, 0x1f, 0x60, 0xa5, 0x10, 0x18, 0x69,
-            0x20, 0x85, 0x10, 0xb0, 0x01, 0x60, 0xe6, 0x11, 0xa9, 0x06, 0xc5, 0x11, 0xf0, 0x0c,
-            0x60, 0xc6, 0x10, 0xa5, 0x10, 0x29, 0x1f, 0xc9, 0x1f, 0xf0, 0x01, 0x60, 0x4c, 0x35,
-            0x07, 0xa0, 0x00, 0xa5, 0xfe, 0x91, 0x00, 0x60, 0xa6, 0x03, 0xa9, 0x00, 0x81, 0x10,
-            0xa2, 0x00, 0xa9, 0x01, 0x81, 0x10, 0x60, 0xa2, 0x00, 0xea, 0xea, 0xca, 0xd0, 0xfb,
-            0x60,
-        ];
-
-        self.memory[0x0600..(0x0600 + game_code.len())].copy_from_slice(&game_code[..]);
-        self.mem_write_u16(PC_INIT_LOCATION, 0x0600);
-    }
-
-    pub fn load_program(&mut self, program: &[u8]) {
-        self.memory[PROGRAM_START as usize..(PROGRAM_START as usize + program.len())]
-            .copy_from_slice(&program[..]);
-        self.mem_write_u16(PC_INIT_LOCATION, PROGRAM_START);
-    }
-
-    pub fn tick(&mut self) {
-        if self.debug_active {
-            loop {
-                self.print_debug_info();
-                print!(
-                    "Enter command (n = next mempage, p = previous mempage, <Enter> = continue): "
-                );
-                io::stdout().flush().unwrap();
-                let mut input = String::new();
-                if let Ok(_) = io::stdin().read_line(&mut input) {
-                    match input.trim() {
-                        "n" => self.debug_mem_page = self.debug_mem_page.wrapping_add(1),
-                        "p" => self.debug_mem_page = self.debug_mem_page.wrapping_sub(1),
-                        "" => break,
-                        _ => continue,
+    fn debug_prompt(&mut self) {
+        loop {
+            self.print_debug_info();
+            print!(
+                "Enter command (<Enter> = step, o = step over, u = step out, c = continue, b XXXX = toggle breakpoint, n = next mempage, p = previous mempage): "
+            );
+            io::stdout().flush().unwrap();
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input).is_err() {
+                continue;
+            }
+            let mut words = input.split_whitespace();
+            match (words.next(), words.next()) {
+                (None, _) => {
+                    self.debug_step = true;
+                    break;
+                }
+                (Some("c"), None) => {
+                    self.debug_step = false;
+                    break;
+                }
+                (Some("o"), None) => {
+                    self.step_over();
+                }
+                (Some("u"), None) => {
+                    self.step_out();
+                }
+                (Some("b"), Some(addr)) => {
+                    if let Ok(addr) = u16::from_str_radix(addr, 16)
+                        && !self.remove_breakpoint(addr)
+                    {
+                        self.add_breakpoint(addr);
                     }
                 }
+                (Some("n"), None) => self.debug_mem_page = self.debug_mem_page.wrapping_add(1),
+                (Some("p"), None) => self.debug_mem_page = self.debug_mem_page.wrapping_sub(1),
+                _ => continue,
             }
         }
-        self.execute_current_cycle();
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> CpuStepResult
     where
-        F: FnMut(&mut Cpu),
+        F: FnMut(&mut Self),
     {
-        if !self.running {
-            std::process::exit(0);
+        if self.running {
+            self.execute_cycle(&mut callback);
         }
-        if self.current_inst.is_empty() {
-            callback(self);
-            self.current_opcode = self.mem_read(self.pc);
-            self.pc += 1;
-            self.current_inst = self.decode_opcode(self.current_opcode);
-        } else if let Some(op) = self.current_inst.pop_front() {
-            self.execute_micro_op(op);
+        self.step_result()
+    }
+
+    fn step_result(&self) -> CpuStepResult {
+        if self.jammed || self.trap_halted || self.error.is_some() {
+            CpuStepResult::Halted
+        } else if !self.running {
+            CpuStepResult::Break
+        } else if self.stuck {
+            CpuStepResult::Stuck(self.loop_pc)
+        } else {
+            CpuStepResult::Running
+        }
+    }
+
+    // Reports Stuck once the same instruction has been fetched back to back for at least
+    // `cycles` cycles, e.g. a JMP * or a branch to itself. A JAM already reports Halted.
+    pub fn set_loop_detection(&mut self, cycles: Option<u64>) {
+        self.loop_limit = cycles;
+        self.loop_since = self.cycles;
+        self.stuck = false;
+    }
+
+    fn detect_loop(&mut self) {
+        let Some(limit) = self.loop_limit else {
+            return;
+        };
+        if self.pc != self.loop_pc {
+            self.loop_pc = self.pc;
+            self.loop_since = self.cycles;
+        } else if self.cycles - self.loop_since >= limit {
+            self.stuck = true;
         }
     }
 
     fn execute_current_cycle(&mut self) {
-        if self.current_inst.is_empty() {
-            self.current_opcode = self.mem_read(self.pc);
-            self.pc += 1;
-            self.current_inst = self.decode_opcode(self.current_opcode);
-        } else if let Some(op) = self.current_inst.pop_front() {
+        self.execute_cycle(|_| {});
+    }
+
+    // One CPU cycle, `before_fetch` runs when it's the first cycle of a new instruction, just
+    // before the opcode is fetched.
+    fn execute_cycle(&mut self, before_fetch: impl FnOnce(&mut Self)) {
+        if self.trap_halted || self.error.is_some() {
+            return;
+        }
+        self.cycles += 1;
+        self.clock_system();
+        if self.jammed {
+            return;
+        }
+        if self.dmc_dma.is_some() && self.execute_dmc_cycle() {
+            return;
+        }
+        if self.oam_dma.is_some() {
+            self.execute_dma_cycle();
+        } else if self.current_inst.is_empty() {
+            before_fetch(self);
+            self.fetch_next_instruction();
+        } else {
+            self.execute_next_micro_op();
+        }
+    }
+
+    // the rest of the system runs its share of the cycle before the CPU touches the bus, and
+    // what it raises is seen by this cycle's interrupt poll
+    fn clock_system(&mut self) {
+        let Some(lines) = self.bus.cpu_cycle() else {
+            return;
+        };
+        if lines.nmi {
+            self.nmi_pending = true;
+        }
+        self.irq_line = lines.irq;
+        if let Some(addr) = lines.dmc_request {
+            self.start_dmc_dma(addr);
+        }
+    }
+
+    // returns false when something else gets the bus this cycle: the CPU because it's on a write
+    // the DMC can't halt, or an OAM DMA while the DMC waits for its get cycle
+    fn execute_dmc_cycle(&mut self) -> bool {
+        let Some(mut dma) = self.dmc_dma.take() else {
+            return false;
+        };
+        if dma.is_waiting_for_halt() {
+            match self.pending_read_address() {
+                Some(addr) => dma.halted_addr = addr,
+                None => {
+                    self.dmc_dma = Some(dma);
+                    return false;
+                }
+            }
+        }
+        match dma.next_cycle(self.cycles) {
+            DmaCycle::Halt | DmaCycle::Align => {
+                self.dmc_dma = Some(dma);
+                if self.oam_dma.is_some() {
+                    return false;
+                }
+                self.bus_read(dma.halted_addr);
+            }
+            DmaCycle::Read(addr) => {
+                let sample = self.bus_read(addr);
+                if !self.bus.dmc_sample(sample) {
+                    self.dmc_sample = Some(sample);
+                }
+            }
+            DmaCycle::Write(_) => unreachable!("DMC DMA only reads"),
+        }
+        true
+    }
+
+    // address the next CPU cycle reads, None when it writes. Cycles that don't touch the bus
+    // in this core read PC on the real chip, so that's what they report
+    fn pending_read_address(&self) -> Option<u16> {
+        if self.oam_dma.is_some() || self.current_inst.is_empty() {
+            return Some(self.pc);
+        }
+        let stack = STACK_BOTTOM + self.sp as u16;
+        match self.current_inst.ops[self.current_inst.front] {
+            MicroOp::PushAccumulator
+            | MicroOp::PushStatusBrkPhp
+            | MicroOp::PushStatusInterrupt
+            | MicroOp::PushPCH
+            | MicroOp::PushPCL
+            | MicroOp::WriteToAddress
+            | MicroOp::WriteBackAndIncrement
+            | MicroOp::WriteBackAndDecrement
+            | MicroOp::StoreAccumulator
+            | MicroOp::StoreX
+            | MicroOp::StoreY
+            | MicroOp::StoreAccumulatorAndX
+            | MicroOp::StoreAccumulatorXHigh
+            | MicroOp::StoreXHigh
+            | MicroOp::StoreYHigh
+            | MicroOp::StoreStackPointerHigh
+            | MicroOp::ArithmeticShiftLeftAddress
+            | MicroOp::LogicalShiftRightAddress
+            | MicroOp::RotateLeftAddress
+            | MicroOp::RotateRightAddress
+            | MicroOp::ShiftLeftInclusiveOrAddress
+            | MicroOp::RotateLeftLogicalAndAddress
+            | MicroOp::ShiftRightExclusiveOrAddress
+            | MicroOp::RotateRightAddWithCarryAddress
+            | MicroOp::DecrementCompareAddress
+            | MicroOp::IncrementSubWithCarryAddress => None,
+            MicroOp::ReadAddress
+            | MicroOp::ReadLowFromIndirect
+            | MicroOp::FetchPointerLowByte
+            | MicroOp::LoadAccumulatorFromAddress
+            | MicroOp::LoadXfromAddress
+            | MicroOp::LoadYfromAddress
+            | MicroOp::LoadAccumulatorXFromAddress
+            | MicroOp::LoadAccumulatorXStackPointerFromAddress
+            | MicroOp::LogicalAndAddress
+            | MicroOp::ExclusiveOrAddress
+            | MicroOp::InclusiveOrAddress
+            | MicroOp::BitTestAddress
+            | MicroOp::AddWithCarryAddress
+            | MicroOp::SubWithCarryAddress
+            | MicroOp::CompareAddress
+            | MicroOp::CompareXAddress
+            | MicroOp::CompareYAddress => Some(self.temp_addr),
+            MicroOp::FetchPointerHighByte | MicroOp::FetchPointerHighByteWithY => {
+                Some((self.temp_ptr as u8).wrapping_add(1) as u16)
+            }
+            MicroOp::ReadHighFromIndirectLatch => Some(if self.temp_addr as u8 == 0xFF {
+                self.temp_addr & 0xFF00
+            } else {
+                self.temp_addr + 1
+            }),
+            MicroOp::ReadUnfixedAddress => Some(self.unfixed_addr),
+            MicroOp::PullAccumulator
+            | MicroOp::PullStatus
+            | MicroOp::PullStatusIncrementSP
+            | MicroOp::PullPCL
+            | MicroOp::PullPCH => Some(stack),
+            MicroOp::FetchInterruptLow => Some(self.interrupt_vector),
+            MicroOp::FetchInterruptHigh => Some(self.interrupt_vector + 1),
+            _ => Some(self.pc),
+        }
+    }
+
+    // called by the APU when the DMC needs its next sample byte
+    pub fn start_dmc_dma(&mut self, addr: u16) {
+        self.dmc_dma = Some(DmcDma::new(addr));
+    }
+
+    // the byte fetched by the last DMC DMA, once it has completed, when the bus didn't take it
+    pub fn take_dmc_sample(&mut self) -> Option<u8> {
+        self.dmc_sample.take()
+    }
+
+    // the CPU is parked at the next instruction fetch while OAM DMA runs
+    fn execute_dma_cycle(&mut self) {
+        let Some(mut dma) = self.oam_dma.take() else {
+            return;
+        };
+        match dma.next_cycle(self.cycles) {
+            DmaCycle::Halt | DmaCycle::Align => {
+                self.bus_read(self.pc);
+            }
+            DmaCycle::Read(addr) => dma.data = self.bus_read(addr),
+            DmaCycle::Write(addr) => self.bus_write(addr, dma.data),
+        }
+        if !dma.is_done() {
+            self.oam_dma = Some(dma);
+        }
+    }
+
+    fn poll_interrupts(&mut self) {
+        self.irq_inhibit = self.status_p & FLAG_INTERRUPT != 0;
+        self.polled_irq = self.irq_line && !self.irq_inhibit;
+        self.polled_nmi = self.nmi_pending;
+    }
+
+    fn execute_next_micro_op(&mut self) {
+        if let Some(op) = self.current_inst.pop_front() {
+            // Interrupts are polled before the last cycle, so CLI/SEI/PLP only count after the
+            // next instruction. A taken branch's last cycle is TakeBranch, which only polls
+            // again if it crosses a page and turns into a fixup.
+            if self.current_inst.is_empty() && !matches!(op, MicroOp::TakeBranch(_)) {
+                self.poll_interrupts();
+            }
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.count_cycle();
+            }
+            let Some(mut hook) = self.micro_op_hook.take() else {
+                self.execute_micro_op(op);
+                return;
+            };
+            hook.before(&self.micro_op_event(op));
             self.execute_micro_op(op);
+            hook.after(&self.micro_op_event(op));
+            self.micro_op_hook = Some(hook);
+        }
+    }
+
+    // instruction boundary: either decode the next opcode or, if an interrupt is pending,
+    // throw the fetched opcode away and run the interrupt sequence instead
+    fn fetch_next_instruction(&mut self) {
+        self.bus.begin_instruction(self.pc);
+        self.current_opcode = self.bus_read(self.pc);
+        let (nmi, irq) = if self.poll_skipped {
+            self.poll_skipped = false;
+            (self.polled_nmi, self.polled_irq)
+        } else {
+            (self.nmi_pending, self.irq_line && !self.irq_inhibit)
+        };
+        if nmi || irq {
+            // a loop that gets interrupted is waiting for something, not stuck
+            self.loop_since = self.cycles;
+        }
+        if nmi {
+            self.current_inst = Self::dispatch_interrupt();
+            self.interrupt = Interrupt::Nmi;
+            self.call_depth += 1;
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.begin_interrupt();
+            }
+        } else if irq {
+            self.current_inst = Self::dispatch_interrupt();
+            self.interrupt = Interrupt::Irq;
+            self.call_depth += 1;
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.begin_interrupt();
+            }
+        } else {
+            self.detect_loop();
+            self.trace_instruction();
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.begin_instruction(self.current_opcode);
+            }
+            self.pc = self.pc.wrapping_add(1);
+            self.current_inst = self.decode_opcode(self.current_opcode);
+        }
+    }
+
+    fn trace_instruction(&mut self) {
+        if let Some(mut sink) = self.trace_sink.take() {
+            // the fetch cycle is already counted, traces show the count before it
+            let entry = trace::entry_at(self, self.cycles - 1);
+            sink.trace(&entry);
+            self.trace_sink = Some(sink);
         }
     }
 
-    fn print_debug_info(&self) {
+    fn dispatch_interrupt() -> InstructionQueue {
+        let mut queue = InstructionQueue::new();
+        queue.push_back(MicroOp::DummyCycle);
+        queue.push_back(MicroOp::PushPCH);
+        queue.push_back(MicroOp::PushPCL);
+        queue.push_back(MicroOp::PushStatusInterrupt);
+        queue.push_back(MicroOp::FetchInterruptLow);
+        queue.push_back(MicroOp::FetchInterruptHigh);
+        queue
+    }
+
+    fn print_debug_info(&mut self) {
         print!("{}", CLS);
         println!(
             "PC: {:04X} | SP: {:02X} | OP: {:02X}",
@@ -670,1081 +1469,268 @@ impl Cpu {
             self.index_x, self.index_y, self.accumulator
         );
         println!("P: {:b}", self.status_p);
-        println!(
-            "temp_addr: {:04X} val: {:02X}",
-            self.temp_addr,
-            self.mem_read(self.temp_addr)
-        );
+        let mut breakpoints: Vec<&u16> = self.breakpoints.iter().collect();
+        breakpoints.sort();
+        print!("Breakpoints:");
+        for addr in breakpoints {
+            print!(" {:04X}", addr);
+        }
+        println!();
+        let temp_val = self.mem_peek(self.temp_addr);
+        println!("temp_addr: {:04X} val: {:02X}", self.temp_addr, temp_val);
 
         println!("Memory page {:02X}:", self.debug_mem_page);
         for i in 0..=0xFF {
-            print!(
-                "{:02X} ",
-                self.memory[(self.debug_mem_page << 2 | i) as usize]
-            );
+            let value = self.mem_peek(((self.debug_mem_page as u16) << 8) | i);
+            print!("{:02X} ", value);
         }
-        println!("");
+        println!();
     }
 
     fn decode_opcode(&mut self, opcode: u8) -> InstructionQueue {
+        let info = opcode_info(opcode);
         let mut queue = InstructionQueue::new();
-        match opcode {
-            0xA9 => {
-                // LDA
-                queue.push_back(MicroOp::LoadAccumulator);
-            }
-            0xA5 => {
-                // LDA zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::LoadAccumulatorFromAddress,
-                    InstType::Read,
-                )
-            }
-            0xB5 => {
-                // LDA zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::LoadAccumulatorFromAddress,
-                    InstType::Read,
-                )
-            }
-            0xAD => {
-                // LDA absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::LoadAccumulatorFromAddress,
-                    InstType::Read,
-                );
-            }
-            0xBD => {
-                // LDA absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::LoadAccumulatorFromAddress,
-                    InstType::Read,
-                );
-            }
-            0xB9 => {
-                // LDA absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::LoadAccumulatorFromAddress,
-                    InstType::Read,
-                );
-            }
-            0xA1 => {
-                // LDA indexed indirect
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndexedIndirect,
-                    MicroOp::LoadAccumulatorFromAddress,
-                    InstType::Read,
-                );
-            }
-            0xB1 => {
-                // LDA indirect indexed
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndirectIndexed,
-                    MicroOp::LoadAccumulatorFromAddress,
-                    InstType::Read,
-                );
-            }
-            0xA2 => {
-                // LDX
-                queue.push_back(MicroOp::LoadX);
-            }
-            0xA6 => {
-                // LDX zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::LoadXfromAddress,
-                    InstType::Read,
-                );
-            }
-            0xB6 => {
-                // LDX zero page + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageY,
-                    MicroOp::LoadXfromAddress,
-                    InstType::Read,
-                );
-            }
-            0xAE => {
-                // LDX absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::LoadXfromAddress,
-                    InstType::Read,
-                );
-            }
-            0xBE => {
-                // LDX absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::LoadXfromAddress,
-                    InstType::Read,
-                );
-            }
-            0xA0 => {
-                // LDY immediate
-                queue.push_back(MicroOp::LoadY);
-            }
-            0xA4 => {
-                // LDY zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::LoadYfromAddress,
-                    InstType::Read,
-                );
-            }
-            0xB4 => {
-                // LDY zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageY,
-                    MicroOp::LoadYfromAddress,
-                    InstType::Read,
-                );
-            }
-            0xAC => {
-                // LDY absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::LoadYfromAddress,
-                    InstType::Read,
-                );
-            }
-            0xBC => {
-                // LDY absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::LoadYfromAddress,
-                    InstType::Read,
-                );
-            }
-            0x85 => {
-                // STA zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::StoreAccumulator,
-                    InstType::Write,
-                );
-            }
-            0x95 => {
-                // STA zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::StoreAccumulator,
-                    InstType::Write,
-                );
-            }
-            0x8D => {
-                // STA absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::StoreAccumulator,
-                    InstType::Write,
-                );
-            }
-            0x9D => {
-                // STA absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::StoreAccumulator,
-                    InstType::Write,
-                );
-            }
-            0x99 => {
-                // STA absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::StoreAccumulator,
-                    InstType::Write,
-                );
-            }
-            0x81 => {
-                // STA indexed indirect
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndexedIndirect,
-                    MicroOp::StoreAccumulator,
-                    InstType::Write,
-                );
-            }
-            0x91 => {
-                //STA indirect indexed
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndirectIndexed,
-                    MicroOp::StoreAccumulator,
-                    InstType::Write,
-                );
-            }
-            0x86 => {
-                // STX zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::StoreX,
-                    InstType::Write,
-                );
-            }
-            0x96 => {
-                // STX zero page + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageY,
-                    MicroOp::StoreX,
-                    InstType::Write,
-                );
-            }
-            0x8E => {
-                // STX absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::StoreX,
-                    InstType::Write,
-                );
-            }
-            0x84 => {
-                // STY zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::StoreY,
-                    InstType::Write,
-                );
-            }
-            0x94 => {
-                // STY zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::StoreY,
-                    InstType::Write,
-                );
-            }
-            0x8C => {
-                // STY absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::StoreY,
-                    InstType::Write,
-                );
-            }
-            0xAA => {
-                // TAX
-                queue.push_back(MicroOp::LoadXAccumulator);
-            }
-            0xA8 => {
-                // TAY
-                queue.push_back(MicroOp::LoadYAccumulator);
-            }
-            0xBA => {
-                // TSX
-                queue.push_back(MicroOp::LoadXStackPointer);
-            }
-            0x8A => {
-                // TXA
-                queue.push_back(MicroOp::LoadAccumulatorX);
-            }
-            0x9A => {
-                // TXS
-                queue.push_back(MicroOp::LoadStackPointerX);
-            }
-            0x98 => {
-                // TYA
-                queue.push_back(MicroOp::LoadAccumulatorY);
-            }
-            0x48 => {
-                // PHA
+        match info.mode {
+            AddressingMode::Immediate => {
+                if let Some(op) = Self::immediate_op(info.mnemonic) {
+                    queue.push_back(op);
+                    return queue;
+                }
+            }
+            AddressingMode::Implied | AddressingMode::Accumulator => {
+                if let Some(op) = Self::implied_op(info.mnemonic) {
+                    queue.push_back(op);
+                    return queue;
+                }
+            }
+            AddressingMode::Indirect | AddressingMode::Relative => {}
+            mode => {
+                if let Some((op, inst_type)) = Self::memory_op(info.mnemonic) {
+                    return Self::dispatch_generic_instruction(mode, op, inst_type);
+                }
+            }
+        }
+
+        match info.mnemonic {
+            Mnemonic::Pha => {
                 queue.push_back(MicroOp::DummyCycle);
                 queue.push_back(MicroOp::PushAccumulator);
             }
-            0x08 => {
-                // PHP
+            Mnemonic::Php => {
                 queue.push_back(MicroOp::DummyCycle);
                 queue.push_back(MicroOp::PushStatusBrkPhp);
             }
-            0x68 => {
-                // PLA
+            Mnemonic::Pla => {
                 queue.push_back(MicroOp::DummyCycle);
                 queue.push_back(MicroOp::IncrementSP(1));
                 queue.push_back(MicroOp::PullAccumulator);
             }
-            0x28 => {
-                // PLP
+            Mnemonic::Plp => {
                 queue.push_back(MicroOp::DummyCycle);
                 queue.push_back(MicroOp::IncrementSP(1));
                 queue.push_back(MicroOp::PullStatus);
             }
-            0x29 => {
-                // AND Immediate
-                queue.push_back(MicroOp::LogicalAnd);
-            }
-            0x25 => {
-                // AND zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::LogicalAndAddress,
-                    InstType::Read,
-                );
-            }
-            0x35 => {
-                // AND zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::LogicalAndAddress,
-                    InstType::Read,
-                );
-            }
-            0x2D => {
-                // AND absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::LogicalAndAddress,
-                    InstType::Read,
-                );
-            }
-            0x3D => {
-                // AND absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::LogicalAndAddress,
-                    InstType::Read,
-                );
-            }
-            0x39 => {
-                // AND absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::LogicalAndAddress,
-                    InstType::Read,
-                );
-            }
-            0x21 => {
-                // AND indexed indirect
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndexedIndirect,
-                    MicroOp::LogicalAndAddress,
-                    InstType::Read,
-                );
-            }
-            0x31 => {
-                // AND indirect indexed
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndirectIndexed,
-                    MicroOp::LogicalAndAddress,
-                    InstType::Read,
-                );
-            }
-            0x49 => {
-                // EOR immediate
-                queue.push_back(MicroOp::ExclusiveOr);
-            }
-            0x45 => {
-                // EOR zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::ExclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x55 => {
-                // EOR zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::ExclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x4D => {
-                // EOR absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::ExclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x5D => {
-                // EOR absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::ExclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x59 => {
-                // EOR absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::ExclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x41 => {
-                // EOR indexed indirect
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndexedIndirect,
-                    MicroOp::ExclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x51 => {
-                // EOR indirect indexed
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndirectIndexed,
-                    MicroOp::ExclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x09 => {
-                // ORA immediate
-                queue.push_back(MicroOp::InclusiveOr);
-            }
-            0x05 => {
-                // ORA zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::InclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x15 => {
-                // ORA zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::InclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x0D => {
-                // ORA absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::InclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x1D => {
-                // ORA absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::InclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x19 => {
-                // ORA absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::InclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x01 => {
-                // ORA indexed indirect
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndexedIndirect,
-                    MicroOp::InclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x11 => {
-                // ORA indirect indexed
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndirectIndexed,
-                    MicroOp::InclusiveOrAddress,
-                    InstType::Read,
-                );
-            }
-            0x24 => {
-                // BIT zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::BitTestAddress,
-                    InstType::Read,
-                );
-            }
-            0x2C => {
-                // BIT absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::BitTestAddress,
-                    InstType::Read,
-                );
-            }
-            0x69 => {
-                // ADC
-                queue.push_back(MicroOp::AddWithCarry);
-            }
-            0x65 => {
-                // ADC zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::AddWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0x75 => {
-                // ADC zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::AddWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0x6D => {
-                // ADC absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::AddWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0x7D => {
-                // ADC absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::AddWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0x79 => {
-                // ADC absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::AddWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0x61 => {
-                // ADC indexed indirect
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndexedIndirect,
-                    MicroOp::AddWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0x71 => {
-                // ADC indirect indexed
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndirectIndexed,
-                    MicroOp::AddWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0xE9 => {
-                // SBC
-                queue.push_back(MicroOp::SubWithCarry);
-            }
-            0xE5 => {
-                // SBC zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::SubWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0xF5 => {
-                // SBC zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::SubWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0xED => {
-                // SBC absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::SubWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0xFD => {
-                // SBC absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::SubWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0xF9 => {
-                // SBC absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::SubWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0xE1 => {
-                // SBC indexed indirect
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndexedIndirect,
-                    MicroOp::SubWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0xF1 => {
-                // SBC indirect indexed
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndirectIndexed,
-                    MicroOp::SubWithCarryAddress,
-                    InstType::Read,
-                );
-            }
-            0xC9 => {
-                // CMP
-                queue.push_back(MicroOp::Compare);
-            }
-            0xC5 => {
-                // CMP zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::CompareAddress,
-                    InstType::Read,
-                );
-            }
-            0xD5 => {
-                // CMP zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::CompareAddress,
-                    InstType::Read,
-                );
-            }
-            0xCD => {
-                // CMP absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::CompareAddress,
-                    InstType::Read,
-                );
-            }
-            0xDD => {
-                // CMP absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::CompareAddress,
-                    InstType::Read,
-                );
-            }
-            0xD9 => {
-                // CMP absolute + y
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteY,
-                    MicroOp::CompareAddress,
-                    InstType::Read,
-                );
-            }
-            0xC1 => {
-                // CMP indexed indirect
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndexedIndirect,
-                    MicroOp::CompareAddress,
-                    InstType::Read,
-                );
-            }
-            0xD1 => {
-                // CMP indirect indexed
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::IndirectIndexed,
-                    MicroOp::CompareAddress,
-                    InstType::Read,
-                );
-            }
-            0xE0 => {
-                // CPX
-                queue.push_back(MicroOp::CompareX);
-            }
-            0xE4 => {
-                // CPX zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::CompareXAddress,
-                    InstType::Read,
-                );
-            }
-            0xEC => {
-                // CPX absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::CompareXAddress,
-                    InstType::Read,
-                );
-            }
-            0xC0 => {
-                // CPY
-                queue.push_back(MicroOp::CompareY);
-            }
-            0xC4 => {
-                // CPY zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::CompareYAddress,
-                    InstType::Read,
-                );
-            }
-            0xCC => {
-                // CPY absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::CompareYAddress,
-                    InstType::Read,
-                );
-            }
-            0x0A => {
-                // ASL
-                queue.push_back(MicroOp::ArithmeticShiftLeft);
-            }
-            0x06 => {
-                // ASL zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::ArithmeticShiftLeftAddress,
-                    InstType::RMW,
-                );
-            }
-            0x16 => {
-                // ASL zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::ArithmeticShiftLeftAddress,
-                    InstType::RMW,
-                );
-            }
-            0x0E => {
-                // ASL absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::ArithmeticShiftLeftAddress,
-                    InstType::RMW,
-                );
-            }
-            0x1E => {
-                // ASL absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::ArithmeticShiftLeftAddress,
-                    InstType::RMW,
-                );
-            }
-            0x4A => {
-                // LSR
-                queue.push_back(MicroOp::LogicalShiftRight);
-            }
-            0x46 => {
-                // LSR zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::LogicalShiftRightAddress,
-                    InstType::RMW,
-                );
-            }
-            0x56 => {
-                // LSR zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::LogicalShiftRightAddress,
-                    InstType::RMW,
-                );
-            }
-            0x4E => {
-                // LSR absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::LogicalShiftRightAddress,
-                    InstType::RMW,
-                );
-            }
-            0x5E => {
-                // LSR absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::LogicalShiftRightAddress,
-                    InstType::RMW,
-                );
-            }
-            0x2A => {
-                // ROL
-                queue.push_back(MicroOp::RotateLeft);
-            }
-            0x26 => {
-                // ROL zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::RotateLeftAddress,
-                    InstType::RMW,
-                );
-            }
-            0x36 => {
-                // ROL zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::RotateLeftAddress,
-                    InstType::RMW,
-                );
-            }
-            0x2E => {
-                // ROL absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::RotateLeftAddress,
-                    InstType::RMW,
-                );
-            }
-            0x3E => {
-                // ROL absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::RotateLeftAddress,
-                    InstType::RMW,
-                );
-            }
-            0x6A => {
-                // ROR
-                queue.push_back(MicroOp::RotateRight);
-            }
-            0x66 => {
-                // ROR zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::RotateRightAddress,
-                    InstType::RMW,
-                );
-            }
-            0x76 => {
-                // ROR zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::RotateRightAddress,
-                    InstType::RMW,
-                );
-            }
-            0x6E => {
-                // ROR absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::RotateRightAddress,
-                    InstType::RMW,
-                );
-            }
-            0x7E => {
-                // ROR absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::RotateRightAddress,
-                    InstType::RMW,
-                );
-            }
-            0xE6 => {
-                // INC zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::WriteBackAndIncrement,
-                    InstType::RMW,
-                );
-            }
-            0xF6 => {
-                // INC zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::WriteBackAndIncrement,
-                    InstType::RMW,
-                );
-            }
-            0xEE => {
-                // INC absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::WriteBackAndIncrement,
-                    InstType::RMW,
-                );
-            }
-            0xFE => {
-                // INC absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::WriteBackAndIncrement,
-                    InstType::RMW,
-                );
-            }
-            0xE8 => {
-                // INX
-                queue.push_back(MicroOp::IncrementX);
-            }
-            0xCA => {
-                // DEX
-                queue.push_back(MicroOp::DecrementX);
-            }
-            0xC8 => {
-                // INY
-                queue.push_back(MicroOp::IncrementY);
-            }
-            0x88 => {
-                // DEY
-                queue.push_back(MicroOp::DecrementY);
-            }
-            0xC6 => {
-                // DEC zero page
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPage,
-                    MicroOp::WriteBackAndDecrement,
-                    InstType::RMW,
-                );
-            }
-            0xD6 => {
-                // DEC zero page + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::ZeroPageX,
-                    MicroOp::WriteBackAndDecrement,
-                    InstType::RMW,
-                );
-            }
-            0xCE => {
-                // DEC absolute
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::Absolute,
-                    MicroOp::WriteBackAndDecrement,
-                    InstType::RMW,
-                );
-            }
-            0xDE => {
-                // DEC absolute + x
-                return Cpu::dispatch_generic_instruction(
-                    AddressingMode::AbsoluteX,
-                    MicroOp::WriteBackAndDecrement,
-                    InstType::RMW,
-                );
-            }
-            0x4C => {
-                // JMP absolute
+            Mnemonic::Jmp if info.mode == AddressingMode::Absolute => {
                 queue.push_back(MicroOp::FetchLowAddrByte);
                 queue.push_back(MicroOp::CopyLowFetchHightoPC);
             }
-            0x6C => {
-                // JMP indirect
+            Mnemonic::Jmp => {
                 queue.push_back(MicroOp::FetchLowAddrByte);
                 queue.push_back(MicroOp::FetchHighAddrByte);
                 queue.push_back(MicroOp::ReadLowFromIndirect);
                 queue.push_back(MicroOp::ReadHighFromIndirectLatch);
             }
-            0x20 => {
-                // JSR
+            Mnemonic::Jsr => {
+                self.call_depth += 1;
                 queue.push_back(MicroOp::FetchLowAddrByte);
                 queue.push_back(MicroOp::DummyCycle); //TODO: this isn't actually performing a dummy read. see if it brings problems.
                 queue.push_back(MicroOp::PushPCH);
                 queue.push_back(MicroOp::PushPCL);
                 queue.push_back(MicroOp::CopyLowFetchHightoPC);
             }
-            0x60 => {
-                // RTS
+            Mnemonic::Rts => {
+                self.call_depth -= 1;
                 queue.push_back(MicroOp::DummyCycle);
                 queue.push_back(MicroOp::IncrementSP(1));
                 queue.push_back(MicroOp::PullPCL);
                 queue.push_back(MicroOp::PullPCH);
                 queue.push_back(MicroOp::IncrementPC);
             }
-            0x90 => {
-                // BCC
-                queue.push_back(MicroOp::FetchRelativeOffset(
-                    self.status_p & FLAG_CARRY,
-                    0x00,
-                ));
-            }
-            0xB0 => {
-                // BCS
-                queue.push_back(MicroOp::FetchRelativeOffset(
-                    self.status_p & FLAG_CARRY,
-                    FLAG_CARRY,
-                ));
-            }
-            0xF0 => {
-                // BEQ
-                queue.push_back(MicroOp::FetchRelativeOffset(
-                    self.status_p & FLAG_ZERO,
-                    FLAG_ZERO,
-                ));
-            }
-            0xD0 => {
-                // BNE
-                queue.push_back(MicroOp::FetchRelativeOffset(
-                    self.status_p & FLAG_ZERO,
-                    0x00,
-                ));
-            }
-            0x30 => {
-                // BMI
-                queue.push_back(MicroOp::FetchRelativeOffset(
-                    self.status_p & FLAG_NEGATIVE,
-                    FLAG_NEGATIVE,
-                ));
-            }
-            0x10 => {
-                // BPL
-                queue.push_back(MicroOp::FetchRelativeOffset(
-                    self.status_p & FLAG_NEGATIVE,
-                    0x00,
-                ));
-            }
-            0x50 => {
-                // BVC
-                queue.push_back(MicroOp::FetchRelativeOffset(
-                    self.status_p & FLAG_OVERFLOW,
-                    0x00,
-                ));
-            }
-            0x70 => {
-                // BVS
-                queue.push_back(MicroOp::FetchRelativeOffset(
-                    self.status_p & FLAG_OVERFLOW,
-                    FLAG_OVERFLOW,
-                ));
-            }
-            0x18 => {
-                // CLC
-                queue.push_back(MicroOp::ClearCarry);
-            }
-            0x38 => {
-                // SEC
-                queue.push_back(MicroOp::SetCarry);
-            }
-            0xD8 => {
-                // CLD
-                queue.push_back(MicroOp::ClearDecimalMode);
-            }
-            0xF8 => {
-                // SED
-                queue.push_back(MicroOp::SetDecimalMode);
-            }
-            0x78 => {
-                // SEI
-                queue.push_back(MicroOp::SetInterrupt);
-            }
-            0x58 => {
-                // CLI
-                queue.push_back(MicroOp::ClearInterrupt);
-            }
-            0xB8 => {
-                // CLV
-                queue.push_back(MicroOp::ClearOverflow);
-            }
-            0xEA => {
-                // NOP
+            Mnemonic::Rti => {
+                self.call_depth -= 1;
                 queue.push_back(MicroOp::DummyCycle);
+                queue.push_back(MicroOp::IncrementSP(1));
+                queue.push_back(MicroOp::PullStatusIncrementSP);
+                queue.push_back(MicroOp::PullPCL);
+                queue.push_back(MicroOp::PullPCH);
             }
-            0x00 => {
-                // BRK
+            Mnemonic::Brk => {
+                self.interrupt = Interrupt::Brk;
+                self.call_depth += 1;
                 queue.push_back(MicroOp::IncrementPC2);
                 queue.push_back(MicroOp::PushPCH);
                 queue.push_back(MicroOp::PushPCL);
-                queue.push_back(MicroOp::PushStatusBrkPhp);
+                queue.push_back(MicroOp::PushStatusInterrupt);
                 queue.push_back(MicroOp::FetchInterruptLow);
                 queue.push_back(MicroOp::FetchInterruptHigh);
             }
-            0x40 => {
-                // RTI
-                queue.push_back(MicroOp::DummyCycle);
-                queue.push_back(MicroOp::IncrementSP(1));
-                queue.push_back(MicroOp::PullStatus);
-                queue.push_back(MicroOp::PullPCL);
-                queue.push_back(MicroOp::PullPCH);
+            // branches take the flag value they're testing and the value that makes them taken
+            Mnemonic::Bcc => queue.push_back(MicroOp::FetchRelativeOffset(
+                self.status_p & FLAG_CARRY,
+                0x00,
+            )),
+            Mnemonic::Bcs => queue.push_back(MicroOp::FetchRelativeOffset(
+                self.status_p & FLAG_CARRY,
+                FLAG_CARRY,
+            )),
+            Mnemonic::Beq => queue.push_back(MicroOp::FetchRelativeOffset(
+                self.status_p & FLAG_ZERO,
+                FLAG_ZERO,
+            )),
+            Mnemonic::Bne => queue.push_back(MicroOp::FetchRelativeOffset(
+                self.status_p & FLAG_ZERO,
+                0x00,
+            )),
+            Mnemonic::Bmi => queue.push_back(MicroOp::FetchRelativeOffset(
+                self.status_p & FLAG_NEGATIVE,
+                FLAG_NEGATIVE,
+            )),
+            Mnemonic::Bpl => queue.push_back(MicroOp::FetchRelativeOffset(
+                self.status_p & FLAG_NEGATIVE,
+                0x00,
+            )),
+            Mnemonic::Bvc => queue.push_back(MicroOp::FetchRelativeOffset(
+                self.status_p & FLAG_OVERFLOW,
+                0x00,
+            )),
+            Mnemonic::Bvs => queue.push_back(MicroOp::FetchRelativeOffset(
+                self.status_p & FLAG_OVERFLOW,
+                FLAG_OVERFLOW,
+            )),
+            _ => return self.illegal_opcode(opcode),
+        }
+        queue
+    }
+
+    // single micro-op instructions that take their operand from the byte after the opcode
+    fn immediate_op(mnemonic: Mnemonic) -> Option<MicroOp> {
+        let op = match mnemonic {
+            Mnemonic::Lda => MicroOp::LoadAccumulator,
+            Mnemonic::Ldx => MicroOp::LoadX,
+            Mnemonic::Ldy => MicroOp::LoadY,
+            Mnemonic::And => MicroOp::LogicalAnd,
+            Mnemonic::Eor => MicroOp::ExclusiveOr,
+            Mnemonic::Ora => MicroOp::InclusiveOr,
+            Mnemonic::Adc => MicroOp::AddWithCarry,
+            Mnemonic::Sbc => MicroOp::SubWithCarry,
+            Mnemonic::Cmp => MicroOp::Compare,
+            Mnemonic::Cpx => MicroOp::CompareX,
+            Mnemonic::Cpy => MicroOp::CompareY,
+            Mnemonic::Anc => MicroOp::AndWithCarry,
+            Mnemonic::Alr => MicroOp::AndShiftRight,
+            Mnemonic::Arr => MicroOp::AndRotateRight,
+            Mnemonic::Axs => MicroOp::AndXSubtract,
+            // the operand is read and ignored
+            Mnemonic::Nop => MicroOp::ReadImmediate,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // single micro-op instructions working on registers only
+    fn implied_op(mnemonic: Mnemonic) -> Option<MicroOp> {
+        let op = match mnemonic {
+            Mnemonic::Tax => MicroOp::LoadXAccumulator,
+            Mnemonic::Tay => MicroOp::LoadYAccumulator,
+            Mnemonic::Tsx => MicroOp::LoadXStackPointer,
+            Mnemonic::Txa => MicroOp::LoadAccumulatorX,
+            Mnemonic::Txs => MicroOp::LoadStackPointerX,
+            Mnemonic::Tya => MicroOp::LoadAccumulatorY,
+            Mnemonic::Inx => MicroOp::IncrementX,
+            Mnemonic::Iny => MicroOp::IncrementY,
+            Mnemonic::Dex => MicroOp::DecrementX,
+            Mnemonic::Dey => MicroOp::DecrementY,
+            Mnemonic::Asl => MicroOp::ArithmeticShiftLeft,
+            Mnemonic::Lsr => MicroOp::LogicalShiftRight,
+            Mnemonic::Rol => MicroOp::RotateLeft,
+            Mnemonic::Ror => MicroOp::RotateRight,
+            Mnemonic::Clc => MicroOp::ClearCarry,
+            Mnemonic::Sec => MicroOp::SetCarry,
+            Mnemonic::Cld => MicroOp::ClearDecimalMode,
+            Mnemonic::Sed => MicroOp::SetDecimalMode,
+            Mnemonic::Cli => MicroOp::ClearInterrupt,
+            Mnemonic::Sei => MicroOp::SetInterrupt,
+            Mnemonic::Clv => MicroOp::ClearOverflow,
+            Mnemonic::Nop => MicroOp::DummyCycle,
+            // the CPU locks up until reset, PC stays right after the JAM opcode
+            Mnemonic::Jam => MicroOp::Jam,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // instructions built by dispatch_generic_instruction from their addressing mode
+    fn memory_op(mnemonic: Mnemonic) -> Option<(MicroOp, InstType)> {
+        let op = match mnemonic {
+            Mnemonic::Lda => (MicroOp::LoadAccumulatorFromAddress, InstType::Read),
+            Mnemonic::Ldx => (MicroOp::LoadXfromAddress, InstType::Read),
+            Mnemonic::Ldy => (MicroOp::LoadYfromAddress, InstType::Read),
+            Mnemonic::And => (MicroOp::LogicalAndAddress, InstType::Read),
+            Mnemonic::Eor => (MicroOp::ExclusiveOrAddress, InstType::Read),
+            Mnemonic::Ora => (MicroOp::InclusiveOrAddress, InstType::Read),
+            Mnemonic::Bit => (MicroOp::BitTestAddress, InstType::Read),
+            Mnemonic::Adc => (MicroOp::AddWithCarryAddress, InstType::Read),
+            Mnemonic::Sbc => (MicroOp::SubWithCarryAddress, InstType::Read),
+            Mnemonic::Cmp => (MicroOp::CompareAddress, InstType::Read),
+            Mnemonic::Cpx => (MicroOp::CompareXAddress, InstType::Read),
+            Mnemonic::Cpy => (MicroOp::CompareYAddress, InstType::Read),
+            Mnemonic::Lax => (MicroOp::LoadAccumulatorXFromAddress, InstType::Read),
+            Mnemonic::Las => (MicroOp::LoadAccumulatorXStackPointerFromAddress, InstType::Read),
+            Mnemonic::Nop => (MicroOp::ReadAddress, InstType::Read),
+            Mnemonic::Sta => (MicroOp::StoreAccumulator, InstType::Write),
+            Mnemonic::Stx => (MicroOp::StoreX, InstType::Write),
+            Mnemonic::Sty => (MicroOp::StoreY, InstType::Write),
+            Mnemonic::Sax => (MicroOp::StoreAccumulatorAndX, InstType::Write),
+            // SHA/SHX/SHY/TAS are unstable, see store_high_and
+            Mnemonic::Sha => (MicroOp::StoreAccumulatorXHigh, InstType::Write),
+            Mnemonic::Shx => (MicroOp::StoreXHigh, InstType::Write),
+            Mnemonic::Shy => (MicroOp::StoreYHigh, InstType::Write),
+            Mnemonic::Tas => (MicroOp::StoreStackPointerHigh, InstType::Write),
+            Mnemonic::Asl => (MicroOp::ArithmeticShiftLeftAddress, InstType::Rmw),
+            Mnemonic::Lsr => (MicroOp::LogicalShiftRightAddress, InstType::Rmw),
+            Mnemonic::Rol => (MicroOp::RotateLeftAddress, InstType::Rmw),
+            Mnemonic::Ror => (MicroOp::RotateRightAddress, InstType::Rmw),
+            Mnemonic::Inc => (MicroOp::WriteBackAndIncrement, InstType::Rmw),
+            Mnemonic::Dec => (MicroOp::WriteBackAndDecrement, InstType::Rmw),
+            Mnemonic::Slo => (MicroOp::ShiftLeftInclusiveOrAddress, InstType::Rmw),
+            Mnemonic::Rla => (MicroOp::RotateLeftLogicalAndAddress, InstType::Rmw),
+            Mnemonic::Sre => (MicroOp::ShiftRightExclusiveOrAddress, InstType::Rmw),
+            Mnemonic::Rra => (MicroOp::RotateRightAddWithCarryAddress, InstType::Rmw),
+            Mnemonic::Dcp => (MicroOp::DecrementCompareAddress, InstType::Rmw),
+            Mnemonic::Isb => (MicroOp::IncrementSubWithCarryAddress, InstType::Rmw),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn illegal_opcode(&mut self, opcode: u8) -> InstructionQueue {
+        let mut queue = InstructionQueue::new();
+        let pc = self.pc.wrapping_sub(1);
+        match self.illegal_opcode_policy {
+            IllegalOpcodePolicy::Panic => unimplemented!("{}", opcode),
+            IllegalOpcodePolicy::TreatAsNop => {
+                // the operand bytes are read and ignored, so they don't run as opcodes
+                let operands = opcode_info(opcode).len - 1;
+                if operands == 0 {
+                    queue.push_back(MicroOp::DummyCycle);
+                }
+                for _ in 0..operands {
+                    queue.push_back(MicroOp::ReadImmediate);
+                }
+            }
+            IllegalOpcodePolicy::ReturnError => {
+                self.error = Some(CpuError::IllegalOpcode { opcode, pc });
             }
-            _ => unimplemented!("{}", opcode),
         }
         queue
     }
@@ -1752,60 +1738,71 @@ impl Cpu {
     fn execute_micro_op(&mut self, operation: MicroOp) {
         match operation {
             MicroOp::ReadAddress => {
-                self.temp_val = self.mem_read(self.temp_addr);
+                self.temp_val = self.bus_read(self.temp_addr);
+            }
+            MicroOp::ReadImmediate => {
+                self.temp_val = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
             }
             MicroOp::FetchZeroPage => {
-                self.temp_addr = self.memory[self.pc as usize] as u16;
-                self.pc += 1;
+                self.temp_addr = self.bus_read(self.pc) as u16;
+                self.pc = self.pc.wrapping_add(1);
             }
             MicroOp::AddXtoZeroPageAddress => {
                 let address = self.temp_addr as u8;
-                self.temp_addr = address.wrapping_add(self.index_x as u8) as u16;
+                self.temp_addr = address.wrapping_add(self.index_x) as u16;
             }
             MicroOp::AddYtoZeroPageAddress => {
                 let address = self.temp_addr as u8;
-                self.temp_addr = address.wrapping_add(self.index_y as u8) as u16;
+                self.temp_addr = address.wrapping_add(self.index_y) as u16;
             }
             MicroOp::AddXtoPointer => {
                 let pointer = self.temp_addr as u8;
                 self.temp_addr = pointer.wrapping_add(self.index_x) as u16;
             }
             MicroOp::FetchLowAddrByte => {
-                self.temp_addr = self.mem_read(self.pc) as u16;
-                self.pc += 1;
+                self.temp_addr = self.bus_read(self.pc) as u16;
+                self.pc = self.pc.wrapping_add(1);
             }
             MicroOp::FetchHighAddrByte => {
-                self.temp_addr |= (self.mem_read(self.pc) as u16) << 8;
-                self.pc += 1;
+                self.temp_addr |= (self.bus_read(self.pc) as u16) << 8;
+                self.pc = self.pc.wrapping_add(1);
             }
             MicroOp::FetchInterruptLow => {
-                self.pc = self.mem_read(INTERRUPT_VEC_LOW) as u16;
+                // keep the return address around for the trap hook
+                self.temp_addr = self.pc;
+                self.pc = self.bus_read(self.interrupt_vector) as u16;
             }
             MicroOp::FetchInterruptHigh => {
-                self.pc |= (self.mem_read(INTERRUPT_VEC_HIGH) as u16) << 8;
-                self.running = false; // TODO: research this better
+                self.pc |= (self.bus_read(self.interrupt_vector + 1) as u16) << 8;
+                // with a trap hook installed the host decides what BRK means
+                if self.interrupt == Interrupt::Brk && self.trap_hook.is_none() {
+                    self.running = false; // TODO: research this better
+                }
+                self.run_trap_hook(self.temp_addr);
             }
             MicroOp::CopyLowFetchHightoPC => {
-                let high_byte = (self.mem_read(self.pc) as u16) << 8;
-                self.pc += 1;
+                let high_byte = (self.bus_read(self.pc) as u16) << 8;
+                self.pc = self.pc.wrapping_add(1);
                 self.pc = high_byte | self.temp_addr;
             }
             MicroOp::ReadLowFromIndirect => {
-                self.temp_ptr = self.mem_read(self.temp_addr) as u16;
+                self.temp_ptr = self.bus_read(self.temp_addr) as u16;
             }
             MicroOp::ReadHighFromIndirectLatch => {
-                let high_addr = if self.temp_ptr as u8 == 0xFF {
+                let high_addr = if self.temp_addr as u8 == 0xFF {
                     self.temp_addr & 0xFF00
                 } else {
                     self.temp_addr + 1
                 };
-                let high_byte = (self.mem_read(high_addr) as u16) << 8;
+                let high_byte = (self.bus_read(high_addr) as u16) << 8;
                 self.pc = high_byte | self.temp_ptr;
             }
             MicroOp::FetchHighAddrByteWithX => {
-                self.temp_addr |= (self.mem_read(self.pc) as u16) << 8;
-                self.pc += 1;
+                self.temp_addr |= (self.bus_read(self.pc) as u16) << 8;
+                self.pc = self.pc.wrapping_add(1);
                 let new_addr = self.temp_addr.wrapping_add(self.index_x as u16);
+                self.unfixed_addr = (self.temp_addr & 0xFF00) | (new_addr & 0x00FF);
                 self.page_crossed = (self.temp_addr & 0xFF00) != (new_addr & 0xFF00);
                 self.temp_addr = new_addr;
                 if self.page_crossed {
@@ -1813,9 +1810,10 @@ impl Cpu {
                 }
             }
             MicroOp::FetchHighAddrByteWithY => {
-                self.temp_addr |= (self.mem_read(self.pc) as u16) << 8;
-                self.pc += 1;
+                self.temp_addr |= (self.bus_read(self.pc) as u16) << 8;
+                self.pc = self.pc.wrapping_add(1);
                 let new_addr = self.temp_addr.wrapping_add(self.index_y as u16);
+                self.unfixed_addr = (self.temp_addr & 0xFF00) | (new_addr & 0x00FF);
                 self.page_crossed = (self.temp_addr & 0xFF00) != (new_addr & 0xFF00);
                 self.temp_addr = new_addr;
                 if self.page_crossed {
@@ -1824,14 +1822,18 @@ impl Cpu {
             }
             MicroOp::FetchPointerLowByte => {
                 self.temp_ptr = self.temp_addr;
-                self.temp_addr = self.mem_read(self.temp_ptr) as u16;
+                self.temp_addr = self.bus_read(self.temp_ptr) as u16;
             }
             MicroOp::FetchPointerHighByte => {
-                self.temp_addr |= (self.mem_read(self.temp_ptr.wrapping_add(1)) as u16) << 8; 
+                // the pointer never leaves the zero page, ($FF) takes its high byte from $00
+                let high_ptr = (self.temp_ptr as u8).wrapping_add(1) as u16;
+                self.temp_addr |= (self.bus_read(high_ptr) as u16) << 8;
             }
             MicroOp::FetchPointerHighByteWithY => {
-                self.temp_addr |= (self.mem_read(self.temp_ptr.wrapping_add(1)) as u16) << 8;
+                let high_ptr = (self.temp_ptr as u8).wrapping_add(1) as u16;
+                self.temp_addr |= (self.bus_read(high_ptr) as u16) << 8;
                 let new_addr = self.temp_addr.wrapping_add(self.index_y as u16);
+                self.unfixed_addr = (self.temp_addr & 0xFF00) | (new_addr & 0x00FF);
                 self.page_crossed = (self.temp_addr & 0xFF00) != (new_addr & 0xFF00);
                 self.temp_addr = new_addr;
                 if self.page_crossed {
@@ -1839,8 +1841,8 @@ impl Cpu {
                 }
             }
             MicroOp::FetchRelativeOffset(value, cond) => {
-                let offset = self.mem_read(self.pc);
-                self.pc += 1;
+                let offset = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.schedule_branch(value, cond, offset);
             }
             MicroOp::TakeBranch(offset) => {
@@ -1849,51 +1851,62 @@ impl Cpu {
                 } else {
                     self.pc.wrapping_add(offset as u16)
                 };
+                self.unfixed_addr = (self.pc & 0xFF00) | (new_addr & 0x00FF);
                 self.page_crossed = (self.pc & 0xFF00) != (new_addr & 0xFF00);
                 if self.page_crossed {
                     self.add_page_cross_penalty();
+                } else {
+                    self.poll_skipped = true;
                 }
                 self.pc = new_addr;
             }
             MicroOp::LoadAccumulator => {
-                let value = self.memory[self.pc as usize];
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.accumulator = value;
 
                 self.set_flags_zero_neg(value);
             }
             MicroOp::LoadAccumulatorFromAddress => {
-                let value = self.memory[self.temp_addr as usize];
+                let value = self.bus_read(self.temp_addr);
                 self.accumulator = value;
 
                 self.set_flags_zero_neg(value);
             }
             MicroOp::LoadX => {
-                let value = self.memory[self.pc as usize];
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.index_x = value;
 
                 self.set_flags_zero_neg(value);
             }
             MicroOp::LoadXfromAddress => {
-                let value = self.memory[self.temp_addr as usize];
+                let value = self.bus_read(self.temp_addr);
                 self.index_x = value;
 
                 self.set_flags_zero_neg(value);
             }
             MicroOp::LoadY => {
-                let value = self.memory[self.pc as usize];
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.index_y = value;
 
                 self.set_flags_zero_neg(value);
             }
             MicroOp::LoadYfromAddress => {
-                let value = self.memory[self.temp_addr as usize];
+                let value = self.bus_read(self.temp_addr);
                 self.index_y = value;
 
                 self.set_flags_zero_neg(value);
             }
+            MicroOp::LoadAccumulatorXFromAddress => {
+                // LAX: LDA + LDX with a single read
+                let value = self.bus_read(self.temp_addr);
+                self.accumulator = value;
+                self.index_x = value;
+
+                self.set_flags_zero_neg(value);
+            }
             MicroOp::LoadXAccumulator => {
                 self.index_x = self.accumulator;
 
@@ -1921,56 +1934,83 @@ impl Cpu {
             }
             MicroOp::PushAccumulator => {
                 let address: u16 = STACK_BOTTOM + self.sp as u16;
-                self.mem_write(address, self.accumulator);
+                self.bus_write(address, self.accumulator);
                 self.sp = self.sp.wrapping_sub(1);
             }
+            MicroOp::PushStatusInterrupt => {
+                let status = if self.interrupt == Interrupt::Brk {
+                    self.status_p | FLAG_BREAK | FLAG_UNUSED
+                } else {
+                    (self.status_p & !FLAG_BREAK) | FLAG_UNUSED
+                };
+                let address: u16 = STACK_BOTTOM + self.sp as u16;
+                self.bus_write(address, status);
+                self.sp = self.sp.wrapping_sub(1);
+                self.status_p |= FLAG_INTERRUPT;
+
+                // the vector is picked here, so an NMI asserted up to this cycle hijacks BRK and IRQ
+                self.interrupt_vector = if self.nmi_pending {
+                    self.nmi_pending = false;
+                    NMI_VEC_LOW
+                } else {
+                    INTERRUPT_VEC_LOW
+                };
+            }
             MicroOp::PushStatusBrkPhp => {
-                let status_w_b = self.status_p | FLAG_BREAK;
+                let status_w_b = self.status_p | FLAG_BREAK | FLAG_UNUSED;
                 let address: u16 = STACK_BOTTOM + self.sp as u16;
-                self.mem_write(address, status_w_b);
+                self.bus_write(address, status_w_b);
                 self.sp = self.sp.wrapping_sub(1);
             }
             MicroOp::PushPCH => {
                 let address = STACK_BOTTOM + self.sp as u16;
                 let pch: u8 = (self.pc >> 8) as u8;
-                self.mem_write(address, pch);
+                self.bus_write(address, pch);
                 self.sp = self.sp.wrapping_sub(1);
             }
             MicroOp::PushPCL => {
                 let address = STACK_BOTTOM + self.sp as u16;
                 let pcl = self.pc as u8;
-                self.mem_write(address, pcl);
+                self.bus_write(address, pcl);
                 self.sp = self.sp.wrapping_sub(1);
             }
             MicroOp::PullPCL => {
                 let address = STACK_BOTTOM + self.sp as u16;
-                let pcl = self.mem_read(address);
+                let pcl = self.bus_read(address);
                 self.sp = self.sp.wrapping_add(1);
                 self.temp_addr = pcl as u16;
             }
             MicroOp::PullPCH => {
                 let address = STACK_BOTTOM + self.sp as u16;
-                let pch = (self.mem_read(address) as u16) << 8;
+                let pch = (self.bus_read(address) as u16) << 8;
                 self.temp_addr |= pch;
+                self.pc = self.temp_addr;
             }
             MicroOp::IncrementPC => {
                 self.pc = self.temp_addr.wrapping_add(1);
             }
             MicroOp::IncrementPC2 => {
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
             }
             MicroOp::IncrementSP(value) => {
                 self.sp = self.sp.wrapping_add(value);
             }
             MicroOp::PullAccumulator => {
                 let address: u16 = STACK_BOTTOM + self.sp as u16;
-                self.accumulator = self.mem_read(address);
+                self.accumulator = self.bus_read(address);
 
                 self.set_flags_zero_neg(self.accumulator);
             }
             MicroOp::PullStatus => {
                 let address: u16 = STACK_BOTTOM + self.sp as u16;
-                self.status_p = self.mem_read(address);
+                let value = self.bus_read(address);
+                self.pull_status(value);
+            }
+            MicroOp::PullStatusIncrementSP => {
+                let address: u16 = STACK_BOTTOM + self.sp as u16;
+                let value = self.bus_read(address);
+                self.sp = self.sp.wrapping_add(1);
+                self.pull_status(value);
             }
             MicroOp::IncrementX => {
                 self.index_x = self.index_x.wrapping_add(1);
@@ -1993,67 +2033,145 @@ impl Cpu {
                 self.set_flags_zero_neg(self.index_y);
             }
             MicroOp::WriteBackAndIncrement => {
-                self.mem_write(self.temp_addr, self.temp_val);
+                self.bus_write(self.temp_addr, self.temp_val);
                 self.temp_val = self.temp_val.wrapping_add(1);
+
+                self.set_flags_zero_neg(self.temp_val);
             }
             MicroOp::WriteBackAndDecrement => {
-                self.mem_write(self.temp_addr, self.temp_val);
+                self.bus_write(self.temp_addr, self.temp_val);
                 self.temp_val = self.temp_val.wrapping_sub(1);
+
+                self.set_flags_zero_neg(self.temp_val);
             }
             MicroOp::WriteToAddress => {
-                self.mem_write(self.temp_addr, self.temp_val);
-                self.set_flags_zero_neg(self.temp_val);
+                self.bus_write(self.temp_addr, self.temp_val);
             }
             MicroOp::StoreAccumulator => {
-                self.mem_write(self.temp_addr, self.accumulator);
+                self.bus_write(self.temp_addr, self.accumulator);
             }
             MicroOp::StoreX => {
-                self.mem_write(self.temp_addr, self.index_x);
+                self.bus_write(self.temp_addr, self.index_x);
             }
             MicroOp::StoreY => {
-                self.mem_write(self.temp_addr, self.index_y);
+                self.bus_write(self.temp_addr, self.index_y);
+            }
+            MicroOp::StoreAccumulatorAndX => {
+                self.bus_write(self.temp_addr, self.accumulator & self.index_x);
             }
             MicroOp::LogicalAnd => {
-                let value = self.mem_read(self.pc);
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.accumulator &= value;
 
                 self.set_flags_zero_neg(self.accumulator);
             }
             MicroOp::LogicalAndAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 self.accumulator &= value;
 
                 self.set_flags_zero_neg(self.accumulator);
             }
+            MicroOp::AndWithCarry => {
+                // ANC: AND, then copy bit 7 into carry
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
+                self.accumulator &= value;
+
+                self.set_flags_zero_neg(self.accumulator);
+                if self.accumulator & BIT_7 != 0 {
+                    self.status_p |= FLAG_CARRY;
+                } else {
+                    self.status_p &= !FLAG_CARRY;
+                }
+            }
+            MicroOp::AndShiftRight => {
+                // ALR: AND + LSR A
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
+                self.accumulator = self.lsr(self.accumulator & value);
+            }
+            MicroOp::AndRotateRight => {
+                // ARR: AND + ROR A, with C from bit 6 and V from bit 6 ^ bit 5
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
+                let carry = self.status_p & FLAG_CARRY;
+                let result = ((self.accumulator & value) >> 1) | (carry << 7);
+                self.accumulator = result;
+
+                self.set_flags_zero_neg(result);
+                if result & 0b0100_0000 != 0 {
+                    self.status_p |= FLAG_CARRY;
+                } else {
+                    self.status_p &= !FLAG_CARRY;
+                }
+                if ((result >> 6) ^ (result >> 5)) & 0x01 != 0 {
+                    self.status_p |= FLAG_OVERFLOW;
+                } else {
+                    self.status_p &= !FLAG_OVERFLOW;
+                }
+            }
+            MicroOp::AndXSubtract => {
+                // AXS: X = (A & X) - imm, flags like CMP
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
+                let a_and_x = self.accumulator & self.index_x;
+                self.compare(a_and_x, value);
+                self.index_x = a_and_x.wrapping_sub(value);
+            }
+            MicroOp::StoreAccumulatorXHigh => {
+                self.store_high_and(self.accumulator & self.index_x, self.index_y);
+            }
+            MicroOp::StoreXHigh => {
+                self.store_high_and(self.index_x, self.index_y);
+            }
+            MicroOp::StoreYHigh => {
+                self.store_high_and(self.index_y, self.index_x);
+            }
+            MicroOp::StoreStackPointerHigh => {
+                self.sp = self.accumulator & self.index_x;
+                self.store_high_and(self.sp, self.index_y);
+            }
+            MicroOp::LoadAccumulatorXStackPointerFromAddress => {
+                let value = self.bus_read(self.temp_addr) & self.sp;
+                self.accumulator = value;
+                self.index_x = value;
+                self.sp = value;
+
+                self.set_flags_zero_neg(value);
+            }
+            MicroOp::Jam => {
+                // the CPU locks up until reset, PC stays right after the JAM opcode
+                self.jammed = true;
+            }
             MicroOp::ExclusiveOr => {
-                let value = self.mem_read(self.pc);
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.accumulator ^= value;
 
                 self.set_flags_zero_neg(self.accumulator);
             }
             MicroOp::ExclusiveOrAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 self.accumulator ^= value;
 
                 self.set_flags_zero_neg(self.accumulator);
             }
             MicroOp::InclusiveOr => {
-                let value = self.mem_read(self.pc);
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.accumulator |= value;
 
                 self.set_flags_zero_neg(self.accumulator);
             }
             MicroOp::InclusiveOrAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 self.accumulator |= value;
 
                 self.set_flags_zero_neg(self.accumulator);
             }
             MicroOp::BitTestAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 let temp = value & self.accumulator;
 
                 // set zero flag
@@ -2063,81 +2181,123 @@ impl Cpu {
                     self.status_p &= !FLAG_ZERO;
                 }
 
-                self.status_p = self.status_p & !(0b1100_0000); // clear neg and overflow flags
+                self.status_p &= !(0b1100_0000); // clear neg and overflow flags
                 self.status_p |= value & 0b1100_0000;
             }
             MicroOp::AddWithCarry => {
-                let value = self.mem_read(self.pc);
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.awc(value);
             }
             MicroOp::AddWithCarryAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 self.awc(value);
             }
             MicroOp::SubWithCarry => {
-                let value = self.mem_read(self.pc);
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.swc(value);
             }
             MicroOp::SubWithCarryAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 self.swc(value);
             }
             MicroOp::Compare => {
-                let value = self.mem_read(self.pc);
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.compare(self.accumulator, value);
             }
             MicroOp::CompareAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 self.compare(self.accumulator, value);
             }
             MicroOp::CompareX => {
-                let value = self.mem_read(self.pc);
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.compare(self.index_x, value);
             }
             MicroOp::CompareXAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 self.compare(self.index_x, value);
             }
             MicroOp::CompareY => {
-                let value = self.mem_read(self.pc);
-                self.pc += 1;
+                let value = self.bus_read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
                 self.compare(self.index_y, value);
             }
             MicroOp::CompareYAddress => {
-                let value = self.mem_read(self.temp_addr);
+                let value = self.bus_read(self.temp_addr);
                 self.compare(self.index_y, value);
             }
             MicroOp::ArithmeticShiftLeft => {
                 self.accumulator = self.asl(self.accumulator);
             }
             MicroOp::ArithmeticShiftLeftAddress => {
-                let result = self.asl(self.temp_val);
-                self.mem_write(self.temp_addr, result);
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.asl(self.temp_val);
             }
             MicroOp::LogicalShiftRight => {
                 self.accumulator = self.lsr(self.accumulator);
             }
             MicroOp::LogicalShiftRightAddress => {
-                let result = self.lsr(self.temp_val);
-                self.mem_write(self.temp_addr, result);
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.lsr(self.temp_val);
             }
             MicroOp::RotateLeft => {
                 self.accumulator = self.rol(self.accumulator);
             }
             MicroOp::RotateLeftAddress => {
-                let result = self.rol(self.temp_val);
-                self.mem_write(self.temp_addr, result);
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.rol(self.temp_val);
             }
             MicroOp::RotateRight => {
                 self.accumulator = self.ror(self.accumulator);
             }
             MicroOp::RotateRightAddress => {
-                let result = self.ror(self.temp_val);
-                self.mem_write(self.temp_addr, result);
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.ror(self.temp_val);
+            }
+            MicroOp::ShiftLeftInclusiveOrAddress => {
+                // SLO: ASL + ORA
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.asl(self.temp_val);
+                self.accumulator |= self.temp_val;
+
+                self.set_flags_zero_neg(self.accumulator);
+            }
+            MicroOp::RotateLeftLogicalAndAddress => {
+                // RLA: ROL + AND
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.rol(self.temp_val);
+                self.accumulator &= self.temp_val;
+
+                self.set_flags_zero_neg(self.accumulator);
+            }
+            MicroOp::ShiftRightExclusiveOrAddress => {
+                // SRE: LSR + EOR
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.lsr(self.temp_val);
+                self.accumulator ^= self.temp_val;
+
+                self.set_flags_zero_neg(self.accumulator);
+            }
+            MicroOp::RotateRightAddWithCarryAddress => {
+                // RRA: ROR + ADC, ADC consumes the carry left by ROR
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.ror(self.temp_val);
+                self.awc(self.temp_val);
+            }
+            MicroOp::DecrementCompareAddress => {
+                // DCP: DEC + CMP
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.temp_val.wrapping_sub(1);
+                self.compare(self.accumulator, self.temp_val);
+            }
+            MicroOp::IncrementSubWithCarryAddress => {
+                // ISC: INC + SBC
+                self.bus_write(self.temp_addr, self.temp_val);
+                self.temp_val = self.temp_val.wrapping_add(1);
+                self.swc(self.temp_val);
             }
             MicroOp::ClearCarry => {
                 self.status_p &= !FLAG_CARRY;
@@ -2160,8 +2320,9 @@ impl Cpu {
             MicroOp::ClearOverflow => {
                 self.status_p &= !FLAG_OVERFLOW;
             }
-            MicroOp::DummyCycle => {
-                return;
+            MicroOp::DummyCycle => {}
+            MicroOp::ReadUnfixedAddress => {
+                self.bus_read(self.unfixed_addr);
             }
             _ => unimplemented!(),
         }
@@ -2191,10 +2352,6 @@ impl Cpu {
         self.status_p
     }
 
-    pub fn get_memory(&self) -> &[u8; 0x10000] {
-        &self.memory
-    }
-
     pub fn get_temp_addr(&self) -> u16 {
         self.temp_addr
     }
@@ -2217,6 +2374,11 @@ impl Cpu {
 
     pub fn set_status_p(&mut self, val: u8) {
         self.status_p = val;
+        self.irq_inhibit = val & FLAG_INTERRUPT != 0;
+    }
+
+    pub fn set_pc(&mut self, val: u16) {
+        self.pc = val;
     }
 
     pub fn set_sp(&mut self, val: u8) {
@@ -2226,4 +2388,120 @@ impl Cpu {
     pub fn is_running(&self) -> bool {
         self.running
     }
+
+    // true between instructions, when the next tick fetches an opcode
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.current_inst.is_empty() && self.oam_dma.is_none() && self.dmc_dma.is_none()
+    }
+
+    pub fn is_dma_active(&self) -> bool {
+        self.oam_dma.is_some()
+    }
+
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    pub fn get_error(&self) -> Option<CpuError> {
+        self.error
+    }
+
+    pub fn get_current_opcode(&self) -> u8 {
+        self.current_opcode
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+}
+
+// Registers, interrupt lines and DMAs. States are taken between instructions, the micro-ops of
+// one half done aren't saved; the bus is saved on its own. Debugger and hook setup is
+// configuration and is left alone.
+impl<B: Read + Write + Peek> Savestate for Cpu<B> {
+    fn save_state(&self, w: &mut StateWriter) {
+        debug_assert!(self.current_inst.is_empty(), "CPU state saved mid-instruction");
+        for value in [self.accumulator, self.index_x, self.index_y, self.sp, self.status_p] {
+            w.write_u8(value);
+        }
+        w.write_u16(self.pc);
+        w.write_u64(self.cycles);
+        w.write_u8(self.current_opcode);
+        for flag in [
+            self.running,
+            self.jammed,
+            self.nmi_pending,
+            self.irq_line,
+            self.irq_inhibit,
+            self.polled_irq,
+            self.polled_nmi,
+            self.poll_skipped,
+        ] {
+            w.write_bool(flag);
+        }
+        w.write_bool(self.oam_dma.is_some());
+        if let Some(dma) = &self.oam_dma {
+            dma.save_state(w);
+        }
+        w.write_bool(self.dmc_dma.is_some());
+        if let Some(dma) = &self.dmc_dma {
+            dma.save_state(w);
+        }
+        w.write_bool(self.dmc_sample.is_some());
+        w.write_u8(self.dmc_sample.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.accumulator = r.read_u8()?;
+        self.index_x = r.read_u8()?;
+        self.index_y = r.read_u8()?;
+        self.sp = r.read_u8()?;
+        self.status_p = r.read_u8()?;
+        self.pc = r.read_u16()?;
+        self.cycles = r.read_u64()?;
+        self.current_opcode = r.read_u8()?;
+        for flag in [
+            &mut self.running,
+            &mut self.jammed,
+            &mut self.nmi_pending,
+            &mut self.irq_line,
+            &mut self.irq_inhibit,
+            &mut self.polled_irq,
+            &mut self.polled_nmi,
+            &mut self.poll_skipped,
+        ] {
+            *flag = r.read_bool()?;
+        }
+        self.oam_dma = None;
+        if r.read_bool()? {
+            let mut dma = OamDma::new(0);
+            dma.load_state(r)?;
+            self.oam_dma = Some(dma);
+        }
+        self.dmc_dma = None;
+        if r.read_bool()? {
+            let mut dma = DmcDma::new(0);
+            dma.load_state(r)?;
+            self.dmc_dma = Some(dma);
+        }
+        let has_sample = r.read_bool()?;
+        let sample = r.read_u8()?;
+        self.dmc_sample = has_sample.then_some(sample);
+
+        self.current_inst.clear();
+        self.page_crossed = false;
+        self.trap_halted = false;
+        self.error = None;
+        self.loop_since = self.cycles;
+        self.stuck = false;
+        Ok(())
+    }
 }
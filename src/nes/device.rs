@@ -0,0 +1,34 @@
+// Input devices other than the standard controller. On a Famicom they go in the expansion port,
+// which sees the $4016 writes and both joypad reads and can drive data bits 1-4 of either; NES
+// versions of the same devices plug into a controller port instead and take it over, driving
+// its D0, D3 and D4 lines. Either way the Bus hands the device every $4016 write and every read
+// of $4016 and $4017, and ORs what it drives in with what the controllers do.
+
+pub mod vaus;
+
+use super::savestate::Savestate;
+
+// the data lines a device can drive, D0 only for one standing in for a controller
+pub const DEVICE_BITS: u8 = 0b0001_1111;
+
+pub trait InputDevice: Savestate {
+    // The controller port the device is plugged into in place of the standard controller, which
+    // the Bus then leaves unread. None for the Famicom expansion port.
+    fn port(&self) -> Option<usize> {
+        None
+    }
+
+    // a write to $4016, OUT0-OUT2 in bits 0-2
+    fn write(&mut self, data: u8);
+
+    // The bits a read of `port`, 0 for $4016 and 1 for $4017, drives. This is the read pulse
+    // that clocks the device.
+    fn read(&mut self, port: usize) -> u8;
+
+    fn peek(&self, port: usize) -> u8;
+
+    // Where the mouse is, from 0.0 at the left or top edge of the picture to 1.0 at the right or
+    // bottom, and whether its button is held. Devices that aren't steered with the mouse ignore
+    // it.
+    fn mouse(&mut self, _x: f32, _y: f32, _button: bool) {}
+}
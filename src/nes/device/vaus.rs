@@ -0,0 +1,132 @@
+// Taito's Arkanoid controller, the Vaus: a knob on a potentiometer and a fire button. Writing 1
+// to bit 0 of $4016 latches the knob's position into an 8 bit shift register, and each read of
+// the data port then returns the next bit, most significant first and inverted. The knob only
+// turns through part of the range, games see about $62 to $F2 from one end to the other.
+//
+// The Famicom Vaus sits in the expansion port and reports the button in D1 of $4016 and the
+// position in D1 of $4017. The NES one plugs into the second controller port and reports both
+// on $4017, the button in D4 and the position in D3.
+
+use super::InputDevice;
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+pub const POSITION_MIN: u8 = 0x62;
+pub const POSITION_MAX: u8 = 0xF2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VausVariant {
+    Famicom,
+    Nes,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vaus {
+    variant: VausVariant,
+    // the knob and button are the player's, set by the frontend
+    position: u8,
+    fire: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Vaus {
+    // the knob starts in the middle
+    pub fn new(variant: VausVariant) -> Self {
+        Vaus {
+            variant,
+            position: POSITION_MIN + (POSITION_MAX - POSITION_MIN) / 2,
+            fire: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    pub fn variant(&self) -> VausVariant {
+        self.variant
+    }
+
+    // kept within the knob's travel, takes effect at the next latch
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position.clamp(POSITION_MIN, POSITION_MAX);
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    pub fn position(&self) -> u8 {
+        self.position
+    }
+
+    pub fn set_fire(&mut self, pressed: bool) {
+        self.fire = pressed;
+    }
+
+    pub fn fire(&self) -> bool {
+        self.fire
+    }
+
+    // the next position bit, as the port drives it
+    fn data_bit(&self) -> u8 {
+        let shift = if self.strobe { self.position } else { self.shift };
+        !shift >> 7
+    }
+
+    // the data port, D1 of $4017 or D3 and D4 of it
+    fn drive(&self, port: usize) -> u8 {
+        match (self.variant, port) {
+            (VausVariant::Famicom, 0) => (self.fire as u8) << 1,
+            (VausVariant::Famicom, _) => self.data_bit() << 1,
+            (VausVariant::Nes, 0) => 0,
+            (VausVariant::Nes, _) => (self.fire as u8) << 4 | self.data_bit() << 3,
+        }
+    }
+}
+
+impl InputDevice for Vaus {
+    fn port(&self) -> Option<usize> {
+        match self.variant {
+            VausVariant::Famicom => None,
+            VausVariant::Nes => Some(1),
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    // only reads of $4017 clock the shift register, ones shift in behind the position
+    fn read(&mut self, port: usize) -> u8 {
+        let bits = self.drive(port);
+        if port == 1 && !self.strobe {
+            self.shift = self.shift << 1 | 1;
+        }
+        bits
+    }
+
+    fn peek(&self, port: usize) -> u8 {
+        self.drive(port)
+    }
+
+    // the knob follows the mouse across the picture, the mouse button fires
+    fn mouse(&mut self, x: f32, _y: f32, button: bool) {
+        let travel = (POSITION_MAX - POSITION_MIN) as f32;
+        self.set_position(POSITION_MIN + (x.clamp(0.0, 1.0) * travel).round() as u8);
+        self.fire = button;
+    }
+}
+
+impl Savestate for Vaus {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.strobe);
+        w.write_u8(self.shift);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.strobe = r.read_bool()?;
+        self.shift = r.read_u8()?;
+        Ok(())
+    }
+}
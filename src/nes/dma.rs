@@ -0,0 +1,170 @@
+// OAM DMA, started by a write to $4014. The 2A03 halts the CPU, waits for a read ("get")
+// cycle and then copies 256 bytes from $XX00-$XXFF to OAMDATA, one read/write pair at a time:
+// 1 halt cycle + 1 alignment cycle when needed + 512 transfer cycles = 513 or 514 cycles.
+
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+pub const OAM_DMA_REGISTER: u16 = 0x4014;
+pub const OAM_DATA_REGISTER: u16 = 0x2004;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DmaCycle {
+    // the CPU is parked, its pending read is repeated
+    Halt,
+    // waiting for a get cycle
+    Align,
+    Read(u16),
+    Write(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Halt,
+    Align,
+    Get,
+    Put,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OamDma {
+    page: u8,
+    offset: u16,
+    state: State,
+    // byte carried from the get cycle to the put cycle
+    pub data: u8,
+}
+
+impl OamDma {
+    pub fn new(page: u8) -> Self {
+        OamDma {
+            page,
+            offset: 0,
+            state: State::Halt,
+            data: 0,
+        }
+    }
+
+    // what to do on this CPU cycle, get cycles land on even cycle counts
+    pub fn next_cycle(&mut self, cycle: u64) -> DmaCycle {
+        match self.state {
+            State::Halt => {
+                self.state = State::Align;
+                DmaCycle::Halt
+            }
+            // a DMC fetch can take a get cycle, the next read then waits for the one after
+            State::Align | State::Get if cycle % 2 == 1 => DmaCycle::Align,
+            State::Align | State::Get => {
+                self.state = State::Put;
+                DmaCycle::Read(((self.page as u16) << 8) | self.offset)
+            }
+            State::Put => {
+                self.offset += 1;
+                self.state = State::Get;
+                DmaCycle::Write(OAM_DATA_REGISTER)
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.offset == 0x100
+    }
+}
+
+impl Savestate for OamDma {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.page);
+        w.write_u16(self.offset);
+        w.write_u8(self.state as u8);
+        w.write_u8(self.data);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.page = r.read_u8()?;
+        self.offset = r.read_u16()?;
+        self.state = match r.read_u8()? {
+            0 => State::Halt,
+            1 => State::Align,
+            2 => State::Get,
+            3 => State::Put,
+            _ => return Err(StateError::Corrupt("invalid OAM DMA state")),
+        };
+        self.data = r.read_u8()?;
+        Ok(())
+    }
+}
+
+// DMC sample fetch. The APU pulls RDY low, the CPU stops on its next read cycle and keeps
+// repeating that read until the sample byte has been fetched:
+// halt + dummy + optional alignment + get = 3 or 4 cycles.
+// During an OAM DMA the CPU is already halted, so the halt, dummy and alignment cycles run
+// alongside the OAM transfer and only the get cycle is taken from it. OAM DMA then has to
+// realign, which makes the usual cost 2 cycles instead of 3 or 4.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DmcState {
+    Halt,
+    Dummy,
+    Align,
+    Get,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DmcDma {
+    addr: u16,
+    state: DmcState,
+    // read the CPU was stopped on, repeated on every stalled cycle
+    pub halted_addr: u16,
+}
+
+impl DmcDma {
+    pub fn new(addr: u16) -> Self {
+        DmcDma {
+            addr,
+            state: DmcState::Halt,
+            halted_addr: 0,
+        }
+    }
+
+    // true until the CPU has actually been stopped, RDY is ignored on write cycles
+    pub fn is_waiting_for_halt(&self) -> bool {
+        self.state == DmcState::Halt
+    }
+
+    pub fn next_cycle(&mut self, cycle: u64) -> DmaCycle {
+        match self.state {
+            DmcState::Halt => {
+                self.state = DmcState::Dummy;
+                DmaCycle::Halt
+            }
+            DmcState::Dummy => {
+                self.state = DmcState::Align;
+                DmaCycle::Halt
+            }
+            DmcState::Align if cycle % 2 == 1 => DmaCycle::Align,
+            DmcState::Align | DmcState::Get => {
+                self.state = DmcState::Get;
+                DmaCycle::Read(self.addr)
+            }
+        }
+    }
+}
+
+impl Savestate for DmcDma {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.addr);
+        w.write_u8(self.state as u8);
+        w.write_u16(self.halted_addr);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.addr = r.read_u16()?;
+        self.state = match r.read_u8()? {
+            0 => DmcState::Halt,
+            1 => DmcState::Dummy,
+            2 => DmcState::Align,
+            3 => DmcState::Get,
+            _ => return Err(StateError::Corrupt("invalid DMC DMA state")),
+        };
+        self.halted_addr = r.read_u16()?;
+        Ok(())
+    }
+}
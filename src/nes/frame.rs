@@ -0,0 +1,101 @@
+// A finished picture from the PPU. Pixels are kept as the 6 bit NES colors palette RAM held when
+// they were drawn, with PPUMASK's emphasis bits above them, and turned into RGBA through a master
+// palette only when a frontend asks, so nothing here depends on how or where the frame ends up
+// being shown.
+
+use super::palette::{Palette, COLORS};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Frame {
+    // row major, one master palette index per pixel
+    pixels: Box<[u16]>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Frame { pixels: vec![0u16; WIDTH * HEIGHT].into_boxed_slice() }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * WIDTH + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, index: u16) {
+        self.pixels[y * WIDTH + x] = index & 0x1FF;
+    }
+
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    // FNV-1a over the pixels as little endian u16s. Unlike std's hashers it's the same on every
+    // platform and toolchain, so known-good values can be written into tests.
+    pub fn hash(&self) -> u64 {
+        self.pixels.iter().flat_map(|pixel| pixel.to_le_bytes()).fold(
+            FNV_OFFSET_BASIS,
+            |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME),
+        )
+    }
+
+    // Fills `out`, 4 bytes a pixel, with the frame as opaque RGBA. With the simd feature on an
+    // x86-64 machine that has AVX2, eight pixels at a time are looked up with a gather.
+    pub fn write_rgba(&self, palette: &Palette, out: &mut [u8]) {
+        assert_eq!(out.len(), WIDTH * HEIGHT * 4, "RGBA buffer must be 256x240x4 bytes");
+        let table = palette.rgba_table();
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        if std::arch::is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 was just detected
+            unsafe { write_rgba_avx2(&self.pixels, table, out) };
+            return;
+        }
+        for (rgba, &index) in out.chunks_exact_mut(4).zip(self.pixels.iter()) {
+            rgba.copy_from_slice(&table[index as usize % COLORS].to_le_bytes());
+        }
+    }
+
+    // Fills `out`, 3 bytes a pixel, with the frame as packed RGB, the layout of an RGB24
+    // texture.
+    pub fn write_rgb(&self, palette: &Palette, out: &mut [u8]) {
+        assert_eq!(out.len(), WIDTH * HEIGHT * 3, "RGB buffer must be 256x240x3 bytes");
+        for (rgb, &index) in out.chunks_exact_mut(3).zip(self.pixels.iter()) {
+            rgb.copy_from_slice(&palette.rgb(index));
+        }
+    }
+
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        let mut out = vec![0u8; WIDTH * HEIGHT * 4];
+        self.write_rgba(palette, &mut out);
+        out
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+fn write_rgba_avx2(pixels: &[u16], table: &[u32], out: &mut [u8]) {
+    use std::arch::x86_64::*;
+
+    assert!(table.len() == COLORS && out.len() == pixels.len() * 4 && pixels.len().is_multiple_of(8));
+    let wrap = _mm256_set1_epi32(COLORS as i32 - 1);
+    for (indices, rgba) in pixels.chunks_exact(8).zip(out.chunks_exact_mut(32)) {
+        // SAFETY: the chunks are 16 and 32 bytes, the unaligned loads and stores stay inside
+        // them, and the indices are wrapped to the 512 entries of the table
+        unsafe {
+            let indices = _mm_loadu_si128(indices.as_ptr() as *const __m128i);
+            let indices = _mm256_and_si256(_mm256_cvtepu16_epi32(indices), wrap);
+            let colors = _mm256_i32gather_epi32::<4>(table.as_ptr() as *const i32, indices);
+            _mm256_storeu_si256(rgba.as_mut_ptr() as *mut __m256i, colors);
+        }
+    }
+}
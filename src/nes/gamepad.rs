@@ -0,0 +1,218 @@
+// Gamepads through SDL's GameController API, which gives every supported pad the same Xbox
+// style layout. Pads are opened as they're plugged in (SDL reports the ones already there
+// when the subsystem starts, too) and each takes the lowest free player, so the first pad is
+// player 1's controller and the second player 2's; pads beyond that wait for a free player.
+//
+// The NES buttons sit where they do on a NES pad: the bottom face button is B and the right
+// one A, with X and Y next to them as turbo B and turbo A, Back is Select and Start is Start.
+// The D-pad and the left stick both steer, the stick only past the deadzone.
+
+use super::controller::Button;
+use sdl2::controller::{Axis, Button as PadButton, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+pub const PLAYERS: usize = 2;
+// of the stick's travel either way
+pub const DEFAULT_DEADZONE: f32 = 0.25;
+
+// what one pad is holding
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PadState {
+    pressed: Button,
+    // held for turbo, see input::Turbo
+    turbo: Button,
+    // the left stick, -32768 to 32767 with down and right positive
+    x: i16,
+    y: i16,
+}
+
+impl PadState {
+    pub fn button_event(&mut self, button: PadButton, pressed: bool) {
+        match button {
+            PadButton::X => self.turbo.set(Button::B, pressed),
+            PadButton::Y => self.turbo.set(Button::A, pressed),
+            _ => {
+                if let Some(mapped) = nes_button(button) {
+                    self.pressed.set(mapped, pressed);
+                }
+            }
+        }
+    }
+
+    pub fn turbo(&self) -> Button {
+        self.turbo
+    }
+
+    pub fn axis_event(&mut self, axis: Axis, value: i16) {
+        match axis {
+            Axis::LeftX => self.x = value,
+            Axis::LeftY => self.y = value,
+            _ => {}
+        }
+    }
+
+    // `deadzone` is a fraction of the stick's travel, 0.0 to 1.0
+    pub fn buttons(&self, deadzone: f32) -> Button {
+        let threshold = (deadzone.clamp(0.0, 1.0) * i16::MAX as f32) as i32;
+        let mut buttons = self.pressed;
+        let (x, y) = (self.x as i32, self.y as i32);
+        for (button, past) in [
+            (Button::LEFT, x < -threshold),
+            (Button::RIGHT, x > threshold),
+            (Button::UP, y < -threshold),
+            (Button::DOWN, y > threshold),
+        ] {
+            if past {
+                buttons.insert(button);
+            }
+        }
+        buttons
+    }
+}
+
+fn nes_button(button: PadButton) -> Option<Button> {
+    match button {
+        PadButton::A => Some(Button::B),
+        PadButton::B => Some(Button::A),
+        PadButton::Back => Some(Button::SELECT),
+        PadButton::Start => Some(Button::START),
+        PadButton::DPadUp => Some(Button::UP),
+        PadButton::DPadDown => Some(Button::DOWN),
+        PadButton::DPadLeft => Some(Button::LEFT),
+        PadButton::DPadRight => Some(Button::RIGHT),
+        _ => None,
+    }
+}
+
+struct Pad {
+    controller: GameController,
+    player: Option<usize>,
+    state: PadState,
+}
+
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    pads: Vec<Pad>,
+    deadzone: f32,
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Gamepads { subsystem, pads: Vec::new(), deadzone: DEFAULT_DEADZONE }
+    }
+
+    pub fn set_deadzone(&mut self, deadzone: f32) {
+        self.deadzone = deadzone.clamp(0.0, 1.0);
+    }
+
+    pub fn deadzone(&self) -> f32 {
+        self.deadzone
+    }
+
+    // Call with every SDL event; the ones that aren't about gamepads are ignored.
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => self.connect(which),
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.pads.retain(|pad| pad.controller.instance_id() != which);
+                self.fill_players();
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                if let Some(pad) = self.pad_mut(which) {
+                    pad.state.button_event(button, true);
+                }
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                if let Some(pad) = self.pad_mut(which) {
+                    pad.state.button_event(button, false);
+                }
+            }
+            Event::ControllerAxisMotion { which, axis, value, .. } => {
+                if let Some(pad) = self.pad_mut(which) {
+                    pad.state.axis_event(axis, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // `index` is SDL's joystick index, not an instance id
+    fn connect(&mut self, index: u32) {
+        let controller = match self.subsystem.open(index) {
+            Ok(controller) => controller,
+            Err(err) => {
+                log::warn!("could not open gamepad {}: {}", index, err);
+                return;
+            }
+        };
+        // SDL can report a pad twice, when it was there at startup
+        let id = controller.instance_id();
+        if self.pads.iter().any(|pad| pad.controller.instance_id() == id) {
+            return;
+        }
+        log::info!("gamepad connected: {}", controller.name());
+        self.pads.push(Pad { controller, player: None, state: PadState::default() });
+        self.fill_players();
+    }
+
+    // gives pads without a player the free ones, in the order the pads were connected
+    fn fill_players(&mut self) {
+        for player in 0..PLAYERS {
+            if self.pads.iter().any(|pad| pad.player == Some(player)) {
+                continue;
+            }
+            let Some(pad) = self.pads.iter_mut().find(|pad| pad.player.is_none()) else {
+                return;
+            };
+            pad.player = Some(player);
+        }
+    }
+
+    fn pad_mut(&mut self, id: u32) -> Option<&mut Pad> {
+        self.pads.iter_mut().find(|pad| pad.controller.instance_id() == id)
+    }
+
+    // instance ids, names and players of the pads connected, in the order they were
+    pub fn pads(&self) -> Vec<(u32, String, Option<usize>)> {
+        self.pads
+            .iter()
+            .map(|pad| (pad.controller.instance_id(), pad.controller.name(), pad.player))
+            .collect()
+    }
+
+    // Hands `player` to the pad with instance id `id`, swapping with the pad that had it.
+    // None takes the pad's player away and leaves it free.
+    pub fn assign(&mut self, id: u32, player: Option<usize>) {
+        let player = player.filter(|&player| player < PLAYERS);
+        let Some(index) = self.pads.iter().position(|pad| pad.controller.instance_id() == id)
+        else {
+            return;
+        };
+        let previous = self.pads[index].player;
+        if player.is_some() {
+            for pad in &mut self.pads {
+                if pad.player == player {
+                    pad.player = previous;
+                }
+            }
+        }
+        self.pads[index].player = player;
+    }
+
+    // what `player`'s pad is holding, nothing without one
+    pub fn buttons(&self, player: usize) -> Button {
+        self.pads
+            .iter()
+            .find(|pad| pad.player == Some(player))
+            .map_or(Button::NONE, |pad| pad.state.buttons(self.deadzone))
+    }
+
+    // what `player`'s pad is holding for turbo
+    pub fn turbo(&self, player: usize) -> Button {
+        self.pads
+            .iter()
+            .find(|pad| pad.player == Some(player))
+            .map_or(Button::NONE, |pad| pad.state.turbo())
+    }
+}
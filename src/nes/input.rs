@@ -0,0 +1,386 @@
+// Which keyboard keys press which controller buttons. A button has at most one key and a key
+// presses at most one button; binding a key that's already in use takes it off the button it
+// was on. The defaults are WASD for the D-pad, K and J for A and B, Return for Start and the
+// right shift for Select.
+//
+// A and B can also have turbo keys, I and U by default, which press and release the button
+// every few frames for as long as they're held. The alternating presses are worked out here,
+// before they reach the controller, so anything recording the controller sees them too.
+//
+// Left and Right, or Up and Down, can't both be pressed on a real D-pad and some games glitch
+// badly when they are. What happens when the keys say they are is a policy: pass both through
+// (what TASers want), drop both, or keep the one pressed last.
+//
+// Hotkeys drive the emulator rather than the game. By default Tab holds fast-forward, P pauses,
+// backslash advances a paused game by one frame and F5 and F7 save and load a state. 1-5 mute
+// and unmute pulse 1, pulse 2, triangle, noise and DMC, or play one alone with shift held, and
+// 0 brings them all back. R starts and stops recording a WAV, with a file per channel too if
+// shift is held. A key bound to a hotkey doesn't also press a button.
+
+use super::apu::{Apu, Channel};
+use super::controller::Button;
+use sdl2::keyboard::Keycode;
+
+// the buttons that can have turbo keys
+pub const TURBO_BUTTONS: [Button; 2] = [Button::A, Button::B];
+// frames pressed, then as many released: 15 presses a second at 60 frames a second
+pub const DEFAULT_TURBO_PERIOD: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputMap {
+    // indexed by the button's bit, A first
+    keys: [Option<Keycode>; 8],
+    // indexed like TURBO_BUTTONS
+    turbo_keys: [Option<Keycode>; 2],
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut map = InputMap::empty();
+        map.set_binding(Button::A, Keycode::K);
+        map.set_binding(Button::B, Keycode::J);
+        map.set_binding(Button::SELECT, Keycode::RShift);
+        map.set_binding(Button::START, Keycode::Return);
+        map.set_binding(Button::UP, Keycode::W);
+        map.set_binding(Button::DOWN, Keycode::S);
+        map.set_binding(Button::LEFT, Keycode::A);
+        map.set_binding(Button::RIGHT, Keycode::D);
+        map.set_turbo_binding(Button::A, Keycode::I);
+        map.set_turbo_binding(Button::B, Keycode::U);
+        map
+    }
+}
+
+fn index(button: Button) -> Option<usize> {
+    Button::ALL.iter().position(|&single| single == button)
+}
+
+fn turbo_index(button: Button) -> Option<usize> {
+    TURBO_BUTTONS.iter().position(|&turbo| turbo == button)
+}
+
+impl InputMap {
+    // nothing bound
+    pub fn empty() -> Self {
+        InputMap { keys: [None; 8], turbo_keys: [None; 2] }
+    }
+
+    fn unbind(&mut self, key: Keycode) {
+        for bound in self.keys.iter_mut().chain(&mut self.turbo_keys) {
+            if *bound == Some(key) {
+                *bound = None;
+            }
+        }
+    }
+
+    // `button` is a single button, a set of them is ignored
+    pub fn set_binding(&mut self, button: Button, key: Keycode) {
+        let Some(index) = index(button) else {
+            return;
+        };
+        self.unbind(key);
+        self.keys[index] = Some(key);
+    }
+
+    pub fn clear_binding(&mut self, button: Button) {
+        if let Some(index) = index(button) {
+            self.keys[index] = None;
+        }
+    }
+
+    pub fn binding(&self, button: Button) -> Option<Keycode> {
+        index(button).and_then(|index| self.keys[index])
+    }
+
+    // the button `key` presses
+    pub fn button(&self, key: Keycode) -> Option<Button> {
+        let index = self.keys.iter().position(|&bound| bound == Some(key))?;
+        Some(Button::ALL[index])
+    }
+
+    // only A and B have turbo, other buttons are ignored
+    pub fn set_turbo_binding(&mut self, button: Button, key: Keycode) {
+        let Some(index) = turbo_index(button) else {
+            return;
+        };
+        self.unbind(key);
+        self.turbo_keys[index] = Some(key);
+    }
+
+    pub fn clear_turbo_binding(&mut self, button: Button) {
+        if let Some(index) = turbo_index(button) {
+            self.turbo_keys[index] = None;
+        }
+    }
+
+    pub fn turbo_binding(&self, button: Button) -> Option<Keycode> {
+        turbo_index(button).and_then(|index| self.turbo_keys[index])
+    }
+
+    // the button `key` presses in turbo
+    pub fn turbo_button(&self, key: Keycode) -> Option<Button> {
+        let index = self.turbo_keys.iter().position(|&bound| bound == Some(key))?;
+        Some(TURBO_BUTTONS[index])
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    FastForward,
+    Pause,
+    FrameAdvance,
+    SaveState,
+    LoadState,
+    MutePulse1,
+    MutePulse2,
+    MuteTriangle,
+    MuteNoise,
+    MuteDmc,
+    UnmuteAll,
+    Record,
+}
+
+impl Hotkey {
+    pub const ALL: [Hotkey; 12] = [
+        Hotkey::FastForward,
+        Hotkey::Pause,
+        Hotkey::FrameAdvance,
+        Hotkey::SaveState,
+        Hotkey::LoadState,
+        Hotkey::MutePulse1,
+        Hotkey::MutePulse2,
+        Hotkey::MuteTriangle,
+        Hotkey::MuteNoise,
+        Hotkey::MuteDmc,
+        Hotkey::UnmuteAll,
+        Hotkey::Record,
+    ];
+
+    // as it's written in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            Hotkey::FastForward => "fast_forward",
+            Hotkey::Pause => "pause",
+            Hotkey::FrameAdvance => "frame_advance",
+            Hotkey::SaveState => "save_state",
+            Hotkey::LoadState => "load_state",
+            Hotkey::MutePulse1 => "mute_pulse1",
+            Hotkey::MutePulse2 => "mute_pulse2",
+            Hotkey::MuteTriangle => "mute_triangle",
+            Hotkey::MuteNoise => "mute_noise",
+            Hotkey::MuteDmc => "mute_dmc",
+            Hotkey::UnmuteAll => "unmute_all",
+            Hotkey::Record => "record",
+        }
+    }
+
+    // the APU channel a mute hotkey is for
+    pub fn channel(self) -> Option<Channel> {
+        match self {
+            Hotkey::MutePulse1 => Some(Channel::Pulse1),
+            Hotkey::MutePulse2 => Some(Channel::Pulse2),
+            Hotkey::MuteTriangle => Some(Channel::Triangle),
+            Hotkey::MuteNoise => Some(Channel::Noise),
+            Hotkey::MuteDmc => Some(Channel::Dmc),
+            _ => None,
+        }
+    }
+}
+
+// What a press of one of the channel hotkeys does, for games and NSFs alike: a mute hotkey
+// mutes or unmutes its channel, or with `solo` plays it alone or stops doing so, and
+// UnmuteAll brings every channel back. The other hotkeys are left alone.
+pub fn channel_hotkey(apu: &mut Apu, hotkey: Hotkey, solo: bool) {
+    if hotkey == Hotkey::UnmuteAll {
+        apu.set_solo(None);
+        for channel in Channel::ALL {
+            apu.set_muted(channel, false);
+        }
+        return;
+    }
+    let Some(channel) = hotkey.channel() else {
+        return;
+    };
+    if solo {
+        apu.set_solo((apu.solo() != Some(channel)).then_some(channel));
+    } else {
+        apu.set_muted(channel, !apu.is_muted(channel));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hotkeys {
+    // indexed like Hotkey::ALL
+    keys: [Option<Keycode>; Hotkey::ALL.len()],
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        let mut hotkeys = Hotkeys { keys: [None; Hotkey::ALL.len()] };
+        hotkeys.set_binding(Hotkey::FastForward, Keycode::Tab);
+        hotkeys.set_binding(Hotkey::Pause, Keycode::P);
+        hotkeys.set_binding(Hotkey::FrameAdvance, Keycode::Backslash);
+        hotkeys.set_binding(Hotkey::SaveState, Keycode::F5);
+        hotkeys.set_binding(Hotkey::LoadState, Keycode::F7);
+        hotkeys.set_binding(Hotkey::MutePulse1, Keycode::Num1);
+        hotkeys.set_binding(Hotkey::MutePulse2, Keycode::Num2);
+        hotkeys.set_binding(Hotkey::MuteTriangle, Keycode::Num3);
+        hotkeys.set_binding(Hotkey::MuteNoise, Keycode::Num4);
+        hotkeys.set_binding(Hotkey::MuteDmc, Keycode::Num5);
+        hotkeys.set_binding(Hotkey::UnmuteAll, Keycode::Num0);
+        hotkeys.set_binding(Hotkey::Record, Keycode::R);
+        hotkeys
+    }
+}
+
+impl Hotkeys {
+    // a key drives one hotkey at most, like it presses one button
+    pub fn set_binding(&mut self, hotkey: Hotkey, key: Keycode) {
+        for bound in &mut self.keys {
+            if *bound == Some(key) {
+                *bound = None;
+            }
+        }
+        self.keys[hotkey as usize] = Some(key);
+    }
+
+    pub fn clear_binding(&mut self, hotkey: Hotkey) {
+        self.keys[hotkey as usize] = None;
+    }
+
+    pub fn binding(&self, hotkey: Hotkey) -> Option<Keycode> {
+        self.keys[hotkey as usize]
+    }
+
+    pub fn hotkey(&self, key: Keycode) -> Option<Hotkey> {
+        let index = self.keys.iter().position(|&bound| bound == Some(key))?;
+        Some(Hotkey::ALL[index])
+    }
+}
+
+// what's held on the keyboard, kept up to date from key events
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeldKeys {
+    pub buttons: Button,
+    pub turbo: Button,
+}
+
+impl HeldKeys {
+    pub fn key_event(&mut self, map: &InputMap, key: Keycode, pressed: bool) {
+        if let Some(button) = map.button(key) {
+            self.buttons.set(button, pressed);
+        }
+        if let Some(button) = map.turbo_button(key) {
+            self.turbo.set(button, pressed);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OppositeDirections {
+    Allow,
+    Neutralize,
+    #[default]
+    LastPressed,
+}
+
+impl OppositeDirections {
+    pub const ALL: [OppositeDirections; 3] = [
+        OppositeDirections::Allow,
+        OppositeDirections::Neutralize,
+        OppositeDirections::LastPressed,
+    ];
+
+    // as it's written in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            OppositeDirections::Allow => "allow",
+            OppositeDirections::Neutralize => "neutralize",
+            OppositeDirections::LastPressed => "last",
+        }
+    }
+}
+
+const OPPOSITES: [(Button, Button); 2] =
+    [(Button::LEFT, Button::RIGHT), (Button::UP, Button::DOWN)];
+
+// applies an OppositeDirections policy to the buttons of each frame in turn
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DpadFilter {
+    policy: OppositeDirections,
+    // held last frame, before filtering
+    held: Button,
+    // of each pair, the direction pressed more recently
+    latest: Button,
+}
+
+impl DpadFilter {
+    pub fn new(policy: OppositeDirections) -> Self {
+        DpadFilter { policy, ..DpadFilter::default() }
+    }
+
+    pub fn policy(&self) -> OppositeDirections {
+        self.policy
+    }
+
+    // Call once a frame with everything held. When both directions of a pair are pressed on
+    // the same frame there's no telling which came last, and neither is kept until one is let
+    // go.
+    pub fn apply(&mut self, buttons: Button) -> Button {
+        let pressed = Button::from_bits(buttons.bits() & !self.held.bits());
+        self.held = buttons;
+        let mut filtered = buttons;
+        for (first, second) in OPPOSITES {
+            let pair = first | second;
+            let latest = match (pressed.contains(first), pressed.contains(second)) {
+                (true, false) => first,
+                (false, true) => second,
+                (true, true) => Button::NONE,
+                (false, false) => self.latest & pair,
+            };
+            self.latest.remove(pair);
+            self.latest.insert(latest);
+            if !buttons.contains(pair) {
+                continue;
+            }
+            match self.policy {
+                OppositeDirections::Allow => {}
+                OppositeDirections::Neutralize => filtered.remove(pair),
+                OppositeDirections::LastPressed => {
+                    filtered.remove(pair);
+                    filtered.insert(self.latest & pair);
+                }
+            }
+        }
+        filtered
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Turbo {
+    period: u8,
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Turbo { period: DEFAULT_TURBO_PERIOD }
+    }
+}
+
+impl Turbo {
+    // frames each press and each release lasts, at least 1
+    pub fn new(period: u8) -> Self {
+        Turbo { period: period.max(1) }
+    }
+
+    pub fn period(&self) -> u8 {
+        self.period
+    }
+
+    // Which of the buttons `held` for turbo are pressed on frame `frame`. Every turbo button
+    // is pressed for the first `period` frames out of each 2 * `period`, counted from frame 0,
+    // so the pattern only depends on the frame number.
+    pub fn buttons(&self, held: Button, frame: u64) -> Button {
+        if (frame / self.period as u64).is_multiple_of(2) { held } else { Button::NONE }
+    }
+}
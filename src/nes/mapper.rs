@@ -0,0 +1,134 @@
+// Cartridge boards. The Cart holds the ROM and RAM chips, a Mapper holds the board's own
+// registers and decides which chip answers each CPU access from $4020 up.
+
+pub mod discrete;
+pub mod fme7;
+pub mod inl_nsf;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namcot108;
+mod nrom;
+pub mod vrc6;
+
+use super::cart::Cart;
+use super::mem::{Peek, Write};
+use super::savestate::Savestate;
+use discrete::{Board, Discrete};
+use fme7::Fme7;
+use inl_nsf::InlNsf;
+use mmc3::{Mmc3, Mmc3Revision};
+use mmc5::Mmc5;
+use namcot108::{Namcot108, Namcot108Board};
+use nrom::Nrom;
+use vrc6::{Vrc6, Vrc6Variant};
+
+pub trait Mapper: Savestate {
+    // None where the board doesn't drive the bus, the Bus then falls back to its PRG RAM
+    // at $6000-$7FFF and to open bus everywhere else
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8>;
+
+    // reads that change board state (latches, IRQ acknowledge) override this
+    fn cpu_read(&mut self, cart: &Cart, addr: u16) -> Option<u8> {
+        self.cpu_peek(cart, addr)
+    }
+
+    // true when a board register took the write
+    fn cpu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) -> bool;
+
+    // pattern tables at $0000-$1FFF, a single unbanked 8 KiB unless the board switches them
+    fn ppu_peek(&self, cart: &Cart, addr: u16) -> u8 {
+        cart.chr_rom.peek(addr)
+    }
+
+    // a pattern fetch or $2007 read, boards that watch the PPU's fetches see them here
+    fn ppu_read(&mut self, cart: &Cart, addr: u16) -> u8 {
+        self.ppu_peek(cart, addr)
+    }
+
+    fn ppu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) {
+        cart.chr_rom.write(addr, data);
+    }
+
+    // Nametable reads at $2000-$2FFF. Some when the board drives the data itself, None to
+    // read the console's CIRAM.
+    fn nametable_read(&mut self, _cart: &Cart, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    // true when the board took the write instead of CIRAM
+    fn nametable_write(&mut self, _cart: &mut Cart, _addr: u16, _data: u8) -> bool {
+        false
+    }
+
+    // the 1 KiB CIRAM page a nametable address uses, None to follow the cart's mirroring
+    fn ciram_page(&self, _cart: &Cart, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    // writes to $2000-$2007, for boards that snoop the PPU's configuration
+    fn ppu_register_write(&mut self, _addr: u16, _data: u8) {}
+
+    // once per CPU cycle, for cycle based IRQ counters and expansion audio
+    fn cpu_clock(&mut self) {}
+
+    // expansion audio, on the same 0.0-1.0 scale as the APU's mixed output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // the Bus PRG RAM at $6000-$7FFF, for boards that can switch it off or write protect it
+    fn prg_ram_enabled(&self) -> bool {
+        true
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        true
+    }
+
+    // filtered rising edge of the PPU's A12 line, what MMC3 style scanline counters clock on
+    fn a12_rising_edge(&mut self) {}
+
+    // level of the board's IRQ output
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+pub fn new(id: u8) -> Option<Box<dyn Mapper>> {
+    match id {
+        0 => Some(Box::new(Nrom)),
+        4 => Some(Box::new(Mmc3::new(Mmc3Revision::Sharp))),
+        5 => Some(Box::new(Mmc5::new())),
+        7 => Some(Box::new(Discrete::new(Board::Axrom))),
+        11 => Some(Box::new(Discrete::new(Board::ColorDreams))),
+        24 => Some(Box::new(Vrc6::new(Vrc6Variant::A))),
+        26 => Some(Box::new(Vrc6::new(Vrc6Variant::B))),
+        31 => Some(Box::new(InlNsf::new())),
+        66 => Some(Box::new(Discrete::new(Board::Gxrom))),
+        69 => Some(Box::new(Fme7::new())),
+        76 => Some(Box::new(Namcot108::new(Namcot108Board::Mapper76))),
+        88 => Some(Box::new(Namcot108::new(Namcot108Board::Mapper88))),
+        95 => Some(Box::new(Namcot108::new(Namcot108Board::Mapper95))),
+        154 => Some(Box::new(Namcot108::new(Namcot108Board::Mapper154))),
+        206 => Some(Box::new(Namcot108::new(Namcot108Board::Mapper206))),
+        _ => None,
+    }
+}
+
+pub fn is_supported(id: u8) -> bool {
+    new(id).is_some()
+}
+
+// offset of `addr` within bank `bank` of a chip made of `size` byte banks, bank numbers past
+// the end of the chip wrap around like the missing address lines would
+fn bank_offset(len: usize, bank: usize, size: usize, addr: u16) -> usize {
+    let banks = (len / size).max(1);
+    ((bank % banks) * size + addr as usize % size) % len.max(1)
+}
+
+fn banked(data: &[u8], bank: usize, size: usize, addr: u16) -> Option<u8> {
+    if data.is_empty() {
+        return None;
+    }
+    Some(data[bank_offset(data.len(), bank, size, addr)])
+}
@@ -0,0 +1,94 @@
+// Discrete logic boards with a single latch at $8000-$FFFF that picks a 32 KiB PRG bank and an
+// 8 KiB CHR bank at once, or the nametable page instead of CHR on AxROM.
+
+use super::{banked, Mapper};
+use crate::nes::cart::{Cart, Mirroring};
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Board {
+    // mapper 7, PRG in bits 0-2 and one-screen mirroring from bit 4, CHR RAM
+    Axrom,
+    // mapper 11, PRG in bits 0-1 and CHR in bits 4-7
+    ColorDreams,
+    // mapper 66, PRG in bits 4-5 and CHR in bits 0-1
+    Gxrom,
+}
+
+pub struct Discrete {
+    board: Board,
+    prg_bank: u8,
+    chr_bank: u8,
+}
+
+impl Discrete {
+    pub fn new(board: Board) -> Self {
+        Discrete {
+            board,
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        banked(cart.prg_rom.data(), self.prg_bank as usize, PRG_BANK_SIZE, addr)
+    }
+
+    fn cpu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) -> bool {
+        if addr < PRG_ROM {
+            return false;
+        }
+        // the ROM drives the bus too, GxROM only sees bits both agree on
+        let data = match self.board {
+            Board::Axrom | Board::ColorDreams => data,
+            Board::Gxrom => data & self.cpu_peek(cart, addr).unwrap_or(0xFF),
+        };
+        match self.board {
+            Board::Axrom => {
+                self.prg_bank = data & 0b111;
+                cart.screen_mirroring = if data & 0x10 == 0 {
+                    Mirroring::SingleScreenA
+                } else {
+                    Mirroring::SingleScreenB
+                };
+            }
+            Board::ColorDreams => (self.prg_bank, self.chr_bank) = (data & 0b11, data >> 4),
+            Board::Gxrom => (self.prg_bank, self.chr_bank) = ((data >> 4) & 0b11, data & 0b11),
+        }
+        true
+    }
+
+    fn ppu_peek(&self, cart: &Cart, addr: u16) -> u8 {
+        banked(cart.chr_rom.data(), self.chr_bank as usize, CHR_BANK_SIZE, addr).unwrap_or(0)
+    }
+
+    fn ppu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) {
+        if !cart.chr_rom.is_ram() {
+            return;
+        }
+        // CHR RAM boards are a single unbanked 8 KiB
+        cart.chr_rom.data_mut()[addr as usize % CHR_BANK_SIZE] = data;
+    }
+}
+
+impl Savestate for Discrete {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.prg_bank);
+        w.write_u8(self.chr_bank);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = r.read_u8()?;
+        self.chr_bank = r.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+// Mapper 69, Sunsoft FME-7 and the 5B that adds sound to it. Banking goes through a command
+// register at $8000 and a parameter at $A000: eight 1 KiB CHR banks, four 8 KiB PRG banks with
+// the one at $6000 switchable between ROM and RAM, mirroring and a 16 bit CPU cycle IRQ counter.
+// The 5B's audio is a YM2149 core, three square channels with a shared noise and envelope
+// generator, written through $C000 (register select) and $E000 (data).
+
+use super::{bank_offset, banked, Mapper};
+use crate::nes::cart::{Cart, Mirroring};
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const PRG_RAM: u16 = 0x6000;
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// the sound core's tone, noise and envelope counters step once every 16 CPU cycles
+const AUDIO_DIVIDER: u8 = 16;
+
+// YM2149 subset in the 5B, only the internal clock divider and no I/O ports
+struct Sunsoft5b {
+    selected: u8,
+    registers: [u8; 16],
+    divider: u8,
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    // 17 bit LFSR
+    noise_shift: u32,
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+}
+
+impl Sunsoft5b {
+    fn new() -> Self {
+        Sunsoft5b {
+            selected: 0,
+            registers: [0; 16],
+            divider: 0,
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise_shift: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        // the upper bits of the select act as a chip enable
+        if self.selected & 0xF0 != 0 {
+            return;
+        }
+        let reg = self.selected as usize;
+        self.registers[reg] = data;
+        if reg == 0x0D {
+            self.envelope_step = 0;
+            self.envelope_attack = data & 0b0100 != 0;
+            self.envelope_holding = false;
+            self.envelope_counter = 0;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let lo = self.registers[channel * 2] as u16;
+        let hi = (self.registers[channel * 2 + 1] as u16 & 0x0F) << 8;
+        (hi | lo).max(1)
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < AUDIO_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.registers[6] & 0x1F).max(1) {
+            self.noise_counter = 0;
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+        }
+
+        let envelope_period =
+            u16::from_le_bytes([self.registers[0x0B], self.registers[0x0C]]).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter >= envelope_period {
+            self.envelope_counter = 0;
+            self.step_envelope();
+        }
+    }
+
+    // 32 steps up or down, then the shape in $0D decides whether to hold, repeat or reverse
+    fn step_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        self.envelope_step += 1;
+        if self.envelope_step < 32 {
+            return;
+        }
+        let shape = self.registers[0x0D];
+        let (continues, attack, alternate, hold) = (
+            shape & 0b1000 != 0,
+            shape & 0b0100 != 0,
+            shape & 0b0010 != 0,
+            shape & 0b0001 != 0,
+        );
+        if !continues {
+            // shapes 0-7 fall silent after one ramp
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+            self.envelope_step = 31;
+        } else if hold {
+            self.envelope_holding = true;
+            self.envelope_attack = attack != alternate;
+            self.envelope_step = 31;
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    // 5 bit volume of each channel, 0 while its tone and noise gates are low
+    fn channel_outputs(&self) -> [u8; 3] {
+        let mixer = self.registers[7];
+        let noise = self.noise_shift & 1 != 0;
+        std::array::from_fn(|channel| {
+            let tone_on = self.tone_outputs[channel] || mixer & (1 << channel) != 0;
+            let noise_on = noise || mixer & (0b1000 << channel) != 0;
+            if !(tone_on && noise_on) {
+                return 0;
+            }
+            let volume = self.registers[8 + channel];
+            if volume & 0x10 != 0 {
+                self.envelope_level()
+            } else if volume & 0x0F == 0 {
+                0
+            } else {
+                (volume & 0x0F) * 2 + 1
+            }
+        })
+    }
+
+    // the DAC is logarithmic, 1.5 dB per 5 bit step
+    fn output(&self) -> f32 {
+        let level = |volume: u8| {
+            if volume == 0 {
+                0.0
+            } else {
+                10f32.powf((volume as f32 - 31.0) * 1.5 / 20.0)
+            }
+        };
+        self.channel_outputs().iter().map(|&volume| level(volume)).sum::<f32>() / 3.0
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.selected);
+        w.write_bytes(&self.registers);
+        w.write_u8(self.divider);
+        for counter in self.tone_counters {
+            w.write_u16(counter);
+        }
+        for output in self.tone_outputs {
+            w.write_bool(output);
+        }
+        w.write_u8(self.noise_counter);
+        w.write_u64(self.noise_shift as u64);
+        w.write_u16(self.envelope_counter);
+        w.write_u8(self.envelope_step);
+        w.write_bool(self.envelope_attack);
+        w.write_bool(self.envelope_holding);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.selected = r.read_u8()?;
+        r.read_into(&mut self.registers)?;
+        self.divider = r.read_u8()? % AUDIO_DIVIDER;
+        for counter in &mut self.tone_counters {
+            *counter = r.read_u16()?;
+        }
+        for output in &mut self.tone_outputs {
+            *output = r.read_bool()?;
+        }
+        self.noise_counter = r.read_u8()?;
+        self.noise_shift = (r.read_u64()? as u32 & 0x1_FFFF).max(1);
+        self.envelope_counter = r.read_u16()?;
+        self.envelope_step = r.read_u8()? & 0x1F;
+        self.envelope_attack = r.read_bool()?;
+        self.envelope_holding = r.read_bool()?;
+        Ok(())
+    }
+}
+
+pub struct Fme7 {
+    command: u8,
+    chr_banks: [u8; 8],
+    // $6000 bank, bit 6 selects RAM and bit 7 enables it
+    prg_6000: u8,
+    prg_banks: [u8; 3],
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5b,
+}
+
+impl Default for Fme7 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fme7 {
+    pub fn new() -> Self {
+        Fme7 {
+            command: 0,
+            chr_banks: [0; 8],
+            prg_6000: 0,
+            prg_banks: [0; 3],
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    // 5B channel volumes (0-31), for mixing each on its own
+    pub fn channel_outputs(&self) -> [u8; 3] {
+        self.audio.channel_outputs()
+    }
+
+    fn ram_selected(&self) -> bool {
+        self.prg_6000 & 0x40 != 0
+    }
+
+    fn write_parameter(&mut self, cart: &mut Cart, data: u8) {
+        match self.command {
+            0..=7 => self.chr_banks[self.command as usize] = data,
+            8 => self.prg_6000 = data,
+            9..=0x0B => self.prg_banks[self.command as usize - 9] = data,
+            0x0C => {
+                cart.screen_mirroring = match data & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                }
+            }
+            0x0D => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.irq_counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x0E => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | ((data as u16) << 8),
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        let prg = cart.prg_rom.data();
+        let last = (prg.len() / PRG_BANK_SIZE).saturating_sub(1);
+        let bank = match addr {
+            // RAM is the Bus's, through prg_ram_enabled
+            PRG_RAM..=0x7FFF if self.ram_selected() => return None,
+            PRG_RAM..=0x7FFF => self.prg_6000 as usize & 0x3F,
+            PRG_ROM..=0xDFFF => {
+                self.prg_banks[(addr - PRG_ROM) as usize / PRG_BANK_SIZE] as usize & 0x3F
+            }
+            0xE000..=0xFFFF => last,
+            _ => return None,
+        };
+        banked(prg, bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn cpu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) -> bool {
+        match addr {
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(cart, data),
+            0xC000..=0xDFFF => self.audio.selected = data,
+            0xE000..=0xFFFF => self.audio.write(data),
+            _ => return false,
+        }
+        true
+    }
+
+    fn ppu_peek(&self, cart: &Cart, addr: u16) -> u8 {
+        let page = (addr as usize & 0x1FFF) / CHR_BANK_SIZE;
+        banked(cart.chr_rom.data(), self.chr_banks[page] as usize, CHR_BANK_SIZE, addr).unwrap_or(0)
+    }
+
+    fn ppu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) {
+        if !cart.chr_rom.is_ram() || cart.chr_rom.is_empty() {
+            return;
+        }
+        let bank = self.chr_banks[(addr as usize & 0x1FFF) / CHR_BANK_SIZE] as usize;
+        let offset = bank_offset(cart.chr_rom.len(), bank, CHR_BANK_SIZE, addr);
+        cart.chr_rom.data_mut()[offset] = data;
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.ram_selected() && self.prg_6000 & 0x80 != 0
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // three channels at full volume come out a bit louder than the APU's pulses
+    fn audio_output(&self) -> f32 {
+        self.audio.output() * 0.3
+    }
+}
+
+impl Savestate for Fme7 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.command);
+        w.write_bytes(&self.chr_banks);
+        w.write_u8(self.prg_6000);
+        w.write_bytes(&self.prg_banks);
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.irq_counter_enabled);
+        w.write_u16(self.irq_counter);
+        w.write_bool(self.irq_pending);
+        self.audio.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.command = r.read_u8()? & 0x0F;
+        r.read_into(&mut self.chr_banks)?;
+        self.prg_6000 = r.read_u8()?;
+        r.read_into(&mut self.prg_banks)?;
+        self.irq_enabled = r.read_bool()?;
+        self.irq_counter_enabled = r.read_bool()?;
+        self.irq_counter = r.read_u16()?;
+        self.irq_pending = r.read_bool()?;
+        self.audio.load_state(r)
+    }
+}
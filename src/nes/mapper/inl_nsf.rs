@@ -0,0 +1,65 @@
+// Mapper 31, INL's board for NSF style compilations, and what NSF files run on. Eight 4 KiB
+// PRG banks at $8000-$FFFF picked by writes to $5000-$5FFF, the low three address bits
+// choosing the slot (NSF drivers use $5FF8-$5FFF). 8 KiB of unbanked CHR.
+
+use super::{banked, Mapper};
+use crate::nes::cart::Cart;
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const BANK_REGISTERS: u16 = 0x5000;
+const BANK_REGISTERS_END: u16 = 0x5FFF;
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x1000;
+
+pub struct InlNsf {
+    banks: [u8; 8],
+}
+
+impl Default for InlNsf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InlNsf {
+    // the last bank comes up at $F000 so the vectors are there
+    pub fn new() -> Self {
+        InlNsf::with_banks([0, 0, 0, 0, 0, 0, 0, 0xFF])
+    }
+
+    pub fn with_banks(banks: [u8; 8]) -> Self {
+        InlNsf { banks }
+    }
+
+    pub fn banks(&self) -> [u8; 8] {
+        self.banks
+    }
+}
+
+impl Mapper for InlNsf {
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        let bank = self.banks[(addr - PRG_ROM) as usize / PRG_BANK_SIZE];
+        banked(cart.prg_rom.data(), bank as usize, PRG_BANK_SIZE, addr)
+    }
+
+    fn cpu_write(&mut self, _cart: &mut Cart, addr: u16, data: u8) -> bool {
+        if !(BANK_REGISTERS..=BANK_REGISTERS_END).contains(&addr) {
+            return false;
+        }
+        self.banks[addr as usize & 0b111] = data;
+        true
+    }
+}
+
+impl Savestate for InlNsf {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.banks);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_into(&mut self.banks)
+    }
+}
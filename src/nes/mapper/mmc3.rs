@@ -0,0 +1,198 @@
+// Mapper 4, Nintendo MMC3 (TxROM). Eight bank registers picked through $8000 and written
+// through $8001: R0-R5 map CHR in 2 KiB and 1 KiB pages, R6-R7 map PRG in 8 KiB pages. The
+// scanline counter is clocked by rising edges of PPU A12 and raises IRQ when it hits zero.
+
+use super::Mapper;
+use crate::nes::cart::{Cart, Mirroring};
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// The two IRQ counter behaviours. Most carts don't care, a few only work with one of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mmc3Revision {
+    // MMC3B/C: IRQ whenever the counter is zero after a clock
+    Sharp,
+    // MMC3A: IRQ only when the counter gets to zero by counting down or by a requested reload,
+    // a latch of 0 doesn't fire on every scanline
+    Nec,
+}
+
+pub struct Mmc3 {
+    revision: Mmc3Revision,
+    bank_select: u8,
+    banks: [u8; 8],
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+    pub fn new(revision: Mmc3Revision) -> Self {
+        Mmc3 {
+            revision,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_offset(&self, cart: &Cart, addr: u16) -> usize {
+        let banks = (cart.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let second_last = banks.saturating_sub(2);
+        let r6 = (self.banks[6] & 0b0011_1111) as usize;
+        let r7 = (self.banks[7] & 0b0011_1111) as usize;
+        let swapped = self.bank_select & 0b0100_0000 != 0;
+        let bank = match ((addr - PRG_ROM) / PRG_BANK_SIZE as u16, swapped) {
+            (0, false) | (2, true) => r6,
+            (0, true) | (2, false) => second_last,
+            (1, _) => r7,
+            _ => banks - 1,
+        };
+        (bank % banks) * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE)
+    }
+
+    fn chr_offset(&self, cart: &Cart, addr: u16) -> usize {
+        let banks = (cart.chr_rom.len() / CHR_BANK_SIZE).max(1);
+        // A12 inversion swaps the 2 KiB pages at $0000 with the 1 KiB pages at $1000
+        let addr = if self.bank_select & 0b1000_0000 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        let page = (addr as usize & 0x1FFF) / CHR_BANK_SIZE;
+        let bank = match page {
+            0 | 1 => (self.banks[0] & !1) as usize + page,
+            2 | 3 => (self.banks[1] & !1) as usize + page - 2,
+            _ => self.banks[page - 2] as usize,
+        };
+        (bank % banks) * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        if addr < PRG_ROM || cart.prg_rom.is_empty() {
+            return None;
+        }
+        Some(cart.prg_rom.data()[self.prg_offset(cart, addr)])
+    }
+
+    fn cpu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) -> bool {
+        if addr < PRG_ROM {
+            return false;
+        }
+        // each register repeats across its 8 KiB, even and odd addresses pick the pair member
+        match addr & 0xE001 {
+            0x8000 => self.bank_select = data,
+            0x8001 => self.banks[(self.bank_select & 0b111) as usize] = data,
+            0xA000 => {
+                if cart.screen_mirroring != Mirroring::FourScreen {
+                    cart.screen_mirroring = if data & 1 == 0 {
+                        Mirroring::Vertical
+                    } else {
+                        Mirroring::Horizontal
+                    };
+                }
+            }
+            0xA001 => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_write_protect = data & 0b0100_0000 != 0;
+            }
+            0xC000 => self.irq_latch = data,
+            0xC001 => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000 => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+        true
+    }
+
+    fn ppu_peek(&self, cart: &Cart, addr: u16) -> u8 {
+        if cart.chr_rom.is_empty() {
+            return 0;
+        }
+        cart.chr_rom.data()[self.chr_offset(cart, addr)]
+    }
+
+    fn ppu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) {
+        if !cart.chr_rom.is_ram() || cart.chr_rom.is_empty() {
+            return;
+        }
+        let offset = self.chr_offset(cart, addr);
+        cart.chr_rom.data_mut()[offset] = data;
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_ram_enabled
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_enabled && !self.prg_ram_write_protect
+    }
+
+    fn a12_rising_edge(&mut self) {
+        let before = self.irq_counter;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        let fire = match self.revision {
+            Mmc3Revision::Sharp => self.irq_counter == 0,
+            Mmc3Revision::Nec => self.irq_counter == 0 && (before > 0 || self.irq_reload),
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+        self.irq_reload = false;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+impl Savestate for Mmc3 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.bank_select);
+        w.write_bytes(&self.banks);
+        w.write_bool(self.prg_ram_enabled);
+        w.write_bool(self.prg_ram_write_protect);
+        w.write_u8(self.irq_latch);
+        w.write_u8(self.irq_counter);
+        w.write_bool(self.irq_reload);
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select = r.read_u8()?;
+        r.read_into(&mut self.banks)?;
+        self.prg_ram_enabled = r.read_bool()?;
+        self.prg_ram_write_protect = r.read_bool()?;
+        self.irq_latch = r.read_u8()?;
+        self.irq_counter = r.read_u8()?;
+        self.irq_reload = r.read_bool()?;
+        self.irq_enabled = r.read_bool()?;
+        self.irq_pending = r.read_bool()?;
+        Ok(())
+    }
+}
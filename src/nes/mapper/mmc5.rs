@@ -0,0 +1,760 @@
+// Mapper 5, Nintendo MMC5 (ExROM). PRG and CHR banking in four page sizes, 1 KiB of expansion
+// RAM that works as an extra nametable, extended attributes or plain RAM, a fill mode
+// nametable, a vertical split, an 8x8 multiplier, a scanline IRQ, and two pulse channels plus
+// PCM. There's no scanline input on the cart: the MMC5 works out where the PPU is by watching
+// its fetches.
+
+use super::Mapper;
+use crate::nes::cart::Cart;
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+use std::ops::Range;
+
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const EXRAM: u16 = 0x5C00;
+const EXRAM_END: u16 = 0x5FFF;
+const ATTRIBUTE_TABLE: u16 = 0x03C0;
+// Nametable fetches in a scanline, counted from the one that completes scanline detection:
+// 32 background tiles, 16 from the sprite slots, 2 tiles for the next line and 2 dummies.
+const SPRITE_FETCHES: Range<u8> = 32..48;
+const NEXT_LINE_FETCHES: Range<u8> = 48..50;
+// CPU cycles without a PPU read before the MMC5 decides the frame is over
+const IDLE_CYCLES: u8 = 3;
+// the pulses' envelopes and length counters run at a fixed 240 Hz
+const AUDIO_FRAME_CYCLES: u16 = 7457;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Fetch {
+    Background,
+    Sprite,
+    // $2007 accesses, or anything while the PPU isn't rendering
+    Cpu,
+}
+
+// An APU pulse without the sweep unit
+#[derive(Default)]
+struct Pulse {
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    halt: bool,
+    constant_volume: bool,
+    volume: u8,
+    envelope_start: bool,
+    envelope_divider: u8,
+    envelope_decay: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.duty = data >> 6;
+                self.halt = data & 0b0010_0000 != 0;
+                self.constant_volume = data & 0b0001_0000 != 0;
+                self.volume = data & 0b1111;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_frame(&mut self) {
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.envelope_decay = 15;
+            self.envelope_divider = self.volume;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.volume;
+            if self.envelope_decay > 0 {
+                self.envelope_decay -= 1;
+            } else if self.halt {
+                self.envelope_decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+        if !self.halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || DUTY_CYCLES[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else if self.constant_volume {
+            self.volume
+        } else {
+            self.envelope_decay
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.enabled);
+        w.write_u8(self.duty);
+        w.write_u8(self.step);
+        w.write_u16(self.period);
+        w.write_u16(self.timer);
+        w.write_u8(self.length);
+        w.write_bool(self.halt);
+        w.write_bool(self.constant_volume);
+        w.write_u8(self.volume);
+        w.write_bool(self.envelope_start);
+        w.write_u8(self.envelope_divider);
+        w.write_u8(self.envelope_decay);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = r.read_bool()?;
+        self.duty = r.read_u8()? & 0b11;
+        self.step = r.read_u8()? % 8;
+        self.period = r.read_u16()?;
+        self.timer = r.read_u16()?;
+        self.length = r.read_u8()?;
+        self.halt = r.read_bool()?;
+        self.constant_volume = r.read_bool()?;
+        self.volume = r.read_u8()?;
+        self.envelope_start = r.read_bool()?;
+        self.envelope_divider = r.read_u8()?;
+        self.envelope_decay = r.read_u8()?;
+        Ok(())
+    }
+}
+
+pub struct Mmc5 {
+    prg_mode: u8,
+    chr_mode: u8,
+    // $5102 has to be 0b10 and $5103 0b01 for PRG RAM to take writes
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    // $5120-$5127 (set A) and $5128-$512B (set B), with the $5130 bits they were written with
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    // in 8x8 sprite mode all fetches use whichever set was written last
+    chr_set_b_last: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    exram: [u8; 0x400],
+    // snooped from $2000 and $2001
+    sprite_8x16: bool,
+    rendering: bool,
+    // scanline detection from the PPU's fetches
+    in_frame: bool,
+    scanline: u8,
+    last_read: u16,
+    repeats: u8,
+    nametable_fetch: u8,
+    idle_cycles: u8,
+    // latched at each background tile fetch for the attribute and pattern fetches after it
+    tile_exram: u8,
+    split_tile: Option<(u8, u16)>,
+    pulses: [Pulse; 2],
+    pcm: u8,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    audio_divider: u16,
+    odd_cycle: bool,
+}
+
+impl Default for Mmc5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mmc5 {
+    pub fn new() -> Self {
+        Mmc5 {
+            // carts count on the last PRG bank being at $E000 from power on
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            chr_set_b_last: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            exram: [0u8; 0x400],
+            sprite_8x16: false,
+            rendering: false,
+            in_frame: false,
+            scanline: 0,
+            last_read: 0,
+            repeats: 0,
+            nametable_fetch: 0,
+            idle_cycles: 0,
+            tile_exram: 0,
+            split_tile: None,
+            pulses: [Pulse::default(), Pulse::default()],
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            audio_divider: 0,
+            odd_cycle: false,
+        }
+    }
+
+    // (ROM, 8 KiB page) behind a CPU address from $6000 up
+    fn prg_page(&self, addr: u16) -> (bool, usize) {
+        if addr < PRG_ROM {
+            return (false, self.prg_banks[0] as usize & 0x7F);
+        }
+        let slot = ((addr - PRG_ROM) as usize) / PRG_BANK_SIZE;
+        // (register, size in 8 KiB pages)
+        let (reg, pages) = match (self.prg_mode, slot) {
+            (0, _) => (4, 4),
+            (1, 0 | 1) | (2, 0 | 1) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 2) => (3, 1),
+            (2, _) => (4, 1),
+            (_, slot) => (slot + 1, 1),
+        };
+        let value = self.prg_banks[reg];
+        // $5117 can only select ROM
+        let rom = reg == 4 || value & 0x80 != 0;
+        let base = (value & 0x7F) as usize & !(pages - 1);
+        (rom, base + slot % pages)
+    }
+
+    fn prg_offset(&self, cart: &Cart, addr: u16) -> Option<(bool, usize)> {
+        let (rom, page) = self.prg_page(addr);
+        let size = if rom {
+            cart.prg_rom.len()
+        } else {
+            cart.prg_ram.len()
+        };
+        if size == 0 {
+            return None;
+        }
+        let pages = (size / PRG_BANK_SIZE).max(1);
+        Some((rom, (page % pages) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE))
+    }
+
+    fn prg_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        let (rom, offset) = self.prg_offset(cart, addr)?;
+        Some(if rom {
+            cart.prg_rom.data()[offset]
+        } else {
+            cart.prg_ram.data()[offset]
+        })
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    fn fetch(&self) -> Fetch {
+        if !self.in_frame {
+            Fetch::Cpu
+        } else if SPRITE_FETCHES.contains(&self.nametable_fetch) {
+            Fetch::Sprite
+        } else {
+            Fetch::Background
+        }
+    }
+
+    // 1 KiB CHR page for a pattern address from set A or B
+    fn chr_page(&self, addr: u16, set_b: bool) -> usize {
+        let page = (addr as usize & 0x1FFF) / CHR_BANK_SIZE;
+        let a = &self.chr_banks[..8];
+        let b = &self.chr_banks[8..];
+        let (bank, pages) = if set_b {
+            // set B only has 4 KiB worth of registers, repeated in both halves
+            let page = page % 4;
+            match self.chr_mode {
+                0 => (b[3], 8),
+                1 => (b[3], 4),
+                2 => (b[1 + (page / 2) * 2], 2),
+                _ => (b[page], 1),
+            }
+        } else {
+            match self.chr_mode {
+                0 => (a[7], 8),
+                1 => (a[3 + (page / 4) * 4], 4),
+                2 => (a[1 + (page / 2) * 2], 2),
+                _ => (a[page], 1),
+            }
+        };
+        bank as usize * pages + page % pages
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let fetch = self.fetch();
+        if fetch == Fetch::Background {
+            if let Some((_, y)) = self.split_tile {
+                // the split has its own 4 KiB bank and vertical scroll
+                return self.split_bank as usize * 0x1000 + (addr as usize & 0x0FF8) + (y & 7) as usize;
+            }
+            if self.exram_mode == 1 {
+                let bank = (self.tile_exram & 0x3F) as usize | (self.chr_upper as usize) << 6;
+                return bank * 0x1000 + (addr as usize & 0x0FFF);
+            }
+        }
+        let set_b = match fetch {
+            Fetch::Background if self.sprite_8x16 => true,
+            Fetch::Sprite if self.sprite_8x16 => false,
+            _ => self.chr_set_b_last,
+        };
+        self.chr_page(addr, set_b) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    // Three reads of the same nametable address in a row only happen at the start of a
+    // scanline: the two dummy fetches at the end of the previous one and its first tile.
+    fn note_nametable_read(&mut self, addr: u16) {
+        self.idle_cycles = 0;
+        if addr == self.last_read {
+            self.repeats = self.repeats.saturating_add(1);
+        } else {
+            self.last_read = addr;
+            self.repeats = 1;
+        }
+        if addr & 0x03FF >= ATTRIBUTE_TABLE {
+            return;
+        }
+        if self.repeats == 3 {
+            self.detect_scanline();
+            self.nametable_fetch = 0;
+        } else {
+            self.nametable_fetch = self.nametable_fetch.saturating_add(1);
+        }
+    }
+
+    fn detect_scanline(&mut self) {
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+            return;
+        }
+        self.scanline = self.scanline.wrapping_add(1);
+        if self.scanline == self.irq_compare {
+            self.irq_pending = true;
+        }
+    }
+
+    fn leave_frame(&mut self) {
+        self.in_frame = false;
+        self.scanline = 0;
+        self.last_read = 0;
+        self.repeats = 0;
+    }
+
+    // (tile column, split scroll line) when the current background tile falls in the split
+    fn split_region(&self) -> Option<(u8, u16)> {
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+            return None;
+        }
+        let (x, line) = if NEXT_LINE_FETCHES.contains(&self.nametable_fetch) {
+            (self.nametable_fetch - NEXT_LINE_FETCHES.start, self.scanline as u16 + 1)
+        } else if self.nametable_fetch < SPRITE_FETCHES.start {
+            (self.nametable_fetch + 2, self.scanline as u16)
+        } else {
+            return None;
+        };
+        let threshold = self.split_control & 0x1F;
+        let inside = if self.split_control & 0x40 != 0 {
+            x >= threshold
+        } else {
+            x < threshold
+        };
+        inside.then_some((x, (self.split_scroll as u16 + line) % 240))
+    }
+
+    fn status(&self) -> u8 {
+        (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+
+    fn exram_readable(&self) -> bool {
+        self.exram_mode >= 2
+    }
+}
+
+// attribute byte with the same palette in all four quadrants
+fn attribute(palette: u8) -> u8 {
+    (palette & 0b11) * 0b0101_0101
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        match addr {
+            0x5010 => Some((self.pcm_irq as u8) << 7),
+            0x5015 => {
+                let playing = |pulse: &Pulse| (pulse.length > 0) as u8;
+                Some(playing(&self.pulses[1]) << 1 | playing(&self.pulses[0]))
+            }
+            0x5204 => Some(self.status()),
+            0x5205 => Some(self.product() as u8),
+            0x5206 => Some((self.product() >> 8) as u8),
+            EXRAM..=EXRAM_END if self.exram_readable() => Some(self.exram[(addr - EXRAM) as usize]),
+            0x6000..=0xFFFF => self.prg_peek(cart, addr),
+            _ => None,
+        }
+    }
+
+    fn cpu_read(&mut self, cart: &Cart, addr: u16) -> Option<u8> {
+        let data = self.cpu_peek(cart, addr);
+        match addr {
+            0x5010 => self.pcm_irq = false,
+            0x5204 => self.irq_pending = false,
+            // the CPU fetching the NMI vector means the frame is over
+            0xFFFA | 0xFFFB => {
+                self.leave_frame();
+                self.irq_pending = false;
+            }
+            0x8000..=0xBFFF if self.pcm_read_mode => {
+                if let Some(sample) = data {
+                    self.pcm = sample;
+                    if sample == 0 && self.pcm_irq_enabled {
+                        self.pcm_irq = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+        data
+    }
+
+    fn cpu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) -> bool {
+        match addr {
+            0x5000..=0x5007 => self.pulses[(addr as usize >> 2) & 1].write(addr & 0b11, data),
+            0x5010 => {
+                self.pcm_read_mode = data & 1 != 0;
+                self.pcm_irq_enabled = data & 0x80 != 0;
+            }
+            0x5011 => {
+                if !self.pcm_read_mode && data != 0 {
+                    self.pcm = data;
+                }
+            }
+            0x5015 => {
+                self.pulses[0].set_enabled(data & 0b01 != 0);
+                self.pulses[1].set_enabled(data & 0b10 != 0);
+            }
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102 | 0x5103 => self.prg_ram_protect[(addr - 0x5102) as usize] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0b11,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512B => {
+                let reg = (addr - 0x5120) as usize;
+                self.chr_banks[reg] = data as u16 | (self.chr_upper as u16) << 8;
+                self.chr_set_b_last = reg >= 8;
+            }
+            0x5130 => self.chr_upper = data & 0b11,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            EXRAM..=EXRAM_END => {
+                let index = (addr - EXRAM) as usize;
+                match self.exram_mode {
+                    // as a nametable it can only be written while the PPU is rendering
+                    0 | 1 => self.exram[index] = if self.in_frame { data } else { 0 },
+                    2 => self.exram[index] = data,
+                    _ => {}
+                }
+            }
+            0x6000..=0xFFFF => {
+                if let (true, Some((false, offset))) =
+                    (self.prg_ram_writable(), self.prg_offset(cart, addr))
+                {
+                    cart.prg_ram.data_mut()[offset] = data;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn ppu_peek(&self, cart: &Cart, addr: u16) -> u8 {
+        if cart.chr_rom.is_empty() {
+            return 0;
+        }
+        cart.chr_rom.data()[self.chr_offset(addr) % cart.chr_rom.len()]
+    }
+
+    fn ppu_read(&mut self, cart: &Cart, addr: u16) -> u8 {
+        self.idle_cycles = 0;
+        self.last_read = addr;
+        self.repeats = 1;
+        self.ppu_peek(cart, addr)
+    }
+
+    fn ppu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) {
+        if !cart.chr_rom.is_ram() || cart.chr_rom.is_empty() {
+            return;
+        }
+        let offset = self.chr_offset(addr) % cart.chr_rom.len();
+        cart.chr_rom.data_mut()[offset] = data;
+    }
+
+    fn nametable_read(&mut self, _cart: &Cart, addr: u16) -> Option<u8> {
+        let addr = 0x2000 | (addr & 0x0FFF);
+        self.note_nametable_read(addr);
+        let offset = addr & 0x03FF;
+        let is_attribute = offset >= ATTRIBUTE_TABLE;
+        let background = self.fetch() == Fetch::Background;
+
+        if background && !is_attribute {
+            self.tile_exram = self.exram[offset as usize];
+            self.split_tile = self.split_region();
+        } else if !background {
+            self.split_tile = None;
+        }
+        if let (true, Some((x, y))) = (background, self.split_tile) {
+            let x = x as usize % 32;
+            let y = y as usize;
+            return Some(if is_attribute {
+                let byte = self.exram[ATTRIBUTE_TABLE as usize + (y / 32) * 8 + x / 4];
+                attribute(byte >> (((y / 16) & 1) * 4 + ((x / 2) & 1) * 2))
+            } else {
+                self.exram[(y / 8) * 32 + x]
+            });
+        }
+        if background && is_attribute && self.exram_mode == 1 {
+            return Some(attribute(self.tile_exram >> 6));
+        }
+
+        match (self.nametable_mapping >> (((addr >> 10) & 0b11) * 2)) & 0b11 {
+            2 if self.exram_mode <= 1 => Some(self.exram[offset as usize]),
+            2 => Some(0),
+            3 if is_attribute => Some(attribute(self.fill_attribute)),
+            3 => Some(self.fill_tile),
+            _ => None,
+        }
+    }
+
+    fn nametable_write(&mut self, _cart: &mut Cart, addr: u16, data: u8) -> bool {
+        match (self.nametable_mapping >> (((addr >> 10) & 0b11) * 2)) & 0b11 {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[(addr & 0x03FF) as usize] = data;
+                }
+                true
+            }
+            3 => true,
+            _ => false,
+        }
+    }
+
+    fn ciram_page(&self, _cart: &Cart, addr: u16) -> Option<u8> {
+        match (self.nametable_mapping >> (((addr >> 10) & 0b11) * 2)) & 0b11 {
+            page @ (0 | 1) => Some(page),
+            _ => None,
+        }
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x2000 => self.sprite_8x16 = data & 0b0010_0000 != 0,
+            0x2001 => {
+                self.rendering = data & 0b0001_1000 != 0;
+                if !self.rendering {
+                    self.leave_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.in_frame {
+            self.idle_cycles += 1;
+            if self.idle_cycles >= IDLE_CYCLES {
+                self.leave_frame();
+            }
+        }
+
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            for pulse in &mut self.pulses {
+                pulse.clock_timer();
+            }
+        }
+        self.audio_divider += 1;
+        if self.audio_divider == AUDIO_FRAME_CYCLES {
+            self.audio_divider = 0;
+            for pulse in &mut self.pulses {
+                pulse.clock_frame();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || self.pcm_irq
+    }
+
+    // same curves as the APU's pulse and DMC mixing
+    fn audio_output(&self) -> f32 {
+        let pulses = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_out = if pulses == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulses + 100.0)
+        };
+        let pcm = self.pcm as f32;
+        let pcm_out = if pcm == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / (pcm / 22638.0) + 100.0)
+        };
+        pulse_out + pcm_out
+    }
+}
+
+impl Savestate for Mmc5 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.prg_mode);
+        w.write_u8(self.chr_mode);
+        w.write_bytes(&self.prg_ram_protect);
+        w.write_u8(self.exram_mode);
+        w.write_u8(self.nametable_mapping);
+        w.write_u8(self.fill_tile);
+        w.write_u8(self.fill_attribute);
+        w.write_bytes(&self.prg_banks);
+        for bank in self.chr_banks {
+            w.write_u16(bank);
+        }
+        w.write_u8(self.chr_upper);
+        w.write_bool(self.chr_set_b_last);
+        w.write_u8(self.split_control);
+        w.write_u8(self.split_scroll);
+        w.write_u8(self.split_bank);
+        w.write_u8(self.irq_compare);
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.irq_pending);
+        w.write_u8(self.multiplicand);
+        w.write_u8(self.multiplier);
+        w.write_bytes(&self.exram);
+        w.write_bool(self.sprite_8x16);
+        w.write_bool(self.rendering);
+        w.write_bool(self.in_frame);
+        w.write_u8(self.scanline);
+        for pulse in &self.pulses {
+            pulse.save_state(w);
+        }
+        w.write_u8(self.pcm);
+        w.write_bool(self.pcm_read_mode);
+        w.write_bool(self.pcm_irq_enabled);
+        w.write_bool(self.pcm_irq);
+        w.write_u16(self.audio_divider);
+        w.write_bool(self.odd_cycle);
+    }
+
+    // the fetch tracking is rebuilt by the PPU's next scanline
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.prg_mode = r.read_u8()? & 0b11;
+        self.chr_mode = r.read_u8()? & 0b11;
+        r.read_into(&mut self.prg_ram_protect)?;
+        self.exram_mode = r.read_u8()? & 0b11;
+        self.nametable_mapping = r.read_u8()?;
+        self.fill_tile = r.read_u8()?;
+        self.fill_attribute = r.read_u8()? & 0b11;
+        r.read_into(&mut self.prg_banks)?;
+        for bank in &mut self.chr_banks {
+            *bank = r.read_u16()?;
+        }
+        self.chr_upper = r.read_u8()? & 0b11;
+        self.chr_set_b_last = r.read_bool()?;
+        self.split_control = r.read_u8()?;
+        self.split_scroll = r.read_u8()?;
+        self.split_bank = r.read_u8()?;
+        self.irq_compare = r.read_u8()?;
+        self.irq_enabled = r.read_bool()?;
+        self.irq_pending = r.read_bool()?;
+        self.multiplicand = r.read_u8()?;
+        self.multiplier = r.read_u8()?;
+        r.read_into(&mut self.exram)?;
+        self.sprite_8x16 = r.read_bool()?;
+        self.rendering = r.read_bool()?;
+        self.in_frame = r.read_bool()?;
+        self.scanline = r.read_u8()?;
+        for pulse in &mut self.pulses {
+            pulse.load_state(r)?;
+        }
+        self.pcm = r.read_u8()?;
+        self.pcm_read_mode = r.read_bool()?;
+        self.pcm_irq_enabled = r.read_bool()?;
+        self.pcm_irq = r.read_bool()?;
+        self.audio_divider = r.read_u16()? % AUDIO_FRAME_CYCLES;
+        self.odd_cycle = r.read_bool()?;
+        self.last_read = 0;
+        self.repeats = 0;
+        self.nametable_fetch = 0;
+        self.idle_cycles = 0;
+        self.split_tile = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+// Namcot 108 (mapper 206) and the boards built around it. The chip is the banking half of an
+// MMC3: the same R0-R7 picked through $8000 and written through $8001, but only in the $8000-$9FFF
+// range, PRG fixed in mode 0, no CHR inversion, no IRQ and no PRG RAM. Mirroring is wired on the
+// board unless a variant drives it.
+
+use super::{bank_offset, banked, Mapper};
+use crate::nes::cart::{Cart, Mirroring};
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Namcot108Board {
+    // plain Namcot 108, DxROM
+    Mapper206,
+    // four 2 KiB CHR banks from R2-R5 instead of MMC3 style CHR
+    Mapper76,
+    // R0-R1 fixed to the first 64 KiB of CHR and R2-R5 to the second
+    Mapper88,
+    // R0 and R1 bit 5 pick the nametable for each half of the screen
+    Mapper95,
+    // mapper 88 with one-screen mirroring from bit 6 of any write
+    Mapper154,
+}
+
+pub struct Namcot108 {
+    board: Namcot108Board,
+    bank_select: u8,
+    banks: [u8; 8],
+}
+
+impl Namcot108 {
+    pub fn new(board: Namcot108Board) -> Self {
+        Namcot108 {
+            board,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+        }
+    }
+
+    // CHR bank number in 1 KiB units for the page at `addr`
+    fn chr_bank(&self, addr: u16) -> usize {
+        let page = (addr as usize & 0x1FFF) / CHR_BANK_SIZE;
+        match self.board {
+            Namcot108Board::Mapper76 => (self.banks[2 + page / 2] as usize & 0x3F) * 2 + page % 2,
+            _ => {
+                let bank = match page {
+                    0 | 1 => (self.banks[0] & 0x3E) as usize + page,
+                    2 | 3 => (self.banks[1] & 0x3E) as usize + page - 2,
+                    _ => self.banks[page - 2] as usize & 0x3F,
+                };
+                match self.board {
+                    Namcot108Board::Mapper88 | Namcot108Board::Mapper154 if page >= 4 => {
+                        bank | 0x40
+                    }
+                    Namcot108Board::Mapper95 => bank & 0x1F,
+                    _ => bank,
+                }
+            }
+        }
+    }
+}
+
+impl Mapper for Namcot108 {
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        let prg = cart.prg_rom.data();
+        let banks = (prg.len() / PRG_BANK_SIZE).max(1);
+        let bank = match (addr - PRG_ROM) as usize / PRG_BANK_SIZE {
+            0 => self.banks[6] as usize & 0x0F,
+            1 => self.banks[7] as usize & 0x0F,
+            2 => banks.saturating_sub(2),
+            _ => banks - 1,
+        };
+        banked(prg, bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn cpu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) -> bool {
+        if addr < PRG_ROM {
+            return false;
+        }
+        if self.board == Namcot108Board::Mapper154 {
+            cart.screen_mirroring = if data & 0x40 == 0 {
+                Mirroring::SingleScreenA
+            } else {
+                Mirroring::SingleScreenB
+            };
+        }
+        match addr & 0xE001 {
+            0x8000 => self.bank_select = data & 0b111,
+            0x8001 => self.banks[self.bank_select as usize] = data,
+            _ => {}
+        }
+        true
+    }
+
+    fn ppu_peek(&self, cart: &Cart, addr: u16) -> u8 {
+        banked(cart.chr_rom.data(), self.chr_bank(addr), CHR_BANK_SIZE, addr).unwrap_or(0)
+    }
+
+    fn ppu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) {
+        if !cart.chr_rom.is_ram() || cart.chr_rom.is_empty() {
+            return;
+        }
+        let offset = bank_offset(cart.chr_rom.len(), self.chr_bank(addr), CHR_BANK_SIZE, addr);
+        cart.chr_rom.data_mut()[offset] = data;
+    }
+
+    fn ciram_page(&self, _cart: &Cart, addr: u16) -> Option<u8> {
+        if self.board != Namcot108Board::Mapper95 {
+            return None;
+        }
+        let bank = if addr & 0x0800 == 0 { self.banks[0] } else { self.banks[1] };
+        Some((bank >> 5) & 1)
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        false
+    }
+}
+
+impl Savestate for Namcot108 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.bank_select);
+        w.write_bytes(&self.banks);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select = r.read_u8()? & 0b111;
+        r.read_into(&mut self.banks)?;
+        Ok(())
+    }
+}
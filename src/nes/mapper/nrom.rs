@@ -0,0 +1,31 @@
+// Mapper 0. No registers, 16 KiB carts show up twice in $8000-$FFFF.
+
+use super::Mapper;
+use crate::nes::cart::Cart;
+use crate::nes::mem::Peek;
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const PRG_ROM: u16 = 0x8000;
+
+pub struct Nrom;
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        if addr < PRG_ROM || cart.prg_rom.is_empty() {
+            return None;
+        }
+        Some(cart.prg_rom.peek(addr - PRG_ROM))
+    }
+
+    fn cpu_write(&mut self, _cart: &mut Cart, _addr: u16, _data: u8) -> bool {
+        false
+    }
+}
+
+impl Savestate for Nrom {
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
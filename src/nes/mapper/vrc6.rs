@@ -0,0 +1,391 @@
+// Mappers 24 and 26, Konami VRC6. 16 KiB + 8 KiB of switchable PRG with the last 8 KiB fixed,
+// eight 1 KiB CHR banks, a CPU cycle / scanline IRQ counter and three extra sound channels: two
+// pulses with 16 step duty and a sawtooth. VRC6b (mapper 26) swaps the A0 and A1 lines.
+
+use super::{bank_offset, banked, Mapper};
+use crate::nes::cart::{Cart, Mirroring};
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// the IRQ prescaler runs 3 steps per CPU cycle against 341 per scanline, like PPU dots
+const PRESCALER_PERIOD: i16 = 341;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Vrc6Variant {
+    // mapper 24
+    A,
+    // mapper 26
+    B,
+}
+
+#[derive(Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    // ignore the duty and output the volume constantly
+    digitized: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.digitized = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0b1111;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0b1111) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.volume);
+        w.write_u8(self.duty);
+        w.write_bool(self.digitized);
+        w.write_u16(self.period);
+        w.write_bool(self.enabled);
+        w.write_u16(self.timer);
+        w.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.volume = r.read_u8()? & 0b1111;
+        self.duty = r.read_u8()? & 0b111;
+        self.digitized = r.read_bool()?;
+        self.period = r.read_u16()?;
+        self.enabled = r.read_bool()?;
+        self.timer = r.read_u16()?;
+        self.step = r.read_u8()? % 16;
+        Ok(())
+    }
+}
+
+// Adds the rate to an accumulator on every other clock and starts over after seven additions.
+// The top 5 bits are the output.
+#[derive(Default)]
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    clocks: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.rate = data & 0b0011_1111,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0b1111) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.clocks = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.clocks += 1;
+        if self.clocks == 14 {
+            self.clocks = 0;
+            self.accumulator = 0;
+        } else if self.clocks.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.rate);
+        w.write_u16(self.period);
+        w.write_bool(self.enabled);
+        w.write_u16(self.timer);
+        w.write_u8(self.clocks);
+        w.write_u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.rate = r.read_u8()? & 0b0011_1111;
+        self.period = r.read_u16()?;
+        self.enabled = r.read_bool()?;
+        self.timer = r.read_u16()?;
+        self.clocks = r.read_u8()? % 14;
+        self.accumulator = r.read_u8()?;
+        Ok(())
+    }
+}
+
+pub struct Vrc6 {
+    variant: Vrc6Variant,
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_banks: [u8; 8],
+    // $B003: PPU banking mode, mirroring in bits 2-3 and PRG RAM enable in bit 7
+    banking_control: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_enabled: bool,
+    irq_enable_after_ack: bool,
+    // count CPU cycles instead of scanlines
+    irq_cycle_mode: bool,
+    irq_pending: bool,
+    pulses: [Vrc6Pulse; 2],
+    sawtooth: Sawtooth,
+    // $9003: halt in bit 0, 16x and 256x frequency in bits 1 and 2
+    frequency_control: u8,
+}
+
+impl Vrc6 {
+    pub fn new(variant: Vrc6Variant) -> Self {
+        Vrc6 {
+            variant,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            banking_control: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: PRESCALER_PERIOD,
+            irq_enabled: false,
+            irq_enable_after_ack: false,
+            irq_cycle_mode: false,
+            irq_pending: false,
+            pulses: [Vrc6Pulse::default(), Vrc6Pulse::default()],
+            sawtooth: Sawtooth::default(),
+            frequency_control: 0,
+        }
+    }
+
+    // Levels of the two pulses (0-15) and the sawtooth (0-31), for mixing each on its own
+    pub fn channel_outputs(&self) -> [u8; 3] {
+        [
+            self.pulses[0].output(),
+            self.pulses[1].output(),
+            self.sawtooth.output(),
+        ]
+    }
+
+    // register address as VRC6a decodes it
+    fn register(&self, addr: u16) -> u16 {
+        match self.variant {
+            Vrc6Variant::A => addr & 0xF003,
+            Vrc6Variant::B => (addr & 0xF000) | ((addr & 1) << 1) | ((addr & 2) >> 1),
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, cart: &Cart, addr: u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        let prg = cart.prg_rom.data();
+        let last = (prg.len() / PRG_BANK_SIZE).saturating_sub(1);
+        let bank = match addr {
+            0x8000..=0xBFFF => (self.prg_16k as usize & 0x0F) * 2 + ((addr as usize >> 13) & 1),
+            0xC000..=0xDFFF => self.prg_8k as usize & 0x1F,
+            _ => last,
+        };
+        banked(prg, bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn cpu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) -> bool {
+        if addr < PRG_ROM {
+            return false;
+        }
+        let reg = self.register(addr);
+        match reg {
+            0x8000..=0x8003 => self.prg_16k = data,
+            0x9000..=0x9002 => self.pulses[0].write(reg & 0b11, data),
+            0x9003 => self.frequency_control = data,
+            0xA000..=0xA002 => self.pulses[1].write(reg & 0b11, data),
+            0xB000..=0xB002 => self.sawtooth.write(reg & 0b11, data),
+            0xB003 => {
+                self.banking_control = data;
+                cart.screen_mirroring = match (data >> 2) & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                };
+            }
+            0xC000..=0xC003 => self.prg_8k = data,
+            0xD000..=0xD003 => self.chr_banks[(reg & 0b11) as usize] = data,
+            0xE000..=0xE003 => self.chr_banks[4 + (reg & 0b11) as usize] = data,
+            0xF000 => self.irq_latch = data,
+            0xF001 => {
+                self.irq_enable_after_ack = data & 0b001 != 0;
+                self.irq_enabled = data & 0b010 != 0;
+                self.irq_cycle_mode = data & 0b100 != 0;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = PRESCALER_PERIOD;
+                }
+                self.irq_pending = false;
+            }
+            0xF002 => {
+                self.irq_pending = false;
+                self.irq_enabled = self.irq_enable_after_ack;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    // only the 1 KiB banking mode that nearly every VRC6 game uses
+    fn ppu_peek(&self, cart: &Cart, addr: u16) -> u8 {
+        let page = (addr as usize & 0x1FFF) / CHR_BANK_SIZE;
+        banked(cart.chr_rom.data(), self.chr_banks[page] as usize, CHR_BANK_SIZE, addr).unwrap_or(0)
+    }
+
+    fn ppu_write(&mut self, cart: &mut Cart, addr: u16, data: u8) {
+        if !cart.chr_rom.is_ram() || cart.chr_rom.is_empty() {
+            return;
+        }
+        let bank = self.chr_banks[(addr as usize & 0x1FFF) / CHR_BANK_SIZE] as usize;
+        let offset = bank_offset(cart.chr_rom.len(), bank, CHR_BANK_SIZE, addr);
+        cart.chr_rom.data_mut()[offset] = data;
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.banking_control & 0x80 != 0
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled {
+            if self.irq_cycle_mode {
+                self.clock_irq_counter();
+            } else {
+                self.irq_prescaler -= 3;
+                if self.irq_prescaler <= 0 {
+                    self.irq_prescaler += PRESCALER_PERIOD;
+                    self.clock_irq_counter();
+                }
+            }
+        }
+
+        if self.frequency_control & 1 != 0 {
+            return;
+        }
+        let shift = if self.frequency_control & 0b100 != 0 {
+            8
+        } else if self.frequency_control & 0b010 != 0 {
+            4
+        } else {
+            0
+        };
+        for pulse in &mut self.pulses {
+            pulse.clock(shift);
+        }
+        self.sawtooth.clock(shift);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // the three channels mix linearly, full scale about as loud as both APU pulses together
+    fn audio_output(&self) -> f32 {
+        let sum: u8 = self.channel_outputs().iter().sum();
+        sum as f32 / 61.0 * 0.26
+    }
+}
+
+impl Savestate for Vrc6 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.prg_16k);
+        w.write_u8(self.prg_8k);
+        w.write_bytes(&self.chr_banks);
+        w.write_u8(self.banking_control);
+        w.write_u8(self.irq_latch);
+        w.write_u8(self.irq_counter);
+        w.write_u16(self.irq_prescaler as u16);
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.irq_enable_after_ack);
+        w.write_bool(self.irq_cycle_mode);
+        w.write_bool(self.irq_pending);
+        for pulse in &self.pulses {
+            pulse.save_state(w);
+        }
+        self.sawtooth.save_state(w);
+        w.write_u8(self.frequency_control);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.prg_16k = r.read_u8()?;
+        self.prg_8k = r.read_u8()?;
+        r.read_into(&mut self.chr_banks)?;
+        self.banking_control = r.read_u8()?;
+        self.irq_latch = r.read_u8()?;
+        self.irq_counter = r.read_u8()?;
+        self.irq_prescaler = r.read_u16()? as i16;
+        self.irq_enabled = r.read_bool()?;
+        self.irq_enable_after_ack = r.read_bool()?;
+        self.irq_cycle_mode = r.read_bool()?;
+        self.irq_pending = r.read_bool()?;
+        for pulse in &mut self.pulses {
+            pulse.load_state(r)?;
+        }
+        self.sawtooth.load_state(r)?;
+        self.frequency_control = r.read_u8()?;
+        Ok(())
+    }
+}
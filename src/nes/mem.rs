@@ -0,0 +1,171 @@
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+pub trait Read {
+    fn read(&mut self, addr: u16) -> u8;
+
+    // called with the opcode address before each instruction fetch, for diagnostics
+    fn begin_instruction(&mut self, _pc: u16) {}
+
+    // Called at the start of every CPU cycle, before its access, so whatever else runs off the
+    // CPU clock stays in lockstep with it. Returns the lines it drives back into the CPU, None
+    // when there's nothing but memory on the bus.
+    fn cpu_cycle(&mut self) -> Option<CpuLines> {
+        None
+    }
+
+    // the byte a DMC DMA just fetched, false when nothing on the bus takes it
+    fn dmc_sample(&mut self, _sample: u8) -> bool {
+        false
+    }
+}
+
+// what the rest of the system signals the CPU after a cycle
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CpuLines {
+    // an NMI edge, taken once
+    pub nmi: bool,
+    pub irq: bool,
+    // the DMC wants its next sample byte from this address
+    pub dmc_request: Option<u16>,
+}
+
+pub trait Write {
+    fn write(&mut self, addr: u16, data: u8);
+}
+
+// What a read would return, minus its side effects (open bus latching, read buffers,
+// flags cleared on read). For debuggers and tracers.
+pub trait Peek {
+    fn peek(&self, addr: u16) -> u8;
+}
+
+pub struct Memory<D> {
+    data: D,
+    is_ram: bool,
+}
+
+impl<D> Memory<D> {
+    pub fn new(data: D, is_ram: bool) -> Self {
+        Self { data, is_ram }
+    }
+
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut D {
+        &mut self.data
+    }
+
+    pub fn is_ram(&self) -> bool {
+        self.is_ram
+    }
+}
+
+impl<D: AsRef<[u8]>> Memory<D> {
+    pub fn len(&self) -> usize {
+        self.data.as_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.as_ref().is_empty()
+    }
+
+    // addresses past the end wrap around, the way a chip with fewer address lines
+    // than the bus mirrors itself
+    fn index(&self, addr: u16) -> usize {
+        addr as usize % self.len()
+    }
+
+    fn get(&self, addr: u16) -> u8 {
+        if self.is_empty() {
+            return 0;
+        }
+        self.data.as_ref()[self.index(addr)]
+    }
+}
+
+impl<D: AsRef<[u8]> + AsMut<[u8]>> Memory<D> {
+    // ROM ignores writes, like the real chip does
+    fn set(&mut self, addr: u16, data: u8) {
+        if !self.is_ram || self.is_empty() {
+            return;
+        }
+        let index = self.index(addr);
+        self.data.as_mut()[index] = data;
+    }
+}
+
+// only RAM is saved, ROM contents come back with the cartridge
+impl<D: AsRef<[u8]> + AsMut<[u8]>> Savestate for Memory<D> {
+    fn save_state(&self, w: &mut StateWriter) {
+        if self.is_ram {
+            w.write_block(self.data.as_ref());
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        if self.is_ram {
+            r.read_block_into(self.data.as_mut())?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for Memory<Vec<u8>> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.get(addr)
+    }
+}
+
+impl Peek for Memory<Vec<u8>> {
+    fn peek(&self, addr: u16) -> u8 {
+        self.get(addr)
+    }
+}
+
+impl Write for Memory<Vec<u8>> {
+    fn write(&mut self, addr: u16, data: u8) {
+        self.set(addr, data);
+    }
+}
+
+impl<const N: usize> Read for Memory<[u8; N]> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.get(addr)
+    }
+}
+
+impl<const N: usize> Peek for Memory<[u8; N]> {
+    fn peek(&self, addr: u16) -> u8 {
+        self.get(addr)
+    }
+}
+
+impl<const N: usize> Write for Memory<[u8; N]> {
+    fn write(&mut self, addr: u16, data: u8) {
+        self.set(addr, data);
+    }
+}
+
+// flat 64 KiB address space, used when the CPU runs without the NES bus
+impl Read for Memory<Box<[u8; 0x10000]>> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+}
+
+impl Peek for Memory<Box<[u8; 0x10000]>> {
+    fn peek(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+}
+
+impl Write for Memory<Box<[u8; 0x10000]>> {
+    fn write(&mut self, addr: u16, data: u8) {
+        if !self.is_ram {
+            return;
+        }
+        self.data[addr as usize] = data;
+    }
+}
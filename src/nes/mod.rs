@@ -1,10 +1,51 @@
+pub mod apu;
+pub mod audio;
+pub mod audio_out;
+pub mod bus;
+pub mod cart;
+pub mod config;
+pub mod controller;
 pub mod cpu;
+pub mod device;
+pub mod dma;
+pub mod frame;
+pub mod gamepad;
+pub mod input;
+pub mod mapper;
+pub mod mem;
+pub mod movie;
+pub mod nestest;
+pub mod nsf;
+pub mod opcodes;
+pub mod palette;
+pub mod patch;
+pub mod ppu;
+pub mod ppu_bus;
+pub mod ppu_viewer;
+pub mod profile;
+pub mod romdb;
+pub mod savestate;
+pub mod speed;
+pub mod test_bus;
+pub mod trace;
+pub mod wav;
 
-use cpu::Cpu;
-use rand::prelude::*;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use audio_out::AudioOutput;
+use bus::Bus;
+use cart::Cart;
+use controller::{Button, Controller};
+use cpu::{Cpu, CpuStepResult, TrapAction};
+use frame::{HEIGHT, WIDTH};
+use gamepad::{Gamepads, PLAYERS};
+use input::{
+    channel_hotkey, DpadFilter, HeldKeys, Hotkey, Hotkeys, InputMap, OppositeDirections, Turbo,
+};
+use movie::{Movie, MovieError, MovieStart};
+use palette::Palette;
+use ppu_viewer::PpuViewer;
+use savestate::StateError;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::Canvas;
 use sdl2::render::Texture;
@@ -12,131 +53,457 @@ use sdl2::render::TextureCreator;
 use sdl2::video::Window;
 use sdl2::video::WindowContext;
 use sdl2::EventPump;
+use speed::{FastForward, FrameLimiter};
+use std::path::PathBuf;
+use std::time::Instant;
+
+// a movie being played back, or recorded when there's a path to save it to
+struct MovieSession {
+    movie: Movie,
+    // the machine's frame count on the movie's first frame
+    start_frame: u64,
+    save_path: Option<PathBuf>,
+}
 
 pub struct NES<'a> {
-    clock: u64,
-    cpu: Cpu,
+    // frames run so far, turbo and anything recording input count by it
+    frame: u64,
+    cpu: Cpu<Bus>,
     texture: Texture<'a>,
     canvas: Canvas<Window>,
-    screen_state: [u8; 32 * 3 * 32],
-    rng: ThreadRng,
+    // the PPU's last frame as RGB24, what the texture is updated from
+    screen: Box<[u8]>,
+    palette: Palette,
+    input: InputMap,
+    hotkeys: Hotkeys,
+    // held on the keyboard, player 1's controller also gets player 1's gamepad
+    keys: HeldKeys,
+    gamepads: Option<Gamepads>,
+    turbo: Turbo,
+    // one per player
+    dpad: [DpadFilter; PLAYERS],
+    limiter: FrameLimiter,
+    paused: bool,
+    // a frame to run while paused
+    advance: bool,
+    // where the savestate hotkeys save to and load from
+    state_path: Option<PathBuf>,
+    movie: Option<MovieSession>,
+    // the WAV the record hotkey writes
+    recording_path: Option<PathBuf>,
+    ppu_viewer: Option<PpuViewer>,
+    // fed the APU's samples after every frame run
+    audio: Option<AudioOutput>,
 }
 
 impl<'a> NES<'a> {
     pub fn new(
         texture_creator: &'a TextureCreator<WindowContext>,
         canvas: Canvas<Window>,
-        rng: ThreadRng,
+        cart: Cart,
     ) -> NES<'a> {
         let texture = texture_creator
-            .create_texture_target(PixelFormatEnum::RGB24, 32, 32)
+            .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
             .unwrap();
-        let mut cpu = Cpu::new();
-        cpu.load_test_game();
+        let limiter = FrameLimiter::new(cart.region.frame_rate());
+        let mut cpu = Cpu::with_bus(Bus::with_cart(cart));
+        // games use BRK as an interrupt like any other
+        cpu.set_trap_hook(Box::new(|_| TrapAction::Continue));
         cpu.reset();
 
         NES {
-            clock: 0,
+            frame: 0,
             cpu,
             texture,
             canvas,
-            rng,
-            screen_state: [0u8; 32 * 3 * 32],
+            screen: vec![0u8; WIDTH * HEIGHT * 3].into_boxed_slice(),
+            palette: Palette::ntsc(),
+            input: InputMap::default(),
+            hotkeys: Hotkeys::default(),
+            keys: HeldKeys::default(),
+            gamepads: None,
+            turbo: Turbo::default(),
+            dpad: [DpadFilter::default(); PLAYERS],
+            limiter,
+            paused: false,
+            advance: false,
+            state_path: None,
+            movie: None,
+            recording_path: None,
+            ppu_viewer: None,
+            audio: None,
         }
     }
 
-    pub fn tick(&mut self, event_pump: &mut EventPump) {
-        self.clock += 1;
-        let screen_state = &mut self.screen_state;
-        let texture = &mut self.texture;
-        let canvas = &mut self.canvas;
-        let rng = &mut self.rng;
+    // Runs one frame, up to the PPU's next vblank. Input is gathered once, before the frame
+    // starts, and latched into the controllers for all of it; the game sees it change only
+    // between frames, however often it reads the ports, so the same input on the same frame
+    // always plays out the same way.
+    // While paused nothing runs unless a frame advance asked for one, the last frame is shown
+    // again and the keys are still read.
+    pub fn run_frame(&mut self, event_pump: &mut EventPump) -> CpuStepResult {
+        self.handle_user_input(event_pump);
+        if self.paused && !std::mem::take(&mut self.advance) {
+            self.present();
+            self.wait_for_next_frame();
+            return CpuStepResult::Running;
+        }
+        for player in 0..PLAYERS {
+            let buttons = self.dpad[player].apply(self.buttons(player));
+            self.cpu.bus_mut().controller_mut(player).set_buttons(buttons);
+        }
+        self.movie_frame();
 
-        self.cpu.run_with_callback(|cpu| {
-            NES::handle_user_input(cpu, event_pump);
-            cpu.mem_write(0xFE, rng.random_range(1..16));
+        let mut result = CpuStepResult::Running;
+        while !self.cpu.bus_mut().take_frame_ready() {
+            result = self.cpu.run_instruction();
+            if matches!(result, CpuStepResult::Halted | CpuStepResult::Break) {
+                break;
+            }
+        }
+        self.frame += 1;
+        if let Some(audio) = &mut self.audio {
+            audio.feed(self.cpu.bus_mut().samples_mut()).unwrap();
+        }
 
-            if NES::read_screen_state(cpu, screen_state) {
-                texture.update(None, screen_state, 32 * 3).unwrap();
-                canvas.copy(texture, None, None).unwrap();
-                canvas.present();
+        // an advanced frame is always shown, even with fast-forward's frame skip
+        if self.limiter.present() || self.paused {
+            self.cpu.bus().ppu().frame().write_rgb(&self.palette, &mut self.screen);
+            self.texture.update(None, &self.screen, WIDTH * 3).unwrap();
+            self.present();
+            if let Some(viewer) = &mut self.ppu_viewer {
+                viewer.update(self.cpu.bus_mut(), &self.palette);
             }
+        }
+        self.wait_for_next_frame();
+        result
+    }
+
+    // puts the texture, the last frame shown, on the window
+    fn present(&mut self) {
+        self.canvas.copy(&self.texture, None, None).unwrap();
+        self.canvas.present();
+    }
+
+    fn wait_for_next_frame(&mut self) {
+        if let Some(wait) = self.limiter.wait(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // The whole machine: the CPU, the bus with the PPU, APU, cart and controllers on it, and
+    // the frame count turbo goes by. The buttons held are the player's and aren't saved.
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save_machine(self.frame, &self.cpu)
+    }
+
+    // a state that doesn't load leaves the machine as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        match savestate::load_machine(&mut self.cpu, data) {
+            Ok(frame) => {
+                self.frame = frame;
+                // recording carries on from the frame loaded, over what came after it
+                let recording = self.movie.as_mut().filter(|session| session.save_path.is_some());
+                if let Some(session) = recording {
+                    session.movie.truncate(frame.saturating_sub(session.start_frame) as usize);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                let restored = savestate::load_machine(&mut self.cpu, &backup);
+                restored.expect("the state just saved loads");
+                Err(err)
+            }
+        }
+    }
+
+    pub fn set_state_path(&mut self, path: PathBuf) {
+        self.state_path = Some(path);
+    }
+
+    pub fn set_recording_path(&mut self, path: PathBuf) {
+        self.recording_path = Some(path);
+    }
+
+    // Records both controllers from the next frame on, saved to `path` by finish. The movie
+    // starts at power-on if nothing has run yet, otherwise from a savestate of the machine.
+    pub fn record_movie(&mut self, path: PathBuf) {
+        let start = match self.frame {
+            0 => MovieStart::PowerOn,
+            _ => MovieStart::Savestate(self.save_state()),
+        };
+        let movie = Movie::new(self.rom_crc32(), start);
+        self.movie = Some(MovieSession { movie, start_frame: self.frame, save_path: Some(path) });
+    }
+
+    // Plays `movie` back from the next frame on, in place of the keyboard and gamepads. A
+    // movie from power-on has to be started before the first frame runs.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        if movie.rom_crc32 != self.rom_crc32() {
+            return Err(MovieError::WrongRom);
+        }
+        if let MovieStart::Savestate(state) = &movie.start {
+            self.load_state(state)?;
+        }
+        self.movie = Some(MovieSession { movie, start_frame: self.frame, save_path: None });
+        Ok(())
+    }
+
+    fn rom_crc32(&self) -> u32 {
+        self.cpu.bus().cart().map_or(0, |cart| cart.crc32)
+    }
+
+    // records the buttons the controllers were just given, or swaps in the movie's
+    fn movie_frame(&mut self) {
+        let Some(session) = &mut self.movie else {
+            return;
+        };
+        let bus = self.cpu.bus_mut();
+        if session.save_path.is_some() {
+            session.movie.record(bus);
+            return;
+        }
+        let frame = self.frame.checked_sub(session.start_frame);
+        if !frame.is_some_and(|frame| session.movie.apply(frame as usize, bus)) {
+            println!("Movie finished");
+            self.movie = None;
+        }
+    }
+
+    // Finishes the files being written, so nothing is lost on quitting: the WAV recording's
+    // headers and a movie being recorded.
+    pub fn finish(&mut self) {
+        if let Err(err) = self.cpu.bus_mut().stop_recording() {
+            eprintln!("Recording failed: {}", err);
+        }
+        let Some(MovieSession { movie, save_path: Some(path), .. }) = self.movie.take() else {
+            return;
+        };
+        match movie.save(&path) {
+            Ok(()) => println!("Saved movie to {}", path.display()),
+            Err(err) => eprintln!("Could not save movie to {}: {}", path.display(), err),
+        }
+    }
 
-            std::thread::sleep(std::time::Duration::new(0, 16_667));
-        });
+    fn toggle_recording(&mut self, stems: bool) {
+        let Some(path) = &self.recording_path else {
+            return;
+        };
+        wav::toggle_recording(self.cpu.bus_mut(), path, stems);
+    }
+
+    fn save_state_file(&self) {
+        let Some(path) = &self.state_path else {
+            return;
+        };
+        match std::fs::write(path, self.save_state()) {
+            Ok(()) => println!("Saved state to {}", path.display()),
+            Err(err) => eprintln!("Could not save state to {}: {}", path.display(), err),
+        }
+    }
+
+    fn load_state_file(&mut self) {
+        let Some(path) = self.state_path.clone() else {
+            return;
+        };
+        let loaded = std::fs::read(&path)
+            .map_err(StateError::from)
+            .and_then(|data| self.load_state(&data));
+        if let Some(audio) = &mut self.audio {
+            audio.clear();
+        }
+        match loaded {
+            Ok(()) => println!("Loaded state from {}", path.display()),
+            Err(err) => eprintln!("Could not load state from {}: {}", path.display(), err),
+        }
+    }
+
+    // the master palette the PPU's colors are shown with, the 2C02's NTSC one by default
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    // the sound card the game is heard through, the APU is resampled to its rate
+    pub fn set_audio_output(&mut self, output: AudioOutput) {
+        self.cpu.bus_mut().set_sample_rate(output.sample_rate());
+        self.audio = Some(output);
+    }
+
+    // the pattern table, nametable, palette and sprite windows, updated with every frame shown
+    pub fn set_ppu_viewer(&mut self, viewer: PpuViewer) {
+        self.ppu_viewer = Some(viewer);
     }
 
     pub fn enable_cpu_debug(&mut self) {
         self.cpu.enable_debug();
     }
 
-    pub fn handle_user_input(cpu: &mut Cpu, event_pump: &mut EventPump) {
+    pub fn cpu(&self) -> &Cpu<Bus> {
+        &self.cpu
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        self.cpu.bus_mut()
+    }
+
+    // port 0 is player 1's
+    pub fn controller_mut(&mut self, port: usize) -> &mut Controller {
+        self.cpu.bus_mut().controller_mut(port)
+    }
+
+    // takes effect from the next key press or release
+    pub fn input_map_mut(&mut self) -> &mut InputMap {
+        &mut self.input
+    }
+
+    pub fn set_gamepads(&mut self, gamepads: Gamepads) {
+        self.gamepads = Some(gamepads);
+    }
+
+    pub fn gamepads_mut(&mut self) -> Option<&mut Gamepads> {
+        self.gamepads.as_mut()
+    }
+
+    pub fn set_turbo(&mut self, turbo: Turbo) {
+        self.turbo = turbo;
+    }
+
+    pub fn set_opposite_directions(&mut self, policy: OppositeDirections) {
+        self.dpad = [DpadFilter::new(policy); PLAYERS];
+    }
+
+    // takes effect from the next key press or release
+    pub fn hotkeys_mut(&mut self) -> &mut Hotkeys {
+        &mut self.hotkeys
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: FastForward) {
+        self.limiter.set_fast_forward(fast_forward);
+    }
+
+    pub fn fast_forwarding(&self) -> bool {
+        self.limiter.fast_forwarding()
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused && !paused {
+            self.limiter.restart(Instant::now());
+        }
+        if let Some(audio) = &mut self.audio {
+            audio.clear();
+        }
+        self.paused = paused;
+        self.advance = false;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    // Runs exactly one more frame, up to the next vblank, on the next run_frame. Only while
+    // paused; pressing the hotkey while running pauses instead.
+    pub fn advance_frame(&mut self) {
+        self.advance = self.paused;
+    }
+
+    pub fn handle_user_input(&mut self, event_pump: &mut EventPump) {
         for event in event_pump.poll_iter() {
+            if let Some(gamepads) = &mut self.gamepads {
+                gamepads.handle_event(&event);
+            }
+            let viewer = self.ppu_viewer.as_mut();
+            if viewer.is_some_and(|viewer| viewer.handle_event(&event)) {
+                if self.ppu_viewer.as_ref().is_some_and(PpuViewer::is_empty) {
+                    self.ppu_viewer = None;
+                }
+                continue;
+            }
+            let main_window = self.canvas.window().id();
             match event {
+                Event::Window { window_id, win_event: WindowEvent::Close, .. }
+                    if window_id == main_window =>
+                {
+                    self.finish();
+                    std::process::exit(0);
+                }
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => {
+                    self.finish();
                     std::process::exit(0);
                 }
-                Event::KeyDown {
-                    keycode: Some(Keycode::W),
-                    ..
-                } => {
-                    cpu.mem_write(0xFF, 0x77);
+                Event::KeyDown { keycode: Some(key), keymod, repeat, .. } => {
+                    self.key_event(key, keymod, true, repeat)
                 }
-                Event::KeyDown {
-                    keycode: Some(Keycode::S),
-                    ..
-                } => {
-                    cpu.mem_write(0xFF, 0x73);
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::A),
-                    ..
-                } => {
-                    cpu.mem_write(0xFF, 0x61);
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::D),
-                    ..
-                } => {
-                    cpu.mem_write(0xFF, 0x64);
+                Event::KeyUp { keycode: Some(key), keymod, .. } => {
+                    self.key_event(key, keymod, false, false)
                 }
                 _ => {}
             }
         }
     }
 
-    fn color(byte: u8) -> Color {
-        match byte {
-            0 => sdl2::pixels::Color::BLACK,
-            1 => sdl2::pixels::Color::WHITE,
-            2 | 9 => sdl2::pixels::Color::GREY,
-            3 | 10 => sdl2::pixels::Color::RED,
-            4 | 11 => sdl2::pixels::Color::GREEN,
-            5 | 12 => sdl2::pixels::Color::BLUE,
-            6 | 13 => sdl2::pixels::Color::MAGENTA,
-            7 | 14 => sdl2::pixels::Color::YELLOW,
-            _ => sdl2::pixels::Color::CYAN,
-        }
-    }
-
-    fn read_screen_state(cpu: &Cpu, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-        let mut frame_idx = 0;
-        let mut update = false;
-        for i in 0x0200..0x0600 {
-            let color_idx = cpu.mem_read(i as u16);
-            let (b1, b2, b3) = NES::color(color_idx).rgb();
-            if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-                frame[frame_idx] = b1;
-                frame[frame_idx + 1] = b2;
-                frame[frame_idx + 2] = b3;
-                update = true;
+    // `repeat` for the presses a key held down keeps sending, which don't toggle anything
+    fn key_event(&mut self, key: Keycode, keymod: Mod, pressed: bool, repeat: bool) {
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+        match self.hotkeys.hotkey(key) {
+            Some(Hotkey::FastForward) => self.limiter.set_fast_forwarding(pressed),
+            Some(Hotkey::Pause) => {
+                if pressed && !repeat {
+                    self.set_paused(!self.paused);
+                }
+            }
+            Some(Hotkey::SaveState) => {
+                if pressed && !repeat {
+                    self.save_state_file();
+                }
+            }
+            Some(Hotkey::LoadState) => {
+                if pressed && !repeat {
+                    self.load_state_file();
+                }
+            }
+            Some(Hotkey::FrameAdvance) => {
+                if pressed && !repeat {
+                    if self.paused {
+                        self.advance_frame();
+                    } else {
+                        self.set_paused(true);
+                    }
+                }
             }
-            frame_idx += 3;
+            Some(Hotkey::Record) => {
+                if pressed && !repeat {
+                    self.toggle_recording(shift);
+                }
+            }
+            // the channel mute hotkeys, shift plays the channel alone
+            Some(hotkey) => {
+                if pressed && !repeat {
+                    channel_hotkey(self.cpu.bus_mut().apu_mut(), hotkey, shift);
+                }
+            }
+            None => self.keys.key_event(&self.input, key, pressed),
+        }
+    }
+
+    // `player`'s buttons this frame, from their gamepad and for player 1 the keyboard too
+    fn buttons(&self, player: usize) -> Button {
+        let (mut buttons, mut held_turbo) = match player {
+            0 => (self.keys.buttons, self.keys.turbo),
+            _ => (Button::NONE, Button::NONE),
+        };
+        if let Some(gamepads) = &self.gamepads {
+            buttons |= gamepads.buttons(player);
+            held_turbo |= gamepads.turbo(player);
         }
-        update
+        buttons | self.turbo.buttons(held_turbo, self.frame)
     }
 }
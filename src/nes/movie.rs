@@ -0,0 +1,167 @@
+// Input movies: what both controllers held on every frame, from power-on or from a savestate,
+// so a run can be played back exactly. Recording takes the buttons the controllers were given
+// for a frame, after turbo, and playback hands the same buttons back frame by frame; with the
+// same ROM and the same starting point the machine does the same thing.
+//
+// The file is a magic tag and a format version, the CRC32 of the ROM it was recorded on, how it
+// starts (a savestate is stored whole) and then two bytes a frame, port 1 then port 2, in the
+// bit order of controller::Button. Numbers are little endian. A savestate start is the whole
+// machine as savestate::save_machine saves it, so the CPU is where it was too.
+
+use super::bus::Bus;
+use super::controller::Button;
+use super::savestate::{StateError, StateReader, StateWriter};
+use std::path::Path;
+use thiserror::Error;
+
+pub const MAGIC: [u8; 4] = *b"NMOV";
+pub const VERSION: u16 = 1;
+
+const START_POWER_ON: u8 = 0;
+const START_SAVESTATE: u8 = 1;
+
+#[derive(Debug, Error)]
+pub enum MovieError {
+    #[error("Could not read movie file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("File is not a movie")]
+    BadMagic,
+    #[error("Movie version {0} is not supported")]
+    UnsupportedVersion(u16),
+    #[error("Movie is truncated")]
+    Truncated,
+    #[error("Movie is corrupt: {0}")]
+    Corrupt(&'static str),
+    #[error("Movie was recorded on another ROM")]
+    WrongRom,
+    #[error("Movie's savestate doesn't load: {0}")]
+    State(#[from] StateError),
+}
+
+// where playback begins
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovieStart {
+    PowerOn,
+    // the bytes of a savestate, loaded before the first frame
+    Savestate(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    // of the ROM the movie was recorded on, Cart::crc32
+    pub rom_crc32: u32,
+    pub start: MovieStart,
+    frames: Vec<[Button; 2]>,
+}
+
+impl Movie {
+    pub fn new(rom_crc32: u32, start: MovieStart) -> Self {
+        Movie { rom_crc32, start, frames: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn push_frame(&mut self, buttons: [Button; 2]) {
+        self.frames.push(buttons);
+    }
+
+    // both ports on frame `frame`, None past the end of the movie
+    pub fn frame(&self, frame: usize) -> Option<[Button; 2]> {
+        self.frames.get(frame).copied()
+    }
+
+    // Drops every frame from `frame` on, to record over them from a savestate taken there.
+    pub fn truncate(&mut self, frame: usize) {
+        self.frames.truncate(frame);
+    }
+
+    // appends what the bus's controllers hold for the frame that's about to run
+    pub fn record(&mut self, bus: &Bus) {
+        self.push_frame([bus.controller(0).buttons(), bus.controller(1).buttons()]);
+    }
+
+    // Gives the bus's controllers the buttons of frame `frame`. Returns false once the movie
+    // has run out, leaving the controllers alone.
+    pub fn apply(&self, frame: usize, bus: &mut Bus) -> bool {
+        let Some(buttons) = self.frame(frame) else {
+            return false;
+        };
+        for (port, buttons) in buttons.into_iter().enumerate() {
+            bus.controller_mut(port).set_buttons(buttons);
+        }
+        true
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::without_header();
+        w.write_bytes(&MAGIC);
+        w.write_u16(VERSION);
+        w.write_u32(self.rom_crc32);
+        match &self.start {
+            MovieStart::PowerOn => w.write_u8(START_POWER_ON),
+            MovieStart::Savestate(state) => {
+                w.write_u8(START_SAVESTATE);
+                w.write_block(state);
+            }
+        }
+        w.write_u64(self.frames.len() as u64);
+        for [port1, port2] in &self.frames {
+            w.write_u8(port1.bits());
+            w.write_u8(port2.bits());
+        }
+        w.finish()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        // running out of data is the only way reading can go wrong
+        let truncated = |_: StateError| MovieError::Truncated;
+        let mut r = StateReader::without_header(data);
+        if r.read_bytes(MAGIC.len()).map_err(|_| MovieError::BadMagic)? != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = r.read_u16().map_err(truncated)?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_crc32 = r.read_u32().map_err(truncated)?;
+        let start = match r.read_u8().map_err(truncated)? {
+            START_POWER_ON => MovieStart::PowerOn,
+            START_SAVESTATE => {
+                let len = read_len(&mut r)?;
+                MovieStart::Savestate(r.read_bytes(len).map_err(truncated)?.to_vec())
+            }
+            _ => return Err(MovieError::Corrupt("unknown start")),
+        };
+        let count = read_len(&mut r)?;
+        let len = count.checked_mul(2).ok_or(MovieError::Truncated)?;
+        let frames = r.read_bytes(len).map_err(truncated)?;
+        let frames = frames
+            .chunks_exact(2)
+            .map(|frame| [Button::from_bits(frame[0]), Button::from_bits(frame[1])])
+            .collect();
+        if !r.is_at_end() {
+            return Err(MovieError::Corrupt("data after the last frame"));
+        }
+        Ok(Movie { rom_crc32, start, frames })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Movie, MovieError> {
+        Movie::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+}
+
+// a u64 length, which has to fit in memory
+fn read_len(r: &mut StateReader) -> Result<usize, MovieError> {
+    let len = r.read_u64().map_err(|_| MovieError::Truncated)?;
+    usize::try_from(len).map_err(|_| MovieError::Truncated)
+}
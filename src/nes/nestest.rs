@@ -0,0 +1,80 @@
+// Runs nestest.nes in automation mode (PC = $C000) and diffs the trace against nestest.log.
+// No PPU or APU is attached, PRG ROM is simply copied into a flat 64 KiB memory.
+use super::cart::Cart;
+use super::cpu::Cpu;
+use super::mem::Memory;
+use super::trace::trace_line;
+use std::fmt;
+
+pub type HeadlessCpu = Cpu<Memory<Box<[u8; 0x10000]>>>;
+
+const AUTOMATION_START: u16 = 0xC000;
+const POWER_UP_SP: u8 = 0xFD;
+const POWER_UP_STATUS: u8 = 0x24;
+
+#[derive(Debug, PartialEq)]
+pub struct TraceMismatch {
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "trace diverges at line {}\nexpected: {}\nactual:   {}",
+            self.line, self.expected, self.actual
+        )
+    }
+}
+
+pub fn headless_cpu(rom: &[u8]) -> Result<HeadlessCpu, String> {
+    let cart = Cart::from_bytes(rom).map_err(|err| err.to_string())?;
+    if cart.mapper != 0 || cart.prg_rom.is_empty() {
+        return Err("only NROM carts can run headless".to_string());
+    }
+
+    let mut cpu = Cpu::new();
+    // NROM-128 mirrors its single bank into $C000
+    for (i, byte) in cart.prg_rom.data().iter().cycle().take(0x8000).enumerate() {
+        cpu.mem_write(0x8000 + i as u16, *byte);
+    }
+    cpu.reset();
+    cpu.set_pc(AUTOMATION_START);
+    cpu.set_sp(POWER_UP_SP);
+    cpu.set_status_p(POWER_UP_STATUS);
+    Ok(cpu)
+}
+
+// Returns the number of matching lines, or the first line that differs.
+pub fn compare_trace(cpu: &mut HeadlessCpu, log: &str) -> Result<usize, TraceMismatch> {
+    let mut matched = 0;
+    for (i, expected) in log.lines().enumerate() {
+        let expected = expected.trim_end();
+        if expected.is_empty() {
+            continue;
+        }
+
+        let actual = if cpu.is_jammed() || cpu.get_error().is_some() {
+            format!(
+                "CPU halted on opcode {:02X}, PC:{:04X}",
+                cpu.get_current_opcode(),
+                cpu.get_pc()
+            )
+        } else {
+            trace_line(cpu)
+        };
+        if actual != expected {
+            return Err(TraceMismatch {
+                line: i + 1,
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+
+        matched += 1;
+        cpu.run_instruction();
+    }
+    Ok(matched)
+}
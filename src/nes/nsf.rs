@@ -0,0 +1,454 @@
+// NSF and NSFe music rips. The file holds a game's sound driver and music data along with the
+// addresses of an init routine, called once with the track number in A, and a play routine
+// called at a fixed rate, usually once a frame. NsfPlayer runs them on a CPU and Bus with the
+// data on a mapper 31 board, the APU sees the writes like it would in the game.
+//
+// Expansion audio chips and FDS tunes aren't supported, their register writes go nowhere.
+
+use std::path::Path;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use super::bus::Bus;
+use super::cart::{Cart, Mirroring, Region};
+use super::cpu::{Cpu, CpuStepResult};
+use super::mapper::inl_nsf::InlNsf;
+use super::mem::{Memory, Read, Write};
+
+const NSF_TAG: &[u8] = b"NESM\x1A";
+const NSFE_TAG: &[u8] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x1000;
+const PRG_ROM_SIZE: usize = 0x8000;
+const PRG_RAM_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x2000;
+const BANK_REGISTERS: u16 = 0x5FF8;
+// the NMI rates, for NSFe files without a RATE chunk
+const NTSC_PLAY_SPEED: u16 = 16639;
+const PAL_PLAY_SPEED: u16 = 19997;
+// Routines return here. Nothing is mapped at it, the player stops the CPU before the fetch.
+const RETURN_ADDR: u16 = 0x4F00;
+const STACK: u16 = 0x0100;
+
+#[derive(Debug, Error)]
+pub enum NsfError {
+    #[error("Could not read NSF file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("File is not in NSF or NSFe format")]
+    BadMagic,
+    #[error("{0} is truncated")]
+    Truncated(&'static str),
+    #[error("NSFe file has no {0} chunk")]
+    MissingChunk(&'static str),
+    // chunks starting with a capital letter must be understood to play the file
+    #[error("NSFe chunk {0} is not supported")]
+    UnsupportedChunk(String),
+    #[error("Load address {0:04X} is outside $8000-$FFFF")]
+    BadLoadAddress(u16),
+    #[error("File has no tracks")]
+    NoTracks,
+}
+
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    // 1 based in the file, 0 based here
+    pub starting_track: u8,
+    pub total_tracks: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    // microseconds between play calls
+    pub ntsc_play_speed: u16,
+    pub pal_play_speed: u16,
+    // all zero when the tune isn't bankswitched
+    pub bank_init: [u8; 8],
+    pub pal: bool,
+    pub dual_region: bool,
+    // bit mask of the expansion chips the tune uses, VRC6 first
+    pub expansion_chips: u8,
+    pub data: Vec<u8>,
+    // NSFe only, empty for NSF files
+    pub track_titles: Vec<String>,
+    pub track_lengths: Vec<Option<Duration>>,
+    pub playlist: Vec<u8>,
+}
+
+// text fields, NUL padded in NSF headers and NUL terminated in NSFe chunks
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn word(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+impl Nsf {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Nsf, NsfError> {
+        Nsf::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Nsf, NsfError> {
+        let nsf = if raw.starts_with(NSF_TAG) {
+            Nsf::parse_nsf(raw)?
+        } else if raw.starts_with(NSFE_TAG) {
+            Nsf::parse_nsfe(raw)?
+        } else {
+            return Err(NsfError::BadMagic);
+        };
+        if nsf.total_tracks == 0 {
+            return Err(NsfError::NoTracks);
+        }
+        if nsf.load_addr < PRG_ROM {
+            return Err(NsfError::BadLoadAddress(nsf.load_addr));
+        }
+        Ok(nsf)
+    }
+
+    fn parse_nsf(raw: &[u8]) -> Result<Nsf, NsfError> {
+        let header = raw.get(..NSF_HEADER_SIZE).ok_or(NsfError::Truncated("NSF header"))?;
+        Ok(Nsf {
+            title: text(&header[0x0E..0x2E]),
+            artist: text(&header[0x2E..0x4E]),
+            copyright: text(&header[0x4E..0x6E]),
+            starting_track: header[0x07].saturating_sub(1),
+            total_tracks: header[0x06],
+            load_addr: word(header, 0x08),
+            init_addr: word(header, 0x0A),
+            play_addr: word(header, 0x0C),
+            ntsc_play_speed: word(header, 0x6E),
+            pal_play_speed: word(header, 0x78),
+            bank_init: header[0x70..0x78].try_into().unwrap(),
+            pal: header[0x7A] & 1 != 0,
+            dual_region: header[0x7A] & 0b10 != 0,
+            expansion_chips: header[0x7B],
+            data: raw[NSF_HEADER_SIZE..].to_vec(),
+            track_titles: Vec::new(),
+            track_lengths: Vec::new(),
+            playlist: Vec::new(),
+        })
+    }
+
+    // "NSFE" then chunks of a 4 byte length, a 4 byte id and the data, up to NEND
+    fn parse_nsfe(raw: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = Nsf {
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            starting_track: 0,
+            total_tracks: 1,
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            ntsc_play_speed: NTSC_PLAY_SPEED,
+            pal_play_speed: PAL_PLAY_SPEED,
+            bank_init: [0; 8],
+            pal: false,
+            dual_region: false,
+            expansion_chips: 0,
+            data: Vec::new(),
+            track_titles: Vec::new(),
+            track_lengths: Vec::new(),
+            playlist: Vec::new(),
+        };
+        let (mut has_info, mut has_data) = (false, false);
+        let mut pos = NSFE_TAG.len();
+        loop {
+            let header = raw.get(pos..pos + 8).ok_or(NsfError::MissingChunk("NEND"))?;
+            let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+            let id = &header[4..8];
+            let chunk = raw
+                .get(pos + 8..pos + 8 + len)
+                .ok_or(NsfError::Truncated("NSFe chunk"))?;
+            pos += 8 + len;
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(NsfError::Truncated("INFO chunk"));
+                    }
+                    nsf.load_addr = word(chunk, 0);
+                    nsf.init_addr = word(chunk, 2);
+                    nsf.play_addr = word(chunk, 4);
+                    nsf.pal = chunk[6] & 1 != 0;
+                    nsf.dual_region = chunk[6] & 0b10 != 0;
+                    nsf.expansion_chips = chunk[7];
+                    nsf.total_tracks = chunk.get(8).copied().unwrap_or(1);
+                    nsf.starting_track = chunk.get(9).copied().unwrap_or(0);
+                    has_info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    has_data = true;
+                }
+                b"BANK" => {
+                    let len = chunk.len().min(8);
+                    nsf.bank_init[..len].copy_from_slice(&chunk[..len]);
+                }
+                b"RATE" if chunk.len() >= 4 => {
+                    nsf.ntsc_play_speed = word(chunk, 0);
+                    nsf.pal_play_speed = word(chunk, 2);
+                }
+                b"auth" => {
+                    let mut fields = chunk.split(|&byte| byte == 0).map(text);
+                    nsf.title = fields.next().unwrap_or_default();
+                    nsf.artist = fields.next().unwrap_or_default();
+                    nsf.copyright = fields.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_titles = chunk.split(|&byte| byte == 0).map(text).collect();
+                    // the last title is NUL terminated too
+                    nsf.track_titles.truncate(nsf.total_tracks as usize);
+                }
+                // milliseconds, negative when unknown
+                b"time" => {
+                    nsf.track_lengths = chunk
+                        .chunks_exact(4)
+                        .map(|ms| i32::from_le_bytes(ms.try_into().unwrap()))
+                        .map(|ms| u64::try_from(ms).ok().map(Duration::from_millis))
+                        .collect();
+                }
+                b"plst" => nsf.playlist = chunk.to_vec(),
+                b"NEND" => break,
+                id if id[0].is_ascii_uppercase() => {
+                    return Err(NsfError::UnsupportedChunk(text(id)));
+                }
+                _ => {}
+            }
+        }
+        if !has_info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if !has_data {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        Ok(nsf)
+    }
+
+    pub fn is_bankswitched(&self) -> bool {
+        self.bank_init.iter().any(|&bank| bank != 0)
+    }
+
+    // PAL only tunes run as PAL, dual region ones as NTSC
+    pub fn region(&self) -> Region {
+        if self.pal && !self.dual_region {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+
+    // Lays the data out as the PRG ROM of a mapper 31 cart. Bankswitched tunes are padded by
+    // the load address's offset in its 4 KiB bank, the others by their distance from $8000
+    // so banks 0-7 in order give the flat layout.
+    pub fn cart(&self) -> Cart {
+        let (padding, min_size) = if self.is_bankswitched() {
+            (self.load_addr as usize % PRG_BANK_SIZE, PRG_BANK_SIZE)
+        } else {
+            ((self.load_addr - PRG_ROM) as usize, PRG_ROM_SIZE)
+        };
+        let mut prg = vec![0u8; padding];
+        prg.extend_from_slice(&self.data);
+        if !self.is_bankswitched() {
+            prg.truncate(PRG_ROM_SIZE);
+        }
+        let len = prg.len().max(min_size).next_multiple_of(PRG_BANK_SIZE);
+        prg.resize(len, 0);
+
+        Cart {
+            prg_rom: Memory::new(prg, false),
+            chr_rom: Memory::new(vec![0u8; CHR_RAM_SIZE], true),
+            mapper: 31,
+            submapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+            prg_ram_size: PRG_RAM_SIZE,
+            prg_ram: Memory::new(vec![0u8; PRG_RAM_SIZE], true),
+            battery: false,
+            vram: Memory::new(Vec::new(), true),
+            crc32: crc32fast::hash(&self.data),
+            sha1: sha1_smol::Sha1::from(&self.data).digest().bytes(),
+            title: Some(self.title.clone()).filter(|title| !title.is_empty()),
+            region: self.region(),
+        }
+    }
+
+    fn initial_banks(&self) -> [u8; 8] {
+        if self.is_bankswitched() {
+            self.bank_init
+        } else {
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        }
+    }
+}
+
+// Plays an Nsf track by track. Each frame calls the play routine and runs the CPU for one play
+// period; a routine still running when its period ends carries on into the next one, which
+// then skips its play call.
+pub struct NsfPlayer {
+    nsf: Nsf,
+    cpu: Cpu<Bus>,
+    region: Region,
+    // index into the track order
+    track: usize,
+    // a routine hasn't returned yet
+    in_routine: bool,
+    // bus cycle the current play period ends on, so overrunning instructions don't add up
+    frame_end: u64,
+    // microsecond-cycles left over from the previous periods
+    frame_remainder: u64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> Self {
+        let region = nsf.region();
+        let bus = Bus::with_mapper(nsf.cart(), Box::new(InlNsf::with_banks(nsf.initial_banks())));
+        let start = nsf.starting_track as usize;
+        let mut player = NsfPlayer {
+            nsf,
+            cpu: Cpu::with_bus(bus),
+            region,
+            track: 0,
+            in_routine: false,
+            frame_end: 0,
+            frame_remainder: 0,
+        };
+        let track = player.track_order().iter().position(|&track| track as usize == start);
+        player.select_track(track.unwrap_or(0));
+        player
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn bus(&self) -> &Bus {
+        self.cpu.bus()
+    }
+
+    // for draining audio and setting the sample rate
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        self.cpu.bus_mut()
+    }
+
+    // the NSFe playlist when there is one, every track otherwise
+    pub fn track_order(&self) -> Vec<u8> {
+        if self.nsf.playlist.is_empty() {
+            (0..self.nsf.total_tracks).collect()
+        } else {
+            self.nsf.playlist.clone()
+        }
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.track_order().len()
+    }
+
+    // position in the track order
+    pub fn track(&self) -> usize {
+        self.track
+    }
+
+    pub fn track_title(&self) -> Option<&str> {
+        let track = self.track_order()[self.track] as usize;
+        self.nsf.track_titles.get(track).map(String::as_str)
+    }
+
+    pub fn track_length(&self) -> Option<Duration> {
+        let track = self.track_order()[self.track] as usize;
+        self.nsf.track_lengths.get(track).copied().flatten()
+    }
+
+    pub fn next_track(&mut self) {
+        self.select_track((self.track + 1) % self.track_count());
+    }
+
+    pub fn previous_track(&mut self) {
+        let count = self.track_count();
+        self.select_track((self.track + count - 1) % count);
+    }
+
+    // Resets the machine the way the NSF spec asks and starts the init routine, which runs
+    // from the next run_frame. Positions past the end wrap around.
+    pub fn select_track(&mut self, position: usize) {
+        self.track = position % self.track_count();
+        let song = self.track_order()[self.track];
+        let bus = self.cpu.bus_mut();
+        for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+            bus.write(addr, 0);
+        }
+        for addr in 0x4000..0x4014 {
+            bus.write(addr, 0);
+        }
+        bus.write(0x4015, 0);
+        bus.write(0x4015, 0x0F);
+        bus.write(0x4017, 0x40);
+        for (i, bank) in self.nsf.initial_banks().into_iter().enumerate() {
+            bus.write(BANK_REGISTERS + i as u16, bank);
+        }
+
+        self.cpu.reset();
+        self.cpu.set_accumulator(song);
+        self.cpu.set_index_x((self.region == Region::Pal) as u8);
+        self.call(self.nsf.init_addr);
+        self.frame_end = self.cpu.bus().cycles();
+        self.frame_remainder = 0;
+    }
+
+    // points the CPU at `addr` with a return address on the stack that ends the call
+    fn call(&mut self, addr: u16) {
+        let [low, high] = (RETURN_ADDR - 1).to_le_bytes();
+        let sp = self.cpu.get_sp();
+        self.cpu.mem_write(STACK + sp as u16, high);
+        self.cpu.mem_write(STACK + sp.wrapping_sub(1) as u16, low);
+        self.cpu.set_sp(sp.wrapping_sub(2));
+        self.cpu.set_pc(addr);
+        self.in_routine = true;
+    }
+
+    // CPU cycles until the next play call
+    fn play_period(&mut self) -> u64 {
+        let speed = match self.region {
+            Region::Pal => self.nsf.pal_play_speed,
+            _ => self.nsf.ntsc_play_speed,
+        };
+        self.frame_remainder += speed as u64 * self.region.cpu_clock_hz() as u64;
+        let cycles = self.frame_remainder / 1_000_000;
+        self.frame_remainder %= 1_000_000;
+        cycles
+    }
+
+    // Runs one play period, starting the play routine unless init or the last play call is
+    // still going. Returns Halted if the driver jammed the CPU.
+    pub fn run_frame(&mut self) -> CpuStepResult {
+        self.frame_end += self.play_period();
+        let end = self.frame_end;
+        if !self.in_routine {
+            self.call(self.nsf.play_addr);
+        }
+        while self.cpu.bus().cycles() < end {
+            if !self.in_routine {
+                // nothing to run until the next play call, only the APU moves on; DMC fetches
+                // are read straight off the bus since there's no instruction to stall
+                let bus = self.cpu.bus_mut();
+                bus.tick(1);
+                if let Some(addr) = bus.take_dmc_request() {
+                    let sample = bus.read(addr);
+                    bus.fill_dmc_sample(sample);
+                }
+                continue;
+            }
+            // the bus runs in step with the CPU and passes the DMC's fetches and IRQs back
+            let result = self.cpu.run_instruction();
+            if result == CpuStepResult::Halted {
+                return result;
+            }
+            if self.cpu.get_pc() == RETURN_ADDR && self.cpu.at_instruction_boundary() {
+                self.in_routine = false;
+            }
+        }
+        CpuStepResult::Running
+    }
+}
@@ -0,0 +1,473 @@
+// Static description of all 256 opcodes, shared by the decoder, the tracer and the tests.
+// Cycle counts are the base count, without page cross or taken branch penalties.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndexedIndirect,
+    IndirectIndexed,
+    Relative,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mnemonic {
+    Adc,
+    Alr,
+    Anc,
+    And,
+    Arr,
+    Asl,
+    Axs,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dcp,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Isb,
+    Jam,
+    Jmp,
+    Jsr,
+    Las,
+    Lax,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Lxa,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rla,
+    Rol,
+    Ror,
+    Rra,
+    Rti,
+    Rts,
+    Sax,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sha,
+    Shx,
+    Shy,
+    Slo,
+    Sre,
+    Sta,
+    Stx,
+    Sty,
+    Tas,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+    Xaa,
+}
+
+impl Mnemonic {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mnemonic::Adc => "ADC",
+            Mnemonic::Alr => "ALR",
+            Mnemonic::Anc => "ANC",
+            Mnemonic::And => "AND",
+            Mnemonic::Arr => "ARR",
+            Mnemonic::Asl => "ASL",
+            Mnemonic::Axs => "AXS",
+            Mnemonic::Bcc => "BCC",
+            Mnemonic::Bcs => "BCS",
+            Mnemonic::Beq => "BEQ",
+            Mnemonic::Bit => "BIT",
+            Mnemonic::Bmi => "BMI",
+            Mnemonic::Bne => "BNE",
+            Mnemonic::Bpl => "BPL",
+            Mnemonic::Brk => "BRK",
+            Mnemonic::Bvc => "BVC",
+            Mnemonic::Bvs => "BVS",
+            Mnemonic::Clc => "CLC",
+            Mnemonic::Cld => "CLD",
+            Mnemonic::Cli => "CLI",
+            Mnemonic::Clv => "CLV",
+            Mnemonic::Cmp => "CMP",
+            Mnemonic::Cpx => "CPX",
+            Mnemonic::Cpy => "CPY",
+            Mnemonic::Dcp => "DCP",
+            Mnemonic::Dec => "DEC",
+            Mnemonic::Dex => "DEX",
+            Mnemonic::Dey => "DEY",
+            Mnemonic::Eor => "EOR",
+            Mnemonic::Inc => "INC",
+            Mnemonic::Inx => "INX",
+            Mnemonic::Iny => "INY",
+            Mnemonic::Isb => "ISB",
+            Mnemonic::Jam => "JAM",
+            Mnemonic::Jmp => "JMP",
+            Mnemonic::Jsr => "JSR",
+            Mnemonic::Las => "LAS",
+            Mnemonic::Lax => "LAX",
+            Mnemonic::Lda => "LDA",
+            Mnemonic::Ldx => "LDX",
+            Mnemonic::Ldy => "LDY",
+            Mnemonic::Lsr => "LSR",
+            Mnemonic::Lxa => "LXA",
+            Mnemonic::Nop => "NOP",
+            Mnemonic::Ora => "ORA",
+            Mnemonic::Pha => "PHA",
+            Mnemonic::Php => "PHP",
+            Mnemonic::Pla => "PLA",
+            Mnemonic::Plp => "PLP",
+            Mnemonic::Rla => "RLA",
+            Mnemonic::Rol => "ROL",
+            Mnemonic::Ror => "ROR",
+            Mnemonic::Rra => "RRA",
+            Mnemonic::Rti => "RTI",
+            Mnemonic::Rts => "RTS",
+            Mnemonic::Sax => "SAX",
+            Mnemonic::Sbc => "SBC",
+            Mnemonic::Sec => "SEC",
+            Mnemonic::Sed => "SED",
+            Mnemonic::Sei => "SEI",
+            Mnemonic::Sha => "SHA",
+            Mnemonic::Shx => "SHX",
+            Mnemonic::Shy => "SHY",
+            Mnemonic::Slo => "SLO",
+            Mnemonic::Sre => "SRE",
+            Mnemonic::Sta => "STA",
+            Mnemonic::Stx => "STX",
+            Mnemonic::Sty => "STY",
+            Mnemonic::Tas => "TAS",
+            Mnemonic::Tax => "TAX",
+            Mnemonic::Tay => "TAY",
+            Mnemonic::Tsx => "TSX",
+            Mnemonic::Txa => "TXA",
+            Mnemonic::Txs => "TXS",
+            Mnemonic::Tya => "TYA",
+            Mnemonic::Xaa => "XAA",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub mnemonic: Mnemonic,
+    pub mode: AddressingMode,
+    pub len: u8,
+    pub cycles: u8,
+    pub official: bool,
+}
+
+const fn op(
+    mnemonic: Mnemonic,
+    mode: AddressingMode,
+    len: u8,
+    cycles: u8,
+    official: bool,
+) -> OpcodeInfo {
+    OpcodeInfo {
+        mnemonic,
+        mode,
+        len,
+        cycles,
+        official,
+    }
+}
+
+use AddressingMode::*;
+use Mnemonic::*;
+
+pub static OPCODES: [OpcodeInfo; 256] = [
+    op(Brk, Implied, 1, 7, true), // 00
+    op(Ora, IndexedIndirect, 2, 6, true), // 01
+    op(Jam, Implied, 1, 0, false), // 02
+    op(Slo, IndexedIndirect, 2, 8, false), // 03
+    op(Nop, ZeroPage, 2, 3, false), // 04
+    op(Ora, ZeroPage, 2, 3, true), // 05
+    op(Asl, ZeroPage, 2, 5, true), // 06
+    op(Slo, ZeroPage, 2, 5, false), // 07
+    op(Php, Implied, 1, 3, true), // 08
+    op(Ora, Immediate, 2, 2, true), // 09
+    op(Asl, Accumulator, 1, 2, true), // 0A
+    op(Anc, Immediate, 2, 2, false), // 0B
+    op(Nop, Absolute, 3, 4, false), // 0C
+    op(Ora, Absolute, 3, 4, true), // 0D
+    op(Asl, Absolute, 3, 6, true), // 0E
+    op(Slo, Absolute, 3, 6, false), // 0F
+    op(Bpl, Relative, 2, 2, true), // 10
+    op(Ora, IndirectIndexed, 2, 5, true), // 11
+    op(Jam, Implied, 1, 0, false), // 12
+    op(Slo, IndirectIndexed, 2, 8, false), // 13
+    op(Nop, ZeroPageX, 2, 4, false), // 14
+    op(Ora, ZeroPageX, 2, 4, true), // 15
+    op(Asl, ZeroPageX, 2, 6, true), // 16
+    op(Slo, ZeroPageX, 2, 6, false), // 17
+    op(Clc, Implied, 1, 2, true), // 18
+    op(Ora, AbsoluteY, 3, 4, true), // 19
+    op(Nop, Implied, 1, 2, false), // 1A
+    op(Slo, AbsoluteY, 3, 7, false), // 1B
+    op(Nop, AbsoluteX, 3, 4, false), // 1C
+    op(Ora, AbsoluteX, 3, 4, true), // 1D
+    op(Asl, AbsoluteX, 3, 7, true), // 1E
+    op(Slo, AbsoluteX, 3, 7, false), // 1F
+    op(Jsr, Absolute, 3, 6, true), // 20
+    op(And, IndexedIndirect, 2, 6, true), // 21
+    op(Jam, Implied, 1, 0, false), // 22
+    op(Rla, IndexedIndirect, 2, 8, false), // 23
+    op(Bit, ZeroPage, 2, 3, true), // 24
+    op(And, ZeroPage, 2, 3, true), // 25
+    op(Rol, ZeroPage, 2, 5, true), // 26
+    op(Rla, ZeroPage, 2, 5, false), // 27
+    op(Plp, Implied, 1, 4, true), // 28
+    op(And, Immediate, 2, 2, true), // 29
+    op(Rol, Accumulator, 1, 2, true), // 2A
+    op(Anc, Immediate, 2, 2, false), // 2B
+    op(Bit, Absolute, 3, 4, true), // 2C
+    op(And, Absolute, 3, 4, true), // 2D
+    op(Rol, Absolute, 3, 6, true), // 2E
+    op(Rla, Absolute, 3, 6, false), // 2F
+    op(Bmi, Relative, 2, 2, true), // 30
+    op(And, IndirectIndexed, 2, 5, true), // 31
+    op(Jam, Implied, 1, 0, false), // 32
+    op(Rla, IndirectIndexed, 2, 8, false), // 33
+    op(Nop, ZeroPageX, 2, 4, false), // 34
+    op(And, ZeroPageX, 2, 4, true), // 35
+    op(Rol, ZeroPageX, 2, 6, true), // 36
+    op(Rla, ZeroPageX, 2, 6, false), // 37
+    op(Sec, Implied, 1, 2, true), // 38
+    op(And, AbsoluteY, 3, 4, true), // 39
+    op(Nop, Implied, 1, 2, false), // 3A
+    op(Rla, AbsoluteY, 3, 7, false), // 3B
+    op(Nop, AbsoluteX, 3, 4, false), // 3C
+    op(And, AbsoluteX, 3, 4, true), // 3D
+    op(Rol, AbsoluteX, 3, 7, true), // 3E
+    op(Rla, AbsoluteX, 3, 7, false), // 3F
+    op(Rti, Implied, 1, 6, true), // 40
+    op(Eor, IndexedIndirect, 2, 6, true), // 41
+    op(Jam, Implied, 1, 0, false), // 42
+    op(Sre, IndexedIndirect, 2, 8, false), // 43
+    op(Nop, ZeroPage, 2, 3, false), // 44
+    op(Eor, ZeroPage, 2, 3, true), // 45
+    op(Lsr, ZeroPage, 2, 5, true), // 46
+    op(Sre, ZeroPage, 2, 5, false), // 47
+    op(Pha, Implied, 1, 3, true), // 48
+    op(Eor, Immediate, 2, 2, true), // 49
+    op(Lsr, Accumulator, 1, 2, true), // 4A
+    op(Alr, Immediate, 2, 2, false), // 4B
+    op(Jmp, Absolute, 3, 3, true), // 4C
+    op(Eor, Absolute, 3, 4, true), // 4D
+    op(Lsr, Absolute, 3, 6, true), // 4E
+    op(Sre, Absolute, 3, 6, false), // 4F
+    op(Bvc, Relative, 2, 2, true), // 50
+    op(Eor, IndirectIndexed, 2, 5, true), // 51
+    op(Jam, Implied, 1, 0, false), // 52
+    op(Sre, IndirectIndexed, 2, 8, false), // 53
+    op(Nop, ZeroPageX, 2, 4, false), // 54
+    op(Eor, ZeroPageX, 2, 4, true), // 55
+    op(Lsr, ZeroPageX, 2, 6, true), // 56
+    op(Sre, ZeroPageX, 2, 6, false), // 57
+    op(Cli, Implied, 1, 2, true), // 58
+    op(Eor, AbsoluteY, 3, 4, true), // 59
+    op(Nop, Implied, 1, 2, false), // 5A
+    op(Sre, AbsoluteY, 3, 7, false), // 5B
+    op(Nop, AbsoluteX, 3, 4, false), // 5C
+    op(Eor, AbsoluteX, 3, 4, true), // 5D
+    op(Lsr, AbsoluteX, 3, 7, true), // 5E
+    op(Sre, AbsoluteX, 3, 7, false), // 5F
+    op(Rts, Implied, 1, 6, true), // 60
+    op(Adc, IndexedIndirect, 2, 6, true), // 61
+    op(Jam, Implied, 1, 0, false), // 62
+    op(Rra, IndexedIndirect, 2, 8, false), // 63
+    op(Nop, ZeroPage, 2, 3, false), // 64
+    op(Adc, ZeroPage, 2, 3, true), // 65
+    op(Ror, ZeroPage, 2, 5, true), // 66
+    op(Rra, ZeroPage, 2, 5, false), // 67
+    op(Pla, Implied, 1, 4, true), // 68
+    op(Adc, Immediate, 2, 2, true), // 69
+    op(Ror, Accumulator, 1, 2, true), // 6A
+    op(Arr, Immediate, 2, 2, false), // 6B
+    op(Jmp, Indirect, 3, 5, true), // 6C
+    op(Adc, Absolute, 3, 4, true), // 6D
+    op(Ror, Absolute, 3, 6, true), // 6E
+    op(Rra, Absolute, 3, 6, false), // 6F
+    op(Bvs, Relative, 2, 2, true), // 70
+    op(Adc, IndirectIndexed, 2, 5, true), // 71
+    op(Jam, Implied, 1, 0, false), // 72
+    op(Rra, IndirectIndexed, 2, 8, false), // 73
+    op(Nop, ZeroPageX, 2, 4, false), // 74
+    op(Adc, ZeroPageX, 2, 4, true), // 75
+    op(Ror, ZeroPageX, 2, 6, true), // 76
+    op(Rra, ZeroPageX, 2, 6, false), // 77
+    op(Sei, Implied, 1, 2, true), // 78
+    op(Adc, AbsoluteY, 3, 4, true), // 79
+    op(Nop, Implied, 1, 2, false), // 7A
+    op(Rra, AbsoluteY, 3, 7, false), // 7B
+    op(Nop, AbsoluteX, 3, 4, false), // 7C
+    op(Adc, AbsoluteX, 3, 4, true), // 7D
+    op(Ror, AbsoluteX, 3, 7, true), // 7E
+    op(Rra, AbsoluteX, 3, 7, false), // 7F
+    op(Nop, Immediate, 2, 2, false), // 80
+    op(Sta, IndexedIndirect, 2, 6, true), // 81
+    op(Nop, Immediate, 2, 2, false), // 82
+    op(Sax, IndexedIndirect, 2, 6, false), // 83
+    op(Sty, ZeroPage, 2, 3, true), // 84
+    op(Sta, ZeroPage, 2, 3, true), // 85
+    op(Stx, ZeroPage, 2, 3, true), // 86
+    op(Sax, ZeroPage, 2, 3, false), // 87
+    op(Dey, Implied, 1, 2, true), // 88
+    op(Nop, Immediate, 2, 2, false), // 89
+    op(Txa, Implied, 1, 2, true), // 8A
+    op(Xaa, Immediate, 2, 2, false), // 8B
+    op(Sty, Absolute, 3, 4, true), // 8C
+    op(Sta, Absolute, 3, 4, true), // 8D
+    op(Stx, Absolute, 3, 4, true), // 8E
+    op(Sax, Absolute, 3, 4, false), // 8F
+    op(Bcc, Relative, 2, 2, true), // 90
+    op(Sta, IndirectIndexed, 2, 6, true), // 91
+    op(Jam, Implied, 1, 0, false), // 92
+    op(Sha, IndirectIndexed, 2, 6, false), // 93
+    op(Sty, ZeroPageX, 2, 4, true), // 94
+    op(Sta, ZeroPageX, 2, 4, true), // 95
+    op(Stx, ZeroPageY, 2, 4, true), // 96
+    op(Sax, ZeroPageY, 2, 4, false), // 97
+    op(Tya, Implied, 1, 2, true), // 98
+    op(Sta, AbsoluteY, 3, 5, true), // 99
+    op(Txs, Implied, 1, 2, true), // 9A
+    op(Tas, AbsoluteY, 3, 5, false), // 9B
+    op(Shy, AbsoluteX, 3, 5, false), // 9C
+    op(Sta, AbsoluteX, 3, 5, true), // 9D
+    op(Shx, AbsoluteY, 3, 5, false), // 9E
+    op(Sha, AbsoluteY, 3, 5, false), // 9F
+    op(Ldy, Immediate, 2, 2, true), // A0
+    op(Lda, IndexedIndirect, 2, 6, true), // A1
+    op(Ldx, Immediate, 2, 2, true), // A2
+    op(Lax, IndexedIndirect, 2, 6, false), // A3
+    op(Ldy, ZeroPage, 2, 3, true), // A4
+    op(Lda, ZeroPage, 2, 3, true), // A5
+    op(Ldx, ZeroPage, 2, 3, true), // A6
+    op(Lax, ZeroPage, 2, 3, false), // A7
+    op(Tay, Implied, 1, 2, true), // A8
+    op(Lda, Immediate, 2, 2, true), // A9
+    op(Tax, Implied, 1, 2, true), // AA
+    op(Lxa, Immediate, 2, 2, false), // AB
+    op(Ldy, Absolute, 3, 4, true), // AC
+    op(Lda, Absolute, 3, 4, true), // AD
+    op(Ldx, Absolute, 3, 4, true), // AE
+    op(Lax, Absolute, 3, 4, false), // AF
+    op(Bcs, Relative, 2, 2, true), // B0
+    op(Lda, IndirectIndexed, 2, 5, true), // B1
+    op(Jam, Implied, 1, 0, false), // B2
+    op(Lax, IndirectIndexed, 2, 5, false), // B3
+    op(Ldy, ZeroPageX, 2, 4, true), // B4
+    op(Lda, ZeroPageX, 2, 4, true), // B5
+    op(Ldx, ZeroPageY, 2, 4, true), // B6
+    op(Lax, ZeroPageY, 2, 4, false), // B7
+    op(Clv, Implied, 1, 2, true), // B8
+    op(Lda, AbsoluteY, 3, 4, true), // B9
+    op(Tsx, Implied, 1, 2, true), // BA
+    op(Las, AbsoluteY, 3, 4, false), // BB
+    op(Ldy, AbsoluteX, 3, 4, true), // BC
+    op(Lda, AbsoluteX, 3, 4, true), // BD
+    op(Ldx, AbsoluteY, 3, 4, true), // BE
+    op(Lax, AbsoluteY, 3, 4, false), // BF
+    op(Cpy, Immediate, 2, 2, true), // C0
+    op(Cmp, IndexedIndirect, 2, 6, true), // C1
+    op(Nop, Immediate, 2, 2, false), // C2
+    op(Dcp, IndexedIndirect, 2, 8, false), // C3
+    op(Cpy, ZeroPage, 2, 3, true), // C4
+    op(Cmp, ZeroPage, 2, 3, true), // C5
+    op(Dec, ZeroPage, 2, 5, true), // C6
+    op(Dcp, ZeroPage, 2, 5, false), // C7
+    op(Iny, Implied, 1, 2, true), // C8
+    op(Cmp, Immediate, 2, 2, true), // C9
+    op(Dex, Implied, 1, 2, true), // CA
+    op(Axs, Immediate, 2, 2, false), // CB
+    op(Cpy, Absolute, 3, 4, true), // CC
+    op(Cmp, Absolute, 3, 4, true), // CD
+    op(Dec, Absolute, 3, 6, true), // CE
+    op(Dcp, Absolute, 3, 6, false), // CF
+    op(Bne, Relative, 2, 2, true), // D0
+    op(Cmp, IndirectIndexed, 2, 5, true), // D1
+    op(Jam, Implied, 1, 0, false), // D2
+    op(Dcp, IndirectIndexed, 2, 8, false), // D3
+    op(Nop, ZeroPageX, 2, 4, false), // D4
+    op(Cmp, ZeroPageX, 2, 4, true), // D5
+    op(Dec, ZeroPageX, 2, 6, true), // D6
+    op(Dcp, ZeroPageX, 2, 6, false), // D7
+    op(Cld, Implied, 1, 2, true), // D8
+    op(Cmp, AbsoluteY, 3, 4, true), // D9
+    op(Nop, Implied, 1, 2, false), // DA
+    op(Dcp, AbsoluteY, 3, 7, false), // DB
+    op(Nop, AbsoluteX, 3, 4, false), // DC
+    op(Cmp, AbsoluteX, 3, 4, true), // DD
+    op(Dec, AbsoluteX, 3, 7, true), // DE
+    op(Dcp, AbsoluteX, 3, 7, false), // DF
+    op(Cpx, Immediate, 2, 2, true), // E0
+    op(Sbc, IndexedIndirect, 2, 6, true), // E1
+    op(Nop, Immediate, 2, 2, false), // E2
+    op(Isb, IndexedIndirect, 2, 8, false), // E3
+    op(Cpx, ZeroPage, 2, 3, true), // E4
+    op(Sbc, ZeroPage, 2, 3, true), // E5
+    op(Inc, ZeroPage, 2, 5, true), // E6
+    op(Isb, ZeroPage, 2, 5, false), // E7
+    op(Inx, Implied, 1, 2, true), // E8
+    op(Sbc, Immediate, 2, 2, true), // E9
+    op(Nop, Implied, 1, 2, true), // EA
+    op(Sbc, Immediate, 2, 2, false), // EB
+    op(Cpx, Absolute, 3, 4, true), // EC
+    op(Sbc, Absolute, 3, 4, true), // ED
+    op(Inc, Absolute, 3, 6, true), // EE
+    op(Isb, Absolute, 3, 6, false), // EF
+    op(Beq, Relative, 2, 2, true), // F0
+    op(Sbc, IndirectIndexed, 2, 5, true), // F1
+    op(Jam, Implied, 1, 0, false), // F2
+    op(Isb, IndirectIndexed, 2, 8, false), // F3
+    op(Nop, ZeroPageX, 2, 4, false), // F4
+    op(Sbc, ZeroPageX, 2, 4, true), // F5
+    op(Inc, ZeroPageX, 2, 6, true), // F6
+    op(Isb, ZeroPageX, 2, 6, false), // F7
+    op(Sed, Implied, 1, 2, true), // F8
+    op(Sbc, AbsoluteY, 3, 4, true), // F9
+    op(Nop, Implied, 1, 2, false), // FA
+    op(Isb, AbsoluteY, 3, 7, false), // FB
+    op(Nop, AbsoluteX, 3, 4, false), // FC
+    op(Sbc, AbsoluteX, 3, 4, true), // FD
+    op(Inc, AbsoluteX, 3, 7, true), // FE
+    op(Isb, AbsoluteX, 3, 7, false), // FF
+];
+
+pub fn opcode_info(opcode: u8) -> &'static OpcodeInfo {
+    &OPCODES[opcode as usize]
+}
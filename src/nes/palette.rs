@@ -0,0 +1,138 @@
+// The master palette, turning the PPU's 6 bit colors plus the three PPUMASK emphasis bits into
+// RGB. The NTSC one is generated by modelling the composite signal the 2C02 puts out: each color
+// is a square wave between two voltages, its hue is the wave's phase against the colorburst, and
+// emphasis attenuates the signal during a third of each color cycle. Decoding that back to YIQ
+// and then RGB gives the colors a TV would show.
+//
+// A .pal file can stand in for it: 64 RGB triples, one per color, or 512 with every emphasis
+// combination spelled out in the same order as the indices here.
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+use thiserror::Error;
+
+pub const COLORS: usize = 512;
+
+// output voltages relative to sync, the low then the high level of the wave for each luma
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const ATTENUATION: f32 = 0.746;
+// how far the colorburst is from where color 1 starts, in twelfths of a cycle
+const HUE_OFFSET: f32 = 4.0;
+const GAMMA: f32 = 2.2 / 1.8;
+
+#[derive(Debug, Error)]
+pub enum PaletteError {
+    #[error("Could not read palette file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Palette file is {0} bytes, expected 192 or 1536")]
+    BadSize(usize),
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Box<[[u8; 3]]>,
+    // the same colors as opaque RGBA, one little endian u32 each, for converting whole frames
+    rgba: Box<[u32]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::ntsc()
+    }
+}
+
+impl Palette {
+    pub fn ntsc() -> Self {
+        Palette::with_colors((0..COLORS as u16).map(ntsc_color).collect())
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Palette, PaletteError> {
+        Palette::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Palette, PaletteError> {
+        let file: Vec<[u8; 3]> = match raw.len() {
+            192 | 1536 => raw.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect(),
+            len => return Err(PaletteError::BadSize(len)),
+        };
+        if file.len() == COLORS {
+            return Ok(Palette::with_colors(file.into_boxed_slice()));
+        }
+        // 64 colors only, darken the channels emphasis doesn't favor
+        let colors = (0..COLORS)
+            .map(|index| {
+                let emphasis = index >> 6;
+                let mut rgb = file[index & 0x3F];
+                for (channel, value) in rgb.iter_mut().enumerate() {
+                    if emphasis & !(1 << channel) != 0 {
+                        *value = (*value as f32 * ATTENUATION).round() as u8;
+                    }
+                }
+                rgb
+            })
+            .collect();
+        Ok(Palette::with_colors(colors))
+    }
+
+    fn with_colors(colors: Box<[[u8; 3]]>) -> Self {
+        let rgba = colors.iter().map(|&[r, g, b]| u32::from_le_bytes([r, g, b, 0xFF])).collect();
+        Palette { colors, rgba }
+    }
+
+    // `index` is a 6 bit color with the emphasis bits above it
+    pub fn rgb(&self, index: u16) -> [u8; 3] {
+        self.colors[index as usize % COLORS]
+    }
+
+    // all 512 colors as RGBA bytes packed little endian
+    pub fn rgba_table(&self) -> &[u32] {
+        &self.rgba
+    }
+}
+
+// the composite voltage of `index` at `phase` twelfths into a color cycle
+fn ntsc_signal(index: u16, phase: u16) -> f32 {
+    let color = index & 0x0F;
+    // colors $xE and $xF are always black
+    let level = if color > 0x0D { 1 } else { (index >> 4) & 0b11 } as usize;
+    let emphasis = index >> 6;
+    let in_phase = |color: u16| (color + phase) % 12 < 6;
+
+    let low = LEVELS[level];
+    let high = LEVELS[4 + level];
+    // color 0 stays high, $xD-$xF stay low, the rest alternate
+    let mut signal = match color {
+        0 => high,
+        0x0D.. => low,
+        _ if in_phase(color) => high,
+        _ => low,
+    };
+    if (emphasis & 0b001 != 0 && in_phase(0))
+        || (emphasis & 0b010 != 0 && in_phase(4))
+        || (emphasis & 0b100 != 0 && in_phase(8))
+    {
+        signal *= ATTENUATION;
+    }
+    signal
+}
+
+fn ntsc_color(index: u16) -> [u8; 3] {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let signal = (ntsc_signal(index, phase) - BLACK) / (WHITE - BLACK);
+        let angle = PI * (phase as f32 + HUE_OFFSET) / 6.0;
+        y += signal;
+        i += signal * angle.cos();
+        q += signal * angle.sin();
+    }
+    let (y, i, q) = (y / 12.0, i / 12.0, q / 12.0);
+    let channel = |value: f32| (255.0 * value.max(0.0).powf(GAMMA)).round().min(255.0) as u8;
+    [
+        channel(y + 0.946_882 * i + 0.623_557 * q),
+        channel(y - 0.274_788 * i - 0.635_691 * q),
+        channel(y - 1.108_545 * i + 1.709_007 * q),
+    ]
+}
@@ -0,0 +1,208 @@
+// Soft-patching: IPS and BPS patches applied to a ROM image in memory before it's parsed, so
+// hacks and translations run without touching the original dump.
+
+use thiserror::Error;
+
+const IPS_TAG: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_TAG: &[u8] = b"BPS1";
+// source, target and patch CRC32s
+const BPS_FOOTER_SIZE: usize = 12;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PatchError {
+    #[error("Not an IPS or BPS patch")]
+    UnknownFormat,
+    #[error("Patch is truncated")]
+    Truncated,
+    #[error("Patch was made for a different ROM (CRC32 {expected:08X}, this one is {found:08X})")]
+    WrongSource { expected: u32, found: u32 },
+    #[error("Patch is corrupt: {0}")]
+    Corrupt(&'static str),
+}
+
+// picks the format from the patch's magic
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_TAG) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_TAG) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+// IPS stores offsets and sizes big endian
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |value, &byte| value << 8 | byte as usize)
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(PatchError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn be(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(big_endian(self.bytes(len)?))
+    }
+
+    // BPS numbers: 7 bits per byte, low first, with the top bit ending the number. Each
+    // continuation adds one more so no value has two encodings.
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.u8()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|part| value.checked_add(part))
+                .ok_or(PatchError::Corrupt("number too large"))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Corrupt("number too large"))?;
+            value = value.checked_add(shift).ok_or(PatchError::Corrupt("number too large"))?;
+        }
+    }
+}
+
+// Records of a 3 byte offset and a 2 byte length followed by the data, or a length of 0 for a
+// run of one byte. Writes past the end grow the image. Some patchers add a 3 byte size after
+// the EOF marker to truncate to.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut r = PatchReader { data: patch, pos: 0 };
+    if r.bytes(IPS_TAG.len())? != IPS_TAG {
+        return Err(PatchError::UnknownFormat);
+    }
+    let mut out = rom.to_vec();
+    loop {
+        let offset = r.bytes(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = big_endian(offset);
+        let (len, fill) = match r.be(2)? {
+            0 => (r.be(2)?, Some(r.u8()?)),
+            len => (len, None),
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match fill {
+            Some(byte) => out[offset..offset + len].fill(byte),
+            None => out[offset..offset + len].copy_from_slice(r.bytes(len)?),
+        }
+    }
+    if let Ok(size) = r.be(3) {
+        out.truncate(size);
+    }
+    Ok(out)
+}
+
+// A header with the source, target and metadata sizes, then copy actions that build the
+// target front to back from the source, the patch or what has been written so far. All three
+// files are checked against the CRC32s in the footer.
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.len() < BPS_TAG.len() + BPS_FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+    let footer = &patch[patch.len() - BPS_FOOTER_SIZE..];
+    let crc = |i: usize| u32::from_le_bytes(footer[i * 4..i * 4 + 4].try_into().unwrap());
+    let (source_crc, target_crc, patch_crc) = (crc(0), crc(1), crc(2));
+    if crc32fast::hash(&patch[..patch.len() - 4]) != patch_crc {
+        return Err(PatchError::Corrupt("patch checksum mismatch"));
+    }
+    let found = crc32fast::hash(rom);
+    if found != source_crc {
+        return Err(PatchError::WrongSource {
+            expected: source_crc,
+            found,
+        });
+    }
+
+    let actions_end = patch.len() - BPS_FOOTER_SIZE;
+    let mut r = PatchReader {
+        data: &patch[..actions_end],
+        pos: BPS_TAG.len(),
+    };
+    let source_size = r.varint()?;
+    let target_size = r.varint()?;
+    let metadata_size = r.varint()?;
+    r.bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(PatchError::Corrupt("source size mismatch"));
+    }
+
+    let mut out: Vec<u8> = Vec::new();
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    // relative offsets are stored as a magnitude with the sign in bit 0
+    let seek = |offset: usize, data: usize| -> Result<usize, PatchError> {
+        let delta = data >> 1;
+        let moved = if data & 1 != 0 {
+            offset.checked_sub(delta)
+        } else {
+            offset.checked_add(delta)
+        };
+        moved.ok_or(PatchError::Corrupt("copy offset out of range"))
+    };
+    while r.pos < actions_end {
+        let data = r.varint()?;
+        let len = (data >> 2) + 1;
+        if out.len() + len > target_size {
+            return Err(PatchError::Corrupt("writes past the target size"));
+        }
+        match data & 0b11 {
+            // source read, the same offset in the source
+            0 => {
+                let start = out.len();
+                let bytes = start
+                    .checked_add(len)
+                    .and_then(|end| rom.get(start..end))
+                    .ok_or(PatchError::Corrupt("source read out of range"))?;
+                out.extend_from_slice(bytes);
+            }
+            // target read, literal bytes from the patch
+            1 => out.extend_from_slice(r.bytes(len)?),
+            // source copy
+            2 => {
+                source_offset = seek(source_offset, r.varint()?)?;
+                let end = source_offset
+                    .checked_add(len)
+                    .filter(|&end| end <= rom.len())
+                    .ok_or(PatchError::Corrupt("source copy out of range"))?;
+                out.extend_from_slice(&rom[source_offset..end]);
+                source_offset = end;
+            }
+            // target copy, byte by byte as it may overlap what it writes
+            _ => {
+                target_offset = seek(target_offset, r.varint()?)?;
+                for _ in 0..len {
+                    let byte = *out
+                        .get(target_offset)
+                        .ok_or(PatchError::Corrupt("target copy out of range"))?;
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size || crc32fast::hash(&out) != target_crc {
+        return Err(PatchError::Corrupt("result checksum mismatch"));
+    }
+    Ok(out)
+}
@@ -0,0 +1,518 @@
+// NTSC 2C02 timing by default: 341 dots per scanline, 262 scanlines per frame. The PAL 2C07 and the
+// Dendy clones have 312, the extra 50 in vblank on PAL and after the picture on Dendy, and neither
+// skips a dot on odd frames. The CPU talks to it through eight registers at $2000-$2007, mirrored
+// up to $3FFF. Memory accesses go out over the PPU bus, which the caller passes in since the PPU
+// doesn't own the cart or the nametable RAM. Palette RAM and OAM are inside the chip.
+
+mod debug;
+mod render;
+mod scanline;
+
+use super::cart::Region;
+use super::frame::Frame;
+use super::mem::{Peek, Read, Write};
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+pub use debug::{
+    DebugImage, ScrollRect, SpriteInfo, NAMETABLES_HEIGHT, NAMETABLES_WIDTH, PATTERN_TABLES_HEIGHT,
+    PATTERN_TABLES_WIDTH,
+};
+use render::{Background, Sprites};
+use scanline::LineState;
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+// NTSC, see Region::scanlines_per_frame for the others
+pub const SCANLINES_PER_FRAME: u16 = 262;
+pub const VISIBLE_SCANLINES: u16 = 240;
+pub const VBLANK_SCANLINE: u16 = 241;
+// NTSC, the last line of the frame
+pub const PRE_RENDER_SCANLINE: u16 = 261;
+
+const PPUCTRL: u16 = 0x2000;
+const PPUMASK: u16 = 0x2001;
+const PPUSTATUS: u16 = 0x2002;
+const OAMADDR: u16 = 0x2003;
+const OAMDATA: u16 = 0x2004;
+const PPUSCROLL: u16 = 0x2005;
+const PPUADDR: u16 = 0x2006;
+const PPUDATA: u16 = 0x2007;
+
+const PALETTE_RAM: u16 = 0x3F00;
+
+// how long a bit on the I/O latch holds a 1 without being driven again
+const IO_LATCH_DECAY_SECONDS: f64 = 0.6;
+
+const STATUS_VBLANK: u8 = 0b1000_0000;
+const STATUS_SPRITE_0_HIT: u8 = 0b0100_0000;
+const STATUS_SPRITE_OVERFLOW: u8 = 0b0010_0000;
+const CTRL_NMI_ENABLE: u8 = 0b1000_0000;
+const CTRL_INCREMENT_32: u8 = 0b0000_0100;
+
+// the 15 bit v and t registers, from the top: 3 bits of fine Y, 2 of nametable, 5 of coarse Y
+// and 5 of coarse X
+const COARSE_X: u16 = 0x001F;
+const COARSE_Y: u16 = 0x03E0;
+const NAMETABLE: u16 = 0x0C00;
+const FINE_Y: u16 = 0x7000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PpuBackend {
+    // the fetch pipeline stepped dot by dot, like the real chip
+    #[default]
+    Dot,
+    // whole lines at once, much cheaper for slow machines and fast-forward
+    Scanline,
+}
+
+pub struct Ppu {
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    oam: [u8; 256],
+    oam_addr: u8,
+    // four background palettes then four sprite palettes, 6 bit colors
+    palette: [u8; 32],
+    ctrl: u8,
+    mask: u8,
+    // the top three bits of PPUSTATUS, the rest is open bus
+    status: u8,
+    // current and temporary VRAM address, fine X scroll and the shared write toggle
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    w: bool,
+    // what PPUDATA reads below the palette return, the byte fetched by the previous read
+    read_buffer: u8,
+    // The data bus between the CPU and the PPU's registers. Write-only registers read back
+    // whatever was last on it.
+    io_latch: u8,
+    // the frame each latch bit was last driven in, bits left alone too long fade to 0
+    io_refreshed: [u64; 8],
+    // an NMI the CPU hasn't taken yet
+    nmi_pending: bool,
+    // PPUSTATUS was read the dot before vblank starts, so this frame's flag never gets set
+    suppress_vblank: bool,
+    bg: Background,
+    sprites: Sprites,
+    // the scanline backend's progress through the current line
+    line: LineState,
+    // the picture being drawn, complete from the start of vblank until the next frame starts
+    output: Frame,
+    // a finished frame the frontend hasn't picked up yet
+    frame_ready: bool,
+    backend: PpuBackend,
+    region: Region,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            oam: [0u8; 256],
+            oam_addr: 0,
+            palette: [0u8; 32],
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            io_refreshed: [0; 8],
+            nmi_pending: false,
+            suppress_vblank: false,
+            bg: Background::default(),
+            sprites: Sprites::default(),
+            line: LineState::default(),
+            output: Frame::new(),
+            frame_ready: false,
+            backend: PpuBackend::Dot,
+            region: Region::Ntsc,
+        }
+    }
+
+    // advances one dot, fetching over `bus` while rendering
+    pub fn tick<B: Read>(&mut self, bus: &mut B) {
+        self.dot += 1;
+        // with rendering on, odd NTSC frames skip the last dot of the pre-render line
+        let skip = self.dot == DOTS_PER_SCANLINE - 1
+            && self.scanline == self.pre_render_scanline()
+            && self.frame % 2 == 1
+            && self.region == Region::Ntsc
+            && self.rendering_enabled();
+        if self.dot == DOTS_PER_SCANLINE || skip {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.frame += 1;
+                self.decay_io_latch();
+            }
+        }
+        match self.backend {
+            PpuBackend::Dot => self.render_dot(bus),
+            PpuBackend::Scanline => self.scanline_dot(bus),
+        }
+
+        if self.dot == 1 && self.scanline == self.vblank_scanline() {
+            if !self.suppress_vblank {
+                self.set_status(self.status | STATUS_VBLANK);
+            }
+            self.suppress_vblank = false;
+            self.frame_ready = true;
+        } else if self.dot == 1 && self.scanline == self.pre_render_scanline() {
+            self.set_status(0);
+        }
+    }
+
+    pub fn backend(&self) -> PpuBackend {
+        self.backend
+    }
+
+    // Best switched between frames, a line that changes backend halfway is drawn from
+    // stale state.
+    pub fn set_backend(&mut self, backend: PpuBackend) {
+        self.backend = backend;
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    // set from the cart before running, switching mid-frame moves the pre-render line
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    // Dendy clones keep the NTSC vblank length and put their extra lines before it
+    fn vblank_scanline(&self) -> u16 {
+        match self.region {
+            Region::Dendy => VBLANK_SCANLINE + 50,
+            Region::Ntsc | Region::Pal => VBLANK_SCANLINE,
+        }
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        self.region.scanlines_per_frame() - 1
+    }
+
+    // /NMI is pulled low while both vblank and NMI enable are set
+    fn nmi_output(&self) -> bool {
+        self.status & STATUS_VBLANK != 0 && self.ctrl & CTRL_NMI_ENABLE != 0
+    }
+
+    fn set_status(&mut self, status: u8) {
+        let before = self.nmi_output();
+        self.status = status;
+        self.nmi_pending |= !before && self.nmi_output();
+    }
+
+    fn set_ctrl(&mut self, ctrl: u8) {
+        let before = self.nmi_output();
+        self.ctrl = ctrl;
+        // setting NMI enable during vblank fires another one
+        self.nmi_pending |= !before && self.nmi_output();
+    }
+
+    // true once for each falling edge of /NMI
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    // fetching for the picture, on a visible or the pre-render line with rendering on
+    pub fn is_rendering(&self) -> bool {
+        self.rendering_enabled()
+            && (self.scanline < VISIBLE_SCANLINES || self.scanline == self.pre_render_scanline())
+    }
+
+    pub fn in_vblank(&self) -> bool {
+        self.status & STATUS_VBLANK != 0
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.output
+    }
+
+    // true once for each frame finished since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.oam
+    }
+
+    pub fn palette(&self) -> &[u8; 32] {
+        &self.palette
+    }
+
+    // Entry 0 of each sprite palette is the same byte as the background one below it, so
+    // $3F10/$3F14/$3F18/$3F1C land on $3F00/$3F04/$3F08/$3F0C. $3F20-$3FFF mirror the 32 bytes.
+    fn palette_index(addr: u16) -> usize {
+        let index = addr as usize & 0x1F;
+        if index & 0b10011 == 0b10000 {
+            index & 0x0F
+        } else {
+            index
+        }
+    }
+
+    pub fn read_palette(&self, addr: u16) -> u8 {
+        self.palette[Ppu::palette_index(addr)]
+    }
+
+    pub fn write_palette(&mut self, addr: u16, data: u8) {
+        self.palette[Ppu::palette_index(addr)] = data & 0x3F;
+    }
+
+    pub fn ctrl(&self) -> u8 {
+        self.ctrl
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    // the VRAM address PPUDATA accesses, and where rendering fetches from
+    pub fn vram_addr(&self) -> u16 {
+        self.v
+    }
+
+    // the scroll position PPUCTRL, PPUSCROLL and the first PPUADDR write build up
+    pub fn temp_vram_addr(&self) -> u16 {
+        self.t
+    }
+
+    pub fn fine_x(&self) -> u8 {
+        self.fine_x
+    }
+
+    pub fn write_oam_addr(&mut self, data: u8) {
+        self.oam_addr = data;
+    }
+
+    pub fn write_oam_data(&mut self, data: u8) {
+        // bits 2-4 of the attribute byte don't exist and read back as 0
+        let data = if self.oam_addr & 0b11 == 2 { data & 0xE3 } else { data };
+        self.oam[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    // While rendering, reads see whatever sprite evaluation and the sprite fetches have on
+    // the OAM bus rather than the byte at OAMADDR.
+    pub fn read_oam_data(&self) -> u8 {
+        if !self.is_rendering() {
+            return self.oam[self.oam_addr as usize];
+        }
+        self.oam_bus()
+    }
+
+    // puts the bits of `data` selected by `driven` on the I/O latch, the rest keep fading
+    fn drive_io_latch(&mut self, data: u8, driven: u8) {
+        self.io_latch = (self.io_latch & !driven) | (data & driven);
+        for bit in 0..8 {
+            if driven & (1 << bit) != 0 {
+                self.io_refreshed[bit] = self.frame;
+            }
+        }
+    }
+
+    fn decay_io_latch(&mut self) {
+        let frames = (self.region.frame_rate() * IO_LATCH_DECAY_SECONDS) as u64;
+        for bit in 0..8 {
+            if self.frame - self.io_refreshed[bit] >= frames {
+                self.io_latch &= !(1 << bit);
+            }
+        }
+    }
+
+    // across a row with PPUCTRL bit 2 clear, down a column with it set
+    fn increment_vram_addr(&mut self) {
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 { 32 } else { 1 };
+        self.v = (self.v + step) & 0x7FFF;
+    }
+
+    // A CPU read of `addr`, one of $2000-$2007. PPUSTATUS reads acknowledge vblank and reset
+    // the write toggle, PPUDATA reads move the address on. Only the bits a register drives
+    // refresh the I/O latch, write-only registers just read it back.
+    pub fn read_register<B: Read>(&mut self, addr: u16, bus: &mut B) -> u8 {
+        self.catch_up(bus);
+        let (data, driven) = match addr & 0x2007 {
+            PPUSTATUS => {
+                let data = self.peek(addr);
+                // Racing the flag: a read just before it's set sees it clear and stops it, one
+                // on the same dot or the next sees it set but still cancels the NMI
+                if self.scanline == self.vblank_scanline() {
+                    match self.dot {
+                        0 => self.suppress_vblank = true,
+                        1 | 2 => self.nmi_pending = false,
+                        _ => {}
+                    }
+                }
+                self.set_status(self.status & !STATUS_VBLANK);
+                self.w = false;
+                (data, 0xE0)
+            }
+            OAMDATA => (self.read_oam_data(), 0xFF),
+            PPUDATA => {
+                let addr = self.v & 0x3FFF;
+                let read = if addr >= PALETTE_RAM {
+                    // Palette reads skip the buffer, which picks up the nametable byte the
+                    // palette hides instead. Only 6 bits are driven, the rest is open bus.
+                    self.read_buffer = bus.read(addr - 0x1000);
+                    (self.read_palette(addr) | (self.io_latch & 0xC0), 0x3F)
+                } else {
+                    (std::mem::replace(&mut self.read_buffer, bus.read(addr)), 0xFF)
+                };
+                self.increment_vram_addr();
+                read
+            }
+            _ => (self.io_latch, 0),
+        };
+        self.drive_io_latch(data, driven);
+        data
+    }
+
+    pub fn write_register<B: Read + Write>(&mut self, addr: u16, data: u8, bus: &mut B) {
+        self.catch_up(bus);
+        self.drive_io_latch(data, 0xFF);
+        match addr & 0x2007 {
+            PPUCTRL => {
+                self.set_ctrl(data);
+                self.t = (self.t & !NAMETABLE) | ((data as u16 & 0b11) << 10);
+            }
+            PPUMASK => self.mask = data,
+            // read only
+            PPUSTATUS => {}
+            OAMADDR => self.write_oam_addr(data),
+            OAMDATA => self.write_oam_data(data),
+            PPUSCROLL => {
+                if !self.w {
+                    self.t = (self.t & !COARSE_X) | (data as u16 >> 3);
+                    self.fine_x = data & 0b111;
+                } else {
+                    self.t = (self.t & !(COARSE_Y | FINE_Y))
+                        | ((data as u16 >> 3) << 5)
+                        | ((data as u16 & 0b111) << 12);
+                }
+                self.w = !self.w;
+            }
+            PPUADDR => {
+                if !self.w {
+                    // the top bit of the 15 bit register is cleared too
+                    self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | data as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            }
+            _ => {
+                let addr = self.v & 0x3FFF;
+                if addr >= PALETTE_RAM {
+                    self.write_palette(addr, data);
+                } else {
+                    bus.write(addr, data);
+                }
+                self.increment_vram_addr();
+            }
+        }
+    }
+}
+
+// What reading a register would return, without the side effects
+impl Peek for Ppu {
+    fn peek(&self, addr: u16) -> u8 {
+        match addr & 0x2007 {
+            PPUSTATUS => {
+                let flags = STATUS_VBLANK | STATUS_SPRITE_0_HIT | STATUS_SPRITE_OVERFLOW;
+                (self.status & flags) | (self.io_latch & !flags)
+            }
+            OAMDATA => self.read_oam_data(),
+            PPUDATA if self.v & 0x3FFF >= PALETTE_RAM => {
+                self.read_palette(self.v) | (self.io_latch & 0xC0)
+            }
+            PPUDATA => self.read_buffer,
+            _ => self.io_latch,
+        }
+    }
+}
+
+impl Savestate for Ppu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.scanline);
+        w.write_u16(self.dot);
+        w.write_u64(self.frame);
+        w.write_bytes(&self.oam);
+        w.write_u8(self.oam_addr);
+        w.write_u8(self.ctrl);
+        w.write_u8(self.mask);
+        w.write_u8(self.status);
+        w.write_u16(self.v);
+        w.write_u16(self.t);
+        w.write_u8(self.fine_x);
+        w.write_bool(self.w);
+        w.write_u8(self.read_buffer);
+        w.write_u8(self.io_latch);
+        w.write_bool(self.nmi_pending);
+        w.write_bool(self.suppress_vblank);
+        w.write_bytes(&self.palette);
+        self.bg.save_state(w);
+        self.sprites.save_state(w);
+        self.line.save_state(w);
+        for refreshed in self.io_refreshed {
+            w.write_u64(refreshed);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.scanline = r.read_u16()?;
+        self.dot = r.read_u16()?;
+        if self.scanline >= self.region.scanlines_per_frame() || self.dot >= DOTS_PER_SCANLINE {
+            return Err(StateError::Corrupt("invalid PPU position"));
+        }
+        self.frame = r.read_u64()?;
+        r.read_into(&mut self.oam)?;
+        self.oam_addr = r.read_u8()?;
+        self.ctrl = r.read_u8()?;
+        self.mask = r.read_u8()?;
+        self.status = r.read_u8()?;
+        self.v = r.read_u16()? & 0x7FFF;
+        self.t = r.read_u16()? & 0x7FFF;
+        self.fine_x = r.read_u8()? & 0b111;
+        self.w = r.read_bool()?;
+        self.read_buffer = r.read_u8()?;
+        self.io_latch = r.read_u8()?;
+        self.nmi_pending = r.read_bool()?;
+        self.suppress_vblank = r.read_bool()?;
+        r.read_into(&mut self.palette)?;
+        self.bg.load_state(r)?;
+        self.sprites.load_state(r)?;
+        self.line.load_state(r)?;
+        for refreshed in &mut self.io_refreshed {
+            *refreshed = r.read_u64()?.min(self.frame);
+        }
+        Ok(())
+    }
+}
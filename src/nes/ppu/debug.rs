@@ -0,0 +1,218 @@
+// Views of the PPU's memory for graphics debugging: the pattern tables drawn with a chosen
+// palette, all four nametables, palette RAM and a decoded sprite list. Everything is read with
+// peeks, so looking doesn't disturb the mapper or the picture being drawn. Images hold the same
+// master palette indices as a Frame, without emphasis or grayscale.
+
+use super::render::{
+    CTRL_BACKGROUND_TABLE, CTRL_SPRITE_TABLE, SPRITE_BEHIND, SPRITE_FLIP_H, SPRITE_FLIP_V,
+};
+use super::{Ppu, PALETTE_RAM};
+use crate::nes::frame::{HEIGHT, WIDTH};
+use crate::nes::mem::Peek;
+use crate::nes::palette::Palette;
+
+// both pattern tables side by side, 16x16 tiles each
+pub const PATTERN_TABLES_WIDTH: usize = 256;
+pub const PATTERN_TABLES_HEIGHT: usize = 128;
+// the four nametables in a 2x2 grid, $2000 top left and $2C00 bottom right
+pub const NAMETABLES_WIDTH: usize = WIDTH * 2;
+pub const NAMETABLES_HEIGHT: usize = HEIGHT * 2;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct DebugImage {
+    width: usize,
+    height: usize,
+    // row major, one master palette index per pixel
+    pixels: Box<[u16]>,
+}
+
+impl DebugImage {
+    fn new(width: usize, height: usize) -> Self {
+        DebugImage { width, height, pixels: vec![0u16; width * height].into_boxed_slice() }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * self.width + x]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, index: u16) {
+        self.pixels[y * self.width + x] = index;
+    }
+
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&index| {
+                let [r, g, b] = palette.rgb(index);
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+}
+
+// the part of the nametables the next frame starts drawing from, wrapping around the 512x480
+// grid when it runs off the right or bottom
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScrollRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// one OAM entry, decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpriteInfo {
+    pub index: u8,
+    // the line above the sprite's top row, as stored in OAM
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    // 0-3, the sprite palettes at $3F10-$3F1F
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_h: bool,
+    pub flip_v: bool,
+}
+
+impl Ppu {
+    // the palette RAM color for `color` 0-3 of `palette` 0-7, the backdrop for 0
+    fn debug_color(&self, palette: u8, color: u8) -> u16 {
+        let entry = if color == 0 { 0 } else { (palette & 0b111) << 2 | color };
+        self.read_palette(PALETTE_RAM | entry as u16) as u16
+    }
+
+    // the 2 bit color of row `row`, column `column` of the tile at `addr`
+    fn tile_color<B: Peek>(bus: &B, addr: u16, row: u16, column: u16) -> u8 {
+        let lo = bus.peek(addr + row);
+        let hi = bus.peek(addr + row + 8);
+        let bit = 7 - column;
+        ((lo >> bit) & 1) | (((hi >> bit) & 1) << 1)
+    }
+
+    // $0000-$1FFF as 512 8x8 tiles, the left table then the right one, colored with `palette`
+    // 0-7, four background palettes then four sprite palettes
+    pub fn pattern_tables_image<B: Peek>(&self, bus: &B, palette: u8) -> DebugImage {
+        let mut image = DebugImage::new(PATTERN_TABLES_WIDTH, PATTERN_TABLES_HEIGHT);
+        for tile in 0..512u16 {
+            let left = (tile / 256) as usize * 128 + (tile % 16) as usize * 8;
+            let top = (tile % 256 / 16) as usize * 8;
+            for row in 0..8 {
+                for column in 0..8 {
+                    let color = Ppu::tile_color(bus, tile * 16, row, column);
+                    let index = self.debug_color(palette, color);
+                    image.set_pixel(left + column as usize, top + row as usize, index);
+                }
+            }
+        }
+        image
+    }
+
+    // The four nametables with their attributes, the tiles from the pattern table PPUCTRL
+    // picks for the background. Boards that supply nametable data themselves show CIRAM.
+    pub fn nametables_image<B: Peek>(&self, bus: &B) -> DebugImage {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 { 0x1000 } else { 0 };
+        let mut image = DebugImage::new(NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+        for nametable in 0..4u16 {
+            let base = 0x2000 + nametable * 0x400;
+            let left = (nametable & 1) as usize * WIDTH;
+            let top = (nametable >> 1) as usize * HEIGHT;
+            for tile_y in 0..30u16 {
+                for tile_x in 0..32u16 {
+                    let tile = bus.peek(base + tile_y * 32 + tile_x) as u16;
+                    let attribute = bus.peek(base + 0x3C0 + (tile_y / 4) * 8 + tile_x / 4);
+                    let shift = ((tile_y & 2) << 1) | (tile_x & 2);
+                    let palette = (attribute >> shift) & 0b11;
+                    for row in 0..8 {
+                        for column in 0..8 {
+                            let color = Ppu::tile_color(bus, table + tile * 16, row, column);
+                            let x = left + (tile_x * 8 + column) as usize;
+                            let y = top + (tile_y * 8 + row) as usize;
+                            image.set_pixel(x, y, self.debug_color(palette, color));
+                        }
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    // Where in nametables_image the next frame's picture comes from, out of t and fine X.
+    // Mid-frame scroll changes aren't reflected.
+    pub fn scroll_rect(&self) -> ScrollRect {
+        let t = self.t as usize;
+        let x = (t >> 10 & 1) * WIDTH + (t & 0x1F) * 8 + self.fine_x as usize;
+        let y = (t >> 11 & 1) * HEIGHT + (t >> 5 & 0x1F) * 8 + (t >> 12 & 0b111);
+        ScrollRect { x, y, width: WIDTH, height: HEIGHT }
+    }
+
+    // palette RAM as 8 rows of 4 colors, the background palettes first, entry 0 of each
+    // sprite palette reading through to the background one it mirrors
+    pub fn palettes_image(&self) -> DebugImage {
+        let mut image = DebugImage::new(4, 8);
+        for palette in 0..8u16 {
+            for color in 0..4u16 {
+                let index = self.read_palette(PALETTE_RAM | palette << 2 | color);
+                image.set_pixel(color as usize, palette as usize, index as u16);
+            }
+        }
+        image
+    }
+
+    pub fn sprites(&self) -> Vec<SpriteInfo> {
+        self.oam
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(index, sprite)| {
+                let attribute = sprite[2];
+                SpriteInfo {
+                    index: index as u8,
+                    y: sprite[0],
+                    tile: sprite[1],
+                    x: sprite[3],
+                    palette: attribute & 0b11,
+                    behind_background: attribute & SPRITE_BEHIND != 0,
+                    flip_h: attribute & SPRITE_FLIP_H != 0,
+                    flip_v: attribute & SPRITE_FLIP_V != 0,
+                }
+            })
+            .collect()
+    }
+
+    // Sprite `index` as it would be drawn, 8 pixels wide and 8 or 16 tall with the current
+    // sprite size, flipped and in its own palette. Transparent pixels show the backdrop.
+    pub fn sprite_image<B: Peek>(&self, bus: &B, index: u8) -> DebugImage {
+        let sprite = self.sprites()[index as usize % 64];
+        let height = self.sprite_height();
+        let mut image = DebugImage::new(8, height as usize);
+        for y in 0..height {
+            let row = if sprite.flip_v { height - 1 - y } else { y };
+            let addr = if height == 16 {
+                let table = (sprite.tile as u16 & 1) * 0x1000;
+                table + (sprite.tile as u16 & 0xFE) * 16 + (row & 8) * 2
+            } else {
+                let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 { 0x1000 } else { 0 };
+                table + sprite.tile as u16 * 16
+            };
+            for x in 0..8 {
+                let column = if sprite.flip_h { 7 - x } else { x };
+                let color = Ppu::tile_color(bus, addr, row & 7, column);
+                let index = self.debug_color(4 + sprite.palette, color);
+                image.set_pixel(x as usize, y as usize, index);
+            }
+        }
+        image
+    }
+}
@@ -0,0 +1,417 @@
+// The dot by dot rendering pipeline. Background tiles are fetched two ahead into 16 bit shift
+// registers, eight sprites per line are picked from OAM at the end of the line before and their
+// patterns fetched in dots 257-320. Each visible dot muxes one background and one sprite pixel.
+
+use super::{Ppu, PALETTE_RAM, STATUS_SPRITE_0_HIT, STATUS_SPRITE_OVERFLOW, VISIBLE_SCANLINES};
+use crate::nes::cart::Region;
+use crate::nes::mem::Read;
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+pub(super) const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+pub(super) const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITE_8X16: u8 = 0b0010_0000;
+const MASK_GRAYSCALE: u8 = 0b0000_0001;
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+pub(super) const MASK_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SPRITES: u8 = 0b0001_0000;
+
+const ATTRIBUTE_TABLES: u16 = 0x23C0;
+const SPRITE_PALETTE: u8 = 0x10;
+// the bits sprite_pixel packs above the palette RAM index
+const PIXEL_BEHIND: u8 = 0b0100_0000;
+const PIXEL_SPRITE_0: u8 = 0b1000_0000;
+pub(super) const SPRITE_FLIP_V: u8 = 0b1000_0000;
+pub(super) const SPRITE_FLIP_H: u8 = 0b0100_0000;
+pub(super) const SPRITE_BEHIND: u8 = 0b0010_0000;
+const SPRITES_PER_LINE: usize = 8;
+
+#[derive(Default)]
+pub(super) struct Background {
+    // the tile being fetched
+    pub(super) nametable: u8,
+    pub(super) attribute: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    // the current tile in the high byte, the next one in the low byte
+    shift_pattern_lo: u16,
+    shift_pattern_hi: u16,
+    shift_attribute_lo: u16,
+    shift_attribute_hi: u16,
+}
+
+#[derive(Default)]
+pub(super) struct Sprites {
+    // the sprites found for the next line, 4 OAM bytes each
+    secondary: [u8; SPRITES_PER_LINE * 4],
+    // the line being drawn, patterns already flipped horizontally
+    count: u8,
+    zero_on_line: bool,
+    pattern_lo: [u8; SPRITES_PER_LINE],
+    pattern_hi: [u8; SPRITES_PER_LINE],
+    attribute: [u8; SPRITES_PER_LINE],
+    x: [u8; SPRITES_PER_LINE],
+}
+
+impl Ppu {
+    pub(super) fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    pub(super) fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_8X16 != 0 { 16 } else { 8 }
+    }
+
+    // the work for the dot the PPU just moved to, on the visible and pre-render lines
+    pub(super) fn render_dot<B: Read>(&mut self, bus: &mut B) {
+        let visible = self.scanline < VISIBLE_SCANLINES;
+        if (visible || self.scanline == self.pre_render_scanline()) && self.rendering_enabled() {
+            self.fetch_dot(visible, bus);
+        }
+        if visible && (1..=256).contains(&self.dot) {
+            let x = (self.dot - 1) as u8;
+            let index = if self.rendering_enabled() {
+                let background = self.shifter_pixel();
+                self.compose_pixel(x, background)
+            } else {
+                self.idle_pixel()
+            };
+            self.output_pixel(x, index);
+        }
+    }
+
+    // With rendering off the PPU shows the backdrop, unless v points into palette RAM. Then
+    // the palette entry it addresses goes out instead, which lets programs draw colors by
+    // walking PPUADDR through the palette.
+    pub(super) fn idle_pixel(&self) -> u8 {
+        let addr = self.v & 0x3FFF;
+        if addr >= PALETTE_RAM { (addr & 0x1F) as u8 } else { 0 }
+    }
+
+    fn fetch_dot<B: Read>(&mut self, visible: bool, bus: &mut B) {
+        let dot = self.dot;
+        if let 2..=257 | 321..=337 = dot {
+            self.shift_background();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.bg.nametable = bus.read(0x2000 | (self.v & 0x0FFF));
+                }
+                2 => self.fetch_attribute(bus),
+                4 => self.bg.pattern_lo = bus.read(self.background_pattern_addr()),
+                6 => self.bg.pattern_hi = bus.read(self.background_pattern_addr() + 8),
+                7 => self.increment_coarse_x(),
+                _ => {}
+            }
+        }
+        self.line_events(visible);
+        match dot {
+            258..=320 => self.fetch_sprite(dot, bus),
+            // the two unused nametable fetches at the end of the line
+            338 | 340 => {
+                bus.read(0x2000 | (self.v & 0x0FFF));
+            }
+            _ => {}
+        }
+    }
+
+    // what happens at fixed dots of a rendering line whichever backend draws it: the scroll
+    // updates, sprite evaluation and the OAMADDR side effects
+    pub(super) fn line_events(&mut self, visible: bool) {
+        let dot = self.dot;
+        match dot {
+            256 => self.increment_y(),
+            257 => {
+                // horizontal scroll bits from t
+                self.v = (self.v & !0x041F) | (self.t & 0x041F);
+                self.evaluate_sprites(visible);
+            }
+            _ => {}
+        }
+        if (257..=320).contains(&dot) {
+            self.oam_addr = 0;
+        }
+        if self.scanline == self.pre_render_scanline() && dot == 1 && self.oam_addr >= 8 {
+            // Rendering starting with OAMADDR past the first sprite copies the 8 bytes it's in
+            // over sprites 0 and 1.
+            let row = (self.oam_addr & 0xF8) as usize;
+            self.oam.copy_within(row..row + 8, 0);
+        }
+        if self.scanline == self.pre_render_scanline() && (280..=304).contains(&dot) {
+            // vertical scroll bits from t
+            self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+        }
+    }
+
+    // Puts palette RAM entry `index` at `x` of the current line. Grayscale drops the hue bits
+    // on the way out of palette RAM, emphasis goes along with the color to the palette. The
+    // PAL and Dendy chips have the red and green emphasis bits the other way round, they're
+    // swapped back here so the frame always holds them as red, green, blue.
+    pub(super) fn output_pixel(&mut self, x: u8, index: u8) {
+        let mut color = self.read_palette(PALETTE_RAM | index as u16);
+        if self.mask & MASK_GRAYSCALE != 0 {
+            color &= 0x30;
+        }
+        let mut emphasis = (self.mask >> 5) as u16;
+        if self.region != Region::Ntsc {
+            emphasis = (emphasis & 0b100) | ((emphasis & 1) << 1) | ((emphasis >> 1) & 1);
+        }
+        self.output.set_pixel(x as usize, self.scanline as usize, emphasis << 6 | color as u16);
+    }
+
+    fn shift_background(&mut self) {
+        let bg = &mut self.bg;
+        bg.shift_pattern_lo <<= 1;
+        bg.shift_pattern_hi <<= 1;
+        bg.shift_attribute_lo <<= 1;
+        bg.shift_attribute_hi <<= 1;
+    }
+
+    fn load_background_shifters(&mut self) {
+        let bg = &mut self.bg;
+        bg.shift_pattern_lo = (bg.shift_pattern_lo & 0xFF00) | bg.pattern_lo as u16;
+        bg.shift_pattern_hi = (bg.shift_pattern_hi & 0xFF00) | bg.pattern_hi as u16;
+        let fill = |bit: u8| if bg.attribute & bit != 0 { 0xFF } else { 0x00 };
+        bg.shift_attribute_lo = (bg.shift_attribute_lo & 0xFF00) | fill(0b01);
+        bg.shift_attribute_hi = (bg.shift_attribute_hi & 0xFF00) | fill(0b10);
+    }
+
+    // the 2 bit palette of the tile's 16x16 quadrant of its attribute byte
+    pub(super) fn fetch_attribute<B: Read>(&mut self, bus: &mut B) {
+        let v = self.v;
+        let addr = ATTRIBUTE_TABLES | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let mut attribute = bus.read(addr);
+        if v & 0x40 != 0 {
+            attribute >>= 4;
+        }
+        if v & 0x02 != 0 {
+            attribute >>= 2;
+        }
+        self.bg.attribute = attribute & 0b11;
+    }
+
+    pub(super) fn background_pattern_addr(&self) -> u16 {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 { 0x1000 } else { 0 };
+        table + self.bg.nametable as u16 * 16 + (self.v >> 12)
+    }
+
+    pub(super) fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v = (self.v & !0x001F) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    // fine Y, carrying into coarse Y, which wraps to the next nametable after row 29
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let coarse_y = match (self.v & 0x03E0) >> 5 {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    // Picks the first eight sprites in OAM covering the next line, flagging overflow when
+    // there are more. Nothing is picked on the pre-render line, so no sprites show on line 0.
+    fn evaluate_sprites(&mut self, visible: bool) {
+        let height = self.sprite_height();
+        let sprites = &mut self.sprites;
+        sprites.secondary = [0xFF; SPRITES_PER_LINE * 4];
+        sprites.count = 0;
+        sprites.zero_on_line = false;
+        if !visible {
+            return;
+        }
+        for (i, sprite) in self.oam.chunks_exact(4).enumerate() {
+            let row = self.scanline.wrapping_sub(sprite[0] as u16);
+            if row >= height {
+                continue;
+            }
+            if sprites.count as usize == SPRITES_PER_LINE {
+                self.status |= STATUS_SPRITE_OVERFLOW;
+                break;
+            }
+            let slot = sprites.count as usize * 4;
+            sprites.secondary[slot..slot + 4].copy_from_slice(sprite);
+            sprites.zero_on_line |= i == 0;
+            sprites.count += 1;
+        }
+    }
+
+    // Pattern fetches for the eight slots, two dots apart in each 8 dot group. Empty slots
+    // fetch tile $FF like the real chip does.
+    pub(super) fn fetch_sprite<B: Read>(&mut self, dot: u16, bus: &mut B) {
+        let slot = (dot - 257) as usize / 8;
+        let step = (dot - 257) % 8;
+        if step != 4 && step != 6 {
+            return;
+        }
+        let sprite = &self.sprites.secondary[slot * 4..slot * 4 + 4];
+        let (y, tile, attribute, x) = (sprite[0], sprite[1], sprite[2], sprite[3]);
+        let height = self.sprite_height();
+        let mut row = self.scanline.wrapping_sub(y as u16) % height;
+        if attribute & SPRITE_FLIP_V != 0 {
+            row = height - 1 - row;
+        }
+        let addr = if height == 16 {
+            let table = (tile as u16 & 1) * 0x1000;
+            table + (tile as u16 & 0xFE) * 16 + (row & 8) * 2 + (row & 7)
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 { 0x1000 } else { 0 };
+            table + tile as u16 * 16 + row
+        };
+        let sprites = &mut self.sprites;
+        if step == 4 {
+            let pattern = bus.read(addr);
+            sprites.pattern_lo[slot] = if attribute & SPRITE_FLIP_H != 0 {
+                pattern.reverse_bits()
+            } else {
+                pattern
+            };
+        } else {
+            let pattern = bus.read(addr + 8);
+            sprites.pattern_hi[slot] = if attribute & SPRITE_FLIP_H != 0 {
+                pattern.reverse_bits()
+            } else {
+                pattern
+            };
+            sprites.attribute[slot] = attribute;
+            sprites.x[slot] = x;
+        }
+        if slot >= self.sprites.count as usize {
+            // transparent, whatever the fetch returned
+            self.sprites.pattern_lo[slot] = 0;
+            self.sprites.pattern_hi[slot] = 0;
+        }
+    }
+
+    // What OAMDATA reads while rendering. Clearing secondary OAM reads $FF for the first 64
+    // dots, evaluation then reads OAM at OAMADDR, and the fetches read back secondary OAM.
+    pub(super) fn oam_bus(&self) -> u8 {
+        let secondary = &self.sprites.secondary;
+        match self.dot {
+            1..=64 => 0xFF,
+            65..=256 => self.oam[self.oam_addr as usize],
+            257..=320 => {
+                let step = (self.dot - 257) as usize;
+                secondary[step / 8 * 4 + (step % 8).min(3)]
+            }
+            _ => secondary[0],
+        }
+    }
+
+    // The first opaque sprite pixel at `x` as its palette RAM index, 0 where every sprite is
+    // transparent, with the priority and sprite 0 bits above it.
+    fn sprite_pixel(&self, x: u8) -> u8 {
+        let sprites = &self.sprites;
+        (0..sprites.count as usize)
+            .find_map(|slot| {
+                let offset = x.checked_sub(sprites.x[slot]).filter(|&offset| offset < 8)?;
+                let bit = 7 - offset;
+                let color = ((sprites.pattern_lo[slot] >> bit) & 1)
+                    | (((sprites.pattern_hi[slot] >> bit) & 1) << 1);
+                let attribute = sprites.attribute[slot];
+                let behind = (attribute & SPRITE_BEHIND != 0) as u8 * PIXEL_BEHIND;
+                let zero = (slot == 0 && sprites.zero_on_line) as u8 * PIXEL_SPRITE_0;
+                let pixel = SPRITE_PALETTE | (attribute & 0b11) << 2 | color | behind | zero;
+                (color != 0).then_some(pixel)
+            })
+            .unwrap_or(0)
+    }
+
+    // the background's palette RAM index out of the shifters, 0 when it's transparent
+    fn shifter_pixel(&self) -> u8 {
+        if self.mask & MASK_BACKGROUND == 0 {
+            return 0;
+        }
+        let bit = 15 - self.fine_x;
+        let bg = &self.bg;
+        let color = ((bg.shift_pattern_lo >> bit) & 1) as u8
+            | ((((bg.shift_pattern_hi >> bit) & 1) as u8) << 1);
+        let palette = ((bg.shift_attribute_lo >> bit) & 1) as u8
+            | ((((bg.shift_attribute_hi >> bit) & 1) as u8) << 1);
+        if color == 0 { 0 } else { palette << 2 | color }
+    }
+
+    // The palette RAM index for the pixel at `x` over `background`, setting sprite 0 hit when
+    // an opaque sprite 0 pixel lands on an opaque background pixel. PPUMASK bits 1 and 2 hide
+    // the background and sprites in the left 8 pixels, so a hit can't happen there while
+    // either is clipped, and it never happens at x=255.
+    pub(super) fn compose_pixel(&mut self, x: u8, background: u8) -> u8 {
+        let left = x < 8;
+        let background_shown = !left || self.mask & MASK_BACKGROUND_LEFT != 0;
+        let sprites_shown =
+            self.mask & MASK_SPRITES != 0 && (!left || self.mask & MASK_SPRITES_LEFT != 0);
+        let sprite = if sprites_shown { self.sprite_pixel(x) } else { 0 };
+        let background = background & (background_shown as u8).wrapping_neg();
+        let (index, hit) = mux(background, sprite);
+        self.status |= STATUS_SPRITE_0_HIT & ((hit && x != 255) as u8).wrapping_neg();
+        index
+    }
+}
+
+impl Savestate for Background {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&[self.nametable, self.attribute, self.pattern_lo, self.pattern_hi]);
+        w.write_u16(self.shift_pattern_lo);
+        w.write_u16(self.shift_pattern_hi);
+        w.write_u16(self.shift_attribute_lo);
+        w.write_u16(self.shift_attribute_hi);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut latches = [0u8; 4];
+        r.read_into(&mut latches)?;
+        [self.nametable, self.attribute, self.pattern_lo, self.pattern_hi] = latches;
+        self.shift_pattern_lo = r.read_u16()?;
+        self.shift_pattern_hi = r.read_u16()?;
+        self.shift_attribute_lo = r.read_u16()?;
+        self.shift_attribute_hi = r.read_u16()?;
+        Ok(())
+    }
+}
+
+impl Savestate for Sprites {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.secondary);
+        w.write_u8(self.count);
+        w.write_bool(self.zero_on_line);
+        w.write_bytes(&self.pattern_lo);
+        w.write_bytes(&self.pattern_hi);
+        w.write_bytes(&self.attribute);
+        w.write_bytes(&self.x);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_into(&mut self.secondary)?;
+        self.count = r.read_u8()?.min(SPRITES_PER_LINE as u8);
+        self.zero_on_line = r.read_bool()?;
+        r.read_into(&mut self.pattern_lo)?;
+        r.read_into(&mut self.pattern_hi)?;
+        r.read_into(&mut self.attribute)?;
+        r.read_into(&mut self.x)
+    }
+}
+
+// Picks the background or the sprite pixel, each a palette RAM index that's transparent when its
+// low two bits are 0, the sprite's packed by sprite_pixel. Works on masks rather than branches,
+// which keeps the per-dot cost flat however the priorities fall. Also returns whether the two
+// overlap opaquely with the sprite being sprite 0.
+fn mux(background: u8, sprite: u8) -> (u8, bool) {
+    let background_opaque = background & 0b11 != 0;
+    let sprite_opaque = sprite & 0b11 != 0;
+    let behind = sprite & PIXEL_BEHIND != 0;
+    let show_sprite = ((sprite_opaque & !(behind & background_opaque)) as u8).wrapping_neg();
+    let index = (sprite & 0x1F & show_sprite) | (background & !show_sprite);
+    (index, sprite & PIXEL_SPRITE_0 != 0 && sprite_opaque && background_opaque)
+}
@@ -0,0 +1,132 @@
+// The scanline backend. Rather than stepping the fetch pipeline every dot, it draws visible
+// lines in bulk: at dot 256, or earlier when the CPU touches a PPU register, it catches up on
+// the pixels the dot backend would have drawn by then. Background tiles are fetched when the
+// dot backend would have fetched them, so a write in the middle of a line changes the picture
+// from the right pixel. Only a $2006 write landing halfway through a tile's fetches differs: the
+// dot backend mixes the old and new address into one garbled tile, here the whole tile comes
+// from the new one. Scroll updates, sprite evaluation and the status flags happen on their
+// dots as usual, all eight sprites for the next line are fetched at dot 257.
+
+use super::render::MASK_BACKGROUND;
+use super::{Ppu, PpuBackend, VISIBLE_SCANLINES};
+use crate::nes::frame::WIDTH;
+use crate::nes::mem::Read;
+use crate::nes::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+// the two fetched at the end of the line before, then one every 8 dots up to dot 256
+const TILES_PER_LINE: usize = 34;
+
+pub(super) struct LineState {
+    // the line's background tiles, palette then the two pattern planes
+    tiles: [[u8; 3]; TILES_PER_LINE],
+    fetched: usize,
+    // pixels of the line already drawn
+    drawn: usize,
+}
+
+impl Default for LineState {
+    fn default() -> Self {
+        LineState { tiles: [[0; 3]; TILES_PER_LINE], fetched: 0, drawn: 0 }
+    }
+}
+
+impl Ppu {
+    // the work for the dot the PPU just moved to when drawing in bulk
+    pub(super) fn scanline_dot<B: Read>(&mut self, bus: &mut B) {
+        let visible = self.scanline < VISIBLE_SCANLINES;
+        if visible && self.dot == 256 {
+            self.catch_up(bus);
+        }
+        if self.dot == 336 {
+            self.line = LineState::default();
+        }
+        if !(visible || self.scanline == self.pre_render_scanline()) || !self.rendering_enabled() {
+            return;
+        }
+        self.line_events(visible);
+        match self.dot {
+            // the pattern fetches of dots 257-320, two per sprite slot
+            257 => {
+                for slot in 0..8 {
+                    self.fetch_sprite(257 + slot * 8 + 4, bus);
+                    self.fetch_sprite(257 + slot * 8 + 6, bus);
+                }
+            }
+            // the first two tiles of the next line
+            336 => {
+                self.fetch_tile(bus);
+                self.fetch_tile(bus);
+            }
+            _ => {}
+        }
+    }
+
+    // Draws the current line up to the dot the PPU is at, fetching the tiles the dot backend
+    // would have by now. Called before the CPU reads or writes a register, so a write only
+    // affects the pixels after it.
+    pub(super) fn catch_up<B: Read>(&mut self, bus: &mut B) {
+        if self.backend != PpuBackend::Scanline
+            || self.scanline >= VISIBLE_SCANLINES
+            || !(1..=256).contains(&self.dot)
+        {
+            return;
+        }
+        let due = (2 + self.dot as usize / 8).min(TILES_PER_LINE);
+        if self.rendering_enabled() {
+            while self.line.fetched < due {
+                self.fetch_tile(bus);
+            }
+        } else {
+            // the dot backend doesn't fetch with rendering off either
+            self.line.fetched = self.line.fetched.max(due);
+        }
+        while self.line.drawn < self.dot as usize {
+            let x = self.line.drawn as u8;
+            let index = if self.rendering_enabled() {
+                let background = self.line_pixel(x);
+                self.compose_pixel(x, background)
+            } else {
+                self.idle_pixel()
+            };
+            self.output_pixel(x, index);
+            self.line.drawn += 1;
+        }
+    }
+
+    fn fetch_tile<B: Read>(&mut self, bus: &mut B) {
+        self.bg.nametable = bus.read(0x2000 | (self.v & 0x0FFF));
+        self.fetch_attribute(bus);
+        let addr = self.background_pattern_addr();
+        let tile = [self.bg.attribute << 2, bus.read(addr), bus.read(addr + 8)];
+        self.line.tiles[self.line.fetched] = tile;
+        self.line.fetched += 1;
+        self.increment_coarse_x();
+    }
+
+    // the background's palette RAM index at `x`, 0 where it's transparent
+    fn line_pixel(&self, x: u8) -> u8 {
+        if self.mask & MASK_BACKGROUND == 0 {
+            return 0;
+        }
+        let offset = x as usize + self.fine_x as usize;
+        let [palette, pattern_lo, pattern_hi] = self.line.tiles[offset / 8];
+        let shift = 7 - offset % 8;
+        let color = ((pattern_lo >> shift) & 1) | (((pattern_hi >> shift) & 1) << 1);
+        if color == 0 { 0 } else { palette | color }
+    }
+}
+
+impl Savestate for LineState {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(self.tiles.as_flattened());
+        w.write_u8(self.fetched as u8);
+        w.write_u16(self.drawn as u16);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_into(self.tiles.as_flattened_mut())?;
+        self.fetched = (r.read_u8()? as usize).min(TILES_PER_LINE);
+        self.drawn = (r.read_u16()? as usize).min(WIDTH);
+        Ok(())
+    }
+}
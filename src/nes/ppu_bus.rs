@@ -0,0 +1,182 @@
+// The PPU's 14 bit address space. Pattern tables at $0000-$1FFF come from the cart through its
+// mapper. Nametables at $2000-$2FFF, mirrored up to $3FFF, live in the console's 2 KiB CIRAM,
+// and the cart's current mirroring decides which 1 KiB page each of the four nametables uses.
+// That is looked up on every access, so boards can switch it at any time. Palette RAM is
+// inside the PPU, this sees the nametables under $3F00-$3FFF like the real bus does.
+//
+// Every access also goes past the A12 filter, which tells the mapper when A12 rises after a
+// long enough low stretch. That's what MMC3 style scanline counters clock on.
+
+use super::cart::{Cart, Mirroring};
+use super::mapper::Mapper;
+use super::mem::{Memory, Peek, Read, Write};
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const PATTERN_TABLES_END: u16 = 0x1FFF;
+const NAMETABLES: u16 = 0x2000;
+const NAMETABLE_SIZE: usize = 0x0400;
+const A12: u16 = 0x1000;
+// PPU dots A12 has to stay low before a rise counts, about three CPU cycles. The dips between
+// sprite pattern fetches are shorter, so a line with sprites at $1000 clocks once.
+const A12_FILTER_DOTS: u64 = 10;
+
+#[derive(Default)]
+pub struct A12Filter {
+    // PPU dots since power on
+    dot: u64,
+    high: bool,
+    low_since: u64,
+}
+
+impl A12Filter {
+    pub fn tick(&mut self) {
+        self.dot += 1;
+    }
+
+    // true when putting `addr` on the bus is a rise the mapper should see
+    pub fn watch(&mut self, addr: u16) -> bool {
+        let high = addr & A12 != 0;
+        let rise = high && !self.high && self.dot - self.low_since >= A12_FILTER_DOTS;
+        if !high && self.high {
+            self.low_since = self.dot;
+        }
+        self.high = high;
+        rise
+    }
+}
+
+impl Savestate for A12Filter {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u64(self.dot);
+        w.write_bool(self.high);
+        w.write_u64(self.low_since);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.dot = r.read_u64()?;
+        self.high = r.read_bool()?;
+        self.low_since = r.read_u64()?.min(self.dot);
+        Ok(())
+    }
+}
+
+// where a nametable byte lives
+enum Nametable {
+    Ciram(usize),
+    // RAM on four-screen boards for the third and fourth nametables
+    Cart(usize),
+}
+
+pub struct PpuBus<'a> {
+    ciram: &'a mut Memory<[u8; 2048]>,
+    board: Option<(&'a mut Cart, &'a mut dyn Mapper)>,
+    a12: &'a mut A12Filter,
+}
+
+impl<'a> PpuBus<'a> {
+    pub fn new(
+        ciram: &'a mut Memory<[u8; 2048]>,
+        cart: Option<&'a mut Cart>,
+        mapper: Option<&'a mut dyn Mapper>,
+        a12: &'a mut A12Filter,
+    ) -> Self {
+        PpuBus {
+            ciram,
+            board: cart.zip(mapper),
+            a12,
+        }
+    }
+
+    // `addr` is on the address bus, from an access or from the PPU leaving v there
+    pub fn watch_a12(&mut self, addr: u16) {
+        if self.a12.watch(addr)
+            && let Some((_, mapper)) = &mut self.board
+        {
+            mapper.a12_rising_edge();
+        }
+    }
+
+    // boards picking a page themselves come first, then the cart's mirroring
+    fn locate(&self, addr: u16) -> Nametable {
+        let offset = addr as usize % NAMETABLE_SIZE;
+        let Some((cart, mapper)) = &self.board else {
+            let page = Mirroring::Vertical.ciram_page(addr) as usize;
+            return Nametable::Ciram(page * NAMETABLE_SIZE + offset);
+        };
+        if let Some(page) = mapper.ciram_page(cart, addr) {
+            return Nametable::Ciram((page & 1) as usize * NAMETABLE_SIZE + offset);
+        }
+        let mirroring = cart.screen_mirroring;
+        let quadrant = (addr as usize >> 10) & 0b11;
+        if mirroring == Mirroring::FourScreen && quadrant >= 2 && !cart.vram.is_empty() {
+            return Nametable::Cart((quadrant - 2) * NAMETABLE_SIZE + offset);
+        }
+        Nametable::Ciram(mirroring.ciram_page(addr) as usize * NAMETABLE_SIZE + offset)
+    }
+
+    fn nametable_peek(&self, addr: u16) -> u8 {
+        match self.locate(addr) {
+            Nametable::Ciram(index) => self.ciram.data()[index],
+            Nametable::Cart(index) => {
+                self.board.as_ref().map_or(0, |(cart, _)| cart.vram.data()[index])
+            }
+        }
+    }
+}
+
+impl Peek for PpuBus<'_> {
+    // boards that drive nametable data themselves aren't asked, peeks see CIRAM
+    fn peek(&self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        if addr <= PATTERN_TABLES_END {
+            return self.board.as_ref().map_or(0, |(cart, mapper)| mapper.ppu_peek(cart, addr));
+        }
+        self.nametable_peek(NAMETABLES | (addr & 0x0FFF))
+    }
+}
+
+impl Read for PpuBus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        self.watch_a12(addr);
+        if let Some((cart, mapper)) = &mut self.board {
+            if addr <= PATTERN_TABLES_END {
+                return mapper.ppu_read(cart, addr);
+            }
+            let addr = NAMETABLES | (addr & 0x0FFF);
+            if let Some(data) = mapper.nametable_read(cart, addr) {
+                return data;
+            }
+        } else if addr <= PATTERN_TABLES_END {
+            return 0;
+        }
+        self.nametable_peek(NAMETABLES | (addr & 0x0FFF))
+    }
+}
+
+impl Write for PpuBus<'_> {
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr & 0x3FFF;
+        self.watch_a12(addr);
+        let nametable = NAMETABLES | (addr & 0x0FFF);
+        if let Some((cart, mapper)) = &mut self.board {
+            if addr <= PATTERN_TABLES_END {
+                mapper.ppu_write(cart, addr, data);
+                return;
+            }
+            if mapper.nametable_write(cart, nametable, data) {
+                return;
+            }
+        } else if addr <= PATTERN_TABLES_END {
+            return;
+        }
+        match self.locate(nametable) {
+            Nametable::Ciram(index) => self.ciram.data_mut()[index] = data,
+            Nametable::Cart(index) => {
+                if let Some((cart, _)) = &mut self.board {
+                    cart.vram.data_mut()[index] = data;
+                }
+            }
+        }
+    }
+}
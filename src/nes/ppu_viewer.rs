@@ -0,0 +1,195 @@
+// Debug windows next to the game's: the pattern tables, the four nametables with the part the
+// next frame scrolls to outlined, palette RAM and the 64 sprites in OAM. They're drawn from the
+// PPU's debug views after every frame that's shown. Clicking the pattern tables steps through
+// the eight palettes they're colored with; closing a window only closes that one.
+
+use super::bus::Bus;
+use super::palette::Palette;
+use super::ppu::{
+    DebugImage, ScrollRect, NAMETABLES_HEIGHT, NAMETABLES_WIDTH, PATTERN_TABLES_HEIGHT,
+    PATTERN_TABLES_WIDTH,
+};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use sdl2::VideoSubsystem;
+
+// the sprites are laid out 8 to a row
+const SPRITES_PER_ROW: usize = 8;
+const SCROLL_COLOR: Color = Color::RGB(0xFF, 0x30, 0x30);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum View {
+    PatternTables,
+    Nametables,
+    Palettes,
+    Sprites,
+}
+
+impl View {
+    const ALL: [View; 4] = [View::PatternTables, View::Nametables, View::Palettes, View::Sprites];
+
+    fn title(self) -> &'static str {
+        match self {
+            View::PatternTables => "Pattern tables",
+            View::Nametables => "Nametables",
+            View::Palettes => "Palettes",
+            View::Sprites => "Sprites",
+        }
+    }
+
+    // the window's size, a multiple of the image's so pixels stay square
+    fn window_size(self) -> (u32, u32) {
+        match self {
+            View::PatternTables => {
+                (PATTERN_TABLES_WIDTH as u32 * 2, PATTERN_TABLES_HEIGHT as u32 * 2)
+            }
+            View::Nametables => (NAMETABLES_WIDTH as u32, NAMETABLES_HEIGHT as u32),
+            View::Palettes => (4 * 32, 8 * 32),
+            View::Sprites => (SPRITES_PER_ROW as u32 * 8 * 3, 8 * 16 * 3),
+        }
+    }
+}
+
+struct ViewerWindow {
+    view: View,
+    canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+}
+
+impl ViewerWindow {
+    fn open(video: &VideoSubsystem, view: View) -> Result<Self, String> {
+        let (width, height) = view.window_size();
+        let window = video
+            .window(&format!("nestacean - {}", view.title()), width, height)
+            .build()
+            .map_err(|err| err.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+        let texture_creator = canvas.texture_creator();
+        Ok(ViewerWindow { view, canvas, texture_creator })
+    }
+
+    fn show(&mut self, image: &DebugImage, palette: &Palette, outline: &[Rect]) {
+        let size = (image.width() as u32, image.height() as u32);
+        self.show_rgba(size, &image.to_rgba(palette), outline);
+    }
+
+    // RGBA pixels stretched over the window, and `outline` drawn on top in image pixels
+    fn show_rgba(&mut self, (width, height): (u32, u32), rgba: &[u8], outline: &[Rect]) {
+        let mut texture = self
+            .texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, width, height)
+            .unwrap();
+        texture.update(None, rgba, width as usize * 4).unwrap();
+        self.canvas.set_logical_size(width, height).unwrap();
+        self.canvas.copy(&texture, None, None).unwrap();
+        self.canvas.set_draw_color(SCROLL_COLOR);
+        self.canvas.draw_rects(outline).unwrap();
+        self.canvas.present();
+    }
+}
+
+pub struct PpuViewer {
+    windows: Vec<ViewerWindow>,
+    // 0-7 for the pattern tables, the background palettes then the sprite ones
+    pattern_palette: u8,
+}
+
+impl PpuViewer {
+    // one window for each view
+    pub fn open(video: &VideoSubsystem) -> Result<Self, String> {
+        let windows = View::ALL
+            .into_iter()
+            .map(|view| ViewerWindow::open(video, view))
+            .collect::<Result<_, _>>()?;
+        Ok(PpuViewer { windows, pattern_palette: 0 })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    // Handles closing and clicking the viewer's windows, true if `event` was one of those.
+    // Keys pressed with a viewer window focused are left for the game.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let Some(window_id) = event.get_window_id() else {
+            return false;
+        };
+        let Some(index) = self.windows.iter().position(|w| w.canvas.window().id() == window_id)
+        else {
+            return false;
+        };
+        match event {
+            Event::Window { win_event: WindowEvent::Close, .. } => {
+                self.windows.remove(index);
+                true
+            }
+            Event::MouseButtonDown { .. } => {
+                if self.windows[index].view == View::PatternTables {
+                    self.pattern_palette = (self.pattern_palette + 1) % 8;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn update(&mut self, bus: &mut Bus, palette: &Palette) {
+        let (ppu, ppu_bus) = bus.ppu_view();
+        for window in &mut self.windows {
+            match window.view {
+                View::PatternTables => {
+                    let image = ppu.pattern_tables_image(&ppu_bus, self.pattern_palette);
+                    window.show(&image, palette, &[]);
+                }
+                View::Nametables => {
+                    let size = (NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+                    let outline = wrapped_rects(ppu.scroll_rect(), size);
+                    window.show(&ppu.nametables_image(&ppu_bus), palette, &outline);
+                }
+                View::Palettes => window.show(&ppu.palettes_image(), palette, &[]),
+                View::Sprites => {
+                    let sprites: Vec<DebugImage> =
+                        (0..64).map(|index| ppu.sprite_image(&ppu_bus, index)).collect();
+                    let (size, rgba) = sprite_sheet(&sprites, palette);
+                    window.show_rgba(size, &rgba, &[]);
+                }
+            }
+        }
+    }
+}
+
+// The rectangle at x, y split where it runs off the right or bottom edge of a `size` area that
+// wraps around, up to four pieces.
+fn wrapped_rects(scroll: ScrollRect, size: (usize, usize)) -> Vec<Rect> {
+    let ScrollRect { x, y, width, height } = scroll;
+    let (x, y) = (x % size.0, y % size.1);
+    let columns = [(x, width.min(size.0 - x)), (0, width - width.min(size.0 - x))];
+    let rows = [(y, height.min(size.1 - y)), (0, height - height.min(size.1 - y))];
+    let mut rects = Vec::new();
+    for (left, w) in columns.into_iter().filter(|&(_, w)| w > 0) {
+        for (top, h) in rows.into_iter().filter(|&(_, h)| h > 0) {
+            rects.push(Rect::new(left as i32, top as i32, w as u32, h as u32));
+        }
+    }
+    rects
+}
+
+// the sprites in OAM order, left to right and top to bottom, as RGBA pixels
+fn sprite_sheet(sprites: &[DebugImage], palette: &Palette) -> ((u32, u32), Vec<u8>) {
+    let (width, height) = (sprites[0].width(), sprites[0].height());
+    let sheet_width = width * SPRITES_PER_ROW;
+    let sheet_height = height * sprites.len().div_ceil(SPRITES_PER_ROW);
+    let mut rgba = vec![0u8; sheet_width * sheet_height * 4];
+    for (index, sprite) in sprites.iter().enumerate() {
+        let left = index % SPRITES_PER_ROW * width;
+        let top = index / SPRITES_PER_ROW * height;
+        for (row, pixels) in sprite.to_rgba(palette).chunks_exact(width * 4).enumerate() {
+            let start = ((top + row) * sheet_width + left) * 4;
+            rgba[start..start + width * 4].copy_from_slice(pixels);
+        }
+    }
+    ((sheet_width as u32, sheet_height as u32), rgba)
+}
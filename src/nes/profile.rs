@@ -0,0 +1,96 @@
+// Per-opcode execution and cycle counters, see Cpu::enable_profiling
+use super::opcodes::opcode_info;
+use std::fmt;
+
+pub struct Profiler {
+    executions: [u64; 256],
+    cycles: [u64; 256],
+    // opcode the current cycles belong to, None while an interrupt sequence runs
+    current: Option<u8>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            executions: [0; 256],
+            cycles: [0; 256],
+            current: None,
+        }
+    }
+
+    // counts the opcode fetch cycle too
+    pub fn begin_instruction(&mut self, opcode: u8) {
+        self.executions[opcode as usize] += 1;
+        self.cycles[opcode as usize] += 1;
+        self.current = Some(opcode);
+    }
+
+    pub fn begin_interrupt(&mut self) {
+        self.current = None;
+    }
+
+    pub fn count_cycle(&mut self) {
+        if let Some(opcode) = self.current {
+            self.cycles[opcode as usize] += 1;
+        }
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let mut entries: Vec<ProfileEntry> = (0..=0xFFu8)
+            .filter(|&opcode| self.executions[opcode as usize] > 0)
+            .map(|opcode| ProfileEntry {
+                opcode,
+                mnemonic: opcode_info(opcode).mnemonic.name(),
+                executions: self.executions[opcode as usize],
+                cycles: self.cycles[opcode as usize],
+            })
+            .collect();
+        entries.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.opcode.cmp(&b.opcode)));
+        ProfileReport { entries }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileEntry {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+// executed opcodes, hottest (most cycles) first
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfileReport {
+    pub entries: Vec<ProfileEntry>,
+}
+
+impl ProfileReport {
+    pub fn total_cycles(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.cycles).sum()
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total_cycles().max(1);
+        writeln!(f, "OP  MNEMONIC  EXECUTIONS      CYCLES      %")?;
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:02X}  {:<8}  {:>10}  {:>10}  {:>5.1}",
+                entry.opcode,
+                entry.mnemonic,
+                entry.executions,
+                entry.cycles,
+                entry.cycles as f64 * 100.0 / total as f64
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+// Known dumps, identified by the CRC32 of their PRG and CHR ROM (header and trainer excluded,
+// so re-headered copies of the same dump still match). An entry names the game and can
+// override header fields that are often wrong in old dumps.
+//
+// The database is a text file, one dump per line with tab separated fields:
+//   crc32  sha1  mapper  mirroring  region  title
+// crc32 and sha1 are hex, sha1 may be `-` to match on the CRC alone, mapper is decimal,
+// mirroring one of V, H or 4 and region one of N (NTSC), P (PAL) or D (Dendy). The last
+// three can be `-` to keep what the header says.
+// Blank lines and lines starting with # are skipped.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use super::cart::{Mirroring, Region};
+
+const EMBEDDED: &str = include_str!("romdb.txt");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub sha1: Option<[u8; 20]>,
+    pub mapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub region: Option<Region>,
+}
+
+#[derive(Default)]
+pub struct RomDb {
+    entries: HashMap<u32, RomInfo>,
+}
+
+// the database compiled into the binary
+pub fn embedded() -> &'static RomDb {
+    static DB: OnceLock<RomDb> = OnceLock::new();
+    DB.get_or_init(|| RomDb::parse(EMBEDDED).expect("embedded ROM database is malformed"))
+}
+
+fn parse_sha1(field: &str) -> Option<[u8; 20]> {
+    if field.len() != 40 {
+        return None;
+    }
+    let mut sha1 = [0u8; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(field.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(sha1)
+}
+
+impl RomDb {
+    pub fn parse(text: &str) -> Result<RomDb, String> {
+        let mut entries = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = |what: &str| format!("ROM database line {}: {}", number + 1, what);
+
+            let fields: Vec<&str> = line.splitn(6, '\t').collect();
+            let [crc32, sha1, mapper, mirroring, region, title] = fields[..] else {
+                return Err(bad_line("expected 6 tab separated fields"));
+            };
+            let crc32 = u32::from_str_radix(crc32, 16).map_err(|_| bad_line("bad crc32"))?;
+            let sha1 = match sha1 {
+                "-" => None,
+                sha1 => Some(parse_sha1(sha1).ok_or_else(|| bad_line("bad sha1"))?),
+            };
+            let mapper = match mapper {
+                "-" => None,
+                mapper => Some(mapper.parse().map_err(|_| bad_line("bad mapper"))?),
+            };
+            let mirroring = match mirroring {
+                "-" => None,
+                "V" => Some(Mirroring::Vertical),
+                "H" => Some(Mirroring::Horizontal),
+                "4" => Some(Mirroring::FourScreen),
+                _ => return Err(bad_line("bad mirroring")),
+            };
+            let region = match region {
+                "-" => None,
+                "N" => Some(Region::Ntsc),
+                "P" => Some(Region::Pal),
+                "D" => Some(Region::Dendy),
+                _ => return Err(bad_line("bad region")),
+            };
+            let info = RomInfo {
+                title: title.trim().to_string(),
+                sha1,
+                mapper,
+                mirroring,
+                region,
+            };
+            entries.insert(crc32, info);
+        }
+        Ok(RomDb { entries })
+    }
+
+    // the entry for a dump, when the CRC matches and the SHA-1 does too if the entry has one
+    pub fn lookup(&self, crc32: u32, sha1: &[u8; 20]) -> Option<&RomInfo> {
+        self.entries
+            .get(&crc32)
+            .filter(|info| info.sha1.is_none_or(|known| &known == sha1))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
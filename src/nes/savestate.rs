@@ -0,0 +1,202 @@
+// Binary savestates. A state starts with a magic tag and a format version, followed by each
+// component's fields in a fixed order, little endian. Only states of the current version load;
+// a change to the layout bumps it. The reader and writer also work without the header, for
+// files like movies that have their own.
+
+use super::bus::Bus;
+use super::cpu::Cpu;
+use thiserror::Error;
+
+pub const MAGIC: [u8; 4] = *b"NSST";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("Could not read savestate file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not a savestate")]
+    BadMagic,
+    #[error("Savestate version {0} is not supported")]
+    UnsupportedVersion(u16),
+    #[error("Savestate is truncated")]
+    Truncated,
+    #[error("Savestate has trailing data")]
+    TrailingData,
+    #[error("Savestate block is {found} bytes, expected {expected}")]
+    BlockSize { expected: usize, found: usize },
+    #[error("Savestate is for mapper {state}, the cart uses mapper {cart}")]
+    WrongMapper { state: u8, cart: u8 },
+    // taken with something else in the machine, a cart or an input device
+    #[error("Savestate doesn't match the {0}")]
+    Mismatch(&'static str),
+    #[error("Savestate is corrupt: {0}")]
+    Corrupt(&'static str),
+}
+
+pub trait Savestate {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    // starts a state with the header already written
+    pub fn new() -> Self {
+        let mut w = StateWriter { buf: Vec::new() };
+        w.write_bytes(&MAGIC);
+        w.write_u16(VERSION);
+        w
+    }
+
+    // for a format with a header of its own
+    pub fn without_header() -> Self {
+        StateWriter { buf: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // fixed size data, the reader has to know the length
+    pub fn write_bytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    // variable size data, prefixed with its length
+    pub fn write_block(&mut self, data: &[u8]) {
+        self.write_u64(data.len() as u64);
+        self.write_bytes(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    // checks the header, states from other builds are refused
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        let mut r = StateReader { data, pos: 0 };
+        if r.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.read_u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(r)
+    }
+
+    // for a format with a header of its own, which the caller checks
+    pub fn without_header(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(StateError::Truncated)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    // fills `dest` with fixed size data written with write_bytes
+    pub fn read_into(&mut self, dest: &mut [u8]) -> Result<(), StateError> {
+        dest.copy_from_slice(self.read_bytes(dest.len())?);
+        Ok(())
+    }
+
+    // reads a block written with write_block into `dest`, which must be the same size
+    pub fn read_block_into(&mut self, dest: &mut [u8]) -> Result<(), StateError> {
+        let len = self.read_u64()? as usize;
+        if len != dest.len() {
+            return Err(StateError::BlockSize { expected: dest.len(), found: len });
+        }
+        self.read_into(dest)
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+// The whole machine, the CPU and everything on its bus, and the count of frames run so far.
+// This is what NES::save_state saves and what a movie starting from a savestate holds.
+pub fn save_machine(frame: u64, cpu: &Cpu<Bus>) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    w.write_u64(frame);
+    cpu.save_state(&mut w);
+    Savestate::save_state(cpu.bus(), &mut w);
+    w.finish()
+}
+
+// Loads a state from save_machine and returns its frame count. On error the machine may be
+// partially restored and should be reset or reloaded.
+pub fn load_machine(cpu: &mut Cpu<Bus>, data: &[u8]) -> Result<u64, StateError> {
+    let mut r = StateReader::new(data)?;
+    let frame = r.read_u64()?;
+    cpu.load_state(&mut r)?;
+    Savestate::load_state(cpu.bus_mut(), &mut r)?;
+    if !r.is_at_end() {
+        return Err(StateError::TrailingData);
+    }
+    Ok(frame)
+}
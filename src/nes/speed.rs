@@ -0,0 +1,95 @@
+// How fast frames are run. Normally one frame takes as long as it does on the console, 1/60.0988
+// of a second on NTSC; a frame that runs late starts the schedule over rather than rushing the
+// next ones to catch up. Fast-forward shortens the wait by a speed factor, or drops it with no
+// cap, and can skip showing some of the frames so presenting them doesn't hold it back.
+
+use std::time::{Duration, Instant};
+
+// times normal speed, 0 for as fast as the machine goes
+pub const DEFAULT_FAST_FORWARD_SPEED: f32 = 4.0;
+// frames run but not shown for every one that is
+pub const DEFAULT_FRAME_SKIP: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FastForward {
+    pub speed: f32,
+    pub frame_skip: u8,
+}
+
+impl Default for FastForward {
+    fn default() -> Self {
+        FastForward { speed: DEFAULT_FAST_FORWARD_SPEED, frame_skip: DEFAULT_FRAME_SKIP }
+    }
+}
+
+pub struct FrameLimiter {
+    frame_time: Duration,
+    // when the next frame is due
+    next_frame: Instant,
+    fast_forward: FastForward,
+    fast_forwarding: bool,
+    // frames not shown since the last one that was
+    skipped: u8,
+}
+
+impl FrameLimiter {
+    // `frame_rate` in frames a second, Region::frame_rate
+    pub fn new(frame_rate: f64) -> Self {
+        FrameLimiter {
+            frame_time: Duration::from_secs_f64(1.0 / frame_rate),
+            next_frame: Instant::now(),
+            fast_forward: FastForward::default(),
+            fast_forwarding: false,
+            skipped: 0,
+        }
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: FastForward) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn fast_forward(&self) -> FastForward {
+        self.fast_forward
+    }
+
+    pub fn set_fast_forwarding(&mut self, on: bool) {
+        self.fast_forwarding = on;
+        self.skipped = 0;
+    }
+
+    pub fn fast_forwarding(&self) -> bool {
+        self.fast_forwarding
+    }
+
+    // Whether the frame just run should be shown. Always, unless fast-forwarding, where only
+    // one in every frame_skip + 1 is.
+    pub fn present(&mut self) -> bool {
+        if !self.fast_forwarding || self.skipped >= self.fast_forward.frame_skip {
+            self.skipped = 0;
+            return true;
+        }
+        self.skipped += 1;
+        false
+    }
+
+    // How long to wait at `now`, after a frame, before running the next one. Nothing when the
+    // next frame is already due.
+    pub fn wait(&mut self, now: Instant) -> Option<Duration> {
+        let frame_time = match (self.fast_forwarding, self.fast_forward.speed) {
+            (false, _) => self.frame_time,
+            (true, speed) if speed > 0.0 => self.frame_time.div_f32(speed),
+            (true, _) => Duration::ZERO,
+        };
+        self.next_frame += frame_time;
+        let wait = self.next_frame.checked_duration_since(now).filter(|wait| !wait.is_zero());
+        if wait.is_none() {
+            self.next_frame = now;
+        }
+        wait
+    }
+
+    // starts the schedule over from `now`, after emulation stood still for a while
+    pub fn restart(&mut self, now: Instant) {
+        self.next_frame = now;
+    }
+}
@@ -0,0 +1,96 @@
+use super::cpu::MemAccess;
+use super::mem::{Peek, Read, Write};
+
+// Flat 64 KiB of RAM for running the CPU on its own. With the access log on, every read and
+// write is recorded in order so tests can check exact bus traffic.
+pub struct TestBus {
+    memory: Box<[u8; 0x10000]>,
+    log: Option<Vec<BusAccess>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub access: MemAccess,
+}
+
+impl Default for TestBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestBus {
+    pub fn new() -> Self {
+        TestBus {
+            memory: Box::new([0u8; 0x10000]),
+            log: None,
+        }
+    }
+
+    pub fn with_access_log() -> Self {
+        TestBus {
+            log: Some(Vec::new()),
+            ..TestBus::new()
+        }
+    }
+
+    pub fn memory(&self) -> &[u8; 0x10000] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8; 0x10000] {
+        &mut self.memory
+    }
+
+    // empty when the log is off
+    pub fn access_log(&self) -> &[BusAccess] {
+        self.log.as_deref().unwrap_or(&[])
+    }
+
+    pub fn reads(&self) -> Vec<u16> {
+        self.access_log()
+            .iter()
+            .filter(|entry| entry.access == MemAccess::Read)
+            .map(|entry| entry.addr)
+            .collect()
+    }
+
+    pub fn clear_access_log(&mut self) {
+        if let Some(log) = self.log.as_mut() {
+            log.clear();
+        }
+    }
+
+    fn record(&mut self, addr: u16, value: u8, access: MemAccess) {
+        if let Some(log) = self.log.as_mut() {
+            log.push(BusAccess {
+                addr,
+                value,
+                access,
+            });
+        }
+    }
+}
+
+impl Read for TestBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.memory[addr as usize];
+        self.record(addr, value, MemAccess::Read);
+        value
+    }
+}
+
+impl Peek for TestBus {
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+}
+
+impl Write for TestBus {
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+        self.record(addr, data, MemAccess::Write);
+    }
+}
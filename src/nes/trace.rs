@@ -0,0 +1,225 @@
+// Instruction trace, nestest.log lines look like:
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+use super::cpu::Cpu;
+use super::mem::{Peek, Read, Write};
+use super::opcodes::{opcode_info, AddressingMode};
+
+// PPU dot clock runs 3x the CPU clock, 341 dots per scanline and 262 scanlines per frame
+const PPU_DOTS_PER_SCANLINE: u64 = 341;
+const PPU_SCANLINES_PER_FRAME: u64 = 262;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceFormat {
+    // nestest.log / Mesen "nestest" layout
+    Nestest,
+    // FCEUX trace logger layout, flags spelled out as NVUBDIZC
+    Fceux,
+}
+
+// CPU state at an instruction boundary, before the instruction runs
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub disassembly: String,
+    pub official: bool,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub scanline: u64,
+    pub dot: u64,
+    pub cycles: u64,
+}
+
+impl TraceEntry {
+    pub fn format(&self, format: &TraceFormat) -> String {
+        match format {
+            TraceFormat::Nestest => self.nestest(),
+            TraceFormat::Fceux => self.fceux(),
+        }
+    }
+
+    pub fn nestest(&self) -> String {
+        format!(
+            "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            self.pc,
+            self.hex_bytes(),
+            if self.official { ' ' } else { '*' },
+            self.disassembly,
+            self.a,
+            self.x,
+            self.y,
+            self.p,
+            self.sp,
+            self.scanline,
+            self.dot,
+            self.cycles
+        )
+    }
+
+    pub fn fceux(&self) -> String {
+        let flags: String = "NVUBDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, name)| {
+                if self.p & (0x80 >> i) != 0 {
+                    name
+                } else {
+                    name.to_ascii_lowercase()
+                }
+            })
+            .collect();
+        format!(
+            "${:04X}:{:<9} {:<32}A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}",
+            self.pc,
+            self.hex_bytes(),
+            self.disassembly,
+            self.a,
+            self.x,
+            self.y,
+            self.sp,
+            flags
+        )
+    }
+
+    fn hex_bytes(&self) -> String {
+        let hex: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        hex.join(" ")
+    }
+}
+
+// Receives one entry per executed instruction, see Cpu::set_trace_sink
+pub trait TraceSink {
+    fn trace(&mut self, entry: &TraceEntry);
+}
+
+impl<F: FnMut(&TraceEntry)> TraceSink for F {
+    fn trace(&mut self, entry: &TraceEntry) {
+        self(entry)
+    }
+}
+
+// Formats the instruction at PC together with the register state before it runs.
+// Must be called on an instruction boundary.
+pub fn trace_line<B: Read + Write + Peek>(cpu: &Cpu<B>) -> String {
+    trace_entry(cpu).nestest()
+}
+
+pub fn trace_entry<B: Read + Write + Peek>(cpu: &Cpu<B>) -> TraceEntry {
+    let cycles = cpu.get_cycles();
+    entry_at(cpu, cycles)
+}
+
+// cycles is passed in because the CPU has already counted the fetch cycle when it traces
+pub(crate) fn entry_at<B: Read + Write + Peek>(cpu: &Cpu<B>, cycles: u64) -> TraceEntry {
+    let pc = cpu.get_pc();
+    let opcode = cpu.mem_peek(pc);
+    let info = opcode_info(opcode);
+
+    let mut bytes = vec![opcode];
+    for i in 1..info.len as u16 {
+        bytes.push(cpu.mem_peek(pc.wrapping_add(i)));
+    }
+
+    let disassembly = format!(
+        "{} {}",
+        info.mnemonic.name(),
+        disassemble_operand(cpu, opcode, info.mode, &bytes)
+    );
+    let dots = cycles * 3;
+
+    TraceEntry {
+        pc,
+        disassembly: disassembly.trim_end().to_string(),
+        bytes,
+        official: info.official,
+        a: cpu.get_accumulator(),
+        x: cpu.get_index_x(),
+        y: cpu.get_index_y(),
+        p: cpu.get_status_p(),
+        sp: cpu.get_sp(),
+        scanline: (dots / PPU_DOTS_PER_SCANLINE) % PPU_SCANLINES_PER_FRAME,
+        dot: dots % PPU_DOTS_PER_SCANLINE,
+        cycles,
+    }
+}
+
+fn disassemble_operand<B: Read + Write + Peek>(
+    cpu: &Cpu<B>,
+    opcode: u8,
+    mode: AddressingMode,
+    bytes: &[u8],
+) -> String {
+    let pc = cpu.get_pc();
+    let x = cpu.get_index_x();
+    let y = cpu.get_index_y();
+    match mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", bytes[1]),
+        AddressingMode::ZeroPage => {
+            let value = cpu.mem_peek(bytes[1] as u16);
+            format!("${:02X} = {:02X}", bytes[1], value)
+        }
+        AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+            let (index, name) = if let AddressingMode::ZeroPageX = mode { (x, 'X') } else { (y, 'Y') };
+            let address = bytes[1].wrapping_add(index);
+            let value = cpu.mem_peek(address as u16);
+            format!("${:02X},{} @ {:02X} = {:02X}", bytes[1], name, address, value)
+        }
+        AddressingMode::Absolute => {
+            let address = u16::from_le_bytes([bytes[1], bytes[2]]);
+            // JMP and JSR only show where they go
+            if opcode == 0x4C || opcode == 0x20 {
+                format!("${:04X}", address)
+            } else {
+                let value = cpu.mem_peek(address);
+                format!("${:04X} = {:02X}", address, value)
+            }
+        }
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+            let (index, name) = if let AddressingMode::AbsoluteX = mode { (x, 'X') } else { (y, 'Y') };
+            let base = u16::from_le_bytes([bytes[1], bytes[2]]);
+            let address = base.wrapping_add(index as u16);
+            let value = cpu.mem_peek(address);
+            format!("${:04X},{} @ {:04X} = {:02X}", base, name, address, value)
+        }
+        AddressingMode::Indirect => {
+            let pointer = u16::from_le_bytes([bytes[1], bytes[2]]);
+            // same page wrap bug as the real JMP ($xxFF)
+            let high_pointer = (pointer & 0xFF00) | (pointer as u8).wrapping_add(1) as u16;
+            let target = u16::from_le_bytes([cpu.mem_peek(pointer), cpu.mem_peek(high_pointer)]);
+            format!("(${:04X}) = {:04X}", pointer, target)
+        }
+        AddressingMode::IndexedIndirect => {
+            let pointer = bytes[1].wrapping_add(x);
+            let address = read_zero_page_u16(cpu, pointer);
+            let value = cpu.mem_peek(address);
+            format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                bytes[1], pointer, address, value
+            )
+        }
+        AddressingMode::IndirectIndexed => {
+            let base = read_zero_page_u16(cpu, bytes[1]);
+            let address = base.wrapping_add(y as u16);
+            let value = cpu.mem_peek(address);
+            format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                bytes[1], base, address, value
+            )
+        }
+        AddressingMode::Relative => {
+            let target = pc.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16);
+            format!("${:04X}", target)
+        }
+    }
+}
+
+fn read_zero_page_u16<B: Read + Write + Peek>(cpu: &Cpu<B>, pointer: u8) -> u16 {
+    let low = cpu.mem_peek(pointer as u16);
+    let high = cpu.mem_peek(pointer.wrapping_add(1) as u16);
+    u16::from_le_bytes([low, high])
+}
@@ -0,0 +1,165 @@
+// Recording audio to WAV files, 16 bit mono PCM at the Bus's sample rate. The header is
+// written with zero lengths and patched when the recording stops, so a file cut short by a
+// crash still has all its samples, just a header that undersells them.
+//
+// A recording always has the mix as it's heard, mute and solo included. It can also have one
+// stem per APU channel, each resampled on its own through that channel's DAC curve and left
+// alone by the mixing controls, named after the mix: song.wav, song.pulse1.wav and so on.
+
+use super::apu::{Apu, Channel};
+use super::audio::Resampler;
+use super::bus::Bus;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const HEADER_LEN: u32 = 44;
+
+// The record hotkey for games and NSFs alike: starts recording to `path`, with a stem per
+// channel if `stems`, or stops the recording going.
+pub fn toggle_recording(bus: &mut Bus, path: &Path, stems: bool) {
+    if bus.is_recording() {
+        match bus.stop_recording() {
+            Ok(()) => println!("Recording stopped"),
+            Err(err) => eprintln!("Recording failed: {}", err),
+        }
+        return;
+    }
+    match bus.start_recording(path, stems) {
+        Ok(()) => println!("Recording to {}", path.display()),
+        Err(err) => eprintln!("Could not record to {}: {}", path.display(), err),
+    }
+}
+
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    samples: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&(HEADER_LEN - 8).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM, one channel
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        // bytes a second, bytes a frame, bits a sample
+        out.write_all(&(sample_rate * 2).to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { out, samples: 0 })
+    }
+
+    // samples from -1.0 to 1.0, anything past that clips
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&pcm.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    // fills in the lengths and hands back the output
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_len = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.out.write_all(&data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+struct Stem {
+    channel: Channel,
+    resampler: Resampler,
+    wav: WavWriter<BufWriter<File>>,
+}
+
+// What the Bus keeps while recording. Write errors can't come out of Bus::tick, so the first
+// one stops the recording and waits for stop_recording to report it.
+pub(crate) struct Recording {
+    mix: WavWriter<BufWriter<File>>,
+    stems: Vec<Stem>,
+    error: Option<io::Error>,
+}
+
+impl Recording {
+    pub(crate) fn start(
+        path: &Path,
+        sample_rate: u32,
+        clock_hz: u32,
+        stems: bool,
+    ) -> io::Result<Recording> {
+        let mix = WavWriter::create(path, sample_rate)?;
+        let mut recording = Recording { mix, stems: Vec::new(), error: None };
+        if stems {
+            for channel in Channel::ALL {
+                recording.stems.push(Stem {
+                    channel,
+                    resampler: Resampler::new(clock_hz, sample_rate),
+                    wav: WavWriter::create(stem_path(path, channel), sample_rate)?,
+                });
+            }
+        }
+        Ok(recording)
+    }
+
+    // one mixed sample, at the output rate
+    pub(crate) fn write_mix(&mut self, sample: f32) {
+        if self.error.is_none() {
+            self.error = self.mix.write_samples(&[sample]).err();
+        }
+    }
+
+    // one CPU cycle of the channels, for the stems
+    pub(crate) fn tick_stems(&mut self, apu: &Apu) {
+        if self.error.is_some() {
+            return;
+        }
+        for stem in &mut self.stems {
+            let Some(sample) = stem.resampler.push(apu.channel_output(stem.channel)) else {
+                continue;
+            };
+            if let Err(err) = stem.wav.write_samples(&[sample]) {
+                self.error = Some(err);
+                return;
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> io::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.mix.finish()?;
+        for stem in self.stems {
+            stem.wav.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// song.wav to song.triangle.wav
+fn stem_path(path: &Path, channel: Channel) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.wav", stem, channel.name()))
+}
@@ -0,0 +1,488 @@
+use nestacean::nes::apu::{Apu, Channel, Sweep, WAVEFORM_INTERVAL, WAVEFORM_LEN};
+use nestacean::nes::bus::Bus;
+use nestacean::nes::cart::{Cart, Region};
+use nestacean::nes::controller::Button;
+use nestacean::nes::cpu::Cpu;
+use nestacean::nes::mem::{Peek, Read, Write};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_register_decoding() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0x1F);
+
+        // 50% duty, halted, constant volume 9, sweeping down by 1/8 every 4 half frames
+        apu.write_register(0x4004, 0b1011_1001);
+        apu.write_register(0x4005, 0b1011_1011);
+        apu.write_register(0x4006, 0x34);
+        apu.write_register(0x4007, 0b0000_1010);
+        let pulse = apu.pulse(1);
+        assert_eq!((pulse.duty, pulse.volume), (2, 9));
+        assert!(pulse.length_halt && pulse.constant_volume);
+        let sweep = Sweep { enabled: true, period: 3, negate: true, shift: 3 };
+        assert_eq!(pulse.sweep, sweep);
+        assert_eq!(pulse.timer_period, 0x234);
+        assert_eq!(pulse.length_counter, 254);
+        assert_eq!(apu.pulse(0).timer_period, 0);
+
+        apu.write_register(0x4008, 0x85);
+        apu.write_register(0x400A, 0xFF);
+        apu.write_register(0x400B, 0xFF);
+        let triangle = apu.triangle();
+        assert!(triangle.length_halt);
+        assert_eq!((triangle.linear_reload, triangle.timer_period), (5, 0x7FF));
+        assert_eq!(triangle.length_counter, 30);
+
+        apu.write_register(0x400C, 0x07);
+        apu.write_register(0x400E, 0x8C);
+        apu.write_register(0x400F, 0x00);
+        let noise = apu.noise();
+        assert!(!noise.constant_volume && noise.short_mode);
+        assert_eq!((noise.volume, noise.period, noise.length_counter), (7, 12, 10));
+
+        apu.write_register(0x4010, 0xCF);
+        apu.write_register(0x4011, 0xFF);
+        apu.write_register(0x4012, 0x01);
+        apu.write_register(0x4013, 0x02);
+        let dmc = apu.dmc();
+        assert!(dmc.irq_enabled && dmc.loop_flag);
+        assert_eq!((dmc.rate, dmc.output), (15, 0x7F));
+        assert_eq!((dmc.sample_addr, dmc.sample_length), (0xC040, 33));
+
+        apu.write_register(0x4017, 0xC0);
+        assert!(apu.frame_counter().five_step && apu.frame_counter().irq_inhibit);
+    }
+
+    #[test]
+    fn test_channel_enables() {
+        let mut apu = Apu::new();
+        // disabled channels don't load their length counters
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.pulse(0).length_counter, 0);
+
+        apu.write_register(0x4015, 0x0F);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x400F, 0x08);
+        assert_eq!(apu.pulse(0).length_counter, 254);
+        assert_eq!(apu.noise().length_counter, 254);
+        // and clearing the bit silences them at once
+        apu.write_register(0x4015, 0x07);
+        assert!(!apu.noise().enabled);
+        assert_eq!(apu.noise().length_counter, 0);
+        assert_eq!(apu.pulse(0).length_counter, 254);
+    }
+
+    #[test]
+    fn test_bus_drives_the_apu() {
+        let mut bus = Bus::new();
+        bus.write(0x4015, 0x01);
+        bus.write(0x4000, 0x3F);
+        bus.write(0x4003, 0x08);
+        bus.tick(10);
+        assert_eq!(bus.apu().cycles(), 10);
+        assert_eq!(bus.apu().pulse(0).volume, 0x0F);
+
+        let state = bus.save_state();
+        let mut restored = Bus::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(*restored.apu().pulse(0), *bus.apu().pulse(0));
+        assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn test_dmc_playback() {
+        let mut apu = Apu::new();
+        // fastest rate, a one byte sample at $C000 starting from level 64
+        apu.write_register(0x4010, 0x0F);
+        apu.write_register(0x4011, 64);
+        apu.write_register(0x4015, 0x10);
+        assert_eq!(apu.take_dmc_request(), Some(0xC000));
+        assert_eq!(apu.take_dmc_request(), None);
+        apu.fill_dmc_sample(0b1111_0011);
+        assert_eq!(apu.dmc().bytes_remaining, 0);
+        assert_eq!(apu.take_dmc_request(), None);
+
+        // the output unit finishes the silent byte it started with, then plays the sample
+        for _ in 0..428 + 7 * 54 {
+            apu.tick();
+        }
+        assert_eq!(apu.dmc().output, 64);
+        for _ in 0..8 * 54 {
+            apu.tick();
+        }
+        assert_eq!(apu.dmc().output, 64 + 2 + 2 - 2 - 2 + 2 + 2 + 2 + 2);
+        assert!(!apu.irq());
+
+        // and holds once the buffer runs dry
+        for _ in 0..8 * 54 {
+            apu.tick();
+        }
+        assert_eq!(apu.dmc().output, 72);
+    }
+
+    #[test]
+    fn test_dmc_loop_and_irq() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0x40);
+        // a 65 byte sample at $FFC0 runs off the end of memory and wraps to $8000
+        apu.write_register(0x4012, 0xFF);
+        apu.write_register(0x4013, 0x04);
+        apu.write_register(0x4010, 0x40);
+        apu.write_register(0x4015, 0x10);
+        for _ in 0..64 {
+            let addr = apu.take_dmc_request().unwrap();
+            assert!(addr >= 0xFFC0);
+            apu.fill_dmc_sample(0);
+            for _ in 0..8 * 428 {
+                apu.tick();
+            }
+        }
+        assert_eq!(apu.take_dmc_request(), Some(0x8000));
+        apu.fill_dmc_sample(0);
+        // looping starts it over without an IRQ
+        assert_eq!(apu.dmc().current_addr, 0xFFC0);
+        assert_eq!(apu.dmc().bytes_remaining, 65);
+        assert!(!apu.irq());
+
+        // a one byte sample with IRQs on raises the flag when it ends
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4013, 0x00);
+        apu.write_register(0x4015, 0x00);
+        apu.write_register(0x4015, 0x10);
+        for _ in 0..8 * 428 {
+            apu.tick();
+        }
+        assert_eq!(apu.take_dmc_request(), Some(0xFFC0));
+        apu.fill_dmc_sample(0);
+        assert!(apu.dmc().irq_flag && apu.irq());
+        // clearing the enable in $4010 acknowledges it
+        apu.write_register(0x4010, 0x00);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_dmc_stalls_the_cpu() {
+        let mut bus = Bus::new();
+        // JMP $0000
+        bus.write(0x0000, 0x4C);
+        bus.write(0x0001, 0x00);
+        bus.write(0x0002, 0x00);
+        bus.write(0x4010, 0x0F);
+        bus.write(0x4013, 0xFF);
+        bus.write(0x4015, 0x10);
+        let mut cpu = Cpu::with_bus(bus);
+        cpu.set_pc(0x0000);
+
+        let start = cpu.get_cycles();
+        let mut instructions = 0;
+        // the bus clocks the APU every cycle and hands the DMC's requests to the CPU
+        while cpu.get_cycles() - start < 5000 {
+            cpu.run_instruction();
+            instructions += 1;
+        }
+        // a byte every 8 * 54 cycles, each taking 3 or 4 from the CPU
+        let stalled = cpu.get_cycles() - start - instructions * 3;
+        let fetches = cpu.bus().apu().dmc().current_addr - 0xC000;
+        assert!(fetches >= 10);
+        assert!((3 * fetches as u64..=4 * fetches as u64).contains(&stalled));
+    }
+
+    #[test]
+    fn test_dmc_joypad_conflict() {
+        let mut bus = Bus::new();
+        // LDA $4016, twice
+        for addr in [0x0000, 0x0003] {
+            bus.write(addr, 0xAD);
+            bus.write(addr + 1, 0x16);
+            bus.write(addr + 2, 0x40);
+        }
+        // A and Select, reading 1, 0, 1
+        bus.controller_mut(0).set_buttons(Button::A | Button::SELECT);
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        let mut cpu = Cpu::with_bus(bus);
+        cpu.set_pc(0x0000);
+        cpu.run_instruction();
+        assert_eq!(cpu.get_accumulator() & 1, 1);
+
+        // a fetch starting after the operand bytes halts the CPU on the $4016 read, which
+        // repeats until the get cycle and then runs again for the CPU, skipping B
+        cpu.run_cycles(3);
+        cpu.start_dmc_dma(0xC000);
+        let before = cpu.get_cycles();
+        cpu.run_instruction();
+        assert!(cpu.get_cycles() - before >= 4);
+        // the bus took the byte for the DMC
+        assert!(cpu.take_dmc_sample().is_none());
+        assert_eq!(cpu.get_accumulator() & 1, 1);
+        // Start is next
+        assert_eq!(cpu.bus().controller(0).peek(), 0);
+        assert_eq!(cpu.bus().peek(0x4016) & 1, 0);
+    }
+
+    #[test]
+    fn test_status_read() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0x1B);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x400F, 0x08);
+        // the DMC is playing the sample its enable started
+        assert_eq!(apu.read_status(), 0b0001_1001);
+
+        // four step mode raises the frame IRQ, a read reports it once
+        for _ in 0..29828 {
+            apu.tick();
+        }
+        assert!(apu.irq());
+        assert_eq!(apu.peek(0x4015) & 0x40, 0x40);
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq());
+        assert_eq!(apu.read_status() & 0x40, 0);
+
+        // writing $4015 acknowledges the DMC IRQ but leaves the frame IRQ alone
+        apu.write_register(0x4010, 0x80);
+        apu.take_dmc_request();
+        apu.fill_dmc_sample(0);
+        for _ in 0..29830 {
+            apu.tick();
+        }
+        assert_eq!(apu.peek(0x4015) & 0xD0, 0xC0);
+        apu.write_register(0x4015, 0x00);
+        assert_eq!(apu.peek(0x4015), 0x40);
+        // and inhibiting the frame IRQ clears it
+        apu.write_register(0x4017, 0x40);
+        assert_eq!(apu.peek(0x4015), 0x00);
+    }
+
+    #[test]
+    fn test_frame_irq_modes() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0x80);
+        for _ in 0..100_000 {
+            apu.tick();
+            assert!(!apu.irq());
+        }
+        // $4017 restarts the sequence
+        apu.write_register(0x4017, 0x00);
+        for _ in 0..29827 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+        apu.tick();
+        assert!(apu.irq());
+    }
+
+    #[test]
+    fn test_pal_timing() {
+        let mut apu = Apu::new();
+        apu.set_region(Region::Pal);
+        apu.write_register(0x4017, 0x00);
+        for _ in 0..33251 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+        apu.tick();
+        assert!(apu.irq());
+        // the sequence starts over after 33254 cycles and raises it again as long after
+        apu.read_status();
+        for _ in 0..2 + 33252 - 1 {
+            apu.tick();
+            apu.read_status();
+        }
+        apu.tick();
+        assert!(apu.irq());
+
+        // a PAL cart's bus has the PAL noise and DMC rates, Dendy keeps NTSC's
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        let mut bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        bus.write(0x400E, 0x0F);
+        bus.write(0x4010, 0x00);
+        assert_eq!(bus.apu().noise().timer_period(), 3778);
+        assert_eq!(bus.apu().dmc().timer_period(), 398);
+        apu.set_region(Region::Dendy);
+        apu.write_register(0x400E, 0x0F);
+        assert_eq!(apu.noise().timer_period(), 4068);
+    }
+
+    #[test]
+    fn test_status_through_the_bus() {
+        let mut bus = Bus::new();
+        bus.write(0x4015, 0x01);
+        bus.write(0x4003, 0x08);
+        bus.tick(29830);
+        assert!(bus.irq());
+        assert_eq!(bus.peek(0x4015) & 0x41, 0x41);
+        assert_eq!(bus.read(0x4015) & 0x41, 0x41);
+        assert!(!bus.irq());
+        assert_eq!(bus.read(0x4015) & 0x41, 0x01);
+    }
+
+    #[test]
+    fn test_pulse_waveform() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0x40);
+        apu.write_register(0x4015, 0x01);
+        // 25% duty at constant volume 10, a period of 2 * (100 + 1) cycles a step
+        apu.write_register(0x4000, 0b0101_1010);
+        apu.write_register(0x4002, 100);
+        apu.write_register(0x4003, 0x08);
+        let mut levels = Vec::new();
+        for _ in 0..8 * 202 {
+            apu.tick();
+            levels.push(apu.pulse(0).output());
+        }
+        let high = levels.iter().filter(|&&level| level == 10).count();
+        assert_eq!(high, 2 * 202);
+        assert!(levels.iter().all(|&level| level == 0 || level == 10));
+        assert!(apu.output() >= 0.0 && apu.output() < 1.0);
+    }
+
+    #[test]
+    fn test_envelope_and_length() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0x40);
+        apu.write_register(0x4015, 0x08);
+        // envelope period 1 decays a step every 2 quarter frames, and a length of 10 runs out
+        // after 10 half frames, 2 a frame
+        apu.write_register(0x400C, 0x01);
+        apu.write_register(0x400F, 0x00);
+        let loudest = |apu: &mut Apu, cycles| {
+            (0..cycles).fold(0, |peak, _| {
+                apu.tick();
+                peak.max(apu.noise().output())
+            })
+        };
+        // up to the first quarter frame, which starts the envelope at 15
+        loudest(&mut apu, 7457);
+        assert_eq!(loudest(&mut apu, 22370 - 7457), 15);
+        assert_eq!(loudest(&mut apu, 29830 + 7456 - 22370), 14);
+        assert_eq!(loudest(&mut apu, 2 * 29830 - (29830 + 7456)), 13);
+        assert_eq!(apu.noise().length_counter, 6);
+        loudest(&mut apu, 3 * 29830);
+        assert_eq!(apu.noise().length_counter, 0);
+        assert_eq!(apu.read_status() & 0x08, 0);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0x40);
+        apu.write_register(0x4015, 0x03);
+        // sweeping down by half every half frame, pulse 1 in ones' complement
+        for base in [0x4000, 0x4004] {
+            apu.write_register(base, 0x3F);
+            apu.write_register(base + 1, 0b1000_1001);
+            apu.write_register(base + 2, 0x00);
+            apu.write_register(base + 3, 0x04);
+        }
+        for _ in 0..14913 {
+            apu.tick();
+        }
+        assert_eq!(apu.pulse(0).timer_period, 0x400 - 0x200 - 1);
+        assert_eq!(apu.pulse(1).timer_period, 0x400 - 0x200);
+
+        // a sweep up that would overflow mutes the channel without touching its period
+        apu.write_register(0x4001, 0b0000_0001);
+        apu.write_register(0x4003, 0x07);
+        for _ in 0..100 {
+            apu.tick();
+            assert_eq!(apu.pulse(0).output(), 0);
+        }
+    }
+
+    #[test]
+    fn test_channel_controls() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0x40);
+        apu.write_register(0x4015, 0x01);
+        // a held pulse level and a DMC level, nothing else sounding
+        apu.write_register(0x4000, 0xDF);
+        apu.write_register(0x4002, 0x80);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x4011, 0x40);
+        while apu.pulse(0).output() == 0 {
+            apu.tick();
+        }
+        let both = apu.output();
+
+        apu.set_muted(Channel::Pulse1, true);
+        let dmc_only = apu.output();
+        assert!(dmc_only > 0.0 && dmc_only < both);
+        // solo wins over mute
+        apu.set_solo(Some(Channel::Pulse1));
+        let pulse_only = apu.output();
+        assert!(pulse_only > 0.0 && pulse_only < both);
+        apu.set_volume(Channel::Pulse1, 0.5);
+        assert!(apu.output() > 0.0 && apu.output() < pulse_only);
+        assert_eq!(apu.gain(Channel::Dmc), 0.0);
+
+        apu.set_solo(None);
+        apu.set_muted(Channel::Pulse1, false);
+        apu.set_volume(Channel::Pulse1, 1.0);
+        assert_eq!(apu.output(), both);
+        // they're listening controls, a savestate doesn't carry them
+        let mut bus = Bus::new();
+        bus.apu_mut().set_muted(Channel::Dmc, true);
+        let state = bus.save_state();
+        let mut restored = Bus::new();
+        restored.load_state(&state).unwrap();
+        assert!(!restored.apu().is_muted(Channel::Dmc));
+    }
+
+    #[test]
+    fn test_debug_view() {
+        let clock_hz = 1_789_773;
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0x40);
+        apu.write_register(0x4015, 0x05);
+        // 50% duty at constant volume 12, 253 + 1 is about 440 Hz
+        apu.write_register(0x4000, 0b1011_1100);
+        apu.write_register(0x4002, 253);
+        apu.write_register(0x4003, 0x08);
+        // the triangle an octave down
+        apu.write_register(0x4008, 0xFF);
+        apu.write_register(0x400A, 253);
+        apu.write_register(0x400B, 0x08);
+        apu.write_register(0x400E, 0x04);
+        apu.write_register(0x4010, 0x0F);
+        // past the first quarter frame, which loads the triangle's linear counter
+        for _ in 0..7457 {
+            apu.tick();
+        }
+
+        let [pulse1, pulse2, triangle, noise, dmc] = apu.channels_info(clock_hz);
+        assert_eq!(pulse1.channel, Channel::Pulse1);
+        assert!(pulse1.enabled && pulse1.playing);
+        assert_eq!((pulse1.period, pulse1.duty, pulse1.volume), (253, Some(2), Some(12)));
+        assert!((pulse1.frequency - 440.4).abs() < 0.1);
+        assert!(!pulse2.enabled && !pulse2.playing);
+        assert!(triangle.enabled && triangle.playing);
+        assert!((triangle.frequency - 220.2).abs() < 0.1);
+        assert_eq!((triangle.volume, triangle.duty), (None, None));
+        // noise and DMC periods come out of their tables
+        assert!(!noise.enabled);
+        assert_eq!((noise.period, dmc.period), (64, 54));
+        assert!((dmc.frequency - clock_hz as f32 / 54.0).abs() < 0.1);
+
+        // the scope is off until asked for
+        assert!(apu.waveform(Channel::Pulse1).is_empty());
+        apu.set_waveform_capture(true);
+        for _ in 0..WAVEFORM_LEN as u32 * WAVEFORM_INTERVAL {
+            apu.tick();
+        }
+        let pulse = apu.waveform(Channel::Pulse1);
+        assert_eq!(pulse.len(), WAVEFORM_LEN);
+        assert!(pulse.contains(&0) && pulse.contains(&12));
+        assert!(pulse.iter().all(|&level| level == 0 || level == 12));
+        assert!(apu.waveform(Channel::Pulse2).iter().all(|&level| level == 0));
+        // a period of 4064 cycles is about 101 samples, so the whole ramp shows up
+        let triangle = apu.waveform(Channel::Triangle);
+        assert!(triangle.contains(&0) && triangle.contains(&15));
+        apu.set_waveform_capture(false);
+        assert!(!apu.waveform_capture());
+    }
+}
@@ -0,0 +1,206 @@
+use nestacean::nes::audio::{OutputFilter, Resampler, SampleRing, SINK_BATCH};
+use nestacean::nes::bus::Bus;
+use nestacean::nes::mem::Write;
+use nestacean::nes::wav::WavWriter;
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ring_overrun() {
+        let mut ring = SampleRing::new(4);
+        for sample in 0..6 {
+            ring.push(sample as f32);
+        }
+        // the two oldest were dropped
+        assert_eq!((ring.len(), ring.overruns()), (4, 2));
+        let mut out = [0.0; 3];
+        assert_eq!(ring.pop_into(&mut out), 3);
+        assert_eq!(out, [2.0, 3.0, 4.0]);
+
+        // and a short ring only fills part of the output
+        ring.push(6.0);
+        let mut out = [0.0; 4];
+        assert_eq!(ring.pop_into(&mut out), 2);
+        assert_eq!(out[..2], [5.0, 6.0]);
+        assert!(ring.is_empty());
+    }
+
+    // one second of a square wave `period` CPU cycles long, resampled to 44.1 kHz
+    fn resample_square(period: u32) -> Vec<f32> {
+        let mut resampler = Resampler::new(1_789_773, 44_100);
+        let mut outputs = Vec::new();
+        for cycle in 0..1_789_773 {
+            let input = if cycle % period < period / 2 { 1.0 } else { 0.0 };
+            outputs.extend(resampler.push(input));
+        }
+        outputs
+    }
+
+    // how far the samples swing around their mean
+    fn rms(samples: &[f32]) -> f32 {
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        let power = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>();
+        (power / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_resampler() {
+        // exactly the output rate over one second of input
+        let tone = resample_square(1790);
+        assert_eq!(tone.len(), 44_100);
+        let mean = tone.iter().sum::<f32>() / tone.len() as f32;
+        assert!((mean - 0.5).abs() < 0.01);
+        // a 1 kHz square passes, nearly all of its 0.5 swing
+        assert!(rms(&tone[100..]) > 0.45);
+
+        // and one at 29.8 kHz, well past Nyquist, mostly doesn't instead of aliasing down
+        let ultrasonic = resample_square(60);
+        assert!(rms(&ultrasonic[100..]) < 0.05);
+    }
+
+    #[test]
+    fn test_bus_fills_the_ring() {
+        let mut bus = Bus::new();
+        bus.set_sample_rate(48_000);
+        bus.write(0x4015, 0x04);
+        // a triangle around 440 Hz
+        bus.write(0x4008, 0xFF);
+        bus.write(0x400A, 0xFD);
+        bus.write(0x400B, 0x08);
+        bus.tick(29781);
+        let ring = bus.samples_mut();
+        // one NTSC frame's worth
+        assert!((798..=799).contains(&ring.len()));
+        let mut samples = vec![0.0; ring.len()];
+        ring.pop_into(&mut samples);
+        let lowest = samples.iter().cloned().fold(f32::MAX, f32::min);
+        let highest = samples.iter().cloned().fold(f32::MIN, f32::max);
+        assert!(highest - lowest > 0.1);
+    }
+
+    #[test]
+    fn test_audio_sink() {
+        let mut bus = Bus::new();
+        let batches = Rc::new(RefCell::new(Vec::new()));
+        let received = Rc::clone(&batches);
+        bus.set_audio_sink(Box::new(move |samples: &[f32]| {
+            received.borrow_mut().push(samples.len());
+        }));
+        bus.write(0x4015, 0x01);
+        bus.write(0x4000, 0xBF);
+        bus.write(0x4002, 0xFD);
+        bus.write(0x4003, 0x08);
+        // two NTSC frames, about 1468 samples
+        bus.tick(2 * 29781);
+        assert_eq!(*batches.borrow(), [SINK_BATCH, SINK_BATCH]);
+        bus.flush_audio();
+        let total: usize = batches.borrow().iter().sum();
+        assert!((1467..=1468).contains(&total));
+        // nothing went to the ring
+        assert!(bus.samples_mut().is_empty());
+
+        bus.clear_audio_sink();
+        bus.tick(29781);
+        assert_eq!(batches.borrow().len(), 3);
+        assert!(!bus.samples_mut().is_empty());
+    }
+
+    #[test]
+    fn test_wav_writer() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 48_000).unwrap();
+        wav.write_samples(&[0.0, 0.5, -1.0, 2.0]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 36 + 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 48_000);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8);
+        let pcm: Vec<i16> =
+            data[44..].chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+        // out of range samples clip
+        assert_eq!(pcm, [0, 16383, -32767, 32767]);
+    }
+
+    #[test]
+    fn test_recording() {
+        let dir = std::env::temp_dir().join(format!("nestacean-wav-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.wav");
+
+        let mut bus = Bus::new();
+        bus.write(0x4015, 0x04);
+        bus.write(0x4008, 0xFF);
+        bus.write(0x400A, 0xFD);
+        bus.write(0x400B, 0x08);
+        bus.start_recording(&path, true).unwrap();
+        assert!(bus.is_recording());
+        bus.tick(29781);
+        bus.stop_recording().unwrap();
+        assert!(!bus.is_recording());
+
+        let mix = std::fs::read(&path).unwrap();
+        let samples = (mix.len() - 44) / 2;
+        assert!((733..=734).contains(&samples));
+        // the triangle's stem has it, the others are silent
+        let stem = |name: &str| std::fs::read(dir.join(format!("song.{}.wav", name))).unwrap();
+        let triangle = stem("triangle");
+        assert_eq!(triangle.len(), mix.len());
+        assert!(triangle[44..].iter().any(|&byte| byte != 0));
+        for name in ["pulse1", "pulse2", "noise", "dmc"] {
+            let silent = stem(name);
+            assert_eq!(silent.len(), mix.len());
+            assert!(silent[44..].iter().all(|&byte| byte == 0));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_output_filter() {
+        // a steady level is taken out within a fraction of a second
+        let mut filter = OutputFilter::new(44_100);
+        let mut last = 0.0;
+        for _ in 0..4410 {
+            last = filter.process(0.5);
+        }
+        assert!(last.abs() < 0.001);
+
+        // a 1 kHz tone mostly gets through, 90 Hz far less of it
+        let peak = |hz: f32| {
+            let mut filter = OutputFilter::new(44_100);
+            (0..44_100)
+                .map(|n| {
+                    let t = n as f32 / 44_100.0;
+                    filter.process((2.0 * std::f32::consts::PI * hz * t).sin())
+                })
+                .skip(22_050)
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+        };
+        assert!(peak(1000.0) > 0.85);
+        assert!(peak(90.0) < 0.25);
+    }
+
+    #[test]
+    fn test_bus_output_filter() {
+        // a held DMC level is only a DC offset
+        let held_level = |filtered: bool| {
+            let mut bus = Bus::new();
+            bus.set_output_filter(filtered);
+            assert_eq!(bus.output_filter_enabled(), filtered);
+            bus.write(0x4011, 0x40);
+            bus.tick(29781 * 6);
+            let ring = bus.samples_mut();
+            let mut samples = vec![0.0; ring.len()];
+            ring.pop_into(&mut samples);
+            *samples.last().unwrap()
+        };
+        assert!(held_level(false) > 0.1);
+        assert!(held_level(true).abs() < 0.001);
+    }
+}
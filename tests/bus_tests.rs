@@ -0,0 +1,708 @@
+use nestacean::nes::bus::{Bus, UnmappedAccessPolicy};
+use nestacean::nes::cart::{Cart, CartError, Mirroring, Region};
+use nestacean::nes::cpu::Cpu;
+use nestacean::nes::mem::{Memory, Peek, Read, Write};
+use nestacean::nes::patch::{self, PatchError};
+use nestacean::nes::romdb::{self, RomDb};
+use nestacean::nes::savestate::StateError;
+
+// iNES image with `banks` 16 KiB PRG banks and no CHR, bank n filled with n
+fn ines_rom(banks: u8) -> Vec<u8> {
+    let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, banks, 0, 0, 0];
+    raw.resize(16, 0);
+    for bank in 0..banks {
+        raw.extend(std::iter::repeat_n(bank, 0x4000));
+    }
+    raw
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ram_mirroring() {
+        let mut bus = Bus::new();
+        bus.write(0x0012, 0x34);
+        assert_eq!(bus.read(0x0812), 0x34);
+        assert_eq!(bus.read(0x1012), 0x34);
+        bus.write(0x1FFF, 0x56);
+        assert_eq!(bus.read(0x07FF), 0x56);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.read(0x5000), 0x00);
+        bus.write(0x0010, 0x99);
+        assert_eq!(bus.read(0x5000), 0x99);
+        bus.write(0x0011, 0x42);
+        bus.read(0x0010);
+        assert_eq!(bus.read(0x5000), 0x99);
+        assert_eq!(bus.open_bus(), 0x99);
+    }
+
+    #[test]
+    fn test_cpu_on_bus() {
+        let mut cpu = Cpu::with_bus(Bus::new());
+        let mem: [u8; 4] = [0xA9, 0x42, 0x85, 0x10]; // LDA #$42, STA $10
+        for (i, byte) in mem.iter().enumerate() {
+            cpu.mem_write(0x0800 + i as u16, *byte);
+        }
+        cpu.mem_write(0x0100, 0x00);
+        cpu.reset(); // unmapped reset vector reads the 0 left on the open bus, $0000 mirrors $0800
+        cpu.tick(); // fetch and decode
+        cpu.tick(); // LoadAccumulator
+        cpu.tick(); // fetch and decode
+        cpu.tick(); // FetchZeroPage
+        cpu.tick(); // StoreAccumulator
+        assert_eq!(cpu.bus_mut().read(0x0010), 0x42);
+    }
+
+    #[test]
+    fn test_prg_rom_mapping() {
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(2)).unwrap());
+        assert_eq!(bus.read(0x8000), 0);
+        assert_eq!(bus.read(0xC000), 1);
+        bus.write(0x8000, 0x55);
+        assert_eq!(bus.read(0x8000), 0);
+
+        // a single bank is mirrored into $C000-$FFFF
+        let mut rom = ines_rom(1);
+        rom[16 + 0x3FFC] = 0x34;
+        rom[16 + 0x3FFD] = 0x12;
+        let mut bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        assert_eq!(bus.read(0xFFFC), 0x34);
+        assert_eq!(bus.read(0xBFFC), 0x34);
+    }
+
+    #[test]
+    fn test_cpu_boots_from_prg_rom() {
+        let mut rom = ines_rom(1);
+        // LDA #$42, STA $10 at $C000, which mirrors $8000
+        rom[16..20].copy_from_slice(&[0xA9, 0x42, 0x85, 0x10]);
+        rom[16 + 0x3FFC] = 0x00;
+        rom[16 + 0x3FFD] = 0xC0;
+        let mut cpu = Cpu::with_bus(Bus::with_cart(Cart::from_bytes(&rom).unwrap()));
+        cpu.reset();
+        assert_eq!(cpu.get_pc(), 0xC000);
+        cpu.run_instruction();
+        cpu.run_instruction();
+        assert_eq!(cpu.bus_mut().read(0x0010), 0x42);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut rom = ines_rom(1);
+        rom[6] |= 0b10; // battery
+        let cart = Cart::from_bytes(&rom).unwrap();
+        assert!(cart.battery);
+        assert_eq!(cart.prg_ram_size, 0x2000);
+        let mut bus = Bus::with_cart(cart);
+        bus.write(0x6000, 0x12);
+        bus.write(0x7FFF, 0x34);
+        assert_eq!(bus.read(0x6000), 0x12);
+        assert_eq!(bus.prg_ram()[0x1FFF], 0x34);
+
+        bus.set_prg_ram_enabled(false);
+        bus.write(0x6000, 0x99);
+        bus.write(0x0000, 0x77);
+        assert_eq!(bus.read(0x6000), 0x77);
+        bus.set_prg_ram_enabled(true);
+        assert_eq!(bus.read(0x6000), 0x12);
+
+        bus.load_prg_ram(&[0xAB, 0xCD]);
+        assert_eq!(bus.read(0x6001), 0xCD);
+        assert_eq!(bus.read(0x7FFF), 0x34);
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let mut rom = ines_rom(1);
+        rom[6] |= 0xF0;
+        rom[7] |= 0xF0;
+        let err = Cart::from_bytes(&rom).err().unwrap();
+        assert!(matches!(err, CartError::UnsupportedMapper(255)));
+        assert_eq!(err.to_string(), "Mapper 255 is not supported");
+    }
+
+    #[test]
+    fn test_malformed_roms() {
+        let rom = ines_rom(2);
+        assert!(matches!(Cart::from_bytes(&rom[..10]), Err(CartError::TooShort(10))));
+        assert!(matches!(Cart::from_bytes(&[0; 16]), Err(CartError::BadMagic)));
+
+        let err = Cart::from_bytes(&rom[..0x5000]).err().unwrap();
+        assert_eq!(err.to_string(), "PRG ROM is truncated: expected 32768 bytes, found 20464");
+
+        // one CHR bank in the header but none in the file
+        let mut rom = ines_rom(1);
+        rom[5] = 1;
+        assert!(matches!(
+            Cart::from_bytes(&rom),
+            Err(CartError::Truncated { section: "CHR ROM", expected: 0x2000, found: 0 })
+        ));
+
+        assert!(matches!(
+            Cart::from_file("tests/roms/does_not_exist.nes"),
+            Err(CartError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_region_from_header() {
+        let mut rom = ines_rom(1);
+        assert_eq!(Cart::from_bytes(&rom).unwrap().region, Region::Ntsc);
+        rom[9] = 1;
+        assert_eq!(Cart::from_bytes(&rom).unwrap().region, Region::Pal);
+        rom[9] = 0;
+        rom[10] = 2;
+        let bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        assert_eq!(bus.region(), Region::Pal);
+
+        // junk in the end of the header means bytes 9 and 10 can't be trusted either
+        rom[12] = b'D';
+        assert_eq!(Cart::from_bytes(&rom).unwrap().region, Region::Ntsc);
+        assert_eq!(Bus::new().region(), Region::Ntsc);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut rom = ines_rom(2);
+        rom[7] |= 0b1000;
+        // submapper 1, 8 KiB of PRG RAM and 8 KiB of NVRAM, 16 KiB of CHR RAM, PAL
+        rom[8] = 0x10;
+        rom[10] = 0x77;
+        rom[11] = 0x08;
+        rom[12] = 1;
+        let cart = Cart::from_bytes(&rom).unwrap();
+        assert_eq!(cart.region, Region::Pal);
+        assert_eq!(cart.submapper, 1);
+        assert_eq!(cart.prg_rom.len(), 0x8000);
+        assert_eq!(cart.prg_ram_size, 0x4000);
+        assert!(cart.chr_rom.is_ram());
+        assert_eq!(cart.chr_rom.len(), 0x4000);
+        assert_eq!(Bus::with_cart(cart).region(), Region::Pal);
+
+        // the timing field wins over the iNES TV system bits, 3 is Dendy
+        rom[9] = 0x00;
+        rom[12] = 3;
+        assert_eq!(Cart::from_bytes(&rom).unwrap().region, Region::Dendy);
+
+        // 2^14 * 3 bytes of PRG ROM in exponent-multiplier form
+        rom[4] = 14 << 2 | 1;
+        rom[9] = 0x0F;
+        rom.resize(16 + 0xC000, 0);
+        assert_eq!(Cart::from_bytes(&rom).unwrap().prg_rom.len(), 0xC000);
+        // and one past what fits in usize
+        rom[4] = 0xFF;
+        assert!(matches!(Cart::from_bytes(&rom), Err(CartError::Truncated { .. })));
+
+        // mappers past 255 are NES 2.0 only
+        rom[4] = 2;
+        rom[9] = 0;
+        rom[8] = 0x01;
+        assert!(matches!(Cart::from_bytes(&rom), Err(CartError::UnsupportedMapper(256))));
+    }
+
+    #[test]
+    fn test_rom_database() {
+        // header says mapper 255 with horizontal mirroring, the database knows better
+        let mut rom = ines_rom(1);
+        rom[6] |= 0xF0;
+        rom[7] |= 0xF0;
+        let crc32 = crc32fast::hash(&rom[16..]);
+        let db = RomDb::parse(&format!(
+            "# test entries\n\n{:08X}\t-\t0\tV\tD\tTest Game (U)\n",
+            crc32
+        ))
+        .unwrap();
+        assert_eq!(db.len(), 1);
+
+        let cart = Cart::from_bytes_with_db(&rom, &db).unwrap();
+        assert_eq!(cart.crc32, crc32);
+        assert_eq!(cart.mapper, 0);
+        assert_eq!(cart.screen_mirroring, Mirroring::Vertical);
+        assert_eq!(cart.title.as_deref(), Some("Test Game (U)"));
+        assert_eq!(cart.region, Region::Dendy);
+
+        // a SHA-1 in the entry has to match too
+        let sha1 = "00".repeat(20);
+        let db = RomDb::parse(&format!("{:08X}\t{}\t0\t-\t-\tOther\n", crc32, sha1)).unwrap();
+        assert!(matches!(
+            Cart::from_bytes_with_db(&rom, &db),
+            Err(CartError::UnsupportedMapper(255))
+        ));
+
+        let cart = Cart::from_bytes(&ines_rom(1)).unwrap();
+        assert_eq!(cart.title, None);
+
+        assert_eq!(
+            RomDb::parse("1234\t-\t0\tX\t-\tBad").err(),
+            Some("ROM database line 1: bad mirroring".to_string())
+        );
+        assert!(RomDb::parse("1234 - 0 V Spaces").is_err());
+        romdb::embedded();
+    }
+
+    #[test]
+    fn test_compressed_roms() {
+        use std::io::Write as _;
+
+        let dir = std::env::temp_dir().join(format!("nestacean-archives-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom = ines_rom(2);
+
+        // the first .nes entry is loaded, whatever comes before it
+        let zip_path = dir.join("game.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("readme.txt", options).unwrap();
+        zip.write_all(b"not a rom").unwrap();
+        zip.start_file("Game (U).NES", options).unwrap();
+        zip.write_all(&rom).unwrap();
+        zip.finish().unwrap();
+        let cart = Cart::from_file(&zip_path).unwrap();
+        assert_eq!(cart.prg_rom.data(), &rom[16..]);
+
+        let gz_path = dir.join("game.nes.gz");
+        let mut gz = flate2::write::GzEncoder::new(
+            std::fs::File::create(&gz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        gz.write_all(&rom).unwrap();
+        gz.finish().unwrap();
+        let cart = Cart::from_file(&gz_path).unwrap();
+        assert_eq!(cart.prg_rom.data(), &rom[16..]);
+
+        let empty_path = dir.join("empty.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&empty_path).unwrap());
+        zip.start_file("readme.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.finish().unwrap();
+        assert!(matches!(Cart::from_file(&empty_path), Err(CartError::NoRomInArchive)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apu_io_registers() {
+        let mut bus = Bus::new();
+        bus.write(0x4000, 0xBF);
+        bus.write(0x4017, 0x40);
+        assert_eq!(bus.apu_io_register(0x4000), 0xBF);
+        assert_eq!(bus.apu_io_register(0x4017), 0x40);
+
+        // write only registers float, status and joypads only drive some bits
+        bus.write(0x0000, 0xFF);
+        bus.read(0x0000);
+        assert_eq!(bus.read(0x4000), 0xFF);
+        bus.read(0x0000);
+        assert_eq!(bus.read(0x4015), 0x20);
+        bus.read(0x0000);
+        assert_eq!(bus.read(0x4016), 0xE0);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut rom = ines_rom(1);
+        // LDA #$10, STA $2003, LDA #$03, STA $4014, NOP
+        let program = [0xA9, 0x10, 0x8D, 0x03, 0x20, 0xA9, 0x03, 0x8D, 0x14, 0x40, 0xEA];
+        rom[16..16 + program.len()].copy_from_slice(&program);
+        rom[16 + 0x3FFD] = 0x80;
+        let mut cpu = Cpu::with_bus(Bus::with_cart(Cart::from_bytes(&rom).unwrap()));
+        for i in 0..=0xFF {
+            cpu.mem_write(0x0300 + i, i as u8);
+        }
+        cpu.reset();
+        for _ in 0..4 {
+            cpu.run_instruction();
+        }
+        assert!(!cpu.is_dma_active());
+        assert_eq!(cpu.get_pc(), 0x800A);
+        // the copy starts at OAMADDR and wraps around
+        let oam = cpu.bus().ppu().oam();
+        assert_eq!(oam[0x10], 0x00);
+        assert_eq!(oam[0xFF], 0xEF);
+        assert_eq!(oam[0x00], 0xF0);
+        assert_eq!(cpu.bus_mut().read(0x2004), 0x00);
+    }
+
+    #[test]
+    fn test_master_clock() {
+        let mut bus = Bus::new();
+        bus.tick(1);
+        assert_eq!((bus.ppu().scanline(), bus.ppu().dot()), (0, 3));
+        assert_eq!(bus.apu().cycles(), 1);
+
+        // one NTSC frame is 341 * 262 dots, 29780.67 CPU cycles
+        bus.tick(29779);
+        assert_eq!(bus.ppu().frame_count(), 0);
+        bus.tick(1);
+        assert_eq!(bus.ppu().frame_count(), 1);
+        assert_eq!((bus.ppu().scanline(), bus.ppu().dot()), (0, 1));
+        assert_eq!(bus.cycles(), 29781);
+        assert_eq!(bus.apu().cycles(), 29781);
+    }
+
+    #[test]
+    fn test_pal_master_clock() {
+        let mut rom = ines_rom(1);
+        rom[9] = 1;
+        let mut bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        assert_eq!(bus.ppu().region(), Region::Pal);
+        // 16 dots every 5 CPU cycles
+        bus.tick(4);
+        assert_eq!(bus.ppu().dot(), 12);
+        bus.tick(1);
+        assert_eq!(bus.ppu().dot(), 16);
+
+        // a PAL frame is 341 * 312 dots, 33247.5 CPU cycles, with no dot skipped
+        bus.tick(33247 - 5);
+        assert_eq!(bus.ppu().frame_count(), 0);
+        bus.tick(1);
+        assert_eq!(bus.ppu().frame_count(), 1);
+        assert_eq!((bus.ppu().scanline(), bus.ppu().dot()), (0, 1));
+    }
+
+    #[test]
+    fn test_clock_follows_cpu() {
+        let mut rom = ines_rom(1);
+        rom[16..18].copy_from_slice(&[0xA9, 0x42]); // LDA #$42
+        rom[16 + 0x3FFD] = 0x80;
+        let mut cpu = Cpu::with_bus(Bus::with_cart(Cart::from_bytes(&rom).unwrap()));
+        cpu.reset();
+        cpu.run_instruction();
+        assert_eq!(cpu.bus().cycles(), 2);
+        assert_eq!(cpu.bus().ppu().dot(), 6);
+    }
+
+    #[test]
+    fn test_lockstep_through_oam_dma() {
+        let mut rom = ines_rom(1);
+        // LDA #$03, STA $4014, NOP
+        rom[16..22].copy_from_slice(&[0xA9, 0x03, 0x8D, 0x14, 0x40, 0xEA]);
+        rom[16 + 0x3FFD] = 0x80;
+        let mut cpu = Cpu::with_bus(Bus::with_cart(Cart::from_bytes(&rom).unwrap()));
+        cpu.reset();
+        let start = cpu.get_cycles();
+        // every cycle of the instructions and of the DMA clocks the PPU before the next one
+        while cpu.get_pc() != 0x8006 {
+            cpu.tick();
+            let cycles = cpu.get_cycles() - start;
+            assert_eq!(cpu.bus().cycles(), cycles);
+            assert_eq!(cpu.bus().ppu().dot() as u64, cycles * 3 % 341);
+        }
+        // the NOP was fetched after the DMA
+        assert!(cpu.get_cycles() - start > 2 + 4 + 513);
+    }
+
+    #[test]
+    fn test_ppu_registers() {
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(1)).unwrap());
+        // PPUADDR takes the high byte first, PPUDATA writes move it on
+        bus.write(0x2006, 0x21);
+        bus.write(0x2006, 0x08);
+        bus.write(0x2007, 0x11);
+        bus.write(0x2007, 0x22);
+        assert_eq!(bus.ppu().vram_addr(), 0x210A);
+        assert_eq!(bus.ppu_bus().peek(0x2109), 0x22);
+
+        // reads come a byte late through the buffer
+        bus.write(0x3FFE, 0x21);
+        bus.write(0x3FFE, 0x08);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0x11);
+        assert_eq!(bus.peek(0x200F), 0x22);
+
+        // PPUCTRL and PPUSCROLL build the scroll position in t
+        bus.write(0x2000, 0b10);
+        bus.write(0x2005, 0x7D);
+        bus.write(0x2005, 0x5E);
+        // fine Y 6, nametable 2, coarse Y 11, coarse X 15
+        assert_eq!(bus.ppu().temp_vram_addr(), 0x696F);
+        assert_eq!(bus.ppu().fine_x(), 0b101);
+
+        // a PPUSTATUS read resets the write toggle halfway through an address
+        bus.write(0x2006, 0x23);
+        bus.read(0x2002);
+        bus.write(0x2006, 0x24);
+        bus.write(0x2006, 0x00);
+        assert_eq!(bus.ppu().vram_addr(), 0x2400);
+
+        // write-only registers read back the last value on the PPU's data bus
+        bus.write(0x2001, 0x5A);
+        assert_eq!(bus.read(0x2005), 0x5A);
+        assert_eq!(bus.read(0x2002) & 0x1F, 0x1A);
+    }
+
+    #[test]
+    fn test_ppudata_nametable_mirroring() {
+        let mut rom = ines_rom(1);
+        // horizontal: $2000 and $2400 are the same page
+        rom[6] &= !1;
+        let mut bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        bus.write(0x2006, 0x20);
+        bus.write(0x2006, 0x40);
+        bus.write(0x2007, 0x77);
+        bus.write(0x2006, 0x24);
+        bus.write(0x2006, 0x40);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0x77);
+        bus.write(0x2006, 0x28);
+        bus.write(0x2006, 0x40);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0x00);
+    }
+
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        let mut rom = ines_rom(1);
+        rom[16] = 0xA9;
+        let mut bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        bus.write(0x0005, 0x11);
+        bus.write(0x2003, 0x20);
+        bus.write(0x2004, 0x33);
+        bus.write(0x2003, 0x20);
+        bus.write(0x6000, 0x44);
+        bus.write(0x0000, 0x55);
+
+        assert_eq!(bus.peek(0x0805), 0x11);
+        assert_eq!(bus.peek(0x2004), 0x33);
+        assert_eq!(bus.peek(0x6000), 0x44);
+        assert_eq!(bus.peek(0xC000), 0xA9);
+        assert_eq!(bus.peek(0x5000), 0x55);
+        assert_eq!(bus.open_bus(), 0x55);
+
+        // reads do latch the bus
+        bus.read(0x0005);
+        assert_eq!(bus.peek(0x5000), 0x11);
+    }
+
+    #[test]
+    #[should_panic(expected = "unmapped read at 5000 (PC 8002)")]
+    fn test_unmapped_access_panic_policy() {
+        let mut rom = ines_rom(1);
+        rom[16..21].copy_from_slice(&[0xEA, 0xEA, 0xAD, 0x00, 0x50]); // NOP, NOP, LDA $5000
+        rom[16 + 0x3FFD] = 0x80;
+        let mut bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        bus.set_unmapped_access_policy(UnmappedAccessPolicy::Panic);
+        let mut cpu = Cpu::with_bus(bus);
+        cpu.reset();
+        for _ in 0..3 {
+            cpu.run_instruction();
+        }
+    }
+
+    #[test]
+    fn test_unmapped_access_ignore_policy() {
+        let mut bus = Bus::new();
+        bus.set_unmapped_access_policy(UnmappedAccessPolicy::Ignore);
+        bus.write(0x5000, 0x12);
+        assert_eq!(bus.read(0x5000), 0x12);
+    }
+
+    #[test]
+    fn test_memory_rom_and_mirroring() {
+        let mut rom = Memory::new(vec![1, 2, 3, 4], false);
+        rom.write(0x0001, 0xFF);
+        assert_eq!(rom.read(0x0001), 2);
+        assert_eq!(rom.read(0x0006), 3);
+
+        let mut ram = Memory::new([0u8; 4], true);
+        ram.write(0x0005, 0xAA);
+        assert_eq!(ram.read(0x0001), 0xAA);
+        assert_eq!(ram.peek(0x0009), 0xAA);
+    }
+
+    #[test]
+    fn test_chr_ram_without_chr_banks() {
+        let cart = Cart::from_bytes(&ines_rom(1)).unwrap();
+        assert!(cart.chr_rom.is_ram());
+        assert_eq!(cart.chr_rom.len(), 0x2000);
+        assert!(!cart.prg_rom.is_ram());
+    }
+
+    #[test]
+    fn test_savestate_round_trip() {
+        let cart = || Cart::from_bytes(&ines_rom(1)).unwrap();
+        let mut bus = Bus::with_cart(cart());
+        bus.write(0x0123, 0x45);
+        bus.write(0x6010, 0x67);
+        bus.write(0x2003, 0x10);
+        bus.write(0x2004, 0x89);
+        bus.write(0x4000, 0x3F);
+        bus.ppu_bus().write(0x2345, 0xAB);
+        bus.tick(1000);
+        let state = bus.save_state();
+
+        let mut restored = Bus::with_cart(cart());
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.read(0x0123), 0x45);
+        assert_eq!(restored.read(0x6010), 0x67);
+        assert_eq!(restored.ppu().oam()[0x10], 0x89);
+        assert_eq!(restored.apu_io_register(0x4000), 0x3F);
+        assert_eq!(restored.ppu_bus().read(0x2345), 0xAB);
+        assert_eq!(restored.cycles(), 1000);
+        assert_eq!(restored.ppu().dot(), bus.ppu().dot());
+        assert_eq!(restored.ppu().scanline(), bus.ppu().scanline());
+    }
+
+    #[test]
+    fn test_savestate_rejects_bad_data() {
+        let mut bus = Bus::new();
+        let mut state = bus.save_state();
+        let truncated = bus.load_state(&state[..state.len() - 1]);
+        assert!(matches!(truncated, Err(StateError::Truncated)));
+        assert!(matches!(bus.load_state(b"NSST"), Err(StateError::Truncated)));
+        assert!(matches!(bus.load_state(b"NOPE"), Err(StateError::BadMagic)));
+        let mut longer = state.clone();
+        longer.push(0);
+        assert!(matches!(bus.load_state(&longer), Err(StateError::TrailingData)));
+
+        // another format version
+        state[4] = 0xFF;
+        assert!(matches!(bus.load_state(&state), Err(StateError::UnsupportedVersion(0xFF))));
+
+        // state taken without a cart
+        let mut with_cart = Bus::with_cart(Cart::from_bytes(&ines_rom(1)).unwrap());
+        let mismatch = with_cart.load_state(&Bus::new().save_state());
+        assert!(matches!(mismatch, Err(StateError::Mismatch(_))));
+    }
+
+    #[test]
+    fn test_ppu_bus_nametable_mirroring() {
+        // horizontal: $2000 and $2400 share a page
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(1)).unwrap());
+        let mut ppu_bus = bus.ppu_bus();
+        ppu_bus.write(0x2000, 1);
+        assert_eq!(ppu_bus.read(0x2400), 1);
+        assert_eq!(ppu_bus.read(0x2800), 0);
+        // $3000-$3EFF mirrors the nametables
+        assert_eq!(ppu_bus.read(0x3000), 1);
+        assert_eq!(ppu_bus.peek(0x3400), 1);
+        // CHR RAM through the mapper
+        ppu_bus.write(0x0010, 0x55);
+        assert_eq!(ppu_bus.read(0x0010), 0x55);
+
+        let mut rom = ines_rom(1);
+        rom[6] |= 1;
+        let mut bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        let mut ppu_bus = bus.ppu_bus();
+        ppu_bus.write(0x2000, 2);
+        assert_eq!(ppu_bus.read(0x2800), 2);
+        assert_eq!(ppu_bus.read(0x2400), 0);
+
+        // four-screen: the last two nametables are RAM on the cart
+        let mut rom = ines_rom(1);
+        rom[6] |= 0b1000;
+        let mut bus = Bus::with_cart(Cart::from_bytes(&rom).unwrap());
+        let mut ppu_bus = bus.ppu_bus();
+        for (nametable, value) in [(0x2000, 3), (0x2400, 4), (0x2800, 5), (0x2C00, 6)] {
+            ppu_bus.write(nametable, value);
+        }
+        for (nametable, value) in [(0x2000, 3), (0x2400, 4), (0x2800, 5), (0x2C00, 6)] {
+            assert_eq!(ppu_bus.read(nametable), value);
+        }
+        assert_eq!(bus.cart().unwrap().vram.data()[0x0400], 6);
+    }
+
+    // BPS number encoding, for building patches by hand
+    fn bps_number(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    #[test]
+    fn test_ips_patch() {
+        let rom = ines_rom(1);
+        let mut ips = b"PATCH".to_vec();
+        // two bytes at $10, then a run of four $CC at $14
+        ips.extend([0x00, 0x00, 0x10, 0x00, 0x02, 0xAA, 0xBB]);
+        ips.extend([0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+        ips.extend(b"EOF");
+        let patched = patch::apply(&rom, &ips).unwrap();
+        assert_eq!(&patched[0x10..0x18], &[0xAA, 0xBB, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]);
+        assert_eq!(patched.len(), rom.len());
+
+        assert_eq!(patch::apply(&rom, &ips[..ips.len() - 2]), Err(PatchError::Truncated));
+        assert_eq!(patch::apply(&rom, b"NOT A PATCH"), Err(PatchError::UnknownFormat));
+
+        // picked up from next to the ROM
+        let dir = std::env::temp_dir().join(format!("nestacean-ips-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("game.nes"), &rom).unwrap();
+        assert_eq!(Cart::from_file(dir.join("game.nes")).unwrap().prg_rom.data()[0], 0);
+        std::fs::write(dir.join("game.ips"), &ips).unwrap();
+        let cart = Cart::from_file(dir.join("game.nes")).unwrap();
+        assert_eq!(&cart.prg_rom.data()[..2], &[0xAA, 0xBB]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bps_patch() {
+        let rom = ines_rom(1);
+        let mut target = rom.clone();
+        target[0x10] = 0x99;
+        target[0x11] = 0x99;
+        target.extend_from_within(0x10..0x13);
+
+        let mut bps = b"BPS1".to_vec();
+        bps_number(rom.len(), &mut bps);
+        bps_number(target.len(), &mut bps);
+        bps_number(0, &mut bps);
+        // source read of the header, one literal byte and a target copy of it, a source copy
+        // of the rest of the ROM, then a target copy repeating three bytes from $10
+        bps_number((0x10 - 1) << 2, &mut bps);
+        bps_number(1, &mut bps);
+        bps.push(0x99);
+        bps_number(3, &mut bps);
+        bps_number(0x10 << 1, &mut bps);
+        bps_number(((rom.len() - 0x12 - 1) << 2) | 2, &mut bps);
+        bps_number(0x12 << 1, &mut bps);
+        bps_number(((3 - 1) << 2) | 3, &mut bps);
+        bps_number((1 << 1) | 1, &mut bps);
+        bps.extend(crc32fast::hash(&rom).to_le_bytes());
+        bps.extend(crc32fast::hash(&target).to_le_bytes());
+        bps.extend(crc32fast::hash(&bps).to_le_bytes());
+
+        assert_eq!(patch::apply(&rom, &bps).unwrap(), target);
+
+        let mut other = rom.clone();
+        other[0x20] = 1;
+        assert!(matches!(
+            patch::apply(&other, &bps),
+            Err(PatchError::WrongSource { .. })
+        ));
+        let last = bps.len() - 1;
+        bps[last] ^= 0xFF;
+        assert_eq!(
+            patch::apply(&rom, &bps),
+            Err(PatchError::Corrupt("patch checksum mismatch"))
+        );
+
+        // a source copy with the largest offset and length the encoding holds is refused, not
+        // added up past the end of the address space
+        let mut bps = b"BPS1".to_vec();
+        bps_number(rom.len(), &mut bps);
+        bps_number(usize::MAX, &mut bps);
+        bps_number(0, &mut bps);
+        bps_number(usize::MAX >> 2 << 2 | 2, &mut bps);
+        bps_number(usize::MAX >> 1 << 1, &mut bps);
+        bps.extend(crc32fast::hash(&rom).to_le_bytes());
+        bps.extend([0; 4]);
+        bps.extend(crc32fast::hash(&bps).to_le_bytes());
+        assert_eq!(
+            patch::apply(&rom, &bps),
+            Err(PatchError::Corrupt("source copy out of range"))
+        );
+    }
+}
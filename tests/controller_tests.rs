@@ -0,0 +1,86 @@
+use nestacean::nes::bus::Bus;
+use nestacean::nes::controller::{Button, Controller};
+use nestacean::nes::mem::{Read, Write};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_buttons() {
+        let mut buttons = Button::A | Button::START;
+        assert_eq!(buttons.bits(), 0x09);
+        assert!(buttons.contains(Button::A) && !buttons.contains(Button::A | Button::B));
+        buttons.set(Button::LEFT, true);
+        buttons.remove(Button::A);
+        assert_eq!(buttons, Button::START | Button::LEFT);
+        assert_eq!(buttons & Button::LEFT, Button::LEFT);
+        assert!(Button::NONE.is_empty());
+        assert_eq!(Button::from_bits(0x80), Button::RIGHT);
+    }
+
+    #[test]
+    fn test_serial_reads() {
+        let mut controller = Controller::new();
+        controller.set_buttons(Button::A | Button::SELECT | Button::DOWN | Button::RIGHT);
+        controller.write(1);
+        controller.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| controller.read()).collect();
+        assert_eq!(bits, [1, 0, 1, 0, 0, 1, 0, 1]);
+        // an official controller reads 1 once it's run out
+        assert_eq!((controller.read(), controller.read()), (1, 1));
+
+        // pressing buttons doesn't change what's been latched until the next strobe
+        controller.write(1);
+        controller.write(0);
+        controller.set_buttons(Button::NONE);
+        assert_eq!(controller.read(), 1);
+    }
+
+    #[test]
+    fn test_strobe_held() {
+        let mut controller = Controller::new();
+        controller.set_buttons(Button::A);
+        controller.write(1);
+        assert_eq!((controller.read(), controller.read(), controller.read()), (1, 1, 1));
+        controller.set_buttons(Button::B);
+        assert_eq!(controller.read(), 0);
+        // letting go starts from the buttons held at the time
+        controller.write(0);
+        assert_eq!((controller.read(), controller.read()), (0, 1));
+    }
+
+    #[test]
+    fn test_ports_on_the_bus() {
+        let mut bus = Bus::new();
+        bus.controller_mut(0).set_buttons(Button::B | Button::START);
+        bus.controller_mut(1).set_buttons(Button::A);
+        // one write to $4016 strobes both ports
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        bus.write(0x0000, 0x00);
+        let port = |bus: &mut Bus, addr| {
+            let data = bus.read(addr);
+            bus.read(0x0000);
+            data
+        };
+        assert_eq!(port(&mut bus, 0x4016), 0x00);
+        assert_eq!(port(&mut bus, 0x4016), 0x01);
+        assert_eq!(port(&mut bus, 0x4017), 0x01);
+        assert_eq!(port(&mut bus, 0x4017), 0x00);
+        // the upper bits are open bus
+        bus.write(0x0001, 0xFF);
+        bus.read(0x0001);
+        assert_eq!(bus.read(0x4016), 0xE0);
+
+        // the shift registers are part of a savestate
+        let state = bus.save_state();
+        let mut restored = Bus::new();
+        restored.load_state(&state).unwrap();
+        restored.read(0x0000);
+        assert_eq!(restored.read(0x4016) & 1, 1);
+        restored.read(0x0000);
+        assert_eq!(restored.read(0x4017) & 1, 0);
+        assert!(restored.controller(0).buttons().is_empty());
+    }
+}
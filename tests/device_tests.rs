@@ -0,0 +1,85 @@
+use nestacean::nes::bus::Bus;
+use nestacean::nes::controller::Button;
+use nestacean::nes::device::vaus::{Vaus, VausVariant, POSITION_MAX, POSITION_MIN};
+use nestacean::nes::device::InputDevice;
+use nestacean::nes::mem::{Read, Write};
+
+// a read of $4016 or $4017 as its own pulse, with the open bus bits cleared
+fn port(bus: &mut Bus, addr: u16) -> u8 {
+    bus.write(0x0000, 0x00);
+    bus.read(0x0000);
+    bus.read(addr)
+}
+
+// strobes the ports and reads the knob back, `shift` picks the data bit out of $4017
+fn read_position(bus: &mut Bus, shift: u8) -> u8 {
+    bus.write(0x4016, 1);
+    bus.write(0x4016, 0);
+    (0..8).fold(0, |position, _| position << 1 | (!port(bus, 0x4017) >> shift & 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_famicom_vaus() {
+        let mut vaus = Vaus::new(VausVariant::Famicom);
+        vaus.set_position(0xA5);
+        vaus.set_fire(true);
+        let mut bus = Bus::new();
+        bus.set_device(Some(Box::new(vaus)));
+        // the controllers are still there, the Vaus drives D1 beside them
+        bus.controller_mut(1).set_buttons(Button::A);
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        assert_eq!(port(&mut bus, 0x4016), 0b10);
+        assert_eq!(port(&mut bus, 0x4017) & 1, 1);
+        assert_eq!(read_position(&mut bus, 1), 0xA5);
+        // ones shift in behind the position, which read as zeroes
+        assert_eq!(port(&mut bus, 0x4017) & 0b10, 0);
+
+        // the knob follows the mouse, within its travel
+        let device = bus.device_mut().unwrap();
+        device.mouse(0.0, 0.5, false);
+        assert_eq!(read_position(&mut bus, 1), POSITION_MIN);
+        bus.device_mut().unwrap().mouse(1.5, 0.5, false);
+        assert_eq!(read_position(&mut bus, 1), POSITION_MAX);
+        assert_eq!(port(&mut bus, 0x4016), 0);
+        bus.set_device(None);
+        assert!(bus.device().is_none());
+    }
+
+    #[test]
+    fn test_nes_vaus() {
+        let mut vaus = Vaus::new(VausVariant::Nes);
+        assert_eq!(vaus.port(), Some(1));
+        vaus.set_position(0x80);
+        let mut bus = Bus::new();
+        bus.set_device(Some(Box::new(vaus)));
+        // it takes the second controller's place, the first one works as before
+        bus.controller_mut(0).set_buttons(Button::A);
+        bus.controller_mut(1).set_buttons(Button::A);
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        assert_eq!(port(&mut bus, 0x4016), 1);
+        assert_eq!(port(&mut bus, 0x4017) & 0b1_0001, 0);
+        assert_eq!(read_position(&mut bus, 3), 0x80);
+        bus.device_mut().unwrap().mouse(0.5, 0.5, true);
+        assert_eq!(port(&mut bus, 0x4017) & 0b1_0000, 0b1_0000);
+
+        // a savestate keeps the shift register, halfway through the position here
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        for _ in 0..4 {
+            port(&mut bus, 0x4017);
+        }
+        let state = bus.save_state();
+        let mut restored = Bus::new();
+        assert!(restored.load_state(&state).is_err());
+        restored.set_device(Some(Box::new(Vaus::new(VausVariant::Nes))));
+        restored.load_state(&state).unwrap();
+        let low = (0..4).fold(0, |bits, _| bits << 1 | (!port(&mut restored, 0x4017) >> 3 & 1));
+        assert_eq!(low, 0xAA & 0x0F);
+    }
+}
@@ -0,0 +1,661 @@
+use nestacean::nes::cpu::{Cpu, CpuStepResult};
+use nestacean::nes::nestest::HeadlessCpu;
+use nestacean::nes::opcodes::opcode_info;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const C: u8 = 0b0000_0001;
+const Z: u8 = 0b0000_0010;
+const I: u8 = 0b0000_0100;
+const D: u8 = 0b0000_1000;
+const V: u8 = 0b0100_0000;
+const N: u8 = 0b1000_0000;
+// B and bit 5 only exist on the stack, registers are compared without them
+const REGISTER_FLAGS: u8 = 0b1100_1111;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndexedIndirect,
+    IndirectIndexed,
+    Relative,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Regs {
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+    pc: u16,
+}
+
+// Instruction level 6502 written straight from the datasheet, independent of the opcode
+// table and the micro-op decoder. Only official opcodes, decimal mode is ignored like on
+// the 2A03.
+struct RefCpu {
+    regs: Regs,
+    mem: Vec<u8>,
+}
+
+impl RefCpu {
+    fn read(&self, addr: u16) -> u8 {
+        self.mem[addr as usize]
+    }
+
+    fn read_u16(&self, addr: u16) -> u16 {
+        self.read(addr) as u16 | (self.read(addr.wrapping_add(1)) as u16) << 8
+    }
+
+    // pointer fetch that stays inside the page, for zero page pointers and JMP ($xxFF)
+    fn read_u16_wrapped(&self, addr: u16) -> u16 {
+        let high = (addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF);
+        self.read(addr) as u16 | (self.read(high) as u16) << 8
+    }
+
+    fn push(&mut self, value: u8) {
+        self.mem[0x0100 + self.regs.sp as usize] = value;
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        self.read(0x0100 + self.regs.sp as u16)
+    }
+
+    fn set_zn(&mut self, value: u8) {
+        self.regs.p &= !(Z | N);
+        if value == 0 {
+            self.regs.p |= Z;
+        }
+        self.regs.p |= value & N;
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.regs.p |= flag;
+        } else {
+            self.regs.p &= !flag;
+        }
+    }
+
+    fn add(&mut self, value: u8) {
+        let sum = self.regs.a as u16 + value as u16 + (self.regs.p & C) as u16;
+        let result = sum as u8;
+        self.set_flag(C, sum > 0xFF);
+        self.set_flag(V, (self.regs.a ^ result) & (value ^ result) & 0x80 != 0);
+        self.regs.a = result;
+        self.set_zn(result);
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(C, register >= value);
+        self.set_zn(register.wrapping_sub(value));
+    }
+
+    // effective address and whether indexing crossed a page
+    fn resolve(&self, mode: Mode, operand: u16) -> (u16, bool) {
+        let r = self.regs;
+        let indexed = |base: u16, index: u8| {
+            let addr = base.wrapping_add(index as u16);
+            (addr, addr & 0xFF00 != base & 0xFF00)
+        };
+        match mode {
+            Mode::ZeroPage => (operand & 0xFF, false),
+            Mode::ZeroPageX => ((operand as u8).wrapping_add(r.x) as u16, false),
+            Mode::ZeroPageY => ((operand as u8).wrapping_add(r.y) as u16, false),
+            Mode::Absolute => (operand, false),
+            Mode::AbsoluteX => indexed(operand, r.x),
+            Mode::AbsoluteY => indexed(operand, r.y),
+            Mode::Indirect => (self.read_u16_wrapped(operand), false),
+            Mode::IndexedIndirect => {
+                let ptr = (operand as u8).wrapping_add(r.x) as u16;
+                (self.read_u16_wrapped(ptr), false)
+            }
+            Mode::IndirectIndexed => indexed(self.read_u16_wrapped(operand & 0xFF), r.y),
+            Mode::Implied | Mode::Accumulator | Mode::Immediate | Mode::Relative => (0, false),
+        }
+    }
+
+    // Executes one instruction and returns its cycle count, or None without touching
+    // anything if the instruction is outside what the oracle models.
+    fn step(&mut self) -> Option<u64> {
+        let pc = self.regs.pc;
+        let opcode = self.read(pc);
+        let (name, mode) = decode(opcode)?;
+        let len = match mode {
+            Mode::Implied | Mode::Accumulator => 1,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 3,
+            _ => 2,
+        };
+        let operand = match len {
+            3 => self.read_u16(pc.wrapping_add(1)),
+            2 => self.read(pc.wrapping_add(1)) as u16,
+            _ => 0,
+        };
+        let (addr, crossed) = self.resolve(mode, operand);
+        let is_store = matches!(name, "STA" | "STX" | "STY");
+        let is_rmw = matches!(name, "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC")
+            && mode != Mode::Accumulator;
+        // a write there starts OAM DMA in the emulator, which the oracle doesn't model
+        if (is_store || is_rmw) && addr == 0x4014 {
+            return None;
+        }
+        self.regs.pc = pc.wrapping_add(len);
+
+        let mut cycles: u64 = match mode {
+            Mode::Implied | Mode::Accumulator | Mode::Immediate | Mode::Relative => 2,
+            Mode::ZeroPage => 3,
+            Mode::ZeroPageX | Mode::ZeroPageY | Mode::Absolute => 4,
+            Mode::AbsoluteX | Mode::AbsoluteY => 4,
+            Mode::IndexedIndirect => 6,
+            Mode::IndirectIndexed => 5,
+            Mode::Indirect => 5,
+        };
+        if is_rmw {
+            cycles += match mode {
+                Mode::AbsoluteX => 3,
+                _ => 2,
+            };
+        } else if is_store {
+            if matches!(
+                mode,
+                Mode::AbsoluteX | Mode::AbsoluteY | Mode::IndirectIndexed
+            ) {
+                cycles += 1;
+            }
+        } else if crossed {
+            cycles += 1;
+        }
+
+        let value = |cpu: &Self| match mode {
+            Mode::Immediate => operand as u8,
+            Mode::Accumulator => cpu.regs.a,
+            _ => cpu.read(addr),
+        };
+        let write_back = |cpu: &mut Self, result: u8| {
+            if mode == Mode::Accumulator {
+                cpu.regs.a = result;
+            } else {
+                cpu.mem[addr as usize] = result;
+            }
+            cpu.set_zn(result);
+        };
+
+        match name {
+            "LDA" => {
+                self.regs.a = value(self);
+                self.set_zn(self.regs.a);
+            }
+            "LDX" => {
+                self.regs.x = value(self);
+                self.set_zn(self.regs.x);
+            }
+            "LDY" => {
+                self.regs.y = value(self);
+                self.set_zn(self.regs.y);
+            }
+            "STA" => self.mem[addr as usize] = self.regs.a,
+            "STX" => self.mem[addr as usize] = self.regs.x,
+            "STY" => self.mem[addr as usize] = self.regs.y,
+            "ADC" => self.add(value(self)),
+            "SBC" => self.add(!value(self)),
+            "AND" => {
+                self.regs.a &= value(self);
+                self.set_zn(self.regs.a);
+            }
+            "ORA" => {
+                self.regs.a |= value(self);
+                self.set_zn(self.regs.a);
+            }
+            "EOR" => {
+                self.regs.a ^= value(self);
+                self.set_zn(self.regs.a);
+            }
+            "BIT" => {
+                let v = value(self);
+                self.set_flag(Z, self.regs.a & v == 0);
+                self.regs.p = (self.regs.p & !(N | V)) | (v & (N | V));
+            }
+            "CMP" => self.compare(self.regs.a, value(self)),
+            "CPX" => self.compare(self.regs.x, value(self)),
+            "CPY" => self.compare(self.regs.y, value(self)),
+            "ASL" => {
+                let v = value(self);
+                self.set_flag(C, v & 0x80 != 0);
+                write_back(self, v << 1);
+            }
+            "LSR" => {
+                let v = value(self);
+                self.set_flag(C, v & 0x01 != 0);
+                write_back(self, v >> 1);
+            }
+            "ROL" => {
+                let v = value(self);
+                let carry_in = self.regs.p & C;
+                self.set_flag(C, v & 0x80 != 0);
+                write_back(self, v << 1 | carry_in);
+            }
+            "ROR" => {
+                let v = value(self);
+                let carry_in = (self.regs.p & C) << 7;
+                self.set_flag(C, v & 0x01 != 0);
+                write_back(self, v >> 1 | carry_in);
+            }
+            "INC" => write_back(self, value(self).wrapping_add(1)),
+            "DEC" => write_back(self, value(self).wrapping_sub(1)),
+            "INX" => {
+                self.regs.x = self.regs.x.wrapping_add(1);
+                self.set_zn(self.regs.x);
+            }
+            "INY" => {
+                self.regs.y = self.regs.y.wrapping_add(1);
+                self.set_zn(self.regs.y);
+            }
+            "DEX" => {
+                self.regs.x = self.regs.x.wrapping_sub(1);
+                self.set_zn(self.regs.x);
+            }
+            "DEY" => {
+                self.regs.y = self.regs.y.wrapping_sub(1);
+                self.set_zn(self.regs.y);
+            }
+            "TAX" => {
+                self.regs.x = self.regs.a;
+                self.set_zn(self.regs.x);
+            }
+            "TAY" => {
+                self.regs.y = self.regs.a;
+                self.set_zn(self.regs.y);
+            }
+            "TXA" => {
+                self.regs.a = self.regs.x;
+                self.set_zn(self.regs.a);
+            }
+            "TYA" => {
+                self.regs.a = self.regs.y;
+                self.set_zn(self.regs.a);
+            }
+            "TSX" => {
+                self.regs.x = self.regs.sp;
+                self.set_zn(self.regs.x);
+            }
+            "TXS" => self.regs.sp = self.regs.x,
+            "CLC" => self.set_flag(C, false),
+            "SEC" => self.set_flag(C, true),
+            "CLI" => self.set_flag(I, false),
+            "SEI" => self.set_flag(I, true),
+            "CLD" => self.set_flag(D, false),
+            "SED" => self.set_flag(D, true),
+            "CLV" => self.set_flag(V, false),
+            "NOP" => {}
+            "PHA" => {
+                self.push(self.regs.a);
+                cycles = 3;
+            }
+            "PHP" => {
+                self.push(self.regs.p | 0b0011_0000);
+                cycles = 3;
+            }
+            "PLA" => {
+                self.regs.a = self.pull();
+                self.set_zn(self.regs.a);
+                cycles = 4;
+            }
+            "PLP" => {
+                self.regs.p = self.pull() & REGISTER_FLAGS;
+                cycles = 4;
+            }
+            "JMP" => {
+                self.regs.pc = if mode == Mode::Absolute {
+                    operand
+                } else {
+                    addr
+                };
+                cycles = if mode == Mode::Absolute { 3 } else { 5 };
+            }
+            "JSR" => {
+                let ret = self.regs.pc.wrapping_sub(1);
+                self.push((ret >> 8) as u8);
+                self.push(ret as u8);
+                self.regs.pc = operand;
+                cycles = 6;
+            }
+            "RTS" => {
+                let low = self.pull() as u16;
+                let high = self.pull() as u16;
+                self.regs.pc = (high << 8 | low).wrapping_add(1);
+                cycles = 6;
+            }
+            "RTI" => {
+                self.regs.p = self.pull() & REGISTER_FLAGS;
+                let low = self.pull() as u16;
+                let high = self.pull() as u16;
+                self.regs.pc = high << 8 | low;
+                cycles = 6;
+            }
+            branch => {
+                let p = self.regs.p;
+                let taken = match branch {
+                    "BPL" => p & N == 0,
+                    "BMI" => p & N != 0,
+                    "BVC" => p & V == 0,
+                    "BVS" => p & V != 0,
+                    "BCC" => p & C == 0,
+                    "BCS" => p & C != 0,
+                    "BNE" => p & Z == 0,
+                    "BEQ" => p & Z != 0,
+                    _ => unreachable!("{} has no semantics", branch),
+                };
+                if taken {
+                    let next = self.regs.pc;
+                    let target = next.wrapping_add(operand as u8 as i8 as u16);
+                    cycles += if target & 0xFF00 != next & 0xFF00 {
+                        2
+                    } else {
+                        1
+                    };
+                    self.regs.pc = target;
+                }
+            }
+        }
+        Some(cycles)
+    }
+}
+
+// BRK is left out since the emulator treats it as the end of the program
+fn decode(opcode: u8) -> Option<(&'static str, Mode)> {
+    use Mode::*;
+    let decoded = match opcode {
+        0x69 => ("ADC", Immediate),
+        0x65 => ("ADC", ZeroPage),
+        0x75 => ("ADC", ZeroPageX),
+        0x6D => ("ADC", Absolute),
+        0x7D => ("ADC", AbsoluteX),
+        0x79 => ("ADC", AbsoluteY),
+        0x61 => ("ADC", IndexedIndirect),
+        0x71 => ("ADC", IndirectIndexed),
+        0x29 => ("AND", Immediate),
+        0x25 => ("AND", ZeroPage),
+        0x35 => ("AND", ZeroPageX),
+        0x2D => ("AND", Absolute),
+        0x3D => ("AND", AbsoluteX),
+        0x39 => ("AND", AbsoluteY),
+        0x21 => ("AND", IndexedIndirect),
+        0x31 => ("AND", IndirectIndexed),
+        0x0A => ("ASL", Accumulator),
+        0x06 => ("ASL", ZeroPage),
+        0x16 => ("ASL", ZeroPageX),
+        0x0E => ("ASL", Absolute),
+        0x1E => ("ASL", AbsoluteX),
+        0x90 => ("BCC", Relative),
+        0xB0 => ("BCS", Relative),
+        0xF0 => ("BEQ", Relative),
+        0x30 => ("BMI", Relative),
+        0xD0 => ("BNE", Relative),
+        0x10 => ("BPL", Relative),
+        0x50 => ("BVC", Relative),
+        0x70 => ("BVS", Relative),
+        0x24 => ("BIT", ZeroPage),
+        0x2C => ("BIT", Absolute),
+        0x18 => ("CLC", Implied),
+        0xD8 => ("CLD", Implied),
+        0x58 => ("CLI", Implied),
+        0xB8 => ("CLV", Implied),
+        0xC9 => ("CMP", Immediate),
+        0xC5 => ("CMP", ZeroPage),
+        0xD5 => ("CMP", ZeroPageX),
+        0xCD => ("CMP", Absolute),
+        0xDD => ("CMP", AbsoluteX),
+        0xD9 => ("CMP", AbsoluteY),
+        0xC1 => ("CMP", IndexedIndirect),
+        0xD1 => ("CMP", IndirectIndexed),
+        0xE0 => ("CPX", Immediate),
+        0xE4 => ("CPX", ZeroPage),
+        0xEC => ("CPX", Absolute),
+        0xC0 => ("CPY", Immediate),
+        0xC4 => ("CPY", ZeroPage),
+        0xCC => ("CPY", Absolute),
+        0xC6 => ("DEC", ZeroPage),
+        0xD6 => ("DEC", ZeroPageX),
+        0xCE => ("DEC", Absolute),
+        0xDE => ("DEC", AbsoluteX),
+        0xCA => ("DEX", Implied),
+        0x88 => ("DEY", Implied),
+        0x49 => ("EOR", Immediate),
+        0x45 => ("EOR", ZeroPage),
+        0x55 => ("EOR", ZeroPageX),
+        0x4D => ("EOR", Absolute),
+        0x5D => ("EOR", AbsoluteX),
+        0x59 => ("EOR", AbsoluteY),
+        0x41 => ("EOR", IndexedIndirect),
+        0x51 => ("EOR", IndirectIndexed),
+        0xE6 => ("INC", ZeroPage),
+        0xF6 => ("INC", ZeroPageX),
+        0xEE => ("INC", Absolute),
+        0xFE => ("INC", AbsoluteX),
+        0xE8 => ("INX", Implied),
+        0xC8 => ("INY", Implied),
+        0x4C => ("JMP", Absolute),
+        0x6C => ("JMP", Indirect),
+        0x20 => ("JSR", Absolute),
+        0xA9 => ("LDA", Immediate),
+        0xA5 => ("LDA", ZeroPage),
+        0xB5 => ("LDA", ZeroPageX),
+        0xAD => ("LDA", Absolute),
+        0xBD => ("LDA", AbsoluteX),
+        0xB9 => ("LDA", AbsoluteY),
+        0xA1 => ("LDA", IndexedIndirect),
+        0xB1 => ("LDA", IndirectIndexed),
+        0xA2 => ("LDX", Immediate),
+        0xA6 => ("LDX", ZeroPage),
+        0xB6 => ("LDX", ZeroPageY),
+        0xAE => ("LDX", Absolute),
+        0xBE => ("LDX", AbsoluteY),
+        0xA0 => ("LDY", Immediate),
+        0xA4 => ("LDY", ZeroPage),
+        0xB4 => ("LDY", ZeroPageX),
+        0xAC => ("LDY", Absolute),
+        0xBC => ("LDY", AbsoluteX),
+        0x4A => ("LSR", Accumulator),
+        0x46 => ("LSR", ZeroPage),
+        0x56 => ("LSR", ZeroPageX),
+        0x4E => ("LSR", Absolute),
+        0x5E => ("LSR", AbsoluteX),
+        0xEA => ("NOP", Implied),
+        0x09 => ("ORA", Immediate),
+        0x05 => ("ORA", ZeroPage),
+        0x15 => ("ORA", ZeroPageX),
+        0x0D => ("ORA", Absolute),
+        0x1D => ("ORA", AbsoluteX),
+        0x19 => ("ORA", AbsoluteY),
+        0x01 => ("ORA", IndexedIndirect),
+        0x11 => ("ORA", IndirectIndexed),
+        0x48 => ("PHA", Implied),
+        0x08 => ("PHP", Implied),
+        0x68 => ("PLA", Implied),
+        0x28 => ("PLP", Implied),
+        0x2A => ("ROL", Accumulator),
+        0x26 => ("ROL", ZeroPage),
+        0x36 => ("ROL", ZeroPageX),
+        0x2E => ("ROL", Absolute),
+        0x3E => ("ROL", AbsoluteX),
+        0x6A => ("ROR", Accumulator),
+        0x66 => ("ROR", ZeroPage),
+        0x76 => ("ROR", ZeroPageX),
+        0x6E => ("ROR", Absolute),
+        0x7E => ("ROR", AbsoluteX),
+        0x40 => ("RTI", Implied),
+        0x60 => ("RTS", Implied),
+        0xE9 => ("SBC", Immediate),
+        0xE5 => ("SBC", ZeroPage),
+        0xF5 => ("SBC", ZeroPageX),
+        0xED => ("SBC", Absolute),
+        0xFD => ("SBC", AbsoluteX),
+        0xF9 => ("SBC", AbsoluteY),
+        0xE1 => ("SBC", IndexedIndirect),
+        0xF1 => ("SBC", IndirectIndexed),
+        0x38 => ("SEC", Implied),
+        0xF8 => ("SED", Implied),
+        0x78 => ("SEI", Implied),
+        0x85 => ("STA", ZeroPage),
+        0x95 => ("STA", ZeroPageX),
+        0x8D => ("STA", Absolute),
+        0x9D => ("STA", AbsoluteX),
+        0x99 => ("STA", AbsoluteY),
+        0x81 => ("STA", IndexedIndirect),
+        0x91 => ("STA", IndirectIndexed),
+        0x86 => ("STX", ZeroPage),
+        0x96 => ("STX", ZeroPageY),
+        0x8E => ("STX", Absolute),
+        0x84 => ("STY", ZeroPage),
+        0x94 => ("STY", ZeroPageX),
+        0x8C => ("STY", Absolute),
+        0xAA => ("TAX", Implied),
+        0xA8 => ("TAY", Implied),
+        0xBA => ("TSX", Implied),
+        0x8A => ("TXA", Implied),
+        0x9A => ("TXS", Implied),
+        0x98 => ("TYA", Implied),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+fn cpu_regs(cpu: &HeadlessCpu) -> Regs {
+    Regs {
+        a: cpu.get_accumulator(),
+        x: cpu.get_index_x(),
+        y: cpu.get_index_y(),
+        p: cpu.get_status_p() & REGISTER_FLAGS,
+        sp: cpu.get_sp(),
+        pc: cpu.get_pc(),
+    }
+}
+
+// Runs one random machine state for up to `steps` instructions on both CPUs and returns a
+// description of the first divergence.
+fn run_case(rng: &mut StdRng, steps: usize) -> Result<(), String> {
+    let mut mem = vec![0u8; 0x10000];
+    rng.fill(&mut mem[..]);
+    let regs = Regs {
+        a: rng.random(),
+        x: rng.random(),
+        y: rng.random(),
+        p: rng.random::<u8>() & REGISTER_FLAGS,
+        sp: rng.random(),
+        pc: rng.random(),
+    };
+    // start on an instruction the oracle knows so every case does some work
+    let official: Vec<u8> = (0..=0xFFu8).filter(|&op| decode(op).is_some()).collect();
+    mem[regs.pc as usize] = official[rng.random_range(0..official.len())];
+
+    let mut cpu = Cpu::new();
+    for (addr, byte) in mem.iter().enumerate() {
+        cpu.mem_write(addr as u16, *byte);
+    }
+    cpu.set_accumulator(regs.a);
+    cpu.set_index_x(regs.x);
+    cpu.set_index_y(regs.y);
+    cpu.set_status_p(regs.p);
+    cpu.set_sp(regs.sp);
+    cpu.set_pc(regs.pc);
+    let mut reference = RefCpu { regs, mem };
+
+    for step in 0..steps {
+        let before = reference.regs;
+        let opcode = reference.read(before.pc);
+        let Some(expected_cycles) = reference.step() else {
+            break;
+        };
+        let start = cpu.get_cycles();
+        let result = cpu.run_instruction();
+        let cycles = cpu.get_cycles() - start;
+        let actual = cpu_regs(&cpu);
+        if result != CpuStepResult::Running || actual != reference.regs || cycles != expected_cycles
+        {
+            return Err(format!(
+                "step {} {:02X} {} at {:04X}\n  before:   {:02X?}\n  expected: {:02X?} in {} cycles\n  actual:   {:02X?} in {} cycles ({:?})",
+                step,
+                opcode,
+                opcode_info(opcode).mnemonic.name(),
+                before.pc,
+                before,
+                reference.regs,
+                expected_cycles,
+                actual,
+                cycles,
+                result,
+            ));
+        }
+    }
+
+    let memory = cpu.get_memory();
+    if let Some(addr) = (0..0x10000).find(|&addr| memory[addr] != reference.mem[addr]) {
+        return Err(format!(
+            "memory at {:04X}: expected {:02X}, got {:02X}",
+            addr, reference.mem[addr], memory[addr]
+        ));
+    }
+    Ok(())
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // NESTACEAN_FUZZ_SEED and NESTACEAN_FUZZ_CASES override the defaults for longer runs
+    #[test]
+    fn test_differential_fuzz() {
+        let seed = env_or("NESTACEAN_FUZZ_SEED", 0x6502);
+        let cases = env_or("NESTACEAN_FUZZ_CASES", 200);
+        let mut rng = StdRng::seed_from_u64(seed);
+        for case in 0..cases {
+            if let Err(divergence) = run_case(&mut rng, 64) {
+                panic!("seed {:#x} case {}: {}", seed, case, divergence);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reference_matches_known_sequence() {
+        // LDA #$80, LSR A, ROR $10, with $10 = $01
+        let mut mem = vec![0u8; 0x10000];
+        mem[0x8000..0x8005].copy_from_slice(&[0xA9, 0x80, 0x4A, 0x66, 0x10]);
+        mem[0x10] = 0x01;
+        let regs = Regs {
+            a: 0,
+            x: 0,
+            y: 0,
+            p: C,
+            sp: 0xFD,
+            pc: 0x8000,
+        };
+        let mut reference = RefCpu { regs, mem };
+        assert_eq!(reference.step(), Some(2));
+        assert_eq!(reference.regs.p, C | N);
+        assert_eq!(reference.step(), Some(2));
+        assert_eq!(reference.regs.a, 0x40);
+        assert_eq!(reference.regs.p, 0);
+        assert_eq!(reference.step(), Some(5));
+        assert_eq!(reference.mem[0x10], 0x00);
+        assert_eq!(reference.regs.p, C | Z);
+    }
+}
@@ -0,0 +1,177 @@
+use nestacean::nes::config::Config;
+use nestacean::nes::controller::Button;
+use nestacean::nes::gamepad::{PadState, DEFAULT_DEADZONE};
+use nestacean::nes::input::{
+    DpadFilter, HeldKeys, InputMap, OppositeDirections, Turbo, DEFAULT_TURBO_PERIOD,
+};
+use sdl2::controller::{Axis, Button as PadButton};
+use sdl2::keyboard::Keycode;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_input_map() {
+        let mut map = InputMap::default();
+        assert_eq!(map.binding(Button::UP), Some(Keycode::W));
+        assert_eq!(map.button(Keycode::K), Some(Button::A));
+        assert_eq!(map.button(Keycode::X), None);
+
+        map.set_binding(Button::A, Keycode::X);
+        assert_eq!(map.button(Keycode::X), Some(Button::A));
+        assert_eq!(map.button(Keycode::K), None);
+        // a key only presses one button, it moves to the last one it was bound to
+        map.set_binding(Button::B, Keycode::X);
+        assert_eq!(map.button(Keycode::X), Some(Button::B));
+        assert_eq!(map.binding(Button::A), None);
+        // sets of buttons can't be bound
+        map.set_binding(Button::A | Button::B, Keycode::Z);
+        assert_eq!(map.button(Keycode::Z), None);
+
+        map.clear_binding(Button::B);
+        assert_eq!(map.button(Keycode::X), None);
+        assert_eq!(InputMap::empty().button(Keycode::W), None);
+    }
+
+    #[test]
+    fn test_config_file() {
+        let config = Config::parse(
+            "# arrows instead of WASD\n\
+             input.up = Up\n\
+             input.down = Down\n\
+             \n\
+             input.a = x\n\
+             input.select = none\n",
+        )
+        .unwrap();
+        assert_eq!(config.input.binding(Button::UP), Some(Keycode::Up));
+        assert_eq!(config.input.binding(Button::A), Some(Keycode::X));
+        assert_eq!(config.input.binding(Button::SELECT), None);
+        // the rest keep their defaults
+        assert_eq!(config.input.binding(Button::LEFT), Some(Keycode::A));
+
+        let err = Config::parse("input.a = K\ninput.turbo = J\n").unwrap_err();
+        assert!(err.contains("line 2"), "{}", err);
+        assert!(Config::parse("input.a\n").is_err());
+        assert!(Config::parse("video.scale = 3\n").is_err());
+        assert_eq!(config.gamepad_deadzone, DEFAULT_DEADZONE);
+        let config = Config::parse("gamepad.deadzone = 0.5\ninput.a = x\n").unwrap();
+        assert_eq!(config.gamepad_deadzone, 0.5);
+        assert!(Config::parse("gamepad.deadzone = 2\n").is_err());
+
+        // saving writes every binding back out
+        let dir = std::env::temp_dir().join(format!("nestacean-config-{}", std::process::id()));
+        let path = dir.join("nestacean.cfg");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_gamepad_state() {
+        let mut pad = PadState::default();
+        // laid out like a NES pad, B on the bottom face button and A to its right
+        pad.button_event(PadButton::A, true);
+        pad.button_event(PadButton::B, true);
+        pad.button_event(PadButton::Back, true);
+        pad.button_event(PadButton::LeftShoulder, true);
+        assert_eq!(pad.buttons(0.25), Button::A | Button::B | Button::SELECT);
+        // with turbo on the buttons beside them
+        pad.button_event(PadButton::Y, true);
+        assert_eq!(pad.turbo(), Button::A);
+        pad.button_event(PadButton::Y, false);
+        assert!(pad.turbo().is_empty());
+        pad.button_event(PadButton::A, false);
+        pad.button_event(PadButton::B, false);
+        pad.button_event(PadButton::Back, false);
+        assert!(pad.buttons(0.25).is_empty());
+
+        // the stick steers past the deadzone, diagonals included
+        pad.axis_event(Axis::LeftX, -8000);
+        assert!(pad.buttons(0.25).is_empty());
+        assert_eq!(pad.buttons(0.2), Button::LEFT);
+        pad.axis_event(Axis::LeftX, 30000);
+        pad.axis_event(Axis::LeftY, 30000);
+        assert_eq!(pad.buttons(0.25), Button::RIGHT | Button::DOWN);
+        pad.axis_event(Axis::RightY, -30000);
+        pad.axis_event(Axis::LeftY, 0);
+        assert_eq!(pad.buttons(0.25), Button::RIGHT);
+        // and the D-pad works with the stick centred or not
+        pad.button_event(PadButton::DPadUp, true);
+        assert_eq!(pad.buttons(0.25), Button::RIGHT | Button::UP);
+        pad.axis_event(Axis::LeftX, 0);
+        assert_eq!(pad.buttons(1.0), Button::UP);
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut map = InputMap::default();
+        assert_eq!(map.turbo_button(Keycode::I), Some(Button::A));
+        // only A and B have turbo, and a key is either turbo or not
+        map.set_turbo_binding(Button::START, Keycode::T);
+        assert_eq!(map.turbo_button(Keycode::T), None);
+        map.set_turbo_binding(Button::B, Keycode::J);
+        assert_eq!((map.button(Keycode::J), map.binding(Button::B)), (None, None));
+        map.set_binding(Button::B, Keycode::J);
+        assert_eq!(map.turbo_binding(Button::B), None);
+
+        let mut keys = HeldKeys::default();
+        keys.key_event(&map, Keycode::I, true);
+        keys.key_event(&map, Keycode::W, true);
+        assert_eq!((keys.buttons, keys.turbo), (Button::UP, Button::A));
+
+        // pressed for a period, released for one, in step with the frame count
+        let turbo = Turbo::new(3);
+        let pattern: Vec<bool> =
+            (0..12).map(|frame| turbo.buttons(keys.turbo, frame) == Button::A).collect();
+        let expected = [true, true, true, false, false, false];
+        assert_eq!(pattern, [expected, expected].concat());
+        assert_eq!(Turbo::new(0).period(), 1);
+        keys.key_event(&map, Keycode::I, false);
+        assert!(turbo.buttons(keys.turbo, 0).is_empty());
+
+        let config = Config::parse("input.turbo_b = t\nturbo.period = 4\n").unwrap();
+        assert_eq!(config.input.turbo_binding(Button::B), Some(Keycode::T));
+        assert_eq!(config.turbo.period(), 4);
+        assert_eq!(Config::default().turbo.period(), DEFAULT_TURBO_PERIOD);
+        assert!(Config::parse("input.turbo_start = T\n").is_err());
+        assert!(Config::parse("turbo.period = 0\n").is_err());
+        assert_eq!(Config::parse(&config.to_string()).unwrap(), config);
+    }
+
+    #[test]
+    fn test_opposite_directions() {
+        let (left, right) = (Button::LEFT, Button::RIGHT);
+        let both = left | right | Button::A;
+        let mut allow = DpadFilter::new(OppositeDirections::Allow);
+        assert_eq!(allow.apply(both), both);
+
+        let mut neutralize = DpadFilter::new(OppositeDirections::Neutralize);
+        assert_eq!(neutralize.apply(left | Button::UP), left | Button::UP);
+        assert_eq!(neutralize.apply(both | Button::UP), Button::A | Button::UP);
+
+        // the newer direction wins, and the older one comes back when it's let go
+        let mut last = DpadFilter::default();
+        assert_eq!(last.policy(), OppositeDirections::LastPressed);
+        assert_eq!(last.apply(left), left);
+        assert_eq!(last.apply(left | right), right);
+        assert_eq!(last.apply(left | right | Button::UP), right | Button::UP);
+        assert_eq!(last.apply(left), left);
+        assert_eq!(last.apply(left | right), right);
+        // the same frame for both leaves neither
+        assert_eq!(last.apply(Button::NONE), Button::NONE);
+        assert_eq!(last.apply(both), Button::A);
+        assert_eq!(last.apply(both), Button::A);
+        assert_eq!(last.apply(right | Button::A), right | Button::A);
+        assert_eq!(last.apply(both), left | Button::A);
+
+        let config = Config::parse("input.opposite_directions = allow
+").unwrap();
+        assert_eq!(config.opposite_directions, OppositeDirections::Allow);
+        assert!(Config::parse("input.opposite_directions = both
+").is_err());
+        assert_eq!(Config::parse(&config.to_string()).unwrap(), config);
+    }
+}
@@ -0,0 +1,755 @@
+use nestacean::nes::bus::Bus;
+use nestacean::nes::cart::{Cart, Mirroring};
+use nestacean::nes::mapper::fme7::Fme7;
+use nestacean::nes::mapper::inl_nsf::InlNsf;
+use nestacean::nes::mapper::mmc3::{Mmc3, Mmc3Revision};
+use nestacean::nes::mapper::mmc5::Mmc5;
+use nestacean::nes::mapper::namcot108::{Namcot108, Namcot108Board};
+use nestacean::nes::mapper::vrc6::{Vrc6, Vrc6Variant};
+use nestacean::nes::mapper::{self, Mapper};
+use nestacean::nes::mem::{Read, Write};
+
+// iNES image for `mapper` with every 8 KiB PRG page and 1 KiB CHR page filled with its index
+fn ines_rom(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+    let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, mapper << 4, mapper & 0xF0];
+    raw.resize(16, 0);
+    for page in 0..prg_banks as usize * 2 {
+        raw.extend(std::iter::repeat_n(page as u8, 0x2000));
+    }
+    for page in 0..chr_banks as usize * 8 {
+        raw.extend(std::iter::repeat_n(page as u8, 0x0400));
+    }
+    raw
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // MMC3 tests
+    #[test]
+    fn test_mmc3_prg_banking() {
+        // 16 pages of 8 KiB
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(4, 8, 8)).unwrap());
+        bus.write(0x8000, 6);
+        bus.write(0x8001, 3);
+        bus.write(0x8000, 7);
+        bus.write(0x8001, 5);
+        assert_eq!(bus.read(0x8000), 3);
+        assert_eq!(bus.read(0xA000), 5);
+        assert_eq!(bus.read(0xC000), 14);
+        assert_eq!(bus.read(0xE000), 15);
+
+        // PRG mode 1 swaps $8000 and $C000
+        bus.write(0x8000, 0b0100_0110);
+        assert_eq!(bus.read(0x8000), 14);
+        assert_eq!(bus.read(0xA000), 5);
+        assert_eq!(bus.read(0xC000), 3);
+        assert_eq!(bus.read(0xFFFF), 15);
+    }
+
+    #[test]
+    fn test_mmc3_chr_banking() {
+        let mut mmc3 = Mmc3::new(Mmc3Revision::Sharp);
+        let mut cart = Cart::from_bytes(&ines_rom(4, 2, 8)).unwrap();
+        for (reg, bank) in [(0, 9), (1, 20), (2, 30), (3, 31), (4, 40), (5, 41)] {
+            mmc3.cpu_write(&mut cart, 0x8000, reg);
+            mmc3.cpu_write(&mut cart, 0x8001, bank);
+        }
+        // 2 KiB banks ignore their low bit
+        let pages = |mmc3: &Mmc3, cart: &Cart| {
+            (0..8).map(|page| mmc3.ppu_peek(cart, page * 0x400)).collect::<Vec<_>>()
+        };
+        assert_eq!(pages(&mmc3, &cart), vec![8, 9, 20, 21, 30, 31, 40, 41]);
+
+        mmc3.cpu_write(&mut cart, 0x8000, 0b1000_0000);
+        assert_eq!(pages(&mmc3, &cart), vec![30, 31, 40, 41, 8, 9, 20, 21]);
+    }
+
+    #[test]
+    fn test_mmc3_mirroring_and_prg_ram_protect() {
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(4, 2, 1)).unwrap());
+        bus.write(0xA000, 1);
+        assert_eq!(bus.cart().unwrap().screen_mirroring, Mirroring::Horizontal);
+        bus.ppu_bus().write(0x2000, 0x42);
+        assert_eq!(bus.ppu_bus().read(0x2400), 0x42);
+        bus.write(0xA000, 0);
+        assert_eq!(bus.cart().unwrap().screen_mirroring, Mirroring::Vertical);
+
+        bus.write(0x6000, 0x12);
+        bus.write(0xA001, 0b1100_0000); // enabled, write protected
+        bus.write(0x6000, 0x34);
+        assert_eq!(bus.read(0x6000), 0x12);
+        bus.write(0xA001, 0); // disabled, reads open bus
+        bus.write(0x0000, 0x77);
+        assert_eq!(bus.read(0x6000), 0x77);
+        bus.write(0xA001, 0b1000_0000);
+        bus.write(0x6000, 0x34);
+        assert_eq!(bus.read(0x6000), 0x34);
+    }
+
+    // clocks `mapper` once per scanline and returns the scanlines that raised IRQ
+    fn irq_scanlines(mapper: &mut dyn Mapper, cart: &mut Cart, scanlines: usize) -> Vec<usize> {
+        let mut fired = Vec::new();
+        for scanline in 0..scanlines {
+            mapper.a12_rising_edge();
+            if mapper.irq() {
+                fired.push(scanline);
+                mapper.cpu_write(cart, 0xE000, 0); // acknowledge
+                mapper.cpu_write(cart, 0xE001, 0);
+            }
+        }
+        fired
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq() {
+        let mut cart = Cart::from_bytes(&ines_rom(4, 2, 1)).unwrap();
+        let mut mmc3 = mapper::new(4).unwrap();
+        mmc3.cpu_write(&mut cart, 0xC000, 3);
+        mmc3.cpu_write(&mut cart, 0xC001, 0);
+        mmc3.cpu_write(&mut cart, 0xE001, 0);
+        // reload to 3, then 2, 1, 0
+        assert_eq!(irq_scanlines(mmc3.as_mut(), &mut cart, 10), vec![3, 7]);
+        assert!(!mmc3.irq());
+
+        // disabled counters keep counting but don't fire
+        mmc3.cpu_write(&mut cart, 0xE000, 0);
+        mmc3.a12_rising_edge();
+        mmc3.a12_rising_edge();
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn test_mmc3_irq_revisions_with_zero_latch() {
+        for (revision, expected) in [
+            (Mmc3Revision::Sharp, vec![0, 1, 2, 3]),
+            (Mmc3Revision::Nec, vec![0]),
+        ] {
+            let mut cart = Cart::from_bytes(&ines_rom(4, 2, 1)).unwrap();
+            let mut mmc3 = Mmc3::new(revision);
+            mmc3.cpu_write(&mut cart, 0xC000, 0);
+            mmc3.cpu_write(&mut cart, 0xC001, 0);
+            mmc3.cpu_write(&mut cart, 0xE001, 0);
+            assert_eq!(irq_scanlines(&mut mmc3, &mut cart, 4), expected, "{:?}", revision);
+        }
+    }
+
+    #[test]
+    fn test_mmc3_irq_reaches_bus() {
+        let rom = ines_rom(4, 2, 1);
+        let mut mmc3 = Mmc3::new(Mmc3Revision::Sharp);
+        let mut cart = Cart::from_bytes(&rom).unwrap();
+        mmc3.cpu_write(&mut cart, 0xC001, 0);
+        mmc3.cpu_write(&mut cart, 0xE001, 0);
+        mmc3.a12_rising_edge();
+        let bus = Bus::with_mapper(Cart::from_bytes(&rom).unwrap(), Box::new(mmc3));
+        assert!(bus.irq());
+    }
+
+    // the scanline an MMC3 raises IRQ on in the first frame, rendering set up by `ctrl`
+    fn mmc3_irq_scanline(ctrl: u8, latch: u8) -> Option<u16> {
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(4, 2, 1)).unwrap());
+        bus.write(0xC000, latch);
+        bus.write(0xC001, 0);
+        bus.write(0xE001, 0);
+        // no sprites on screen
+        for _ in 0..256 {
+            bus.write(0x2004, 0xFF);
+        }
+        bus.write(0x2000, ctrl);
+        bus.write(0x2001, 0x18);
+        while bus.ppu().frame_count() == 0 {
+            bus.tick(1);
+            if bus.irq() {
+                return Some(bus.ppu().scanline());
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_mmc3_clocked_by_ppu_a12() {
+        // sprites at $1000: one filtered rise a line, the first one reloads the counter
+        assert_eq!(mmc3_irq_scanline(0x08, 10), Some(10));
+        // 8x16 sprites fetch tile $FF for empty slots, which is in the $1000 table
+        assert_eq!(mmc3_irq_scanline(0x20, 10), Some(10));
+        // everything at $0000 never raises A12
+        assert_eq!(mmc3_irq_scanline(0x00, 10), None);
+
+        // pointing v at $1000 with PPUADDR while not rendering is a rise too
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(4, 2, 1)).unwrap());
+        bus.write(0xC000, 0);
+        bus.write(0xE001, 0);
+        bus.tick(4);
+        bus.write(0x2006, 0x10);
+        assert!(!bus.irq());
+        bus.write(0x2006, 0x00);
+        assert!(bus.irq());
+    }
+
+    // MMC5 tests
+    fn mmc5_cart() -> Cart {
+        Cart::from_bytes(&ines_rom(5, 8, 8)).unwrap()
+    }
+
+    // One scanline of PPU fetches as the MMC5 sees them, starting with the tile fetch that
+    // completes scanline detection. Background attributes come from $23C0, patterns from $0000.
+    fn fetch_scanline(mmc5: &mut Mmc5, cart: &Cart) -> Vec<Option<u8>> {
+        let mut reads = Vec::new();
+        for tile in (2..34).chain(0..2) {
+            if tile == 0 {
+                for _ in 0..8 {
+                    mmc5.nametable_read(cart, 0x2000);
+                    mmc5.nametable_read(cart, 0x2000);
+                    mmc5.ppu_read(cart, 0x1000);
+                    mmc5.ppu_read(cart, 0x1008);
+                }
+            }
+            reads.push(mmc5.nametable_read(cart, 0x2000 + tile % 32));
+            reads.push(mmc5.nametable_read(cart, 0x23C0));
+            mmc5.ppu_read(cart, 0x0000);
+            mmc5.ppu_read(cart, 0x0008);
+        }
+        // the dummy fetches, of the next line's first tile
+        mmc5.nametable_read(cart, 0x2002);
+        mmc5.nametable_read(cart, 0x2002);
+        reads
+    }
+
+    fn start_frame(mmc5: &mut Mmc5, cart: &Cart) {
+        mmc5.nametable_read(cart, 0x2002);
+        mmc5.nametable_read(cart, 0x2002);
+    }
+
+    #[test]
+    fn test_mmc5_prg_banking() {
+        let mut bus = Bus::with_cart(mmc5_cart());
+        // power on: mode 3 with the last page at $E000
+        assert_eq!(bus.read(0xE000), 15);
+
+        bus.write(0x5100, 0); // one 32 KiB bank
+        bus.write(0x5117, 0x87);
+        assert_eq!(bus.read(0x8000), 4);
+        assert_eq!(bus.read(0xE000), 7);
+
+        bus.write(0x5100, 2); // 16 + 8 + 8
+        bus.write(0x5115, 0x83);
+        bus.write(0x5116, 0x89);
+        bus.write(0x5117, 0x8F);
+        assert_eq!(bus.read(0x8000), 2);
+        assert_eq!(bus.read(0xA000), 3);
+        assert_eq!(bus.read(0xC000), 9);
+        assert_eq!(bus.read(0xE000), 15);
+
+        // PRG RAM in a ROM slot, only writable once both protect registers are set
+        bus.write(0x5116, 0x00);
+        bus.write(0xC000, 0x42);
+        assert_eq!(bus.read(0xC000), 0);
+        bus.write(0x5102, 0b10);
+        bus.write(0x5103, 0b01);
+        bus.write(0xC000, 0x42);
+        assert_eq!(bus.read(0xC000), 0x42);
+        bus.write(0x5113, 0);
+        assert_eq!(bus.read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_mmc5_chr_banking() {
+        let mut cart = mmc5_cart();
+        let mut mmc5 = Mmc5::new();
+        mmc5.cpu_write(&mut cart, 0x5101, 3); // 1 KiB pages
+        for reg in 0..12 {
+            mmc5.cpu_write(&mut cart, 0x5120 + reg, 10 + reg as u8);
+        }
+        // set B was written last, so 8x8 mode uses it everywhere
+        assert_eq!(mmc5.ppu_read(&cart, 0x0000), 18);
+        assert_eq!(mmc5.ppu_read(&cart, 0x1C00), 21);
+        mmc5.cpu_write(&mut cart, 0x5127, 7);
+        assert_eq!(mmc5.ppu_read(&cart, 0x1C00), 7);
+
+        mmc5.cpu_write(&mut cart, 0x5101, 1); // 4 KiB pages from $5123 and $5127
+        assert_eq!(mmc5.ppu_read(&cart, 0x0400), 13 * 4 + 1);
+        assert_eq!(mmc5.ppu_read(&cart, 0x1400), 7 * 4 + 1);
+    }
+
+    #[test]
+    fn test_mmc5_sprite_8x16_chr_sets() {
+        let mut cart = mmc5_cart();
+        let mut mmc5 = Mmc5::new();
+        mmc5.cpu_write(&mut cart, 0x5101, 3);
+        mmc5.cpu_write(&mut cart, 0x5124, 7); // A, sprite patterns at $1000
+        mmc5.cpu_write(&mut cart, 0x5128, 9); // B, background patterns at $0000
+        mmc5.ppu_register_write(0x2000, 0b0010_0000);
+        start_frame(&mut mmc5, &cart);
+        mmc5.nametable_read(&cart, 0x2002);
+        assert_eq!(mmc5.ppu_read(&cart, 0x0000), 9);
+        for tile in 3..35 {
+            mmc5.nametable_read(&cart, 0x2000 + tile);
+        }
+        assert_eq!(mmc5.ppu_read(&cart, 0x1000), 7);
+    }
+
+    #[test]
+    fn test_mmc5_scanline_irq() {
+        let mut cart = mmc5_cart();
+        let mut mmc5 = Mmc5::new();
+        mmc5.cpu_write(&mut cart, 0x5203, 5);
+        mmc5.cpu_write(&mut cart, 0x5204, 0x80);
+        start_frame(&mut mmc5, &cart);
+        for _ in 0..5 {
+            fetch_scanline(&mut mmc5, &cart);
+        }
+        assert!(!mmc5.irq());
+        assert_eq!(mmc5.cpu_read(&cart, 0x5204), Some(0x40)); // in frame
+        fetch_scanline(&mut mmc5, &cart);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(&cart, 0x5204), Some(0xC0));
+        assert!(!mmc5.irq());
+
+        // no PPU reads for a few CPU cycles, e.g. vblank
+        for _ in 0..3 {
+            mmc5.cpu_clock();
+        }
+        assert_eq!(mmc5.cpu_read(&cart, 0x5204), Some(0x00));
+    }
+
+    #[test]
+    fn test_mmc5_exram_and_fill_mode() {
+        let mut cart = mmc5_cart();
+        let mut mmc5 = Mmc5::new();
+        // plain RAM in mode 2
+        mmc5.cpu_write(&mut cart, 0x5104, 2);
+        mmc5.cpu_write(&mut cart, 0x5C05, 0x33);
+        assert_eq!(mmc5.cpu_read(&cart, 0x5C05), Some(0x33));
+        // read only in mode 3
+        mmc5.cpu_write(&mut cart, 0x5104, 3);
+        mmc5.cpu_write(&mut cart, 0x5C05, 0x44);
+        assert_eq!(mmc5.cpu_read(&cart, 0x5C05), Some(0x33));
+
+        // $2000 CIRAM page 1, $2400 ExRAM, $2800 fill, $2C00 CIRAM page 0
+        mmc5.cpu_write(&mut cart, 0x5104, 0);
+        mmc5.cpu_write(&mut cart, 0x5105, 0b00_11_10_01);
+        mmc5.cpu_write(&mut cart, 0x5106, 0x7E);
+        mmc5.cpu_write(&mut cart, 0x5107, 2);
+        assert_eq!(mmc5.ciram_page(&cart, 0x2000), Some(1));
+        assert_eq!(mmc5.ciram_page(&cart, 0x2C00), Some(0));
+        assert_eq!(mmc5.nametable_read(&cart, 0x2000), None);
+        assert_eq!(mmc5.nametable_read(&cart, 0x2405), Some(0x33));
+        assert_eq!(mmc5.nametable_read(&cart, 0x2810), Some(0x7E));
+        assert_eq!(mmc5.nametable_read(&cart, 0x2BC0), Some(0b1010_1010));
+        assert!(mmc5.nametable_write(&mut cart, 0x2405, 0x55));
+        assert!(!mmc5.nametable_write(&mut cart, 0x2005, 0x55));
+        assert_eq!(mmc5.nametable_read(&cart, 0x2405), Some(0x55));
+    }
+
+    #[test]
+    fn test_mmc5_extended_attributes() {
+        let mut cart = mmc5_cart();
+        let mut mmc5 = Mmc5::new();
+        mmc5.cpu_write(&mut cart, 0x5104, 2);
+        // tile 2: palette 3, 4 KiB CHR bank 5
+        mmc5.cpu_write(&mut cart, 0x5C02, 0b11_000101);
+        mmc5.cpu_write(&mut cart, 0x5104, 1);
+        start_frame(&mut mmc5, &cart);
+        assert_eq!(mmc5.nametable_read(&cart, 0x2002), None);
+        assert_eq!(mmc5.nametable_read(&cart, 0x23C0), Some(0xFF));
+        assert_eq!(mmc5.ppu_read(&cart, 0x0400), 5 * 4 + 1);
+    }
+
+    #[test]
+    fn test_mmc5_vertical_split() {
+        let mut cart = mmc5_cart();
+        let mut mmc5 = Mmc5::new();
+        mmc5.cpu_write(&mut cart, 0x5104, 2);
+        for i in 0..32 {
+            mmc5.cpu_write(&mut cart, 0x5C00 + 32 + i, 0x80 + i as u8); // split row 1
+        }
+        mmc5.cpu_write(&mut cart, 0x5104, 0);
+        mmc5.cpu_write(&mut cart, 0x5200, 0x80 | 4); // tiles 0-3 from the split
+        mmc5.cpu_write(&mut cart, 0x5201, 8);
+        mmc5.cpu_write(&mut cart, 0x5202, 2);
+        start_frame(&mut mmc5, &cart);
+        let reads = fetch_scanline(&mut mmc5, &cart);
+        // tiles 2 and 3 come first, then 4-33 from CIRAM, then the next line's 0 and 1
+        assert_eq!(reads[0], Some(0x82));
+        assert_eq!(reads[2], Some(0x83));
+        assert_eq!(reads[4], None);
+        assert_eq!(reads[64], Some(0x80));
+        assert_eq!(reads[66], Some(0x81));
+        // split patterns come from its own 4 KiB bank
+        mmc5.nametable_read(&cart, 0x2002);
+        assert_eq!(mmc5.ppu_read(&cart, 0x0008), 2 * 4);
+    }
+
+    #[test]
+    fn test_mmc5_multiplier_and_pcm() {
+        let mut cart = mmc5_cart();
+        let mut mmc5 = Mmc5::new();
+        mmc5.cpu_write(&mut cart, 0x5205, 200);
+        mmc5.cpu_write(&mut cart, 0x5206, 100);
+        assert_eq!(mmc5.cpu_read(&cart, 0x5205), Some((20000 & 0xFF) as u8));
+        assert_eq!(mmc5.cpu_read(&cart, 0x5206), Some((20000 >> 8) as u8));
+
+        assert_eq!(mmc5.audio_output(), 0.0);
+        mmc5.cpu_write(&mut cart, 0x5011, 0x80);
+        assert!(mmc5.audio_output() > 0.0);
+
+        // read mode samples CPU reads from $8000-$BFFF, a 0 raises IRQ
+        mmc5.cpu_write(&mut cart, 0x5100, 3);
+        mmc5.cpu_write(&mut cart, 0x5114, 0x80);
+        mmc5.cpu_write(&mut cart, 0x5010, 0x81);
+        mmc5.cpu_read(&cart, 0x8000);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(&cart, 0x5010), Some(0x80));
+        assert!(!mmc5.irq());
+    }
+
+    #[test]
+    fn test_mmc5_pulse() {
+        let mut cart = mmc5_cart();
+        let mut mmc5 = Mmc5::new();
+        mmc5.cpu_write(&mut cart, 0x5015, 0b01);
+        mmc5.cpu_write(&mut cart, 0x5000, 0b1011_1111); // 50% duty, constant volume 15
+        mmc5.cpu_write(&mut cart, 0x5002, 0x10);
+        mmc5.cpu_write(&mut cart, 0x5003, 0x08);
+        assert_eq!(mmc5.cpu_read(&cart, 0x5015), Some(0b01));
+        let mut levels = Vec::new();
+        for _ in 0..200 {
+            mmc5.cpu_clock();
+            levels.push(mmc5.audio_output() > 0.0);
+        }
+        assert!(levels.contains(&true) && levels.contains(&false));
+        mmc5.cpu_write(&mut cart, 0x5015, 0);
+        assert_eq!(mmc5.cpu_read(&cart, 0x5015), Some(0));
+    }
+
+    // Color Dreams, GxROM and AxROM tests
+    #[test]
+    fn test_color_dreams_banking() {
+        // 128 KiB PRG, 64 KiB CHR
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(11, 8, 8)).unwrap());
+        bus.write(0x8000, 0x32);
+        assert_eq!(bus.read(0x8000), 8);
+        assert_eq!(bus.read(0xFFFF), 11);
+
+        let mut cart = Cart::from_bytes(&ines_rom(11, 8, 8)).unwrap();
+        let mut mapper = mapper::new(11).unwrap();
+        mapper.cpu_write(&mut cart, 0xC000, 0x32);
+        assert_eq!(mapper.ppu_peek(&cart, 0x0400), 3 * 8 + 1);
+    }
+
+    #[test]
+    fn test_gxrom_banking_with_bus_conflicts() {
+        let mut rom = ines_rom(66, 8, 4);
+        // a byte of 0x13 in the first bank to write through
+        rom[16 + 0x10] = 0x13;
+        let mut cart = Cart::from_bytes(&rom).unwrap();
+        let mut mapper = mapper::new(66).unwrap();
+        mapper.cpu_write(&mut cart, 0x8010, 0x12);
+        assert_eq!(mapper.cpu_peek(&cart, 0x8000), Some(4));
+        assert_eq!(mapper.ppu_peek(&cart, 0x1C00), 2 * 8 + 7);
+
+        // the ROM byte at the write address, 4 in this bank, pulls the other bits low
+        mapper.cpu_write(&mut cart, 0x9000, 0x33);
+        assert_eq!(mapper.cpu_peek(&cart, 0x8000), Some(0));
+        assert_eq!(mapper.ppu_peek(&cart, 0x0000), 0);
+    }
+
+    #[test]
+    fn test_axrom_single_screen() {
+        // 256 KiB PRG, CHR RAM
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(7, 16, 0)).unwrap());
+        bus.write(0x8000, 0x13);
+        assert_eq!(bus.read(0x8000), 12);
+        assert_eq!(bus.read(0xE000), 15);
+        assert_eq!(bus.cart().unwrap().screen_mirroring, Mirroring::SingleScreenB);
+
+        // all four nametables land on the selected page, switched at runtime
+        bus.ppu_bus().write(0x2000, 0x77);
+        assert_eq!(bus.ppu_bus().read(0x2C00), 0x77);
+        bus.write(0x8000, 0x03);
+        assert_eq!(bus.ppu_bus().read(0x2400), 0);
+        bus.ppu_bus().write(0x2400, 0x11);
+        bus.write(0x8000, 0x13);
+        assert_eq!(bus.ppu_bus().read(0x2800), 0x77);
+    }
+
+    // VRC6 tests
+    #[test]
+    fn test_vrc6_banking() {
+        // 256 KiB PRG, 128 KiB CHR
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(24, 16, 16)).unwrap());
+        bus.write(0x8000, 3);
+        bus.write(0xC000, 9);
+        assert_eq!(bus.read(0x8000), 6);
+        assert_eq!(bus.read(0xA000), 7);
+        assert_eq!(bus.read(0xC000), 9);
+        assert_eq!(bus.read(0xE000), 31);
+
+        // VRC6b has A0 and A1 swapped, $D001 there is $D002 on VRC6a
+        let mut cart = Cart::from_bytes(&ines_rom(26, 16, 16)).unwrap();
+        let mut vrc6 = Vrc6::new(Vrc6Variant::B);
+        vrc6.cpu_write(&mut cart, 0xD001, 50);
+        vrc6.cpu_write(&mut cart, 0xE003, 70);
+        assert_eq!(vrc6.ppu_peek(&cart, 0x0800), 50);
+        assert_eq!(vrc6.ppu_peek(&cart, 0x1C00), 70);
+
+        // one-screen mirroring from $B003 and PRG RAM enable
+        vrc6.cpu_write(&mut cart, 0xB003, 0b1000_1100);
+        assert_eq!(cart.screen_mirroring, Mirroring::SingleScreenB);
+        assert!(vrc6.prg_ram_enabled());
+    }
+
+    #[test]
+    fn test_vrc6_irq() {
+        let mut cart = Cart::from_bytes(&ines_rom(24, 2, 1)).unwrap();
+        let mut vrc6 = Vrc6::new(Vrc6Variant::A);
+        // cycle mode, fires after 0x100 - 0xF0 = 16 cycles
+        vrc6.cpu_write(&mut cart, 0xF000, 0xF0);
+        vrc6.cpu_write(&mut cart, 0xF001, 0b111);
+        for _ in 0..15 {
+            vrc6.cpu_clock();
+        }
+        assert!(!vrc6.irq());
+        vrc6.cpu_clock();
+        assert!(vrc6.irq());
+        // ack keeps it enabled through the enable-after-ack bit
+        vrc6.cpu_write(&mut cart, 0xF002, 0);
+        assert!(!vrc6.irq());
+        for _ in 0..16 {
+            vrc6.cpu_clock();
+        }
+        assert!(vrc6.irq());
+
+        // scanline mode clocks once per 341 / 3 CPU cycles
+        vrc6.cpu_write(&mut cart, 0xF000, 0xFE);
+        vrc6.cpu_write(&mut cart, 0xF001, 0b010);
+        for _ in 0..227 {
+            vrc6.cpu_clock();
+        }
+        assert!(!vrc6.irq());
+        vrc6.cpu_clock();
+        assert!(vrc6.irq());
+    }
+
+    #[test]
+    fn test_vrc6_audio() {
+        let mut cart = Cart::from_bytes(&ines_rom(24, 2, 1)).unwrap();
+        let mut vrc6 = Vrc6::new(Vrc6Variant::A);
+        assert_eq!(vrc6.audio_output(), 0.0);
+
+        // pulse 1 with duty 8/16, volume 15, period 0 steps every cycle
+        vrc6.cpu_write(&mut cart, 0x9000, 0b0111_1111);
+        vrc6.cpu_write(&mut cart, 0x9002, 0x80);
+        let levels: Vec<u8> = (0..16)
+            .map(|_| {
+                vrc6.cpu_clock();
+                vrc6.channel_outputs()[0]
+            })
+            .collect();
+        assert_eq!(levels.iter().filter(|&&level| level == 15).count(), 8);
+        assert_eq!(levels.iter().filter(|&&level| level == 0).count(), 8);
+
+        // sawtooth with rate 8 climbs by one output step every 2 clocks, resets after 14
+        vrc6.cpu_write(&mut cart, 0x9002, 0);
+        vrc6.cpu_write(&mut cart, 0xB000, 8);
+        vrc6.cpu_write(&mut cart, 0xB002, 0x80);
+        let saw: Vec<u8> = (0..14)
+            .map(|_| {
+                vrc6.cpu_clock();
+                vrc6.channel_outputs()[2]
+            })
+            .collect();
+        assert_eq!(saw, vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+        assert_eq!(vrc6.channel_outputs()[0], 0);
+
+        // halt stops everything
+        vrc6.cpu_write(&mut cart, 0x9003, 1);
+        vrc6.cpu_clock();
+        assert_eq!(vrc6.channel_outputs()[2], 0);
+    }
+
+    // FME-7 / 5B tests
+    #[test]
+    fn test_fme7_banking() {
+        // 256 KiB PRG, 128 KiB CHR
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(69, 16, 16)).unwrap());
+        for (command, bank) in [(9, 4), (0x0A, 5), (0x0B, 6), (8, 20)] {
+            bus.write(0x8000, command);
+            bus.write(0xA000, bank);
+        }
+        assert_eq!(bus.read(0x6000), 20);
+        assert_eq!(bus.read(0x8000), 4);
+        assert_eq!(bus.read(0xA000), 5);
+        assert_eq!(bus.read(0xC000), 6);
+        assert_eq!(bus.read(0xE000), 31);
+
+        // RAM at $6000 once selected and enabled
+        bus.write(0x8000, 8);
+        bus.write(0xA000, 0b1100_0000);
+        bus.write(0x6000, 0x5A);
+        assert_eq!(bus.read(0x6000), 0x5A);
+
+        let mut cart = Cart::from_bytes(&ines_rom(69, 16, 16)).unwrap();
+        let mut fme7 = Fme7::new();
+        fme7.cpu_write(&mut cart, 0x8000, 5);
+        fme7.cpu_write(&mut cart, 0xA000, 99);
+        fme7.cpu_write(&mut cart, 0x8000, 0x0C);
+        fme7.cpu_write(&mut cart, 0xA000, 1);
+        assert_eq!(fme7.ppu_peek(&cart, 0x1400), 99);
+        assert_eq!(cart.screen_mirroring, Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_fme7_irq() {
+        let mut cart = Cart::from_bytes(&ines_rom(69, 2, 1)).unwrap();
+        let mut fme7 = Fme7::new();
+        for (command, value) in [(0x0E, 3), (0x0F, 0), (0x0D, 0x81)] {
+            fme7.cpu_write(&mut cart, 0x8000, command);
+            fme7.cpu_write(&mut cart, 0xA000, value);
+        }
+        // fires when the counter wraps from 0 to $FFFF
+        for _ in 0..3 {
+            fme7.cpu_clock();
+        }
+        assert!(!fme7.irq());
+        fme7.cpu_clock();
+        assert!(fme7.irq());
+
+        // any write to the control register acknowledges
+        fme7.cpu_write(&mut cart, 0x8000, 0x0D);
+        fme7.cpu_write(&mut cart, 0xA000, 0x80);
+        assert!(!fme7.irq());
+    }
+
+    #[test]
+    fn test_sunsoft_5b_audio() {
+        let mut cart = Cart::from_bytes(&ines_rom(69, 2, 1)).unwrap();
+        let mut fme7 = Fme7::new();
+        let mut write = |fme7: &mut Fme7, reg: u8, data: u8| {
+            fme7.cpu_write(&mut cart, 0xC000, reg);
+            fme7.cpu_write(&mut cart, 0xE000, data);
+        };
+        // channel A tone only with period 2, fixed volume 15
+        write(&mut fme7, 0, 2);
+        write(&mut fme7, 7, 0b11_1110);
+        write(&mut fme7, 8, 15);
+        let levels: Vec<u8> = (0..16 * 5)
+            .map(|_| {
+                fme7.cpu_clock();
+                fme7.channel_outputs()[0]
+            })
+            .collect();
+        // toggles every 2 * 16 CPU cycles
+        assert!(levels[..31].iter().all(|&level| level == 0));
+        assert!(levels[31..63].iter().all(|&level| level == 31));
+        assert!(levels[63..].iter().all(|&level| level == 0));
+        assert_eq!(fme7.audio_output(), 0.0);
+
+        // a falling envelope on channel B, tone and noise disabled so it sounds constantly
+        write(&mut fme7, 7, 0b11_1111);
+        write(&mut fme7, 9, 0x10);
+        write(&mut fme7, 0x0B, 1);
+        write(&mut fme7, 0x0D, 0b0000);
+        assert_eq!(fme7.channel_outputs()[1], 31);
+        for _ in 0..16 * 31 {
+            fme7.cpu_clock();
+        }
+        assert_eq!(fme7.channel_outputs()[1], 0);
+        assert!(fme7.audio_output() > 0.0);
+
+        // writes with the upper select bits set don't reach the chip
+        write(&mut fme7, 0x18, 0);
+        assert!(fme7.audio_output() > 0.0);
+    }
+
+    // Namcot 108 tests
+    #[test]
+    fn test_namcot108_banking() {
+        // 128 KiB PRG, 64 KiB CHR
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(206, 8, 8)).unwrap());
+        let mirroring = bus.cart().unwrap().screen_mirroring;
+        for (reg, bank) in [(6, 0x13), (7, 5)] {
+            bus.write(0x8000, reg);
+            bus.write(0x8001, bank);
+        }
+        // 4 bit PRG banks, the upper half of $8000 ignored
+        assert_eq!(bus.read(0x8000), 3);
+        assert_eq!(bus.read(0xA000), 5);
+        assert_eq!(bus.read(0xC000), 14);
+        assert_eq!(bus.read(0xE000), 15);
+        // no mirroring, IRQ or PRG mode registers
+        bus.write(0x8000, 0b0100_0110);
+        bus.write(0xA000, 1);
+        bus.write(0xE001, 0);
+        assert_eq!(bus.read(0x8000), 3);
+        assert_eq!(bus.cart().unwrap().screen_mirroring, mirroring);
+        assert!(!bus.irq());
+
+        let mut cart = Cart::from_bytes(&ines_rom(206, 8, 8)).unwrap();
+        let mut namcot = Namcot108::new(Namcot108Board::Mapper206);
+        for (reg, bank) in [(0, 9), (1, 20), (2, 30), (3, 31), (4, 40), (5, 41)] {
+            namcot.cpu_write(&mut cart, 0x8000, reg);
+            namcot.cpu_write(&mut cart, 0x8001, bank);
+        }
+        let pages = (0..8).map(|page| namcot.ppu_peek(&cart, page * 0x400)).collect::<Vec<_>>();
+        assert_eq!(pages, vec![8, 9, 20, 21, 30, 31, 40, 41]);
+    }
+
+    #[test]
+    fn test_namcot108_variants() {
+        // 128 KiB CHR
+        let mut cart = Cart::from_bytes(&ines_rom(76, 2, 16)).unwrap();
+        let mut namcot = Namcot108::new(Namcot108Board::Mapper76);
+        for (reg, bank) in [(2, 3), (3, 4), (4, 5), (5, 6)] {
+            namcot.cpu_write(&mut cart, 0x8000, reg);
+            namcot.cpu_write(&mut cart, 0x8001, bank);
+        }
+        let pages = (0..8).map(|page| namcot.ppu_peek(&cart, page * 0x400)).collect::<Vec<_>>();
+        assert_eq!(pages, vec![6, 7, 8, 9, 10, 11, 12, 13]);
+
+        // 88 and 154 split CHR into a 64 KiB half for each pattern table
+        let mut namcot = Namcot108::new(Namcot108Board::Mapper154);
+        for (reg, bank) in [(0, 0x42), (2, 3)] {
+            namcot.cpu_write(&mut cart, 0x8000, reg);
+            namcot.cpu_write(&mut cart, 0x8001, bank);
+        }
+        assert_eq!(namcot.ppu_peek(&cart, 0x0000), 0x02);
+        assert_eq!(namcot.ppu_peek(&cart, 0x1000), 0x43);
+        assert_eq!(cart.screen_mirroring, Mirroring::SingleScreenA);
+        namcot.cpu_write(&mut cart, 0xC000, 0x40);
+        assert_eq!(cart.screen_mirroring, Mirroring::SingleScreenB);
+
+        let mut namcot = Namcot108::new(Namcot108Board::Mapper95);
+        for (reg, bank) in [(0, 0x20), (1, 0)] {
+            namcot.cpu_write(&mut cart, 0x8000, reg);
+            namcot.cpu_write(&mut cart, 0x8001, bank);
+        }
+        assert_eq!(namcot.ciram_page(&cart, 0x2400), Some(1));
+        assert_eq!(namcot.ciram_page(&cart, 0x2800), Some(0));
+        assert_eq!(namcot.ppu_peek(&cart, 0x0000), 0);
+    }
+
+    // mapper 31
+    #[test]
+    fn test_inl_nsf_banking() {
+        // 8 KiB pages 0-3, so 4 KiB bank n reads n / 2
+        let mut bus = Bus::with_cart(Cart::from_bytes(&ines_rom(31, 2, 1)).unwrap());
+        assert_eq!(bus.read(0xF000), 3);
+        assert_eq!(bus.read(0x8000), 0);
+        bus.write(0x5FF8, 3);
+        bus.write(0x5003, 5);
+        assert_eq!(bus.read(0x8000), 1);
+        assert_eq!(bus.read(0xB000), 2);
+        // bank numbers wrap to the size of the ROM
+        bus.write(0x5FFF, 9);
+        assert_eq!(bus.read(0xFFFF), 0);
+
+        let mut other = InlNsf::with_banks([7, 6, 5, 4, 3, 2, 1, 0]);
+        let cart = Cart::from_bytes(&ines_rom(31, 2, 1)).unwrap();
+        assert_eq!(other.cpu_peek(&cart, 0x8000), Some(3));
+        assert!(other.cpu_write(&mut Cart::from_bytes(&ines_rom(31, 2, 1)).unwrap(), 0x5FF9, 1));
+        assert_eq!(other.banks()[1], 1);
+    }
+}
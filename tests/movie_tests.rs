@@ -0,0 +1,109 @@
+use nestacean::nes::bus::Bus;
+use nestacean::nes::controller::Button;
+use nestacean::nes::cpu::Cpu;
+use nestacean::nes::mem::{Read, Write};
+use nestacean::nes::movie::{Movie, MovieError, MovieStart};
+use nestacean::nes::savestate::{load_machine, save_machine};
+
+// Strobes port 1, shifts the eight buttons into $10 and adds them up in $11, forever.
+const POLL_LOOP: [u8; 31] = [
+    0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #1, STA $4016
+    0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #0, STA $4016
+    0xA2, 0x08, // LDX #8
+    0xAD, 0x16, 0x40, 0x4A, 0x26, 0x10, // LDA $4016, LSR A, ROL $10
+    0xCA, 0xD0, 0xF7, // DEX, BNE
+    0xA5, 0x10, 0x18, 0x65, 0x11, 0x85, 0x11, // LDA $10, CLC, ADC $11, STA $11
+    0x4C, 0x00, 0x02, // JMP $0200
+];
+// long enough for a poll or two
+const FRAME_CYCLES: u64 = 400;
+
+fn poll_cpu(bus: Bus) -> Cpu<Bus> {
+    let mut cpu = Cpu::with_bus(bus);
+    cpu.set_pc(0x0200);
+    cpu
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_movie_file() {
+        let mut movie = Movie::new(0x1234_5678, MovieStart::PowerOn);
+        assert!(movie.is_empty());
+        movie.push_frame([Button::A | Button::RIGHT, Button::NONE]);
+        movie.push_frame([Button::NONE, Button::START]);
+        assert_eq!(movie.len(), 2);
+        assert_eq!(movie.frame(1), Some([Button::NONE, Button::START]));
+        assert_eq!(movie.frame(2), None);
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+
+        movie.start = MovieStart::Savestate(vec![1, 2, 3]);
+        let dir = std::env::temp_dir().join(format!("nestacean-movie-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.nmv");
+        movie.save(&path).unwrap();
+        assert_eq!(Movie::load(&path).unwrap(), movie);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(Movie::load(&path), Err(MovieError::Io(_))));
+
+        let data = movie.to_bytes();
+        assert!(matches!(Movie::from_bytes(b"NES\x1a"), Err(MovieError::BadMagic)));
+        let truncated = Movie::from_bytes(&data[..data.len() - 1]);
+        assert!(matches!(truncated, Err(MovieError::Truncated)));
+        let mut newer = data.clone();
+        newer[4] = 0xFF;
+        assert!(matches!(Movie::from_bytes(&newer), Err(MovieError::UnsupportedVersion(_))));
+        let mut unversioned = data.clone();
+        unversioned[4] = 0;
+        let unversioned = Movie::from_bytes(&unversioned);
+        assert!(matches!(unversioned, Err(MovieError::UnsupportedVersion(0))));
+        let mut longer = data;
+        longer.push(0);
+        assert!(matches!(Movie::from_bytes(&longer), Err(MovieError::Corrupt(_))));
+
+        // recording over the end of a movie from a savestate in the middle of it
+        movie.truncate(1);
+        assert_eq!(movie.len(), 1);
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut bus = Bus::new();
+        for (offset, &byte) in POLL_LOOP.iter().enumerate() {
+            bus.write(0x0200 + offset as u16, byte);
+        }
+        let mut cpu = poll_cpu(bus);
+        // the whole machine, so the CPU starts where it was too
+        let mut movie = Movie::new(0, MovieStart::Savestate(save_machine(0, &cpu)));
+
+        let pressed = [Button::A, Button::START | Button::UP, Button::NONE, Button::RIGHT];
+        for frame in 0..40 {
+            let buttons = pressed[frame % pressed.len()] | pressed[frame / 10];
+            cpu.bus_mut().controller_mut(0).set_buttons(buttons);
+            movie.record(cpu.bus());
+            cpu.run_cycles(FRAME_CYCLES);
+        }
+        let recorded = cpu.bus_mut().read(0x0011);
+        assert_eq!(movie.len(), 40);
+        assert_eq!(movie.frame(1).unwrap()[0], Button::START | Button::UP | Button::A);
+
+        // the same start and the same buttons end up in the same place
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let MovieStart::Savestate(state) = &movie.start else {
+            panic!("movie lost its savestate");
+        };
+        let mut cpu = Cpu::with_bus(Bus::new());
+        assert_eq!(load_machine(&mut cpu, state).unwrap(), 0);
+        assert_eq!(cpu.get_pc(), 0x0200);
+        let mut frame = 0;
+        while movie.apply(frame, cpu.bus_mut()) {
+            cpu.run_cycles(FRAME_CYCLES);
+            frame += 1;
+        }
+        assert_eq!(frame, 40);
+        assert_eq!(cpu.bus_mut().read(0x0011), recorded);
+        assert_eq!(cpu.get_cycles(), 40 * FRAME_CYCLES);
+    }
+}
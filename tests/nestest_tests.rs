@@ -0,0 +1,105 @@
+use nestacean::nes::nestest::{compare_trace, headless_cpu, TraceMismatch};
+use nestacean::nes::trace::{TraceEntry, TraceFormat};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // nestest.nes and nestest.log are not redistributed with the repo, drop them in tests/roms
+    const NESTEST_ROM: &str = "tests/roms/nestest.nes";
+    const NESTEST_LOG: &str = "tests/roms/nestest.log";
+
+    const LOG_HEAD: &str = "\
+C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10
+C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12
+";
+
+    // NROM-128 image with the first three instructions of nestest
+    fn nestest_head_rom() -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        rom.resize(16, 0x00);
+        let mut prg = vec![0x00; 0x4000];
+        prg[0x0000..0x0003].copy_from_slice(&[0x4C, 0xF5, 0xC5]); // JMP $C5F5
+        prg[0x05F5..0x05F9].copy_from_slice(&[0xA2, 0x00, 0x86, 0x00]); // LDX #$00, STX $00
+        rom.extend(prg);
+        rom.extend(vec![0x00; 0x2000]);
+        rom
+    }
+
+    #[test]
+    fn test_trace_format() {
+        let mut cpu = headless_cpu(&nestest_head_rom()).unwrap();
+        assert_eq!(compare_trace(&mut cpu, LOG_HEAD), Ok(3));
+    }
+
+    #[test]
+    fn test_trace_reports_first_divergence() {
+        let mut cpu = headless_cpu(&nestest_head_rom()).unwrap();
+        let log = LOG_HEAD.replace("P:26", "P:24");
+        let mismatch: TraceMismatch = compare_trace(&mut cpu, &log).unwrap_err();
+        assert_eq!(mismatch.line, 3);
+        assert!(mismatch.actual.contains("P:26"));
+        assert!(mismatch.expected.contains("P:24"));
+    }
+
+    #[test]
+    fn test_trace_sink() {
+        let mut cpu = headless_cpu(&nestest_head_rom()).unwrap();
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let sink_lines = Rc::clone(&lines);
+        cpu.set_trace_sink(Box::new(move |entry: &TraceEntry| {
+            sink_lines.borrow_mut().push(entry.format(&TraceFormat::Nestest));
+        }));
+        for _ in 0..8 {
+            cpu.tick(); // JMP, LDX and STX
+        }
+        let expected: Vec<&str> = LOG_HEAD.lines().collect();
+        assert_eq!(*lines.borrow(), expected);
+
+        cpu.clear_trace_sink();
+        for _ in 0..10 {
+            cpu.tick();
+        }
+        assert_eq!(lines.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_trace_fceux_format() {
+        let entry = TraceEntry {
+            pc: 0xC5F7,
+            bytes: vec![0x86, 0x00],
+            disassembly: "STX $00 = 00".to_string(),
+            official: true,
+            a: 0x00,
+            x: 0x00,
+            y: 0x00,
+            p: 0x26,
+            sp: 0xFD,
+            scanline: 0,
+            dot: 36,
+            cycles: 12,
+        };
+        assert_eq!(
+            entry.format(&TraceFormat::Fceux),
+            "$C5F7:86 00     STX $00 = 00                    A:00 X:00 Y:00 S:FD P:nvUbdIZc"
+        );
+    }
+
+    #[test]
+    fn test_nestest_log() {
+        if !Path::new(NESTEST_ROM).exists() || !Path::new(NESTEST_LOG).exists() {
+            eprintln!("skipping nestest: {} or {} not found", NESTEST_ROM, NESTEST_LOG);
+            return;
+        }
+        let rom = std::fs::read(NESTEST_ROM).unwrap();
+        let log = std::fs::read_to_string(NESTEST_LOG).unwrap();
+        let mut cpu = headless_cpu(&rom).unwrap();
+        if let Err(mismatch) = compare_trace(&mut cpu, &log) {
+            panic!("{}", mismatch);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use nestacean::nes::cart::Region;
+use nestacean::nes::mem::Peek;
+use nestacean::nes::nsf::{Nsf, NsfError, NsfPlayer};
+
+// init stores A and X in $00-$01, play counts its calls in $02 and writes the track to $4000
+const DRIVER: [u8; 24] = [
+    0x85, 0x00, // $8000 STA $00
+    0x86, 0x01, // $8002 STX $01
+    0x60, // $8004 RTS
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // up to $8010
+    0xE6, 0x02, // $8010 INC $02
+    0xA5, 0x00, // $8012 LDA $00
+    0x8D, 0x00, 0x40, // $8014 STA $4000
+    0x60, // $8017 RTS
+];
+
+fn driver() -> Vec<u8> {
+    DRIVER.to_vec()
+}
+
+fn nsf_file(tracks: u8, start: u8, banks: [u8; 8], data: &[u8]) -> Vec<u8> {
+    let mut raw = b"NESM\x1A\x01".to_vec();
+    raw.extend([tracks, start]);
+    raw.extend([0x00, 0x80, 0x00, 0x80, 0x10, 0x80]);
+    let mut title = b"Test Tune".to_vec();
+    title.resize(32, 0);
+    raw.extend(title);
+    raw.extend(b"Composer");
+    raw.resize(0x6E, 0);
+    raw.extend(16639u16.to_le_bytes());
+    raw.extend(banks);
+    raw.extend(19997u16.to_le_bytes());
+    raw.resize(0x80, 0);
+    raw.extend(data);
+    raw
+}
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut raw = (data.len() as u32).to_le_bytes().to_vec();
+    raw.extend(id);
+    raw.extend(data);
+    raw
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nsf_header() {
+        let nsf = Nsf::from_bytes(&nsf_file(3, 2, [0; 8], &driver())).unwrap();
+        assert_eq!(nsf.title, "Test Tune");
+        assert_eq!(nsf.artist, "Composer");
+        assert_eq!(nsf.total_tracks, 3);
+        assert_eq!(nsf.starting_track, 1);
+        assert_eq!((nsf.load_addr, nsf.init_addr, nsf.play_addr), (0x8000, 0x8000, 0x8010));
+        assert_eq!(nsf.ntsc_play_speed, 16639);
+        assert!(!nsf.is_bankswitched());
+        assert_eq!(nsf.region(), Region::Ntsc);
+
+        assert!(matches!(Nsf::from_bytes(b"NESM\x1A"), Err(NsfError::Truncated(_))));
+        assert!(matches!(Nsf::from_bytes(b"NES\x1A"), Err(NsfError::BadMagic)));
+        let no_tracks = nsf_file(0, 1, [0; 8], &driver());
+        assert!(matches!(Nsf::from_bytes(&no_tracks), Err(NsfError::NoTracks)));
+    }
+
+    #[test]
+    fn test_nsfe_chunks() {
+        let mut info = vec![0x00, 0x80, 0x00, 0x80, 0x10, 0x80, 0x01, 0x00, 3, 2];
+        let mut raw = b"NSFE".to_vec();
+        raw.extend(chunk(b"INFO", &info));
+        raw.extend(chunk(b"DATA", &driver()));
+        raw.extend(chunk(b"auth", b"Tune\0Artist\0(c)\0Ripper\0"));
+        raw.extend(chunk(b"tlbl", b"One\0Two\0Three\0"));
+        raw.extend(chunk(b"time", &[1000i32, -1, 2500].map(i32::to_le_bytes).concat()));
+        raw.extend(chunk(b"plst", &[2, 0]));
+        // unknown chunks in lower case are skipped
+        raw.extend(chunk(b"xtra", &[1, 2, 3]));
+        raw.extend(chunk(b"NEND", &[]));
+
+        let nsf = Nsf::from_bytes(&raw).unwrap();
+        assert_eq!((nsf.title.as_str(), nsf.artist.as_str()), ("Tune", "Artist"));
+        assert_eq!(nsf.track_titles, ["One", "Two", "Three"]);
+        assert_eq!(nsf.region(), Region::Pal);
+
+        let mut player = NsfPlayer::new(nsf);
+        assert_eq!(player.track_order(), [2, 0]);
+        assert_eq!(player.track_title(), Some("Three"));
+        assert_eq!(player.track_length(), Some(Duration::from_millis(2500)));
+        player.next_track();
+        assert_eq!(player.track_title(), Some("One"));
+        player.run_frame();
+        // A is the song, X is 1 on PAL
+        assert_eq!(player.bus().peek(0x0000), 0);
+        assert_eq!(player.bus().peek(0x0001), 1);
+
+        info[6] = 0;
+        let mut required = b"NSFE".to_vec();
+        required.extend(chunk(b"INFO", &info));
+        required.extend(chunk(b"DATA", &driver()));
+        required.extend(chunk(b"XTRA", &[]));
+        required.extend(chunk(b"NEND", &[]));
+        assert!(matches!(Nsf::from_bytes(&required), Err(NsfError::UnsupportedChunk(_))));
+        assert!(matches!(
+            Nsf::from_bytes(&raw[..raw.len() - 8]),
+            Err(NsfError::MissingChunk("NEND"))
+        ));
+    }
+
+    #[test]
+    fn test_nsf_player_calls_init_and_play() {
+        let nsf = Nsf::from_bytes(&nsf_file(3, 2, [0; 8], &driver())).unwrap();
+        let mut player = NsfPlayer::new(nsf);
+        assert_eq!(player.track(), 1);
+
+        // init runs in the first period, play from the second on
+        player.run_frame();
+        assert_eq!(player.bus().peek(0x0000), 1);
+        assert_eq!(player.bus().peek(0x0002), 0);
+        assert_eq!(player.bus().apu_io_register(0x4015), 0x0F);
+        for _ in 0..3 {
+            player.run_frame();
+        }
+        assert_eq!(player.bus().peek(0x0002), 3);
+        assert_eq!(player.bus().apu_io_register(0x4000), 1);
+        // 16639 us at the NTSC clock is just under 29780 cycles, the last instruction of a
+        // period can run a few past it
+        assert!((119_119..119_126).contains(&player.bus().cycles()));
+
+        // a new track starts from cleared RAM
+        player.next_track();
+        player.run_frame();
+        player.run_frame();
+        assert_eq!(player.bus().peek(0x0000), 2);
+        assert_eq!(player.bus().peek(0x0002), 1);
+        player.next_track();
+        assert_eq!(player.track(), 0);
+    }
+
+    #[test]
+    fn test_nsf_bankswitching() {
+        // the driver in bank 0, then banks of 1s and 2s
+        let mut data = driver();
+        data.resize(0x1000, 0);
+        data.extend([1; 0x1000]);
+        data.extend([2; 0x1000]);
+        let nsf = Nsf::from_bytes(&nsf_file(1, 1, [0, 2, 1, 0, 0, 0, 0, 0], &data)).unwrap();
+        assert!(nsf.is_bankswitched());
+        assert_eq!(nsf.cart().prg_rom.len(), 0x3000);
+
+        let mut player = NsfPlayer::new(nsf);
+        player.run_frame();
+        assert_eq!(player.bus().peek(0x8000), 0x85);
+        assert_eq!(player.bus().peek(0x9000), 2);
+        assert_eq!(player.bus().peek(0xA000), 1);
+    }
+}
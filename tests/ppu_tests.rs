@@ -0,0 +1,763 @@
+use nestacean::nes::cart::Region;
+use nestacean::nes::frame::{Frame, HEIGHT, WIDTH};
+use nestacean::nes::mem::{Memory, Peek, Write};
+use nestacean::nes::palette::{Palette, PaletteError};
+use nestacean::nes::ppu::{Ppu, PpuBackend, DOTS_PER_SCANLINE};
+
+// a flat 16 KiB stand-in for the PPU bus
+fn vram() -> Memory<Vec<u8>> {
+    Memory::new(vec![0u8; 0x4000], true)
+}
+
+// ticks until the PPU is at `dot` of `scanline`
+fn run_to(ppu: &mut Ppu, bus: &mut Memory<Vec<u8>>, scanline: u16, dot: u16) {
+    while (ppu.scanline(), ppu.dot()) != (scanline, dot) {
+        ppu.tick(bus);
+    }
+}
+
+// tile 1 solid in both pattern tables and the first nametable filled with it
+fn solid_background() -> Memory<Vec<u8>> {
+    let mut bus = vram();
+    for plane in 0..8 {
+        bus.write(0x0010 + plane, 0xFF);
+        bus.write(0x1010 + plane, 0xFF);
+    }
+    for addr in 0x2000..0x23C0 {
+        bus.write(addr, 1);
+    }
+    bus
+}
+
+// pattern tables, nametables, palettes and OAM filled with noise, scrolled to 13,37
+fn busy_scene(backend: PpuBackend) -> (Ppu, Memory<Vec<u8>>) {
+    let mut ppu = Ppu::new();
+    ppu.set_backend(backend);
+    let mut bus = vram();
+    let mut seed = 0x1234_5678u32;
+    let mut noise = move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as u8
+    };
+    for addr in 0..0x3000 {
+        bus.write(addr, noise());
+    }
+    for addr in 0x3F00..0x3F20 {
+        ppu.write_palette(addr, noise());
+    }
+    ppu.write_register(0x2003, 0, &mut bus);
+    for _ in 0..256 {
+        ppu.write_register(0x2004, noise(), &mut bus);
+    }
+    ppu.write_register(0x2005, 13, &mut bus);
+    ppu.write_register(0x2005, 37, &mut bus);
+    ppu.write_register(0x2001, 0x1E, &mut bus);
+    (ppu, bus)
+}
+
+// sprite 0 on tile 1 at `x`, top row on line 30
+fn place_sprite_0(ppu: &mut Ppu, bus: &mut Memory<Vec<u8>>, x: u8) {
+    ppu.write_register(0x2003, 0, bus);
+    for data in [29, 1, 0, x] {
+        ppu.write_register(0x2004, data, bus);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vblank_flag_and_nmi() {
+        let mut ppu = Ppu::new();
+        let mut bus = vram();
+        ppu.write_register(0x2000, 0x80, &mut bus);
+        run_to(&mut ppu, &mut bus, 241, 0);
+        assert!(!ppu.in_vblank());
+        ppu.tick(&mut bus);
+        assert!(ppu.in_vblank());
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+
+        // reading PPUSTATUS acknowledges it
+        assert_eq!(ppu.read_register(0x2002, &mut bus) & 0x80, 0x80);
+        assert_eq!(ppu.read_register(0x2002, &mut bus) & 0x80, 0);
+
+        // cleared at the start of the pre-render line
+        ppu.write_register(0x2000, 0x00, &mut bus);
+        run_to(&mut ppu, &mut bus, 260, 0);
+        for _ in 0..DOTS_PER_SCANLINE {
+            ppu.tick(&mut bus);
+        }
+        assert!(!ppu.in_vblank());
+    }
+
+    #[test]
+    fn test_nmi_enabled_during_vblank() {
+        let mut ppu = Ppu::new();
+        let mut bus = vram();
+        run_to(&mut ppu, &mut bus, 250, 0);
+        assert!(!ppu.take_nmi());
+        ppu.write_register(0x2000, 0x80, &mut bus);
+        assert!(ppu.take_nmi());
+        // only edges count, rewriting the same value doesn't fire again
+        ppu.write_register(0x2000, 0x80, &mut bus);
+        assert!(!ppu.take_nmi());
+        ppu.write_register(0x2000, 0x00, &mut bus);
+        ppu.write_register(0x2000, 0x80, &mut bus);
+        assert!(ppu.take_nmi());
+    }
+
+    #[test]
+    fn test_vblank_read_race() {
+        let mut ppu = Ppu::new();
+        let mut bus = vram();
+        ppu.write_register(0x2000, 0x80, &mut bus);
+
+        // a dot early: reads clear and the flag is never set
+        run_to(&mut ppu, &mut bus, 241, 0);
+        assert_eq!(ppu.read_register(0x2002, &mut bus) & 0x80, 0);
+        ppu.tick(&mut bus);
+        assert!(!ppu.in_vblank());
+        assert!(!ppu.take_nmi());
+
+        // on the dot: reads set, but the NMI is cancelled
+        run_to(&mut ppu, &mut bus, 240, 0);
+        run_to(&mut ppu, &mut bus, 241, 1);
+        assert_eq!(ppu.read_register(0x2002, &mut bus) & 0x80, 0x80);
+        assert!(!ppu.take_nmi());
+
+        // a few dots later it's too late to stop the NMI
+        run_to(&mut ppu, &mut bus, 240, 0);
+        run_to(&mut ppu, &mut bus, 241, 3);
+        ppu.read_register(0x2002, &mut bus);
+        assert!(ppu.take_nmi());
+    }
+
+    #[test]
+    fn test_palette_ram_mirrors() {
+        let mut ppu = Ppu::new();
+        let mut bus = vram();
+        ppu.write_register(0x2006, 0x3F, &mut bus);
+        ppu.write_register(0x2006, 0x10, &mut bus);
+        for color in [0x21, 0x22, 0x23, 0x24, 0xFF] {
+            ppu.write_register(0x2007, color, &mut bus);
+        }
+        // $3F10 is $3F00, $3F14 is $3F04, the other sprite entries are their own
+        assert_eq!(ppu.read_palette(0x3F00), 0x21);
+        assert_eq!(ppu.read_palette(0x3F04), 0x3F);
+        assert_eq!(ppu.read_palette(0x3F11), 0x22);
+        assert_eq!(ppu.read_palette(0x3F01), 0x00);
+        // mirrored every 32 bytes up to $3FFF
+        assert_eq!(ppu.read_palette(0x3FE0), 0x21);
+        assert_eq!(ppu.palette()[0x13], 0x24);
+        // none of it reached the bus
+        assert_eq!(bus.peek(0x3F10), 0);
+
+        // below $3F00 PPUDATA goes out to the bus
+        ppu.write_register(0x2006, 0x3E, &mut bus);
+        ppu.write_register(0x2006, 0xFF, &mut bus);
+        ppu.write_register(0x2007, 0x99, &mut bus);
+        assert_eq!(bus.peek(0x3EFF), 0x99);
+    }
+
+    #[test]
+    fn test_ppudata_read_buffer() {
+        let mut ppu = Ppu::new();
+        let mut bus = vram();
+        bus.write(0x2000, 0x11);
+        bus.write(0x2001, 0x22);
+        bus.write(0x2F00, 0x33);
+        ppu.write_register(0x2006, 0x20, &mut bus);
+        ppu.write_register(0x2006, 0x00, &mut bus);
+        // the first read returns what the buffer held before
+        assert_eq!(ppu.read_register(0x2007, &mut bus), 0x00);
+        assert_eq!(ppu.read_register(0x2007, &mut bus), 0x11);
+        assert_eq!(ppu.read_register(0x2007, &mut bus), 0x22);
+
+        // palette reads are immediate and refill the buffer from the nametable underneath
+        ppu.write_register(0x2006, 0x3F, &mut bus);
+        ppu.write_register(0x2006, 0x00, &mut bus);
+        ppu.write_register(0x2007, 0x2A, &mut bus);
+        ppu.write_register(0x2006, 0x3F, &mut bus);
+        ppu.write_register(0x2006, 0x00, &mut bus);
+        assert_eq!(ppu.read_register(0x2007, &mut bus), 0x2A);
+        ppu.write_register(0x2006, 0x20, &mut bus);
+        ppu.write_register(0x2006, 0x00, &mut bus);
+        assert_eq!(ppu.read_register(0x2007, &mut bus), 0x33);
+    }
+
+    #[test]
+    fn test_ppudata_increment_modes() {
+        let mut ppu = Ppu::new();
+        let mut bus = vram();
+        ppu.write_register(0x2006, 0x20, &mut bus);
+        ppu.write_register(0x2006, 0x00, &mut bus);
+        ppu.write_register(0x2007, 1, &mut bus);
+        assert_eq!(ppu.vram_addr(), 0x2001);
+
+        // down a column
+        ppu.write_register(0x2000, 0b100, &mut bus);
+        ppu.write_register(0x2007, 2, &mut bus);
+        ppu.write_register(0x2007, 3, &mut bus);
+        assert_eq!(ppu.vram_addr(), 0x2041);
+        assert_eq!(bus.peek(0x2021), 3);
+        ppu.read_register(0x2007, &mut bus);
+        assert_eq!(ppu.vram_addr(), 0x2061);
+    }
+
+    #[test]
+    fn test_sprite_0_hit() {
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        place_sprite_0(&mut ppu, &mut bus, 40);
+        ppu.write_register(0x2001, 0x1E, &mut bus);
+        run_to(&mut ppu, &mut bus, 30, 0);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0);
+        // the sprite's first pixel is x=40, drawn on dot 41
+        run_to(&mut ppu, &mut bus, 30, 40);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0);
+        ppu.tick(&mut bus);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0x40);
+
+        // it stays set until the pre-render line, reads don't clear it
+        ppu.read_register(0x2002, &mut bus);
+        run_to(&mut ppu, &mut bus, 261, 0);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0x40);
+        run_to(&mut ppu, &mut bus, 261, 2);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0);
+    }
+
+    #[test]
+    fn test_sprite_0_hit_needs_opaque_pixels() {
+        // over a transparent background
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        for addr in 0x2000..0x23C0 {
+            bus.write(addr, 0);
+        }
+        place_sprite_0(&mut ppu, &mut bus, 40);
+        ppu.write_register(0x2001, 0x1E, &mut bus);
+        run_to(&mut ppu, &mut bus, 240, 0);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0);
+
+        // with background rendering off
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        place_sprite_0(&mut ppu, &mut bus, 40);
+        ppu.write_register(0x2001, 0x14, &mut bus);
+        run_to(&mut ppu, &mut bus, 240, 0);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0);
+
+        // never at x=255
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        place_sprite_0(&mut ppu, &mut bus, 255);
+        ppu.write_register(0x2001, 0x1E, &mut bus);
+        run_to(&mut ppu, &mut bus, 240, 0);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0);
+    }
+
+    #[test]
+    fn test_sprite_0_hit_left_column() {
+        // with the left 8 pixels of sprites clipped the hit waits for x=8
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        place_sprite_0(&mut ppu, &mut bus, 2);
+        ppu.write_register(0x2001, 0x1A, &mut bus);
+        run_to(&mut ppu, &mut bus, 30, 8);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0);
+        run_to(&mut ppu, &mut bus, 30, 9);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0x40);
+
+        // and with nothing clipped it's on the sprite's first pixel
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        place_sprite_0(&mut ppu, &mut bus, 2);
+        ppu.write_register(0x2001, 0x1E, &mut bus);
+        run_to(&mut ppu, &mut bus, 30, 3);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0x40);
+    }
+
+    #[test]
+    fn test_left_edge_clipping() {
+        let draw = |mask: u8| {
+            let mut ppu = Ppu::new();
+            let mut bus = solid_background();
+            for (addr, color) in [(0x3F00, 0x0F), (0x3F01, 0x21), (0x3F11, 0x16)] {
+                ppu.write_palette(addr, color);
+            }
+            place_sprite_0(&mut ppu, &mut bus, 4);
+            ppu.write_register(0x2001, mask, &mut bus);
+            run_to(&mut ppu, &mut bus, 240, 0);
+            run_to(&mut ppu, &mut bus, 241, 1);
+            let line: Vec<u16> = (0..16).map(|x| ppu.frame().pixel(x, 30)).collect();
+            (line, ppu.peek(0x2002) & 0x40 != 0)
+        };
+        let [backdrop, background, sprite] = [0x0F, 0x21, 0x16];
+
+        let (line, hit) = draw(0x1E);
+        assert_eq!(line[..4], [background; 4]);
+        assert_eq!(line[4..12], [sprite; 8]);
+        assert!(hit);
+
+        // background hidden, the sprite still shows over the backdrop
+        let (line, hit) = draw(0x1C);
+        assert_eq!(line[..4], [backdrop; 4]);
+        assert_eq!(line[4..12], [sprite; 8]);
+        assert!(hit);
+
+        // sprites hidden, the part of the sprite from x=8 on still shows
+        let (line, _) = draw(0x1A);
+        assert_eq!(line[..8], [background; 8]);
+        assert_eq!(line[8..12], [sprite; 4]);
+
+        // both hidden
+        let (line, _) = draw(0x18);
+        assert_eq!(line[..8], [backdrop; 8]);
+        assert_eq!(line[8..12], [sprite; 4]);
+        assert_eq!(line[12..], [background; 4]);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        // nine sprites on line 50
+        ppu.write_register(0x2003, 0, &mut bus);
+        for i in 0..9 {
+            for data in [49, 1, 0, i * 10] {
+                ppu.write_register(0x2004, data, &mut bus);
+            }
+        }
+        for _ in 9..64 {
+            for data in [0xFF, 0, 0, 0] {
+                ppu.write_register(0x2004, data, &mut bus);
+            }
+        }
+        ppu.write_register(0x2001, 0x18, &mut bus);
+        run_to(&mut ppu, &mut bus, 48, 300);
+        assert_eq!(ppu.peek(0x2002) & 0x20, 0);
+        run_to(&mut ppu, &mut bus, 49, 300);
+        assert_eq!(ppu.peek(0x2002) & 0x20, 0x20);
+    }
+
+    #[test]
+    fn test_frame_output() {
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        // backdrop $0F, tile 1 is color 1: $21 in background palette 0, $16 in sprite palette 0
+        for (addr, color) in [(0x3F00, 0x0F), (0x3F01, 0x21), (0x3F11, 0x16)] {
+            ppu.write_palette(addr, color);
+        }
+        place_sprite_0(&mut ppu, &mut bus, 40);
+
+        // nothing but the backdrop with rendering off
+        run_to(&mut ppu, &mut bus, 241, 0);
+        assert!(!ppu.take_frame_ready());
+        ppu.tick(&mut bus);
+        assert!(ppu.take_frame_ready());
+        assert!(!ppu.take_frame_ready());
+        assert!(ppu.frame().pixels().iter().all(|&color| color == 0x0F));
+
+        ppu.write_register(0x2001, 0x1E, &mut bus);
+        run_to(&mut ppu, &mut bus, 240, 0);
+        run_to(&mut ppu, &mut bus, 241, 1);
+        assert!(ppu.take_frame_ready());
+        let frame = ppu.frame();
+        assert_eq!(frame.pixels().len(), WIDTH * HEIGHT);
+        assert_eq!(frame.pixel(0, 0), 0x21);
+        assert_eq!(frame.pixel(255, 239), 0x21);
+        assert_eq!(frame.pixel(40, 30), 0x16);
+        assert_eq!(frame.pixel(47, 37), 0x16);
+        assert_eq!(frame.pixel(48, 30), 0x21);
+
+        let palette = Palette::ntsc();
+        let rgba = frame.to_rgba(&palette);
+        assert_eq!(rgba.len(), WIDTH * HEIGHT * 4);
+        assert_eq!(rgba[..3], palette.rgb(0x21));
+        assert_eq!(rgba[3], 0xFF);
+        let sprite = (30 * WIDTH + 40) * 4;
+        assert_eq!(rgba[sprite..sprite + 3], palette.rgb(0x16));
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        let blank = Frame::new();
+        assert_eq!(blank.hash(), 0x7114_B985_2317_A325);
+        let mut dot = blank.clone();
+        dot.set_pixel(100, 100, 0x21);
+        assert_ne!(dot.hash(), blank.hash());
+
+        // a known-good picture, this only changes if rendering does
+        let (mut ppu, mut bus) = busy_scene(PpuBackend::Dot);
+        run_to(&mut ppu, &mut bus, 241, 1);
+        assert_eq!(ppu.frame().hash(), 0x589C_7334_A850_0E99);
+    }
+
+    #[test]
+    fn test_rgba_conversion() {
+        // every color with every emphasis, whichever conversion path is built in
+        let (mut ppu, mut bus) = busy_scene(PpuBackend::Dot);
+        run_to(&mut ppu, &mut bus, 241, 1);
+        let mut frame = ppu.frame().clone();
+        for index in 0..512 {
+            frame.set_pixel(index % WIDTH, index / WIDTH, index as u16);
+        }
+        let file: Vec<u8> = (0..192).map(|byte| (byte * 7) as u8).collect();
+        for palette in [Palette::ntsc(), Palette::from_bytes(&file).unwrap()] {
+            let rgba = frame.to_rgba(&palette);
+            for (i, &index) in frame.pixels().iter().enumerate() {
+                let [r, g, b] = palette.rgb(index);
+                assert_eq!(rgba[i * 4..i * 4 + 4], [r, g, b, 0xFF], "pixel {}", i);
+            }
+            let mut rgb = vec![0u8; WIDTH * HEIGHT * 3];
+            frame.write_rgb(&palette, &mut rgb);
+            let packed = rgba.chunks_exact(4).flat_map(|rgba| &rgba[..3]);
+            assert!(rgb.iter().eq(packed));
+        }
+    }
+
+    #[test]
+    fn test_debug_views() {
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        // tile 2 in the right table: color 1 on its top row, color 2 in its left column
+        bus.write(0x1020, 0xFF);
+        for row in 1..8 {
+            bus.write(0x1028 + row, 0x80);
+        }
+        let colors = [(0x3F00, 0x0F), (0x3F05, 0x21), (0x3F0D, 0x2A), (0x3F19, 0x30), (0x3F1A, 0x16)];
+        for (addr, color) in colors {
+            ppu.write_palette(addr, color);
+        }
+        // the bottom right 16x16 of the top left attribute byte uses palette 1, and the
+        // fourth nametable is tile 1 in palette 3
+        bus.write(0x23C0, 0b01 << 6);
+        for addr in 0x2C00..0x3000 {
+            bus.write(addr, if addr < 0x2FC0 { 1 } else { 0xFF });
+        }
+
+        let tables = ppu.pattern_tables_image(&bus, 6);
+        assert_eq!((tables.width(), tables.height()), (256, 128));
+        assert_eq!(tables.pixel(8, 0), 0x30);
+        assert_eq!(tables.pixel(128 + 16, 0), 0x30);
+        assert_eq!(tables.pixel(128 + 16, 1), 0x16);
+        assert_eq!(tables.pixel(128 + 17, 1), 0x0F);
+
+        let nametables = ppu.nametables_image(&bus);
+        assert_eq!(nametables.pixels().len(), 512 * 480);
+        assert_eq!(nametables.pixel(0, 0), 0x00);
+        assert_eq!(nametables.pixel(20, 20), 0x21);
+        assert_eq!(nametables.pixel(300, 300), 0x2A);
+
+        ppu.write_register(0x2000, 0b11, &mut bus);
+        ppu.write_register(0x2005, 13, &mut bus);
+        ppu.write_register(0x2005, 37, &mut bus);
+        let rect = ppu.scroll_rect();
+        assert_eq!((rect.x, rect.y, rect.width, rect.height), (256 + 13, 240 + 37, 256, 240));
+
+        let palettes = ppu.palettes_image();
+        assert_eq!((palettes.width(), palettes.height()), (4, 8));
+        assert_eq!(palettes.pixel(1, 1), 0x21);
+        assert_eq!(palettes.pixel(0, 6), 0x00);
+        assert_eq!(palettes.pixel(2, 6), 0x16);
+
+        // sprite 3 on tile 2, palette 2, flipped both ways, with 8x8 sprites from $1000
+        ppu.write_register(0x2000, 0x08, &mut bus);
+        ppu.write_register(0x2003, 12, &mut bus);
+        for data in [50, 2, 0b1110_0010, 60] {
+            ppu.write_register(0x2004, data, &mut bus);
+        }
+        let sprite = ppu.sprites()[3];
+        assert_eq!((sprite.index, sprite.y, sprite.x, sprite.tile), (3, 50, 60, 2));
+        assert_eq!(sprite.palette, 2);
+        assert!(sprite.behind_background && sprite.flip_h && sprite.flip_v);
+        let image = ppu.sprite_image(&bus, 3);
+        assert_eq!((image.width(), image.height()), (8, 8));
+        assert_eq!(image.pixel(7, 0), 0x16);
+        assert_eq!(image.pixel(6, 0), 0x0F);
+        assert_eq!(image.pixel(0, 7), 0x30);
+        assert_eq!(image.to_rgba(&Palette::ntsc())[..3], Palette::ntsc().rgb(0x0F));
+    }
+
+    #[test]
+    fn test_palette_hack() {
+        for backend in [PpuBackend::Dot, PpuBackend::Scanline] {
+            let mut ppu = Ppu::new();
+            ppu.set_backend(backend);
+            let mut bus = vram();
+            ppu.write_palette(0x3F00, 0x0F);
+            ppu.write_palette(0x3F15, 0x21);
+            // rendering off with v on $3F15 shows that entry instead of the backdrop
+            ppu.write_register(0x2006, 0x3F, &mut bus);
+            ppu.write_register(0x2006, 0x15, &mut bus);
+            run_to(&mut ppu, &mut bus, 100, 100);
+            ppu.write_register(0x2006, 0x20, &mut bus);
+            ppu.write_register(0x2006, 0x00, &mut bus);
+            run_to(&mut ppu, &mut bus, 241, 1);
+            let frame = ppu.frame();
+            assert_eq!(frame.pixel(0, 0), 0x21, "{:?}", backend);
+            assert_eq!(frame.pixel(99, 100), 0x21, "{:?}", backend);
+            assert_eq!(frame.pixel(100, 100), 0x0F, "{:?}", backend);
+            assert_eq!(frame.pixel(0, 101), 0x0F, "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn test_grayscale_and_emphasis() {
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        ppu.write_palette(0x3F01, 0x21);
+        // grayscale with red and blue emphasized
+        ppu.write_register(0x2001, 0b1010_1001, &mut bus);
+        run_to(&mut ppu, &mut bus, 241, 1);
+        assert_eq!(ppu.frame().pixel(100, 100), 0b101 << 6 | 0x20);
+    }
+
+    #[test]
+    fn test_ntsc_palette() {
+        let palette = Palette::ntsc();
+        let luma = |[r, g, b]: [u8; 3]| r as u32 + g as u32 + b as u32;
+        // the grays get brighter with each row and $xD-$xF are black
+        assert!(luma(palette.rgb(0x00)) < luma(palette.rgb(0x10)));
+        assert!(luma(palette.rgb(0x10)) < luma(palette.rgb(0x20)));
+        assert_eq!(palette.rgb(0x0D), [0, 0, 0]);
+        assert_eq!(palette.rgb(0x1F), [0, 0, 0]);
+        assert_eq!(palette.rgb(0x20), palette.rgb(0x30));
+        // $12 is blue, $16 red, $1A green
+        let [r, g, b] = palette.rgb(0x12);
+        assert!(b > r && b > g);
+        let [r, g, b] = palette.rgb(0x16);
+        assert!(r > g && r > b);
+        let [r, g, b] = palette.rgb(0x1A);
+        assert!(g > r && g > b);
+
+        // emphasizing a color darkens the other two on white
+        let [r, g, b] = palette.rgb(0x30 | 0b001 << 6);
+        assert!(r > g && r > b);
+        let [r, g, b] = palette.rgb(0x30 | 0b100 << 6);
+        assert!(b > r && b > g);
+        let [r, g, b] = palette.rgb(0x30 | 0b111 << 6);
+        assert!(r < 255 && r == g && g == b);
+    }
+
+    #[test]
+    fn test_pal_files() {
+        // 512 colors are used as they are
+        let raw: Vec<u8> = (0..512 * 3).map(|i| (i % 251) as u8).collect();
+        let palette = Palette::from_bytes(&raw).unwrap();
+        assert_eq!(palette.rgb(0), [0, 1, 2]);
+        assert_eq!(palette.rgb(0x1FF), [27, 28, 29]);
+
+        // 64 colors fill in emphasis by dimming the other channels
+        let raw = [200u8; 64 * 3];
+        let palette = Palette::from_bytes(&raw).unwrap();
+        assert_eq!(palette.rgb(0x3F), [200, 200, 200]);
+        assert_eq!(palette.rgb(0x3F | 0b001 << 6), [200, 149, 149]);
+        assert_eq!(palette.rgb(0x3F | 0b110 << 6), [149, 149, 149]);
+        assert_eq!(palette.rgb(0x3F | 0b111 << 6), [149, 149, 149]);
+
+        assert!(matches!(Palette::from_bytes(&[0; 190]), Err(PaletteError::BadSize(190))));
+        assert!(matches!(Palette::from_file("no/such.pal"), Err(PaletteError::Io(_))));
+    }
+
+    #[test]
+    fn test_odd_frame_dot_skip() {
+        let frame_length = |ppu: &mut Ppu, bus: &mut Memory<Vec<u8>>| {
+            let frame = ppu.frame_count();
+            let mut dots = 0;
+            while ppu.frame_count() == frame {
+                ppu.tick(bus);
+                dots += 1;
+            }
+            dots
+        };
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        ppu.write_register(0x2001, 0x08, &mut bus);
+        assert_eq!(frame_length(&mut ppu, &mut bus), 341 * 262);
+        assert_eq!(frame_length(&mut ppu, &mut bus), 341 * 262 - 1);
+        assert_eq!(frame_length(&mut ppu, &mut bus), 341 * 262);
+        // the odd frame goes from dot 339 of the pre-render line straight to 0,0
+        run_to(&mut ppu, &mut bus, 261, 339);
+        ppu.tick(&mut bus);
+        assert_eq!((ppu.scanline(), ppu.dot()), (0, 0));
+
+        // not with rendering off
+        ppu.write_register(0x2001, 0x00, &mut bus);
+        assert_eq!(frame_length(&mut ppu, &mut bus), 341 * 262);
+        assert_eq!(frame_length(&mut ppu, &mut bus), 341 * 262);
+    }
+
+    #[test]
+    fn test_pal_and_dendy_timing() {
+        let mut ppu = Ppu::new();
+        ppu.set_region(Region::Pal);
+        let mut bus = solid_background();
+        ppu.write_register(0x2000, 0x80, &mut bus);
+        ppu.write_register(0x2001, 0x08, &mut bus);
+        // vblank at the same line as NTSC, then 70 lines of it and no odd frame skip
+        run_to(&mut ppu, &mut bus, 241, 1);
+        assert!(ppu.in_vblank() && ppu.take_nmi());
+        run_to(&mut ppu, &mut bus, 310, 340);
+        assert!(ppu.in_vblank());
+        run_to(&mut ppu, &mut bus, 311, 1);
+        assert!(!ppu.in_vblank());
+        for _ in 0..2 {
+            run_to(&mut ppu, &mut bus, 311, 340);
+            ppu.tick(&mut bus);
+            assert_eq!((ppu.scanline(), ppu.dot()), (0, 0));
+        }
+
+        // Dendy has the same frame with vblank 50 lines later
+        let mut ppu = Ppu::new();
+        ppu.set_region(Region::Dendy);
+        run_to(&mut ppu, &mut bus, 241, 1);
+        assert!(!ppu.in_vblank());
+        run_to(&mut ppu, &mut bus, 291, 1);
+        assert!(ppu.in_vblank());
+    }
+
+    #[test]
+    fn test_pal_emphasis_bits() {
+        let mut ppu = Ppu::new();
+        ppu.set_region(Region::Pal);
+        let mut bus = solid_background();
+        // PPUMASK bit 5 is green on PAL, it ends up in the frame as green
+        ppu.write_register(0x2001, 0b0010_1000, &mut bus);
+        run_to(&mut ppu, &mut bus, 241, 1);
+        assert_eq!(ppu.frame().pixel(100, 100) >> 6, 0b010);
+    }
+
+    #[test]
+    fn test_io_latch_decay() {
+        let run_frames = |ppu: &mut Ppu, bus: &mut Memory<Vec<u8>>, frames: u64| {
+            let end = ppu.frame_count() + frames;
+            while ppu.frame_count() < end {
+                ppu.tick(bus);
+            }
+        };
+        let mut ppu = Ppu::new();
+        let mut bus = vram();
+        ppu.write_palette(0x3F00, 0x3F);
+        ppu.write_register(0x2006, 0x3F, &mut bus);
+        ppu.write_register(0x2006, 0x00, &mut bus);
+        // write-only registers read back the last byte written
+        ppu.write_register(0x2002, 0xFF, &mut bus);
+        assert_eq!(ppu.read_register(0x2001, &mut bus), 0xFF);
+        assert_eq!(ppu.read_register(0x2005, &mut bus), 0xFF);
+
+        // a palette read only refreshes the 6 bits it drives
+        run_frames(&mut ppu, &mut bus, 20);
+        assert_eq!(ppu.read_register(0x2007, &mut bus), 0xFF);
+        run_frames(&mut ppu, &mut bus, 20);
+        assert_eq!(ppu.read_register(0x2000, &mut bus), 0x3F);
+        run_frames(&mut ppu, &mut bus, 20);
+        assert_eq!(ppu.read_register(0x2000, &mut bus), 0x00);
+    }
+
+    #[test]
+    fn test_oam_corner_cases() {
+        let mut ppu = Ppu::new();
+        let mut bus = solid_background();
+        // attribute bits 2-4 aren't stored
+        place_sprite_0(&mut ppu, &mut bus, 40);
+        assert_eq!(ppu.oam()[2], 0);
+        ppu.write_register(0x2003, 2, &mut bus);
+        ppu.write_register(0x2004, 0xFF, &mut bus);
+        assert_eq!(ppu.oam()[2], 0xE3);
+        ppu.write_register(0x2003, 2, &mut bus);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 0xE3);
+
+        // reads while rendering see secondary OAM being cleared, then the sprite fetches
+        ppu.write_register(0x2003, 0, &mut bus);
+        ppu.write_register(0x2001, 0x18, &mut bus);
+        run_to(&mut ppu, &mut bus, 29, 10);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 0xFF);
+        run_to(&mut ppu, &mut bus, 29, 257);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 29);
+        run_to(&mut ppu, &mut bus, 29, 260);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 40);
+        // and the fetches leave OAMADDR at 0
+        assert_eq!(ppu.peek(0x2004), ppu.read_register(0x2004, &mut bus));
+        run_to(&mut ppu, &mut bus, 240, 0);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 29);
+
+        // starting to render with OAMADDR at 8 or more copies its row over the first 8 bytes
+        ppu.write_register(0x2001, 0x00, &mut bus);
+        ppu.write_register(0x2003, 0x10, &mut bus);
+        for data in [1, 2, 3, 4, 5, 6, 7, 8] {
+            ppu.write_register(0x2004, data, &mut bus);
+        }
+        ppu.write_register(0x2003, 0x13, &mut bus);
+        ppu.write_register(0x2001, 0x18, &mut bus);
+        run_to(&mut ppu, &mut bus, 261, 2);
+        assert_eq!(ppu.oam()[..8], [1, 2, 3, 4, 5, 6, 7 & 0xE3, 8]);
+    }
+
+    #[test]
+    fn test_scanline_backend_matches_dots() {
+        let (mut dots, mut dot_bus) = busy_scene(PpuBackend::Dot);
+        let (mut lines, mut line_bus) = busy_scene(PpuBackend::Scanline);
+        // the first frame starts without a pre-render line to load the scroll and first tiles
+        run_to(&mut dots, &mut dot_bus, 261, 0);
+        run_to(&mut lines, &mut line_bus, 261, 0);
+        run_to(&mut dots, &mut dot_bus, 0, 0);
+        run_to(&mut lines, &mut line_bus, 0, 0);
+        for _ in 0..2 {
+            run_to(&mut dots, &mut dot_bus, 240, 0);
+            run_to(&mut lines, &mut line_bus, 240, 0);
+            assert!(dots.frame() == lines.frame());
+            assert_eq!(dots.peek(0x2002), lines.peek(0x2002));
+            assert_eq!(dots.vram_addr(), lines.vram_addr());
+            run_to(&mut dots, &mut dot_bus, 0, 0);
+            run_to(&mut lines, &mut line_bus, 0, 0);
+        }
+        assert_eq!(lines.backend(), PpuBackend::Scanline);
+
+        // sprite 0 hit lands when the line is drawn, or when a PPUSTATUS read catches up
+        let mut ppu = Ppu::new();
+        ppu.set_backend(PpuBackend::Scanline);
+        let mut bus = solid_background();
+        place_sprite_0(&mut ppu, &mut bus, 40);
+        ppu.write_register(0x2001, 0x1E, &mut bus);
+        run_to(&mut ppu, &mut bus, 30, 40);
+        assert_eq!(ppu.read_register(0x2002, &mut bus) & 0x40, 0);
+        run_to(&mut ppu, &mut bus, 30, 41);
+        assert_eq!(ppu.peek(0x2002) & 0x40, 0);
+        assert_eq!(ppu.read_register(0x2002, &mut bus) & 0x40, 0x40);
+    }
+
+    #[test]
+    fn test_mid_scanline_writes() {
+        // emphasis from the middle of line 100, a $2006 split at line 120 between two tiles'
+        // fetches and a fine X change halfway along line 150
+        let writes = [
+            (100, 130, 0x2001, 0x3E),
+            (120, 55, 0x2006, 0x05),
+            (120, 56, 0x2006, 0x23),
+            (150, 200, 0x2005, 0x03),
+        ];
+        let frame_with_writes = |backend, writes: &[(u16, u16, u16, u8)]| {
+            let (mut ppu, mut bus) = busy_scene(backend);
+            run_to(&mut ppu, &mut bus, 0, 0);
+            run_to(&mut ppu, &mut bus, 261, 0);
+            ppu.write_register(0x2005, 13, &mut bus);
+            ppu.write_register(0x2005, 37, &mut bus);
+            for &(scanline, dot, addr, data) in writes {
+                run_to(&mut ppu, &mut bus, scanline, dot);
+                ppu.write_register(addr, data, &mut bus);
+            }
+            run_to(&mut ppu, &mut bus, 240, 0);
+            ppu.frame().clone()
+        };
+        let dots = frame_with_writes(PpuBackend::Dot, &writes);
+        let lines = frame_with_writes(PpuBackend::Scanline, &writes);
+        assert!(dots == lines);
+        // and they did change the picture
+        let untouched = frame_with_writes(PpuBackend::Scanline, &[]);
+        assert!(lines.pixels()[..100 * 256 + 129] == untouched.pixels()[..100 * 256 + 129]);
+        assert!(lines.pixel(130, 100) != untouched.pixel(130, 100));
+    }
+}
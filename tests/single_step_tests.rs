@@ -0,0 +1,159 @@
+use nestacean::nes::cpu::{Cpu, MemAccess};
+use nestacean::nes::test_bus::{BusAccess, TestBus};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct CpuState {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Deserialize)]
+struct TestCase {
+    name: String,
+    initial: CpuState,
+    #[serde(rename = "final")]
+    expected: CpuState,
+    cycles: Vec<(u16, u8, String)>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // SingleStepTests/65x02 nes6502/v1/*.json, not redistributed with the repo
+    const SINGLE_STEP_DIR: &str = "tests/roms/nes6502";
+
+    // Runs one case and returns a description of the first difference, if any.
+    fn run_case(case: &TestCase) -> Result<(), String> {
+        let mut bus = TestBus::with_access_log();
+        for &(addr, value) in &case.initial.ram {
+            bus.memory_mut()[addr as usize] = value;
+        }
+
+        let mut cpu = Cpu::with_bus(bus);
+        cpu.set_pc(case.initial.pc);
+        cpu.set_sp(case.initial.s);
+        cpu.set_accumulator(case.initial.a);
+        cpu.set_index_x(case.initial.x);
+        cpu.set_index_y(case.initial.y);
+        cpu.set_status_p(case.initial.p);
+
+        cpu.run_instruction();
+
+        let registers = |pc: u16, s: u8, a: u8, x: u8, y: u8, p: u8| {
+            format!("PC:{:04X} S:{:02X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X}", pc, s, a, x, y, p)
+        };
+        let expected = &case.expected;
+        let expected_regs = registers(
+            expected.pc,
+            expected.s,
+            expected.a,
+            expected.x,
+            expected.y,
+            expected.p,
+        );
+        let actual_regs = registers(
+            cpu.get_pc(),
+            cpu.get_sp(),
+            cpu.get_accumulator(),
+            cpu.get_index_x(),
+            cpu.get_index_y(),
+            cpu.get_status_p(),
+        );
+        if expected_regs != actual_regs {
+            return Err(format!("registers: expected {}, got {}", expected_regs, actual_regs));
+        }
+
+        for &(addr, value) in &expected.ram {
+            let actual = cpu.bus().memory()[addr as usize];
+            if actual != value {
+                return Err(format!(
+                    "ram[{:04X}]: expected {:02X}, got {:02X}",
+                    addr, value, actual
+                ));
+            }
+        }
+
+        let expected_cycles: Vec<BusAccess> = case
+            .cycles
+            .iter()
+            .map(|&(addr, value, ref kind)| {
+                let access = if kind == "write" { MemAccess::Write } else { MemAccess::Read };
+                BusAccess { addr, value, access }
+            })
+            .collect();
+        let actual_cycles = cpu.bus().access_log();
+        for (cycle, expected) in expected_cycles.iter().enumerate() {
+            if actual_cycles.get(cycle) != Some(expected) {
+                return Err(format!(
+                    "cycle {}: expected {:?}, got {:?}",
+                    cycle,
+                    expected,
+                    actual_cycles.get(cycle)
+                ));
+            }
+        }
+        if actual_cycles.len() != expected_cycles.len() {
+            return Err(format!(
+                "expected {} bus accesses, got {}",
+                expected_cycles.len(),
+                actual_cycles.len()
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_step_case() {
+        let json = r#"[{
+            "name": "a9 42 00",
+            "initial": { "pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                         "ram": [[512, 169], [513, 66]] },
+            "final": { "pc": 514, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36,
+                       "ram": [[512, 169], [513, 66]] },
+            "cycles": [[512, 169, "read"], [513, 66, "read"]]
+        }]"#;
+        let cases: Vec<TestCase> = serde_json::from_str(json).unwrap();
+        assert_eq!(run_case(&cases[0]), Ok(()));
+    }
+
+    #[test]
+    fn test_single_step_suite() {
+        let dir = Path::new(SINGLE_STEP_DIR);
+        if !dir.is_dir() {
+            eprintln!("skipping SingleStepTests: {} not found", SINGLE_STEP_DIR);
+            return;
+        }
+
+        let mut failures = Vec::new();
+        for opcode in 0..=0xFFu8 {
+            let path = dir.join(format!("{:02x}.json", opcode));
+            let Ok(json) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let cases: Vec<TestCase> = serde_json::from_str(&json).unwrap();
+            let failed: Vec<(&TestCase, String)> = cases
+                .iter()
+                .filter_map(|case| run_case(case).err().map(|err| (case, err)))
+                .collect();
+            if let Some((case, err)) = failed.first() {
+                failures.push(format!(
+                    "{:02X}: {}/{} failed, first \"{}\": {}",
+                    opcode,
+                    failed.len(),
+                    cases.len(),
+                    case.name,
+                    err
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,111 @@
+use nestacean::nes::config::Config;
+use nestacean::nes::apu::{Apu, Channel};
+use nestacean::nes::input::{channel_hotkey, Hotkey, Hotkeys};
+use nestacean::nes::speed::{FastForward, FrameLimiter, DEFAULT_FAST_FORWARD_SPEED};
+use sdl2::keyboard::Keycode;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_limiter() {
+        let mut limiter = FrameLimiter::new(50.0);
+        let frame = Duration::from_millis(20);
+        // a late frame starts the schedule over
+        let start = Instant::now() + Duration::from_secs(1);
+        assert_eq!(limiter.wait(start), None);
+        // then frames are due one after another however long each took to run
+        assert_eq!(limiter.wait(start), Some(frame));
+        assert_eq!(limiter.wait(start + frame), Some(frame));
+        assert_eq!(limiter.wait(start + frame * 5), None);
+        assert_eq!(limiter.wait(start + frame * 5), Some(frame));
+        assert!(limiter.present() && limiter.present());
+
+        // fast-forward at the cap, showing one frame in three
+        limiter.set_fast_forward(FastForward { speed: 4.0, frame_skip: 2 });
+        limiter.set_fast_forwarding(true);
+        let now = start + frame * 6;
+        assert_eq!(limiter.wait(now), Some(frame.div_f32(4.0)));
+        let shown: Vec<bool> = (0..6).map(|_| limiter.present()).collect();
+        assert_eq!(shown, [false, false, true, false, false, true]);
+        // uncapped it never waits
+        limiter.set_fast_forward(FastForward { speed: 0.0, frame_skip: 0 });
+        let now = now + frame;
+        assert_eq!(limiter.wait(now), None);
+        assert_eq!(limiter.wait(now), None);
+        assert!(limiter.present());
+
+        limiter.set_fast_forwarding(false);
+        assert!(!limiter.fast_forwarding());
+        assert_eq!(limiter.wait(now), Some(frame));
+
+        // after a pause the next frame is due a frame from when it ended
+        let resumed = now + Duration::from_secs(10);
+        limiter.restart(resumed);
+        assert_eq!(limiter.wait(resumed), Some(frame));
+    }
+
+    #[test]
+    fn test_hotkeys() {
+        let mut hotkeys = Hotkeys::default();
+        assert_eq!(hotkeys.hotkey(Keycode::Tab), Some(Hotkey::FastForward));
+        assert_eq!(hotkeys.hotkey(Keycode::P), Some(Hotkey::Pause));
+        assert_eq!(hotkeys.hotkey(Keycode::Backslash), Some(Hotkey::FrameAdvance));
+        assert_eq!(hotkeys.hotkey(Keycode::F5), Some(Hotkey::SaveState));
+        assert_eq!(hotkeys.hotkey(Keycode::F7), Some(Hotkey::LoadState));
+        assert_eq!(hotkeys.hotkey(Keycode::Num3), Some(Hotkey::MuteTriangle));
+        assert_eq!(hotkeys.hotkey(Keycode::Num0), Some(Hotkey::UnmuteAll));
+        assert_eq!(hotkeys.hotkey(Keycode::R), Some(Hotkey::Record));
+        hotkeys.set_binding(Hotkey::FastForward, Keycode::Space);
+        assert_eq!(hotkeys.hotkey(Keycode::Tab), None);
+        assert_eq!(hotkeys.binding(Hotkey::FastForward), Some(Keycode::Space));
+        hotkeys.clear_binding(Hotkey::FastForward);
+        assert_eq!(hotkeys.hotkey(Keycode::Space), None);
+        // one key, one hotkey
+        hotkeys.set_binding(Hotkey::FastForward, Keycode::P);
+        assert_eq!(hotkeys.binding(Hotkey::Pause), None);
+
+        let config = Config::parse(
+            "hotkey.fast_forward = Space\n\
+             hotkey.pause = Pause\n\
+             hotkey.frame_advance = F6\n\
+             fast_forward.speed = 0\n\
+             fast_forward.frame_skip = 1\n",
+        )
+        .unwrap();
+        assert_eq!(config.hotkeys.binding(Hotkey::FastForward), Some(Keycode::Space));
+        assert_eq!(config.hotkeys.binding(Hotkey::Pause), Some(Keycode::Pause));
+        assert_eq!(config.hotkeys.binding(Hotkey::FrameAdvance), Some(Keycode::F6));
+        assert_eq!(config.fast_forward, FastForward { speed: 0.0, frame_skip: 1 });
+        assert_eq!(Config::default().fast_forward.speed, DEFAULT_FAST_FORWARD_SPEED);
+        assert!(Config::parse("hotkey.rewind = R\n").is_err());
+        assert!(Config::parse("hotkey.fast_forward = Nope\n").is_err());
+        assert!(Config::parse("fast_forward.speed = -1\n").is_err());
+        assert!(Config::parse("fast_forward.frame_skip = 300\n").is_err());
+        assert_eq!(Config::parse(&config.to_string()).unwrap(), config);
+    }
+
+    #[test]
+    fn test_channel_hotkeys() {
+        let mut apu = Apu::new();
+        channel_hotkey(&mut apu, Hotkey::MuteNoise, false);
+        assert!(apu.is_muted(Channel::Noise));
+        channel_hotkey(&mut apu, Hotkey::MuteNoise, false);
+        assert!(!apu.is_muted(Channel::Noise));
+        // shift solos, and the same again stops
+        channel_hotkey(&mut apu, Hotkey::MutePulse2, true);
+        assert_eq!(apu.solo(), Some(Channel::Pulse2));
+        channel_hotkey(&mut apu, Hotkey::MutePulse2, true);
+        assert_eq!(apu.solo(), None);
+        channel_hotkey(&mut apu, Hotkey::MuteDmc, false);
+        channel_hotkey(&mut apu, Hotkey::MuteTriangle, true);
+        channel_hotkey(&mut apu, Hotkey::UnmuteAll, false);
+        assert_eq!(apu.solo(), None);
+        assert!(Channel::ALL.iter().all(|&channel| !apu.is_muted(channel)));
+        // the rest aren't channel hotkeys
+        channel_hotkey(&mut apu, Hotkey::Pause, true);
+        assert_eq!(apu.solo(), None);
+    }
+}